@@ -0,0 +1,69 @@
+//! Value iteration on a deterministic 100x100 grid, of 10,000 states,
+//! through the general backup against the fast path reading
+//! `transition` directly. The two are checked to agree before timing.
+
+use std::collections::HashSet;
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use reinforce_rust::dp;
+use reinforce_rust::env::IDeterministicEnvironment;
+
+const SIZE: i32 = 100;
+const GAMMA: f32 = 0.9;
+const THETA: f32 = 1e-4;
+const MAX_ITERS: usize = 10_000;
+
+/// A grid in which each move costs 0.01 and entering the far corner
+/// pays 1.
+struct Grid;
+
+impl IDeterministicEnvironment for Grid {
+    type State = (i32, i32);
+    type Action = (i32, i32);
+
+    fn transition(&self, &(x, y): &(i32, i32), &(dx, dy): &(i32, i32)) -> ((i32, i32), f32) {
+        let to = ((x + dx).clamp(0, SIZE - 1), (y + dy).clamp(0, SIZE - 1));
+        (
+            to,
+            if to == (SIZE - 1, SIZE - 1) {
+                1.0
+            } else {
+                -0.01
+            },
+        )
+    }
+
+    fn actions_from(&self, _: &(i32, i32)) -> HashSet<(i32, i32)> {
+        HashSet::from([(1, 0), (-1, 0), (0, 1), (0, -1)])
+    }
+
+    fn is_terminal(&self, state: &(i32, i32)) -> bool {
+        *state == (SIZE - 1, SIZE - 1)
+    }
+
+    fn start_states(&self) -> HashSet<(i32, i32)> {
+        HashSet::from([(0, 0)])
+    }
+}
+
+fn sweep(c: &mut Criterion) {
+    let (v, _) = dp::value_iteration(&Grid, Some(GAMMA), THETA, MAX_ITERS).unwrap();
+    let (fast, _) = dp::value_iteration_deterministic(&Grid, Some(GAMMA), THETA, MAX_ITERS);
+    assert_eq!(v, fast);
+
+    let mut group = c.benchmark_group("deterministic_10k_states");
+    group.sample_size(10);
+    group.bench_function("general", |b| {
+        b.iter(|| dp::value_iteration(black_box(&Grid), Some(GAMMA), THETA, MAX_ITERS))
+    });
+    group.bench_function("fast_path", |b| {
+        b.iter(|| {
+            dp::value_iteration_deterministic(black_box(&Grid), Some(GAMMA), THETA, MAX_ITERS)
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, sweep);
+criterion_main!(benches);
@@ -0,0 +1,28 @@
+//! Serial against parallel value iteration on a slippery 100x100
+//! `GridWorld`, of 10,000 states.
+
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use reinforce_rust::dp;
+use reinforce_rust::env::examples::GridWorld;
+
+const GAMMA: f32 = 0.9;
+const THETA: f32 = 1e-4;
+const MAX_ITERS: usize = 10_000;
+
+fn sweep(c: &mut Criterion) {
+    let env = GridWorld::new(100, 100, [], (99, 99), 0.01, 0.2);
+    let mut group = c.benchmark_group("10k_states");
+    group.sample_size(10);
+    group.bench_function("serial", |b| {
+        b.iter(|| dp::value_iteration(black_box(&env), Some(GAMMA), THETA, MAX_ITERS))
+    });
+    group.bench_function("parallel", |b| {
+        b.iter(|| dp::par_value_iteration(black_box(&env), Some(GAMMA), THETA, MAX_ITERS))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, sweep);
+criterion_main!(benches);
@@ -0,0 +1,102 @@
+//! Time to convergence of the dynamic-programming planners on square
+//! `GridWorld`s of increasing size, with and without slipping.
+//!
+//! Every planner runs with the same `gamma` and `theta`, and before
+//! timing each one its result is checked to be within `TOLERANCE` of
+//! a fixed point of the Bellman optimality backup, so the times
+//! compare runs that reached the same answer.
+
+use std::hint::black_box;
+
+use criterion::measurement::WallTime;
+use criterion::{criterion_group, criterion_main, BenchmarkGroup, BenchmarkId, Criterion};
+use reinforce_rust::dp::{self, ValueFunction};
+use reinforce_rust::env::examples::GridWorld;
+use reinforce_rust::env::IEnvironment;
+
+const GAMMA: f32 = 0.9;
+const THETA: f32 = 1e-4;
+const MAX_ITERS: usize = 10_000;
+/// The largest Bellman residual a result may have to count as
+/// converged.
+const TOLERANCE: f32 = 10.0 * THETA;
+
+/// The side lengths of the grids benchmarked.
+const SIZES: [usize; 4] = [10, 25, 50, 100];
+
+/// A square grid of side `n`, from one corner to the opposite one,
+/// slipping with probability `slip`.
+fn square(n: usize, slip: f32) -> GridWorld {
+    GridWorld::new(n, n, [], (n - 1, n - 1), 0.01, slip)
+}
+
+/// The largest change the Bellman optimality backup makes to any
+/// state's value in `v`.
+fn bellman_residual<E: IEnvironment>(env: &E, v: &ValueFunction<E>) -> f32 {
+    let q = dp::q_from_v(env, v, Some(GAMMA)).expect("grid rewards are enumerable");
+    env.all_states()
+        .iter()
+        .map(|s| (q.max_action_value(env, s) - v[s]).abs())
+        .fold(0.0, f32::max)
+}
+
+fn assert_converged<E: IEnvironment>(env: &E, v: &ValueFunction<E>, planner: &str, n: usize) {
+    let residual = bellman_residual(env, v);
+    assert!(
+        residual <= TOLERANCE,
+        "{planner} on {n}x{n} stopped with a Bellman residual of {residual}"
+    );
+}
+
+fn bench_grid(group: &mut BenchmarkGroup<'_, WallTime>, env: &GridWorld) {
+    let n = env.width();
+
+    let (v, _) = dp::value_iteration(env, Some(GAMMA), THETA, MAX_ITERS).unwrap();
+    assert_converged(env, &v, "value_iteration", n);
+    group.bench_function(BenchmarkId::new("value_iteration", n), |b| {
+        b.iter(|| dp::value_iteration(black_box(env), Some(GAMMA), THETA, MAX_ITERS))
+    });
+
+    #[cfg(feature = "parallel")]
+    {
+        let (v, _) = dp::par_value_iteration(env, Some(GAMMA), THETA, MAX_ITERS).unwrap();
+        assert_converged(env, &v, "par_value_iteration", n);
+        group.bench_function(BenchmarkId::new("par_value_iteration", n), |b| {
+            b.iter(|| dp::par_value_iteration(black_box(env), Some(GAMMA), THETA, MAX_ITERS))
+        });
+    }
+
+    // Policy iteration evaluates every intermediate policy to
+    // convergence, so on the largest grids a single run takes tens of
+    // seconds.
+    let (v, _, report) = dp::policy_iteration(env, Some(GAMMA), THETA, MAX_ITERS).unwrap();
+    assert!(
+        report.converged,
+        "policy_iteration on {n}x{n} didn't converge"
+    );
+    assert_converged(env, &v, "policy_iteration", n);
+    group.bench_function(BenchmarkId::new("policy_iteration", n), |b| {
+        b.iter(|| dp::policy_iteration(black_box(env), Some(GAMMA), THETA, MAX_ITERS))
+    });
+}
+
+fn deterministic(c: &mut Criterion) {
+    let mut group = c.benchmark_group("deterministic");
+    group.sample_size(10);
+    for n in SIZES {
+        bench_grid(&mut group, &square(n, 0.0));
+    }
+    group.finish();
+}
+
+fn slippery(c: &mut Criterion) {
+    let mut group = c.benchmark_group("slippery");
+    group.sample_size(10);
+    for n in SIZES {
+        bench_grid(&mut group, &square(n, 0.2));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, deterministic, slippery);
+criterion_main!(benches);
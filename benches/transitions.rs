@@ -0,0 +1,227 @@
+//! Allocations and time spent computing every transition's
+//! probability and expected reward through the default methods, which
+//! fetch `rewards()` on every call, against fetching it once and
+//! reusing it, as the planners do.
+//!
+//! The environment has a large reward set, allocated afresh by every
+//! call to `rewards()`. The allocation counts are printed before
+//! timing.
+//!
+//! Also the next states of every transition of a large `GridWorld`,
+//! collected into a fresh `HashSet` by `states_from` against written
+//! into one reused buffer by `states_from_into`, as the planners do.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::hint::black_box;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use std::collections::HashSet;
+
+use reinforce_rust::env::examples::GridWorld;
+use reinforce_rust::env::IEnvironment;
+
+/// The system allocator, counting every allocation made through it.
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+/// The number of allocations `f` makes.
+fn allocations(f: impl FnOnce()) -> usize {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    f();
+    ALLOCATIONS.load(Ordering::Relaxed) - before
+}
+
+/// A ring of `STATES` states, in which each action moves one step
+/// either way, with a reward drawn uniformly from `REWARDS` values.
+struct NoisyRing;
+
+const STATES: usize = 1_000;
+const REWARDS: usize = 256;
+
+impl NoisyRing {
+    fn next(from: usize, take: bool) -> usize {
+        if take {
+            (from + 1) % STATES
+        } else {
+            (from + STATES - 1) % STATES
+        }
+    }
+}
+
+impl IEnvironment for NoisyRing {
+    type State = usize;
+    type Action = bool;
+
+    fn prob(&self, from: &usize, take: &bool, to: &usize, _: &f32) -> f32 {
+        if *to == Self::next(*from, *take) {
+            1.0 / REWARDS as f32
+        } else {
+            0.0
+        }
+    }
+
+    fn actions_from(&self, _: &usize) -> HashSet<bool> {
+        HashSet::from([false, true])
+    }
+
+    fn states_from(&self, from: &usize, take: &bool) -> HashSet<usize> {
+        HashSet::from([Self::next(*from, *take)])
+    }
+
+    fn is_terminal(&self, _: &usize) -> bool {
+        false
+    }
+
+    fn start_states(&self) -> HashSet<usize> {
+        HashSet::from([0])
+    }
+
+    fn all_states(&self) -> HashSet<usize> {
+        (0..STATES).collect()
+    }
+
+    fn rewards(&self) -> Vec<f32> {
+        (0..REWARDS).map(|i| i as f32 / REWARDS as f32).collect()
+    }
+}
+
+/// Sums `f` over every transition of `env`.
+fn sum_transitions<E: IEnvironment>(
+    env: &E,
+    f: impl Fn(&E::State, &E::Action, &E::State) -> f32,
+) -> f32 {
+    let mut total = 0.0;
+    for s in env.all_states() {
+        if env.is_terminal(&s) {
+            continue;
+        }
+        for a in env.actions_from(&s) {
+            for to in env.states_from(&s, &a) {
+                total += f(&s, &a, &to);
+            }
+        }
+    }
+    total
+}
+
+/// The probability and expected reward of every transition, from the
+/// default methods.
+fn per_transition<E: IEnvironment>(env: &E) -> f32 {
+    sum_transitions(env, |s, a, to| {
+        env.prob_transition(s, a, to).unwrap() + env.expected_reward_at(s, a, to).unwrap()
+    })
+}
+
+/// The probability and expected reward of every transition, from a
+/// single enumeration of the rewards.
+fn enumerated_once<E: IEnvironment>(env: &E) -> f32 {
+    let rewards = env.rewards();
+    sum_transitions(env, |s, a, to| {
+        rewards
+            .iter()
+            .map(|r| {
+                let p = env.prob(s, a, to, r);
+                p + p * r
+            })
+            .sum()
+    })
+}
+
+fn enumerate(c: &mut Criterion) {
+    let env = NoisyRing;
+    let before = allocations(|| {
+        black_box(per_transition(&env));
+    });
+    let after = allocations(|| {
+        black_box(enumerated_once(&env));
+    });
+    eprintln!(
+        "{REWARDS} rewards: {before} allocations through the default methods, \
+         {after} with the rewards fetched once"
+    );
+
+    let mut group = c.benchmark_group("enumerate");
+    group.bench_function("per_transition", |b| {
+        b.iter(|| per_transition(black_box(&env)))
+    });
+    group.bench_function("enumerated_once", |b| {
+        b.iter(|| enumerated_once(black_box(&env)))
+    });
+    group.finish();
+}
+
+/// The side length of the grid whose next states are enumerated.
+const SIDE: usize = 100;
+
+/// The sum over every state-action pair of `GridWorld` of its next
+/// states' coordinates, from `states_from`.
+fn next_states_allocating(env: &GridWorld) -> usize {
+    let mut total = 0;
+    for s in env.all_states() {
+        for a in env.actions_from(&s) {
+            total += env
+                .states_from(&s, &a)
+                .iter()
+                .map(|(x, y)| x + y)
+                .sum::<usize>();
+        }
+    }
+    total
+}
+
+/// As `next_states_allocating`, from `states_from_into` and a single
+/// buffer.
+fn next_states_into(env: &GridWorld) -> usize {
+    let mut total = 0;
+    let mut buf = Vec::new();
+    for s in env.all_states() {
+        for a in env.actions_from(&s) {
+            env.states_from_into(&s, &a, &mut buf);
+            total += buf.iter().map(|(x, y)| x + y).sum::<usize>();
+        }
+    }
+    total
+}
+
+fn next_states(c: &mut Criterion) {
+    let env = GridWorld::new(SIDE, SIDE, [], (SIDE - 1, SIDE - 1), 0.01, 0.2);
+    assert_eq!(next_states_allocating(&env), next_states_into(&env));
+    let before = allocations(|| {
+        black_box(next_states_allocating(&env));
+    });
+    let after = allocations(|| {
+        black_box(next_states_into(&env));
+    });
+    eprintln!(
+        "{SIDE}x{SIDE} grid: {before} allocations collecting next states into sets, \
+         {after} writing them into one buffer"
+    );
+
+    let mut group = c.benchmark_group("next_states");
+    group.bench_function("states_from", |b| {
+        b.iter(|| next_states_allocating(black_box(&env)))
+    });
+    group.bench_function("states_from_into", |b| {
+        b.iter(|| next_states_into(black_box(&env)))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, enumerate, next_states);
+criterion_main!(benches);
@@ -0,0 +1,571 @@
+//! Function approximation for environments too large to tabulate.
+
+use crate::dp::{solve_linear, ValueFunction};
+use crate::env::IEnvironment;
+use crate::policy::{fixed_order, Policy};
+use crate::rng::StdRng;
+use crate::simulate::{self, EPISODE_STEP_LIMIT};
+
+type FeatureMap<S> = Box<dyn Fn(&S) -> Vec<f32>>;
+
+/// A state-value function linear in a feature map:
+/// `V(s) = w . features(s)`.
+///
+/// The weights start at zero, taking their length from the feature
+/// vector of the first update; until then every prediction is 0.
+/// From then on every feature vector must have the same length, and
+/// a mismatch panics.
+pub struct LinearVF<E: IEnvironment> {
+    features: FeatureMap<E::State>,
+    weights: Vec<f32>,
+}
+
+impl<E: IEnvironment> LinearVF<E> {
+    pub fn new(features: impl Fn(&E::State) -> Vec<f32> + 'static) -> Self {
+        LinearVF {
+            features: Box::new(features),
+            weights: Vec::new(),
+        }
+    }
+
+    /// The features of `s`, checked against the weights' length.
+    pub fn features(&self, s: &E::State) -> Vec<f32> {
+        let x = (self.features)(s);
+        assert!(
+            self.weights.is_empty() || x.len() == self.weights.len(),
+            "feature vector has length {}, expected {}",
+            x.len(),
+            self.weights.len()
+        );
+        x
+    }
+
+    pub fn weights(&self) -> &[f32] {
+        &self.weights
+    }
+
+    pub fn predict(&self, s: &E::State) -> f32 {
+        dot(&self.weights, &self.features(s))
+    }
+
+    /// A gradient step moving `predict(s)` towards `target`:
+    /// `w += alpha * (target - predict(s)) * features(s)`.
+    pub fn update(&mut self, s: &E::State, target: f32, alpha: f32) {
+        let x = self.features(s);
+        if self.weights.is_empty() {
+            self.weights = vec![0.0; x.len()];
+        }
+        let error = target - dot(&self.weights, &x);
+        for (w, x) in self.weights.iter_mut().zip(x) {
+            *w += alpha * error * x;
+        }
+    }
+}
+
+fn dot(w: &[f32], x: &[f32]) -> f32 {
+    w.iter().zip(x).map(|(w, x)| w * x).sum()
+}
+
+/// Semi-gradient TD(0) prediction: trains `vf` towards the
+/// state-value function of `policy` over `episodes` simulated
+/// episodes.
+///
+/// Each step updates `vf` towards `r + gamma * V(s')`, treating the
+/// bootstrapped target as fixed; terminal states have value 0.
+/// `gamma` defaults to `env.gamma()`.
+pub fn semi_gradient_td<E, P>(
+    env: &E,
+    vf: &mut LinearVF<E>,
+    policy: &P,
+    episodes: usize,
+    alpha: f32,
+    gamma: Option<f32>,
+    rng: &mut StdRng,
+) where
+    E: IEnvironment,
+    P: Policy<E>,
+{
+    let gamma = gamma.unwrap_or_else(|| env.gamma());
+    for _ in 0..episodes {
+        let Some(mut state) = simulate::sample_start(env, rng) else {
+            break;
+        };
+        for _ in 0..EPISODE_STEP_LIMIT {
+            if env.is_terminal(&state) || env.actions_from(&state).is_empty() {
+                break;
+            }
+            let action = policy.sample(env, &state, rng);
+            let Some((next, reward)) = env.sample_transition(&state, &action, rng) else {
+                break;
+            };
+            let next_value = if env.is_terminal(&next) {
+                0.0
+            } else {
+                vf.predict(&next)
+            };
+            vf.update(&state, reward + gamma * next_value, alpha);
+            state = next;
+        }
+    }
+}
+
+/// A model fitted to predict a value from a feature vector, as used by
+/// `fitted_value_iteration`.
+pub trait Regressor {
+    /// Fits the model to predict each of `y` from the corresponding
+    /// feature vector of `x`, replacing any earlier fit.
+    fn fit(&mut self, x: &[Vec<f32>], y: &[f32]);
+
+    fn predict(&self, x: &[f32]) -> f32;
+}
+
+/// The ridge penalty added to the least-squares fit of `LinearVF`,
+/// just enough to keep the normal equations solvable when features
+/// are collinear.
+const RIDGE: f64 = 1e-6;
+
+/// By least squares on the feature vectors given, ignoring the
+/// feature map, which should produce those same features for the
+/// weights to be meaningful to `LinearVF::predict`.
+impl<E: IEnvironment> Regressor for LinearVF<E> {
+    fn fit(&mut self, x: &[Vec<f32>], y: &[f32]) {
+        let Some(n) = x.first().map(Vec::len) else {
+            return;
+        };
+        // The normal equations `(X^T X + ridge I) w = X^T y`.
+        let mut gram = vec![vec![0.0; n]; n];
+        let mut moment = vec![0.0; n];
+        for (row, target) in x.iter().zip(y) {
+            assert_eq!(row.len(), n, "feature vectors have different lengths");
+            for i in 0..n {
+                moment[i] += f64::from(row[i]) * f64::from(*target);
+                for j in 0..n {
+                    gram[i][j] += f64::from(row[i]) * f64::from(row[j]);
+                }
+            }
+        }
+        for (i, line) in gram.iter_mut().enumerate() {
+            line[i] += RIDGE;
+        }
+        let weights = solve_linear(gram, moment).expect("the ridge keeps the fit solvable");
+        self.weights = weights.into_iter().map(|w| w as f32).collect();
+    }
+
+    fn predict(&self, x: &[f32]) -> f32 {
+        dot(&self.weights, x)
+    }
+}
+
+/// Fitted value iteration: value iteration with the value function
+/// represented by `regressor` over the features `features` gives each
+/// state, rather than by a table, so that only `samples` need be
+/// backed up.
+///
+/// The regressor is first fitted to the initial value given with each
+/// sampled state. Each of `iterations` iterations then computes the
+/// Bellman target of every sample from the environment's model,
+/// `max_a (r(s, a) + gamma * sum_s' P(s'|s, a) V(s'))` with `V` the
+/// current fit, terminal states worth 0, and refits the regressor to
+/// the targets. Which states are sampled is up to the caller; they
+/// need not be all, nor even reachable. `gamma` defaults to
+/// `env.gamma()`.
+///
+/// Unlike tabular value iteration this needn't converge, depending
+/// on how well the regressor can represent the values. Returns the
+/// largest change in a sample's fitted value over the last iteration,
+/// `f32::INFINITY` if `iterations` is 0 so that none was measured, or
+/// `None` if the environment's transition distributions or expected
+/// rewards are undefined.
+pub fn fitted_value_iteration<E, R>(
+    env: &E,
+    samples: &[(E::State, f32)],
+    features: impl Fn(&E::State) -> Vec<f32>,
+    regressor: &mut R,
+    iterations: usize,
+    gamma: Option<f32>,
+) -> Option<f32>
+where
+    E: IEnvironment,
+    R: Regressor,
+{
+    let gamma = gamma.unwrap_or_else(|| env.gamma());
+    let x: Vec<Vec<f32>> = samples.iter().map(|(s, _)| features(s)).collect();
+    let initial: Vec<f32> = samples.iter().map(|(_, value)| *value).collect();
+    regressor.fit(&x, &initial);
+    // Changes are measured from the fit, not the initial values it may
+    // not represent exactly.
+    let mut y: Vec<f32> = x.iter().map(|x| regressor.predict(x)).collect();
+    let value = |s: &E::State, regressor: &R| {
+        if env.is_terminal(s) {
+            0.0
+        } else {
+            regressor.predict(&features(s))
+        }
+    };
+    let mut delta = f32::INFINITY;
+    for _ in 0..iterations {
+        let mut targets = Vec::with_capacity(samples.len());
+        for (s, _) in samples {
+            let mut best: Option<f32> = None;
+            if !env.is_terminal(s) {
+                for a in fixed_order(env.action_mask(s)) {
+                    let mut q = env.expected_reward(s, &a)?;
+                    for (to, p) in env.transition_distribution(s, &a)? {
+                        q += gamma * p * value(&to, regressor);
+                    }
+                    best = Some(best.map_or(q, |b| b.max(q)));
+                }
+            }
+            targets.push(best.unwrap_or(0.0));
+        }
+        regressor.fit(&x, &targets);
+        delta = x
+            .iter()
+            .zip(&y)
+            .map(|(x, old)| (regressor.predict(x) - old).abs())
+            .fold(0.0, f32::max);
+        y = x.iter().map(|x| regressor.predict(x)).collect();
+    }
+    Some(delta)
+}
+
+/// Tile coding: maps continuous observations to sparse binary
+/// features through several overlapping grids ("tilings").
+///
+/// Each tiling divides the observation ranges into `tiles_per_dim`
+/// tiles per dimension, and is offset from the others by a fraction
+/// of a tile, differently in each dimension. An observation activates
+/// exactly one tile in each tiling. Observations outside the ranges
+/// are clamped into them.
+#[derive(Debug, Clone)]
+pub struct TileCoder {
+    num_tilings: usize,
+    tiles_per_dim: usize,
+    ranges: Vec<(f32, f32)>,
+}
+
+impl TileCoder {
+    /// A coder over observations whose `i`-th component lies in
+    /// `ranges[i]`, given as `(low, high)`.
+    ///
+    /// Panics if there are no tilings or tiles.
+    pub fn new(num_tilings: usize, tiles_per_dim: usize, ranges: Vec<(f32, f32)>) -> Self {
+        assert!(num_tilings > 0, "tile coder needs at least one tiling");
+        assert!(tiles_per_dim > 0, "tile coder needs at least one tile");
+        TileCoder {
+            num_tilings,
+            tiles_per_dim,
+            ranges,
+        }
+    }
+
+    pub fn num_tilings(&self) -> usize {
+        self.num_tilings
+    }
+
+    /// The number of tiles in each tiling. The offsets push part of
+    /// each tiling past the high end of the ranges, so this counts one
+    /// extra tile per dimension.
+    fn tiles_per_tiling(&self) -> usize {
+        (self.tiles_per_dim + 1).pow(self.ranges.len() as u32)
+    }
+
+    /// The total number of features, i.e. of tiles across all
+    /// tilings.
+    pub fn num_features(&self) -> usize {
+        self.num_tilings * self.tiles_per_tiling()
+    }
+
+    /// The index of the active tile in each tiling for `obs`, in
+    /// order of tiling.
+    ///
+    /// Panics if `obs` doesn't have one component per range.
+    pub fn features(&self, obs: &[f32]) -> Vec<usize> {
+        assert_eq!(
+            obs.len(),
+            self.ranges.len(),
+            "observation has the wrong number of dimensions"
+        );
+        (0..self.num_tilings)
+            .map(|t| {
+                let mut index = 0;
+                for (d, (x, (low, high))) in obs.iter().zip(&self.ranges).enumerate() {
+                    let width = (high - low) / self.tiles_per_dim as f32;
+                    // Offset each dimension by a different multiple of
+                    // 1 / num_tilings of a tile, so the tilings don't
+                    // all line up along the diagonal.
+                    let offset =
+                        ((t * (2 * d + 1)) % self.num_tilings) as f32 / self.num_tilings as f32;
+                    let x = x.clamp(*low, *high);
+                    let tile = if width > 0.0 {
+                        ((x - low) / width + offset) as usize
+                    } else {
+                        0
+                    };
+                    index = index * (self.tiles_per_dim + 1) + tile.min(self.tiles_per_dim);
+                }
+                t * self.tiles_per_tiling() + index
+            })
+            .collect()
+    }
+
+    /// The features of `obs` as a dense binary vector of length
+    /// `num_features`, e.g. for a `LinearVF`.
+    pub fn dense(&self, obs: &[f32]) -> Vec<f32> {
+        let mut x = vec![0.0; self.num_features()];
+        for i in self.features(obs) {
+            x[i] = 1.0;
+        }
+        x
+    }
+}
+
+/// A value function over points in a metric space, interpolating
+/// between known values by inverse-distance weighting of the `k`
+/// nearest, e.g. to evaluate a value function computed over a
+/// discretization at states between its grid points.
+///
+/// A query matching a known point exactly returns its value, so with
+/// `k = 1` this is a lookup of the nearest point. Between two points
+/// the interpolation is linear. Distances are Euclidean, and every
+/// query scans all known points. With no known points every
+/// prediction is 0; once there are some, every point must have the
+/// same number of dimensions, and a mismatch panics.
+#[derive(Debug, Clone)]
+pub struct NearestNeighborVF {
+    points: Vec<(Vec<f32>, f32)>,
+    k: usize,
+}
+
+impl NearestNeighborVF {
+    /// Interpolates between the `k` nearest known points.
+    ///
+    /// Panics if `k` is 0.
+    pub fn new(k: usize) -> Self {
+        assert!(k > 0, "interpolation needs at least one neighbor");
+        NearestNeighborVF {
+            points: Vec::new(),
+            k,
+        }
+    }
+
+    /// Interpolates the state-value function `v`, placing each state at
+    /// the point `coords` gives it.
+    pub fn from_values<E: IEnvironment>(
+        v: &ValueFunction<E>,
+        coords: impl Fn(&E::State) -> Vec<f32>,
+        k: usize,
+    ) -> Self {
+        let mut vf = Self::new(k);
+        // In a fixed order, so that ties between equally near points
+        // are broken the same way every run.
+        for s in fixed_order(v.keys()) {
+            vf.insert(coords(s), v[s]);
+        }
+        vf
+    }
+
+    /// Adds a known `value` at `point`.
+    pub fn insert(&mut self, point: Vec<f32>, value: f32) {
+        self.check(&point);
+        self.points.push((point, value));
+    }
+
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    pub fn k(&self) -> usize {
+        self.k
+    }
+
+    fn check(&self, point: &[f32]) {
+        if let Some((known, _)) = self.points.first() {
+            assert_eq!(
+                point.len(),
+                known.len(),
+                "point has the wrong number of dimensions"
+            );
+        }
+    }
+
+    /// The interpolated value at `query`.
+    pub fn predict(&self, query: &[f32]) -> f32 {
+        self.check(query);
+        let mut nearest: Vec<(f32, f32)> = self
+            .points
+            .iter()
+            .map(|(point, value)| (distance(point, query), *value))
+            .collect();
+        nearest.sort_by(|a, b| a.0.total_cmp(&b.0));
+        nearest.truncate(self.k);
+        if let Some((_, value)) = nearest.iter().find(|(d, _)| *d == 0.0) {
+            return *value;
+        }
+        let total: f32 = nearest.iter().map(|(d, _)| 1.0 / d).sum();
+        if total == 0.0 {
+            return 0.0;
+        }
+        nearest.iter().map(|(d, value)| value / d).sum::<f32>() / total
+    }
+}
+
+fn distance(a: &[f32], b: &[f32]) -> f32 {
+    a.iter()
+        .zip(b)
+        .map(|(a, b)| (a - b).powi(2))
+        .sum::<f32>()
+        .sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::dp;
+    use crate::rng::make_rng;
+    use crate::testing::Chain;
+
+    #[test]
+    fn updates_fit_a_linear_function() {
+        let mut vf = LinearVF::<Chain>::new(|s| vec![*s as f32, 1.0]);
+        assert_eq!(vf.predict(&2), 0.0);
+        for _ in 0..2000 {
+            for s in 0..Chain::GOAL {
+                vf.update(&s, 2.0 * s as f32 - 1.0, 0.1);
+            }
+        }
+        assert!((vf.weights()[0] - 2.0).abs() < 1e-3);
+        assert!((vf.weights()[1] + 1.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn semi_gradient_td_learns_a_linear_value_function() {
+        // Undiscounted, every state on the way to the goal is worth
+        // its reward of 1, which the weights (0, 1) represent exactly.
+        let policy = Chain::rightward();
+        let v = dp::evaluate_policy(&Chain, &policy, Some(1.0), 1e-6, 1000).unwrap();
+        let mut vf = LinearVF::<Chain>::new(|s| vec![*s as f32 / 3.0, 1.0]);
+        semi_gradient_td(
+            &Chain,
+            &mut vf,
+            &policy,
+            2000,
+            0.1,
+            Some(1.0),
+            &mut make_rng(0),
+        );
+        for s in 0..Chain::GOAL {
+            assert!((vf.predict(&s) - v[&s]).abs() < 1e-3, "{s}");
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "feature vector has length 2, expected 1")]
+    fn feature_lengths_must_agree() {
+        let mut vf = LinearVF::<Chain>::new(|s| vec![1.0; *s as usize + 1]);
+        vf.update(&0, 1.0, 0.1);
+        vf.predict(&1);
+    }
+
+    #[cfg(feature = "examples")]
+    #[test]
+    fn fitted_value_iteration_converges_on_linear_values() {
+        use crate::env::examples::GridWorld;
+
+        // Undiscounted, the optimal value falls by the step cost with
+        // each step further from the goal.
+        let corridor = GridWorld::new(6, 1, [], (5, 0), 0.1, 0.0).with_start((0, 0));
+        let (v, _) = dp::value_iteration(&corridor, Some(1.0), 1e-6, 1000).unwrap();
+        let features = |s: &(usize, usize)| vec![s.0 as f32, 1.0];
+        let samples: Vec<_> = (0..5).map(|x| ((x, 0), 0.0)).collect();
+        let mut vf = LinearVF::<GridWorld>::new(features);
+        let delta =
+            fitted_value_iteration(&corridor, &samples, features, &mut vf, 50, Some(1.0)).unwrap();
+        assert!(delta < 1e-4, "{}", delta);
+        for x in 0..5 {
+            assert!((vf.predict(&(x, 0)) - v[&(x, 0)]).abs() < 1e-3, "{}", x);
+        }
+        assert!((vf.weights()[0] - 0.1).abs() < 1e-3);
+    }
+
+    #[cfg(feature = "examples")]
+    #[test]
+    fn fitted_value_iteration_measures_changes_from_the_first_fit() {
+        use crate::env::examples::GridWorld;
+
+        // Initial values scattered evenly about the optimal ones fit
+        // them exactly, so an iteration changes nothing.
+        let corridor = GridWorld::new(6, 1, [], (5, 0), 0.1, 0.0).with_start((0, 0));
+        let (v, _) = dp::value_iteration(&corridor, Some(1.0), 1e-6, 1000).unwrap();
+        let features = |s: &(usize, usize)| vec![s.0 as f32, 1.0];
+        let samples: Vec<_> = (0..5)
+            .flat_map(|x| [((x, 0), v[&(x, 0)] - 1.0), ((x, 0), v[&(x, 0)] + 1.0)])
+            .collect();
+        let mut vf = LinearVF::<GridWorld>::new(features);
+        let delta =
+            fitted_value_iteration(&corridor, &samples, features, &mut vf, 1, Some(1.0)).unwrap();
+        assert!(delta < 1e-3, "{}", delta);
+
+        let none = fitted_value_iteration(&corridor, &samples, features, &mut vf, 0, Some(1.0));
+        assert_eq!(none, Some(f32::INFINITY));
+    }
+
+    #[test]
+    fn linear_fits_are_least_squares() {
+        let mut vf = LinearVF::<Chain>::new(|s| vec![*s as f32, 1.0]);
+        let x = [vec![0.0, 1.0], vec![1.0, 1.0], vec![2.0, 1.0]];
+        Regressor::fit(&mut vf, &x, &[1.0, 2.0, 4.0]);
+        assert!((vf.weights()[0] - 1.5).abs() < 1e-4);
+        assert!((vf.weights()[1] - 5.0 / 6.0).abs() < 1e-4);
+        assert_eq!(Regressor::predict(&vf, &[1.0, 1.0]), vf.predict(&1));
+    }
+
+    #[test]
+    fn every_point_activates_one_tile_per_tiling() {
+        let coder = TileCoder::new(8, 4, vec![(0.0, 1.0), (-2.0, 2.0)]);
+        assert_eq!(coder.num_features(), 8 * 25);
+        for obs in [[0.0, 0.0], [1.0, 2.0], [5.0, -9.0], [0.37, 1.2]] {
+            let active = coder.features(&obs);
+            assert_eq!(active, coder.features(&obs));
+            assert_eq!(active.len(), coder.num_tilings());
+            assert!(active.iter().all(|i| *i < coder.num_features()));
+            assert_eq!(coder.dense(&obs).iter().sum::<f32>(), 8.0);
+        }
+        // Out-of-range observations are clamped to the nearest corner.
+        assert_eq!(coder.features(&[5.0, -9.0]), coder.features(&[1.0, -2.0]));
+    }
+
+    #[test]
+    fn nearby_points_share_most_tiles() {
+        let coder = TileCoder::new(8, 4, vec![(0.0, 1.0)]);
+        let shared = |a: f32, b: f32| {
+            let (a, b) = (coder.features(&[a]), coder.features(&[b]));
+            a.iter().zip(&b).filter(|(x, y)| x == y).count()
+        };
+        assert_eq!(shared(0.5, 0.54), 7);
+        assert_eq!(shared(0.1, 0.9), 0);
+    }
+
+    #[test]
+    fn nearest_neighbors_interpolate_between_known_values() {
+        let v = HashMap::from([(0, 0.0), (1, 2.0), (2, 5.0)]);
+        let vf = NearestNeighborVF::from_values::<Chain>(&v, |s| vec![*s as f32], 2);
+        assert_eq!(vf.len(), 3);
+        assert_eq!(vf.predict(&[0.5]), 1.0);
+        assert!((vf.predict(&[0.25]) - 0.5).abs() < 1e-6);
+        assert_eq!(vf.predict(&[1.0]), 2.0);
+
+        let nearest = NearestNeighborVF::from_values::<Chain>(&v, |s| vec![*s as f32], 1);
+        for (s, value) in &v {
+            assert_eq!(nearest.predict(&[*s as f32]), *value);
+        }
+        assert_eq!(nearest.predict(&[1.8]), 5.0);
+        assert_eq!(NearestNeighborVF::new(1).predict(&[0.0, 1.0]), 0.0);
+    }
+}
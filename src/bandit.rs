@@ -0,0 +1,498 @@
+use rand::Rng;
+
+use crate::env::IEnvironment;
+use crate::policy::fixed_order;
+use crate::rng::{RngConfig, StdRng};
+use crate::simulate::{self, sample_weighted};
+
+/// The UCB1 algorithm for multi-armed bandits.
+///
+/// Treats the environment as a bandit: each step pulls an arm (an
+/// action from a start state) and observes a reward, ignoring any
+/// change of state. Every arm is pulled once, after which each step
+/// pulls the arm maximizing `mean + sqrt(2 ln t / n)`, where `mean`
+/// is the arm's running average reward, `n` the number of times it
+/// has been pulled and `t` the total number of pulls so far.
+///
+/// Returns the arm pulled and the reward observed at every step.
+pub fn ucb1<E: IEnvironment>(env: &E, steps: usize, rng: &mut StdRng) -> Vec<(E::Action, f32)> {
+    let Some(state) = simulate::sample_start(env, rng) else {
+        return Vec::new();
+    };
+    let arms = fixed_order(env.actions_from(&state));
+    let mut means = vec![0.0; arms.len()];
+    let mut counts = vec![0usize; arms.len()];
+
+    let mut history = Vec::with_capacity(steps);
+    for t in 0..steps {
+        let Some(i) = select(&means, &counts, t) else {
+            break;
+        };
+        let Some((_, reward)) = env.sample_transition(&state, &arms[i], rng) else {
+            break;
+        };
+        counts[i] += 1;
+        means[i] += (reward - means[i]) / counts[i] as f32;
+        history.push((arms[i].clone(), reward));
+    }
+    history
+}
+
+/// The epsilon-greedy algorithm for multi-armed bandits.
+///
+/// Treats the environment as a bandit, like `ucb1`. Each step pulls a
+/// uniformly random arm with probability `epsilon`, and otherwise the
+/// arm with the highest average reward so far, ties going to the
+/// first in a fixed order; arms not yet pulled average 0.
+///
+/// Returns the arm pulled and the reward observed at every step.
+pub fn epsilon_greedy_bandit<E: IEnvironment>(
+    env: &E,
+    steps: usize,
+    epsilon: f32,
+    rng: &mut StdRng,
+) -> Vec<(E::Action, f32)> {
+    let Some(state) = simulate::sample_start(env, rng) else {
+        return Vec::new();
+    };
+    let arms = fixed_order(env.actions_from(&state));
+    if arms.is_empty() {
+        return Vec::new();
+    }
+    let mut means = vec![0.0; arms.len()];
+    let mut counts = vec![0usize; arms.len()];
+
+    let mut history = Vec::with_capacity(steps);
+    for _ in 0..steps {
+        let i = if rng.gen::<f32>() < epsilon {
+            rng.gen_range(0..arms.len())
+        } else {
+            (0..arms.len())
+                .reduce(|best, i| if means[i] > means[best] { i } else { best })
+                .expect("arms is not empty")
+        };
+        let Some((_, reward)) = env.sample_transition(&state, &arms[i], rng) else {
+            break;
+        };
+        counts[i] += 1;
+        means[i] += (reward - means[i]) / counts[i] as f32;
+        history.push((arms[i].clone(), reward));
+    }
+    history
+}
+
+/// The gradient bandit algorithm: learns a preference `H(a)` for each
+/// arm and pulls arms from the softmax distribution over preferences.
+///
+/// Treats the environment as a bandit, like `ucb1`. After pulling
+/// `A_t` and observing `R`, every preference is updated by
+/// `H(a) += alpha * (R - baseline) * (1{a = A_t} - pi(a))`. With
+/// `use_baseline` the baseline is the average of the rewards so far,
+/// including `R`; without it the baseline is 0, so the updates depend
+/// on the rewards' offset and vary much more.
+///
+/// Returns each arm with its final probability of being pulled.
+pub fn gradient_bandit<E: IEnvironment>(
+    env: &E,
+    steps: usize,
+    alpha: f32,
+    use_baseline: bool,
+    rng: &mut StdRng,
+) -> Vec<(E::Action, f32)> {
+    gradient_run(env, steps, alpha, use_baseline, rng).1
+}
+
+/// Runs the gradient bandit, returning the arm pulled and the reward
+/// observed at every step, and each arm with its final probability of
+/// being pulled.
+#[allow(clippy::type_complexity)]
+fn gradient_run<E: IEnvironment>(
+    env: &E,
+    steps: usize,
+    alpha: f32,
+    use_baseline: bool,
+    rng: &mut StdRng,
+) -> (Vec<(E::Action, f32)>, Vec<(E::Action, f32)>) {
+    let Some(state) = simulate::sample_start(env, rng) else {
+        return (Vec::new(), Vec::new());
+    };
+    let arms = fixed_order(env.actions_from(&state));
+    let mut preferences = vec![0.0; arms.len()];
+    let mut history = Vec::with_capacity(steps);
+    let mut average = 0.0;
+    for t in 0..steps {
+        let pi = softmax(&preferences);
+        let Some(i) = sample_weighted(pi.iter().copied().enumerate().collect(), rng) else {
+            break;
+        };
+        let Some((_, reward)) = env.sample_transition(&state, &arms[i], rng) else {
+            break;
+        };
+        average += (reward - average) / (t + 1) as f32;
+        let baseline = if use_baseline { average } else { 0.0 };
+        for (a, h) in preferences.iter_mut().enumerate() {
+            let taken = if a == i { 1.0 } else { 0.0 };
+            *h += alpha * (reward - baseline) * (taken - pi[a]);
+        }
+        history.push((arms[i].clone(), reward));
+    }
+    let probs = arms.into_iter().zip(softmax(&preferences)).collect();
+    (history, probs)
+}
+
+/// The softmax of `preferences`, shifted by their maximum so the
+/// exponentials can't overflow.
+fn softmax(preferences: &[f32]) -> Vec<f32> {
+    let max = preferences
+        .iter()
+        .copied()
+        .fold(f32::NEG_INFINITY, f32::max);
+    let weights: Vec<f32> = preferences.iter().map(|h| (h - max).exp()).collect();
+    let total: f32 = weights.iter().sum();
+    weights.into_iter().map(|w| w / total).collect()
+}
+
+/// Thompson sampling for bandits with Bernoulli rewards.
+///
+/// Treats the environment as a bandit, like `ucb1`, keeping a
+/// `Beta(1 + successes, 1 + failures)` posterior over each arm's
+/// probability of paying 1. Each step draws a sample from every
+/// posterior and pulls the arm with the largest.
+///
+/// Returns the arm pulled and the reward observed at every step, from
+/// which both the regret and the posteriors can be recovered, or
+/// `None` if `env.rewards()` isn't a non-empty subset of `{0, 1}`.
+pub fn thompson_bernoulli<E: IEnvironment>(
+    env: &E,
+    steps: usize,
+    rng: &mut StdRng,
+) -> Option<Vec<(E::Action, f32)>> {
+    let rewards = env.rewards();
+    if rewards.is_empty() || rewards.iter().any(|r| *r != 0.0 && *r != 1.0) {
+        return None;
+    }
+    let Some(state) = simulate::sample_start(env, rng) else {
+        return Some(Vec::new());
+    };
+    let arms = fixed_order(env.actions_from(&state));
+    let mut successes = vec![0usize; arms.len()];
+    let mut failures = vec![0usize; arms.len()];
+
+    let mut history = Vec::with_capacity(steps);
+    for _ in 0..steps {
+        let Some(i) = (0..arms.len())
+            .map(|i| {
+                let alpha = 1.0 + successes[i] as f32;
+                let beta = 1.0 + failures[i] as f32;
+                (i, sample_beta(alpha, beta, rng))
+            })
+            .reduce(|best, x| if x.1 > best.1 { x } else { best })
+            .map(|(i, _)| i)
+        else {
+            break;
+        };
+        let Some((_, reward)) = env.sample_transition(&state, &arms[i], rng) else {
+            break;
+        };
+        if reward == 1.0 {
+            successes[i] += 1;
+        } else {
+            failures[i] += 1;
+        }
+        history.push((arms[i].clone(), reward));
+    }
+    Some(history)
+}
+
+/// A draw from `Beta(alpha, beta)`, as `x / (x + y)` for
+/// `x ~ Gamma(alpha)` and `y ~ Gamma(beta)`.
+fn sample_beta(alpha: f32, beta: f32, rng: &mut StdRng) -> f32 {
+    let x = sample_gamma(alpha, rng);
+    let y = sample_gamma(beta, rng);
+    x / (x + y)
+}
+
+/// A draw from `Gamma(shape, 1)` for `shape >= 1`, by Marsaglia and
+/// Tsang's rejection method.
+fn sample_gamma(shape: f32, rng: &mut StdRng) -> f32 {
+    let d = shape - 1.0 / 3.0;
+    let c = 1.0 / (9.0 * d).sqrt();
+    loop {
+        let z = sample_normal(rng);
+        let v = (1.0 + c * z).powi(3);
+        if v <= 0.0 {
+            continue;
+        }
+        let u: f32 = rng.gen();
+        if u.ln() < 0.5 * z * z + d - d * v + d * v.ln() {
+            return d * v;
+        }
+    }
+}
+
+/// A standard normal draw, by the Box-Muller transform.
+fn sample_normal(rng: &mut StdRng) -> f32 {
+    let u: f32 = 1.0 - rng.gen::<f32>();
+    let v: f32 = rng.gen();
+    (-2.0 * u.ln()).sqrt() * (std::f32::consts::TAU * v).cos()
+}
+
+/// A bandit algorithm for `bandit_regret` to run.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BanditAlg {
+    /// `ucb1`.
+    Ucb1,
+    /// `epsilon_greedy_bandit` with the given `epsilon`.
+    EpsilonGreedy(f32),
+    /// `gradient_bandit` with the given step size, and a baseline.
+    Gradient(f32),
+    /// `thompson_bernoulli`.
+    Thompson,
+}
+
+/// The cumulative regret of a bandit algorithm over several runs; see
+/// `bandit_regret`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RegretStats {
+    pub runs: usize,
+    /// The mean over the runs of the cumulative regret after each
+    /// step, from the first.
+    pub mean: Vec<f32>,
+    /// The standard error of each mean: the runs' standard deviation
+    /// divided by the square root of their number.
+    pub stderr: Vec<f32>,
+}
+
+impl RegretStats {
+    /// The band of `z` standard errors either side of each mean, e.g.
+    /// about a 95% confidence band for `z` = 1.96.
+    pub fn band(&self, z: f32) -> Vec<(f32, f32)> {
+        self.mean
+            .iter()
+            .zip(&self.stderr)
+            .map(|(m, se)| (m - z * se, m + z * se))
+            .collect()
+    }
+
+    /// The mean cumulative regret after the last step, or `None` if
+    /// there were no steps.
+    pub fn final_mean(&self) -> Option<f32> {
+        self.mean.last().copied()
+    }
+}
+
+/// Runs `alg` for `steps` pulls in each of `runs` independent runs,
+/// and summarizes its cumulative regret at every step.
+///
+/// The environment is treated as a bandit, as by `ucb1`, and must have
+/// a single start state. The regret of a pull is the expected reward
+/// of the best arm less that of the arm pulled, both from the
+/// environment's `expected_reward`, so the luck of the rewards drawn
+/// doesn't add to it. Run `r` draws from `RngConfig::new(seed).run(r)`.
+/// A run that stops early keeps its regret from then on.
+///
+/// Returns `None` if there isn't exactly one start state, if the
+/// expected rewards are undefined, or for `Thompson` if the rewards
+/// aren't Bernoulli.
+pub fn bandit_regret<E: IEnvironment>(
+    env: &E,
+    alg: BanditAlg,
+    steps: usize,
+    runs: usize,
+    seed: u64,
+) -> Option<RegretStats> {
+    let starts = env.start_states();
+    let state = match starts.len() {
+        1 => starts.into_iter().next()?,
+        _ => return None,
+    };
+    let mut means = Vec::new();
+    for arm in env.actions_from(&state) {
+        let mean = env.expected_reward(&state, &arm)?;
+        means.push((arm, mean));
+    }
+    let best = means
+        .iter()
+        .map(|(_, m)| *m)
+        .fold(f32::NEG_INFINITY, f32::max);
+    let gaps: std::collections::HashMap<E::Action, f32> =
+        means.into_iter().map(|(a, m)| (a, best - m)).collect();
+
+    let config = RngConfig::new(seed);
+    let mut sums = vec![0.0f64; steps];
+    let mut squares = vec![0.0f64; steps];
+    for run in 0..runs {
+        let rng = &mut config.run(run as u64);
+        let history = match alg {
+            BanditAlg::Ucb1 => ucb1(env, steps, rng),
+            BanditAlg::EpsilonGreedy(epsilon) => epsilon_greedy_bandit(env, steps, epsilon, rng),
+            BanditAlg::Gradient(alpha) => gradient_run(env, steps, alpha, true, rng).0,
+            BanditAlg::Thompson => thompson_bernoulli(env, steps, rng)?,
+        };
+        let mut regret = 0.0f64;
+        let mut pulls = history.iter();
+        for (sum, square) in sums.iter_mut().zip(&mut squares) {
+            if let Some((arm, _)) = pulls.next() {
+                regret += f64::from(gaps[arm]);
+            }
+            *sum += regret;
+            *square += regret * regret;
+        }
+    }
+    let n = runs.max(1) as f64;
+    let mean: Vec<f32> = sums.iter().map(|s| (s / n) as f32).collect();
+    let stderr = sums
+        .iter()
+        .zip(&squares)
+        .map(|(s, q)| {
+            let m = s / n;
+            ((q / n - m * m).max(0.0) / n).sqrt() as f32
+        })
+        .collect();
+    Some(RegretStats { runs, mean, stderr })
+}
+
+/// The UCB1 choice of arm after `t` pulls: any arm not yet pulled,
+/// or else the arm with the highest upper confidence bound.
+fn select(means: &[f32], counts: &[usize], t: usize) -> Option<usize> {
+    if let Some(i) = counts.iter().position(|n| *n == 0) {
+        return Some(i);
+    }
+    let log_t = (t as f32).ln();
+    (0..means.len())
+        .map(|i| (i, means[i] + (2.0 * log_t / counts[i] as f32).sqrt()))
+        .reduce(|best, x| if x.1 > best.1 { x } else { best })
+        .map(|(i, _)| i)
+}
+
+#[cfg(all(test, feature = "examples"))]
+mod tests {
+    use super::*;
+    use crate::env::examples::{Arm, Bandit};
+    use crate::rng::make_rng;
+    use crate::validate;
+
+    fn three_arms() -> Bandit {
+        Bandit::new(vec![
+            Arm::Bernoulli(0.2),
+            Arm::Bernoulli(0.5),
+            Arm::Gaussian {
+                mean: 0.8,
+                std: 0.3,
+            },
+        ])
+    }
+
+    /// The expected regret of the first `n` pulls of `history`.
+    fn regret(env: &Bandit, history: &[(usize, f32)], n: usize) -> f32 {
+        history[..n]
+            .iter()
+            .map(|(arm, _)| env.best_mean() - env.arms()[*arm].mean())
+            .sum()
+    }
+
+    #[test]
+    fn bandit_arms_are_distributions() {
+        assert!(validate::validate_all(&three_arms(), 1e-5).is_ok());
+    }
+
+    #[test]
+    fn ucb1_regret_grows_sublinearly() {
+        let env = three_arms();
+        let history = ucb1(&env, 5000, &mut make_rng(3));
+        assert_eq!(history.len(), 5000);
+        assert!(regret(&env, &history, 5000) < 5.0 * regret(&env, &history, 1000));
+    }
+
+    /// The mean final probability of pulling arm 2, the best, over
+    /// 20 runs of the gradient bandit.
+    fn best_arm_prob(steps: usize, use_baseline: bool) -> f32 {
+        // Fixed rewards well above 0, so that updates without a
+        // baseline push up whichever arm was pulled.
+        let env = Bandit::new(
+            [4.0, 4.5, 5.5]
+                .into_iter()
+                .map(|mean| Arm::Gaussian { mean, std: 0.0 })
+                .collect(),
+        );
+        let runs = 20;
+        (0..runs)
+            .map(|seed| {
+                let probs = gradient_bandit(&env, steps, 0.1, use_baseline, &mut make_rng(seed));
+                probs.iter().find(|(a, _)| *a == 2).unwrap().1
+            })
+            .sum::<f32>()
+            / runs as f32
+    }
+
+    #[test]
+    fn gradient_bandit_concentrates_on_the_best_arm() {
+        assert!(best_arm_prob(1000, true) > 0.9);
+        assert!(best_arm_prob(200, true) > best_arm_prob(200, false));
+    }
+
+    #[test]
+    fn ucb1_beats_epsilon_greedy_on_regret() {
+        let env = three_arms();
+        let ucb = bandit_regret(&env, BanditAlg::Ucb1, 3000, 8, 0).unwrap();
+        let greedy = bandit_regret(&env, BanditAlg::EpsilonGreedy(0.1), 3000, 8, 0).unwrap();
+        assert_eq!(
+            (ucb.runs, ucb.mean.len(), ucb.stderr.len()),
+            (8, 3000, 3000)
+        );
+        let (ucb_regret, greedy_regret) = (ucb.final_mean().unwrap(), greedy.final_mean().unwrap());
+        assert!(
+            ucb_regret < greedy_regret,
+            "{} vs {}",
+            ucb_regret,
+            greedy_regret
+        );
+        // UCB1 always pulls the same arm first.
+        assert_eq!(ucb.stderr[0], 0.0);
+        let band = ucb.band(1.96);
+        assert!(band
+            .iter()
+            .zip(&ucb.mean)
+            .all(|((lo, hi), m)| lo <= m && m <= hi));
+
+        let gradient = bandit_regret(&env, BanditAlg::Gradient(0.1), 100, 2, 0).unwrap();
+        assert_eq!(gradient.mean.len(), 100);
+        assert_eq!(bandit_regret(&env, BanditAlg::Thompson, 100, 2, 0), None);
+    }
+
+    #[test]
+    fn thompson_sampling_needs_bernoulli_rewards() {
+        assert!(thompson_bernoulli(&three_arms(), 10, &mut make_rng(0)).is_none());
+    }
+
+    #[test]
+    fn thompson_regret_grows_logarithmically() {
+        let env = Bandit::new(vec![
+            Arm::Bernoulli(0.3),
+            Arm::Bernoulli(0.5),
+            Arm::Bernoulli(0.7),
+        ]);
+        let runs = 20;
+        let mut regrets = [0.0; 3];
+        let mut posterior = 0.0;
+        for seed in 0..runs {
+            let history = thompson_bernoulli(&env, 4000, &mut make_rng(seed)).unwrap();
+            for (total, n) in regrets.iter_mut().zip([250, 1000, 4000]) {
+                *total += regret(&env, &history, n);
+            }
+            // The posterior mean of the best arm, under its Beta(1, 1)
+            // prior.
+            let best: Vec<f32> = history
+                .iter()
+                .filter(|(a, _)| *a == 2)
+                .map(|(_, r)| *r)
+                .collect();
+            posterior += (1.0 + best.iter().sum::<f32>()) / (2.0 + best.len() as f32);
+        }
+        // Quadrupling the steps adds about the same regret each time,
+        // rather than quadrupling it.
+        assert!(regrets[2] < 2.0 * regrets[1]);
+        assert!(regrets[2] - regrets[1] < 2.0 * (regrets[1] - regrets[0]) + 20.0);
+        assert!((posterior / runs as f32 - 0.7).abs() < 0.02);
+    }
+}
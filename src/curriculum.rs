@@ -0,0 +1,179 @@
+//! Training on a sequence of progressively harder environments,
+//! carrying what was learned on each over to the next.
+
+use std::collections::VecDeque;
+use std::ops::ControlFlow;
+
+use crate::dp::QTable;
+use crate::env::IEnvironment;
+use crate::observer::TrainingObserver;
+use crate::rng::StdRng;
+use crate::schedule::Schedule;
+use crate::td::{self, LearningRate};
+
+/// When a stage of a curriculum counts as passed: once the mean total
+/// reward of the last `window` episodes exceeds `threshold`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Promotion {
+    pub window: usize,
+    pub threshold: f32,
+}
+
+/// A sequence of environments to train on in order, each promoted
+/// from by `promotion` or after `max_episodes` episodes, whichever
+/// comes first.
+///
+/// The stages share one type, so that the action values learned on
+/// one carry over to the next: typically one environment type built
+/// with increasingly hard configurations.
+pub struct Curriculum<E> {
+    pub stages: Vec<E>,
+    pub promotion: Promotion,
+    pub max_episodes: usize,
+}
+
+impl<E> Curriculum<E> {
+    pub fn new(stages: Vec<E>, promotion: Promotion, max_episodes: usize) -> Self {
+        Curriculum {
+            stages,
+            promotion,
+            max_episodes,
+        }
+    }
+}
+
+/// Stops training once the last `window` episodes have passed.
+struct Promoter {
+    promotion: Promotion,
+    recent: VecDeque<f32>,
+    episodes: usize,
+}
+
+impl<E: IEnvironment> TrainingObserver<E> for Promoter {
+    fn on_episode_end(&mut self, _: usize, total_reward: f32, _: &QTable<E>) -> ControlFlow<()> {
+        self.episodes += 1;
+        if self.recent.len() == self.promotion.window {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(total_reward);
+        let window = self.promotion.window;
+        if window > 0 && self.recent.len() == window {
+            let mean = self.recent.iter().sum::<f32>() / window as f32;
+            if mean > self.promotion.threshold {
+                return ControlFlow::Break(());
+            }
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+/// Trains with Q-learning on each stage of `curriculum` in turn,
+/// starting each stage from the action values the last one ended
+/// with, and returns the values after the final stage along with the
+/// number of episodes spent on each.
+///
+/// A stage ends once passed, or after the curriculum's
+/// `max_episodes`, so a stage that is never passed still gives way to
+/// the next; the final stage ends the same way. Each stage otherwise
+/// trains as `td::q_learning` does, with its schedules and visit
+/// counts starting afresh. `gamma` defaults to each stage's own
+/// `gamma()`.
+pub fn train_curriculum<E: IEnvironment>(
+    curriculum: &Curriculum<E>,
+    alpha: impl Into<LearningRate>,
+    gamma: Option<f32>,
+    epsilon: impl Into<Schedule>,
+    rng: &mut StdRng,
+) -> (QTable<E>, Vec<usize>) {
+    let alpha = alpha.into();
+    let epsilon = epsilon.into();
+    let mut q = QTable::new();
+    let mut episodes = Vec::with_capacity(curriculum.stages.len());
+    for env in &curriculum.stages {
+        let mut promoter = Promoter {
+            promotion: curriculum.promotion,
+            recent: VecDeque::with_capacity(curriculum.promotion.window),
+            episodes: 0,
+        };
+        q = td::q_learning_continued(
+            env,
+            q,
+            curriculum.max_episodes,
+            &alpha,
+            gamma.unwrap_or_else(|| env.gamma()),
+            &epsilon,
+            rng,
+            &mut promoter,
+        );
+        episodes.push(promoter.episodes);
+    }
+    (q, episodes)
+}
+
+#[cfg(all(test, feature = "examples"))]
+mod tests {
+    use super::*;
+    use crate::env::examples::GridWorld;
+    use crate::rng::make_rng;
+
+    /// A grid whose walls make a long path from the start to the goal,
+    /// and the same grid started halfway along it.
+    fn grids() -> (GridWorld, GridWorld) {
+        let walls = [
+            (1, 0),
+            (1, 1),
+            (1, 2),
+            (3, 1),
+            (3, 2),
+            (3, 3),
+            (5, 0),
+            (5, 1),
+            (5, 2),
+        ];
+        let hard = GridWorld::new(7, 4, walls, (6, 0), 0.01, 0.0).with_start((0, 0));
+        let easy = hard.clone().with_start((4, 0));
+        (easy, hard)
+    }
+
+    #[test]
+    fn an_easy_stage_first_speeds_up_the_hard_one() {
+        let (easy, hard) = grids();
+        // The shortest path snakes through the walls in 18 steps, for a
+        // return of 0.83.
+        let promotion = Promotion {
+            window: 3,
+            threshold: 0.8,
+        };
+        let mut direct_total = 0;
+        let mut staged_total = 0;
+        for seed in 0..5 {
+            let direct = Curriculum::new(vec![hard.clone()], promotion, 5000);
+            let (_, direct) = train_curriculum(&direct, 0.5, Some(1.0), 0.01, &mut make_rng(seed));
+            let staged = Curriculum::new(vec![easy.clone(), hard.clone()], promotion, 5000);
+            let (_, staged) = train_curriculum(&staged, 0.5, Some(1.0), 0.01, &mut make_rng(seed));
+            assert!(direct[0] < 5000 && staged.iter().all(|n| *n < 5000));
+            direct_total += direct[0];
+            staged_total += staged[1];
+        }
+        // Having learned the way from halfway, the hard grid is passed in
+        // fewer of its own, longer, episodes.
+        assert!(
+            staged_total < direct_total,
+            "{} vs {}",
+            staged_total,
+            direct_total
+        );
+    }
+
+    #[test]
+    fn stages_that_are_never_passed_give_way_after_the_cap() {
+        let (easy, hard) = grids();
+        let impossible = Promotion {
+            window: 5,
+            threshold: 2.0,
+        };
+        let curriculum = Curriculum::new(vec![easy, hard], impossible, 30);
+        let (_, episodes) = train_curriculum(&curriculum, 0.5, None, 0.1, &mut make_rng(0));
+        assert_eq!(episodes, [30, 30]);
+    }
+}
@@ -0,0 +1,369 @@
+//! Fixed datasets of transitions collected by running a policy, for
+//! offline learning; see `persist` for saving them.
+
+use std::collections::HashMap;
+
+use crate::dp::{q_from_v, value_iteration, QTable};
+use crate::env::IEnvironment;
+use crate::policy::{ActionValuePolicy, DeterministicPolicy, Policy, Selection, TieBreak};
+use crate::replay::Transition;
+use crate::rng::StdRng;
+use crate::simulate::{run_episode, sample_start};
+
+/// A transition, tagged with the index of the episode it came from.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Record<S, A> {
+    pub episode: usize,
+    #[cfg_attr(feature = "serde", serde(flatten))]
+    pub transition: Transition<S, A>,
+}
+
+/// The transitions of a number of episodes, in the order they were
+/// taken.
+///
+/// A transition is `done` only if it reached a terminal state, so the
+/// last transition of an episode cut off at the step cap is not
+/// `done`; episodes are told apart by their index instead.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dataset<E: IEnvironment> {
+    records: Vec<Record<E::State, E::Action>>,
+}
+
+impl<E: IEnvironment> Dataset<E> {
+    pub fn from_records(records: Vec<Record<E::State, E::Action>>) -> Self {
+        Dataset { records }
+    }
+
+    pub fn records(&self) -> &[Record<E::State, E::Action>] {
+        &self.records
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// The transitions, without their episodes.
+    pub fn transitions(&self) -> impl Iterator<Item = &Transition<E::State, E::Action>> {
+        self.records.iter().map(|r| &r.transition)
+    }
+
+    /// The return of each episode with any transitions, discounted by
+    /// `gamma` from its first step, in the order of their indices.
+    pub fn returns(&self, gamma: f32) -> Vec<f32> {
+        self.records
+            .chunk_by(|a, b| a.episode == b.episode)
+            .map(|episode| {
+                episode
+                    .iter()
+                    .rev()
+                    .fold(0.0, |g, r| r.transition.reward + gamma * g)
+            })
+            .collect()
+    }
+}
+
+/// Runs `policy` for `episodes` episodes of at most `max_steps` steps
+/// each, from start states drawn uniformly from `env.start_states()`,
+/// and records every transition taken, with episodes numbered from 0.
+///
+/// Episodes are run exactly as by `simulate::evaluate_episodes`, so
+/// the same `rng` gives the same episodes. An episode that starts in
+/// a terminal state contributes no transitions, but still takes up an
+/// index. Collection stops early if the environment has no start
+/// states.
+pub fn collect_dataset<E, P>(
+    env: &E,
+    policy: &P,
+    episodes: usize,
+    max_steps: usize,
+    rng: &mut StdRng,
+) -> Dataset<E>
+where
+    E: IEnvironment,
+    P: Policy<E>,
+{
+    let mut records = Vec::new();
+    for episode in 0..episodes {
+        let Some(start) = sample_start(env, rng) else {
+            break;
+        };
+        let (trajectory, end) = run_episode(env, policy, start, max_steps, rng);
+        let nexts: Vec<E::State> = trajectory
+            .iter()
+            .skip(1)
+            .map(|(s, _, _)| s.clone())
+            .chain(std::iter::once(end))
+            .collect();
+        for ((state, action, reward), next) in trajectory.into_iter().zip(nexts) {
+            let done = env.is_terminal(&next);
+            records.push(Record {
+                episode,
+                transition: Transition {
+                    state,
+                    action,
+                    reward,
+                    next,
+                    done,
+                },
+            });
+        }
+    }
+    Dataset { records }
+}
+
+/// A noisily optimal expert for `env`, to generate demonstrations
+/// from with `collect_dataset`, e.g. for inverse reinforcement
+/// learning: the Boltzmann distribution over the optimal Q-values, as
+/// found by `value_iteration`, with rationality `beta`.
+///
+/// The expert takes each action with probability proportional to
+/// `exp(beta * Q(s, a))`, so `beta` is the inverse of a `Softmax`
+/// temperature: near 0 it acts almost uniformly at random, and the
+/// higher it is the more optimally it acts, until at infinity it
+/// always takes an optimal action, among several uniformly. Returns
+/// `None` if `value_iteration` fails, or the expected rewards are
+/// undefined.
+///
+/// Panics if `beta` is not positive.
+pub fn boltzmann_expert<E: IEnvironment>(
+    env: &E,
+    beta: f32,
+    gamma: Option<f32>,
+    theta: f32,
+    max_iterations: usize,
+) -> Option<ActionValuePolicy<E>> {
+    assert!(beta > 0.0, "beta must be positive");
+    let (v, _) = value_iteration(env, gamma, theta, max_iterations)?;
+    let q = q_from_v(env, &v, gamma)?;
+    Some(ActionValuePolicy::new(q, Selection::Softmax(1.0 / beta)))
+}
+
+/// Behavior cloning: the policy taking, in each state of `dataset`,
+/// the action taken there most often.
+///
+/// Ties between equally frequent actions are broken by
+/// `TieBreak::First`, so the result doesn't depend on the order of the
+/// transitions. States the dataset never visits get no action, so
+/// the policy's `action` is `None` for them.
+pub fn behavior_clone<E: IEnvironment>(dataset: &Dataset<E>) -> DeterministicPolicy<E> {
+    let mut counts: HashMap<&E::State, HashMap<&E::Action, usize>> = HashMap::new();
+    for t in dataset.transitions() {
+        *counts
+            .entry(&t.state)
+            .or_default()
+            .entry(&t.action)
+            .or_insert(0) += 1;
+    }
+    let mut actions = HashMap::with_capacity(counts.len());
+    for (state, taken) in counts {
+        let most = taken.values().copied().max().unwrap_or(0);
+        let tied = taken
+            .into_iter()
+            .filter(|(_, n)| *n == most)
+            .map(|(a, _)| a.clone())
+            .collect();
+        if let Some(a) = TieBreak::First.choose(state, tied) {
+            actions.insert(state.clone(), a);
+        }
+    }
+    DeterministicPolicy::new(actions)
+}
+
+/// Offline Q-learning in its batch-constrained form: fitted Q
+/// iteration on the transitions of `dataset` alone, never consulting
+/// the environment, for `iterations` sweeps.
+///
+/// Each sweep sets every pair the dataset takes to the average over
+/// its transitions of `r + gamma * max_a' Q(s', a')`, from the previous
+/// sweep's values, as if the dataset's transitions were the whole of
+/// the dynamics. The maximum only ranges over the actions the dataset
+/// takes from `s'`, so values are never bootstrapped from pairs it
+/// has no evidence for, whose estimates would otherwise be
+/// extrapolated arbitrarily. `done` transitions bootstrap nothing.
+///
+/// Pairs the dataset never takes are left out of the table, so they,
+/// and a state it has no transitions from, keep their initial value
+/// of 0, which is also what is bootstrapped from such a state.
+pub fn offline_q<E: IEnvironment>(
+    dataset: &Dataset<E>,
+    iterations: usize,
+    gamma: f32,
+) -> QTable<E> {
+    let mut outcomes = HashMap::new();
+    let mut observed: HashMap<&E::State, Vec<&E::Action>> = HashMap::new();
+    for t in dataset.transitions() {
+        let taken: &mut Vec<_> = outcomes.entry((&t.state, &t.action)).or_default();
+        if taken.is_empty() {
+            observed.entry(&t.state).or_default().push(&t.action);
+        }
+        taken.push(t);
+    }
+    let mut q: QTable<E> = QTable::new();
+    for _ in 0..iterations {
+        let mut next = QTable::new();
+        for (&(state, action), taken) in &outcomes {
+            let total: f32 = taken
+                .iter()
+                .map(|t| {
+                    let future = match observed.get(&t.next) {
+                        Some(actions) if !t.done => actions
+                            .iter()
+                            .map(|&a| q.get(&t.next, a))
+                            .fold(f32::NEG_INFINITY, f32::max),
+                        _ => 0.0,
+                    };
+                    t.reward + gamma * future
+                })
+                .sum();
+            next.set(state.clone(), action.clone(), total / taken.len() as f32);
+        }
+        q = next;
+    }
+    q
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::make_rng;
+    use crate::simulate::{evaluate_episodes, Summary};
+    use crate::testing::{policies_equal, Chain};
+
+    #[test]
+    fn offline_q_matches_online_q_learning_given_every_transition() {
+        use crate::dp::{q_from_v, value_iteration};
+        use crate::td::q_learning;
+
+        let dataset = collect_dataset(&Chain, &Chain::wandering(), 200, 20, &mut make_rng(0));
+        let offline = offline_q(&dataset, 100, 0.9);
+        let online = q_learning(&Chain, 2000, 0.1, Some(0.9), 0.5, &mut make_rng(0));
+        let (v, _) = value_iteration(&Chain, Some(0.9), 1e-6, 1000).unwrap();
+        let optimal = q_from_v(&Chain, &v, Some(0.9)).unwrap();
+        assert_eq!(offline.len(), 6);
+        for (&(s, a), value) in offline.iter() {
+            assert!((value - optimal.get(&s, &a)).abs() < 1e-4, "{:?}", (s, a));
+            assert!((value - online.get(&s, &a)).abs() < 0.05, "{:?}", (s, a));
+        }
+    }
+
+    #[test]
+    fn offline_q_only_bootstraps_from_observed_actions() {
+        // Two steps right from 0 never leave state 2, nor go left.
+        let dataset = collect_dataset(&Chain, &Chain::rightward(), 1, 2, &mut make_rng(0));
+        let q = offline_q(&dataset, 10, 0.9);
+        assert_eq!(q.len(), 2);
+        assert!(!q.contains(&0, &-1));
+        assert!(!q.contains(&2, &1));
+        assert_eq!(q.get(&1, &1), 0.0);
+        assert_eq!(q.get(&0, &1), 0.0);
+
+        let dataset = collect_dataset(&Chain, &Chain::rightward(), 1, 10, &mut make_rng(0));
+        let q = offline_q(&dataset, 10, 0.9);
+        assert_eq!(q.get(&2, &1), 1.0);
+        assert!((q.get(&0, &1) - 0.81).abs() < 1e-6);
+    }
+
+    #[test]
+    fn replayed_returns_match_the_rollouts() {
+        let dataset = collect_dataset(&Chain, &Chain::wandering(), 50, 20, &mut make_rng(0));
+        let stats = evaluate_episodes(
+            &Chain,
+            &Chain::wandering(),
+            50,
+            20,
+            Some(0.9),
+            &mut make_rng(0),
+        )
+        .unwrap();
+        let returns = dataset.returns(0.9);
+        assert_eq!(returns.len(), 50);
+        assert_eq!(Summary::of(&returns), Some(stats.discounted));
+        for pair in dataset.records().windows(2) {
+            let (a, b) = (&pair[0], &pair[1]);
+            if a.episode == b.episode {
+                assert_eq!(a.transition.next, b.transition.state);
+                assert!(!a.transition.done);
+            }
+        }
+    }
+
+    #[test]
+    fn a_fully_rational_expert_demonstrates_the_optimal_policy() {
+        let (_, optimal) = value_iteration(&Chain, Some(0.9), 1e-6, 1000).unwrap();
+        let demos = collect_dataset(&Chain, &optimal, 20, 20, &mut make_rng(0));
+        for beta in [f32::INFINITY, 1000.0] {
+            let expert = boltzmann_expert(&Chain, beta, Some(0.9), 1e-6, 1000).unwrap();
+            let expert_demos = collect_dataset(&Chain, &expert, 20, 20, &mut make_rng(0));
+            assert_eq!(expert_demos.records(), demos.records(), "{beta}");
+        }
+
+        // A less rational one wanders, and takes longer on average.
+        let expert = boltzmann_expert(&Chain, 1.0, Some(0.9), 1e-6, 1000).unwrap();
+        let expert_demos = collect_dataset(&Chain, &expert, 20, 20, &mut make_rng(0));
+        assert!(expert_demos.len() > demos.len());
+        assert!(expert.action_prob(&Chain, &0, &-1) > 0.4);
+    }
+
+    #[test]
+    #[should_panic(expected = "beta must be positive")]
+    fn experts_need_a_positive_rationality() {
+        boltzmann_expert(&Chain, 0.0, Some(0.9), 1e-6, 1000);
+    }
+
+    #[test]
+    fn cloning_recovers_the_collecting_policy() {
+        let dataset = collect_dataset(&Chain, &Chain::rightward(), 3, 10, &mut make_rng(0));
+        let cloned = behavior_clone(&dataset);
+        assert!(policies_equal(
+            &cloned,
+            &Chain::rightward(),
+            &Chain.all_states()
+        ));
+        assert_eq!(cloned.action(&Chain::GOAL), None);
+    }
+
+    #[test]
+    fn cloning_takes_the_most_frequent_action_and_breaks_ties() {
+        let record = |state, action| Record {
+            episode: 0,
+            transition: Transition {
+                state,
+                action,
+                reward: 0.0,
+                next: state,
+                done: false,
+            },
+        };
+        let tied = [record(1, -1), record(1, 1)];
+        let records = [record(0, -1), record(0, 1), record(0, -1)]
+            .into_iter()
+            .chain(tied.clone())
+            .collect();
+        let cloned = behavior_clone(&Dataset::<Chain>::from_records(records));
+        assert_eq!(cloned.action(&0), Some(&-1));
+        let first = TieBreak::First.choose(&1, vec![-1, 1]);
+        assert_eq!(cloned.action(&1), first.as_ref());
+        let reversed = behavior_clone(&Dataset::<Chain>::from_records(
+            tied.into_iter().rev().collect(),
+        ));
+        assert_eq!(reversed.action(&1), first.as_ref());
+        assert_eq!(cloned.action(&2), None);
+    }
+
+    #[test]
+    fn truncated_episodes_end_without_done() {
+        let dataset = collect_dataset(&Chain, &Chain::rightward(), 2, 2, &mut make_rng(0));
+        let episodes: Vec<_> = dataset.records().iter().map(|r| r.episode).collect();
+        assert_eq!(episodes, [0, 0, 1, 1]);
+        assert!(dataset.transitions().all(|t| !t.done));
+
+        let finished = collect_dataset(&Chain, &Chain::rightward(), 1, 10, &mut make_rng(0));
+        assert_eq!(finished.len(), 3);
+        assert!(finished.records()[2].transition.done);
+    }
+}
@@ -0,0 +1,123 @@
+//! Graphviz export of an environment's dynamics.
+
+use std::collections::HashMap;
+use std::fmt::{Debug, Write};
+
+use crate::env::{sorted_actions, sorted_states, transition_at, IEnvironment};
+use crate::policy::fixed_order;
+
+/// Renders the transition graph of `E` in Graphviz DOT format, e.g.
+/// for `dot -Tpng`.
+///
+/// Every state in `env.all_states()` is a node labeled with its `Debug`
+/// form, drawn as a double circle if it is terminal. Each transition
+/// with positive probability is an edge labeled `action, p`; edges
+/// with zero probability are left out to keep the graph readable. If
+/// the environment's transition probabilities are undefined, every
+/// edge from `states_from` is drawn, labeled with only its action.
+///
+/// States, actions and next states are all listed in fixed orders, so
+/// the output is the same on every run.
+pub fn to_dot<E>(env: &E) -> String
+where
+    E: IEnvironment,
+    E::State: Debug,
+    E::Action: Debug,
+{
+    let rewards = env.rewards();
+    let states = fixed_order(env.all_states());
+    let ids: HashMap<&E::State, usize> = states.iter().enumerate().map(|(i, s)| (s, i)).collect();
+
+    let mut dot = String::from("digraph {\n");
+    for (id, s) in states.iter().enumerate() {
+        let shape = if env.is_terminal(s) {
+            "doublecircle"
+        } else {
+            "circle"
+        };
+        // Writing to a `String` never fails.
+        let _ = writeln!(
+            dot,
+            "    {} [label=\"{}\", shape={}];",
+            id,
+            escape(&format!("{:?}", s)),
+            shape
+        );
+    }
+    for from in &states {
+        if env.is_terminal(from) {
+            continue;
+        }
+        for take in sorted_actions(env, from) {
+            for to in sorted_states(env, from, &take) {
+                let label = match transition_at(env, from, &take, &to, &rewards) {
+                    Some((p, _)) if p > 0.0 => format!("{:?}, {}", take, p),
+                    Some(_) => continue,
+                    None => format!("{:?}", take),
+                };
+                let Some(to) = ids.get(&to) else {
+                    continue;
+                };
+                let _ = writeln!(
+                    dot,
+                    "    {} -> {} [label=\"{}\"];",
+                    ids[from],
+                    to,
+                    escape(&label)
+                );
+            }
+        }
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+/// `s` escaped for a quoted DOT string.
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Chain;
+
+    #[cfg(feature = "examples")]
+    #[test]
+    fn dull_is_a_single_self_loop() {
+        use crate::env::examples::Dull;
+
+        assert_eq!(
+            to_dot(&Dull),
+            "digraph {\n    0 [label=\"Same\", shape=circle];\n    0 -> 0 [label=\"Nothing, 1\"];\n}\n"
+        );
+    }
+
+    #[test]
+    fn terminal_states_are_double_circles_without_edges() {
+        let dot = to_dot(&Chain);
+        let goal = dot
+            .lines()
+            .find(|l| l.contains("label=\"3\""))
+            .expect("the goal is a node");
+        assert!(goal.ends_with("shape=doublecircle];"));
+        let id = goal.trim().split(' ').next().unwrap();
+        assert!(!dot.contains(&format!("    {id} -> ")));
+        // Two moves from each of the other three states.
+        assert_eq!(dot.matches(" -> ").count(), 6);
+        assert_eq!(dot.matches("shape=circle").count(), 3);
+    }
+
+    #[cfg(feature = "examples")]
+    #[test]
+    fn output_is_reproducible() {
+        use crate::env::examples::GridWorld;
+
+        // Every call enumerates freshly seeded `HashSet`s.
+        let grid = GridWorld::new(4, 3, [(1, 1)], (3, 0), 0.04, 0.2);
+        let dot = to_dot(&grid);
+        for _ in 0..10 {
+            assert_eq!(to_dot(&grid), dot);
+        }
+    }
+}
@@ -0,0 +1,2489 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::{Duration, Instant};
+
+use crate::env::{search, transition_at, IDeterministicEnvironment, IEnvironment};
+use crate::policy::{fixed_order, DeterministicPolicy, Policy, TabularStochasticPolicy, TieBreak};
+use crate::simulate::Summary;
+
+mod cache;
+mod constrained;
+mod precise;
+mod qtable;
+mod robust;
+mod soft;
+
+pub use cache::TransitionCache;
+pub use constrained::{lagrangian_value_iteration, solve_cmdp, CmdpSolution};
+pub use precise::{evaluate_policy_f64, value_iteration_f64, PreciseValueFunction};
+pub use qtable::{q_diff, q_diff_summary, MergeStrategy, QDiffSummary, QTable};
+pub use robust::{robust_value_iteration, Robustness};
+pub use soft::soft_value_iteration;
+
+/// A state-value function: the expected return from each state.
+pub type ValueFunction<E> = HashMap<<E as IEnvironment>::State, f32>;
+
+/// One-step dynamics used by the backups: either queried from the
+/// environment or read from a `TransitionCache`.
+trait Dynamics<E: IEnvironment> {
+    /// The expected return of taking action `take` from state
+    /// `from`, then continuing with the state values `v` discounted
+    /// by `gamma`.
+    ///
+    /// Terminal states and states missing from `v` are treated as
+    /// having value 0. Returns `None` if the environment's rewards
+    /// are not enumerable.
+    fn lookahead(
+        &self,
+        env: &E,
+        from: &E::State,
+        take: &E::Action,
+        v: &ValueFunction<E>,
+        gamma: f32,
+    ) -> Option<f32>;
+}
+
+/// The value of `state` under `v` as seen by a backup: 0 if the
+/// state is terminal or missing.
+fn next_value<E: IEnvironment>(env: &E, v: &ValueFunction<E>, state: &E::State) -> f32 {
+    if env.is_terminal(state) {
+        0.0
+    } else {
+        v.get(state).copied().unwrap_or(0.0)
+    }
+}
+
+/// The discount a backup applies to the value of `to`: the
+/// environment's `gamma_at(to)` where that differs from its `gamma()`,
+/// and `gamma` elsewhere.
+fn discount_at<E: IEnvironment>(env: &E, gamma: f32, to: &E::State) -> f32 {
+    let own = env.gamma_at(to);
+    if own == env.gamma() {
+        gamma
+    } else {
+        own
+    }
+}
+
+/// Dynamics queried from the environment on every backup, with its
+/// reward values fetched once up front, and one buffer of next states
+/// reused by every backup.
+struct Direct<E: IEnvironment> {
+    rewards: Vec<f32>,
+    next: RefCell<Vec<E::State>>,
+}
+
+impl<E: IEnvironment> Direct<E> {
+    fn new(env: &E) -> Self {
+        Direct {
+            rewards: env.rewards(),
+            next: RefCell::new(Vec::new()),
+        }
+    }
+}
+
+impl<E: IEnvironment> Dynamics<E> for Direct<E> {
+    fn lookahead(
+        &self,
+        env: &E,
+        from: &E::State,
+        take: &E::Action,
+        v: &ValueFunction<E>,
+        gamma: f32,
+    ) -> Option<f32> {
+        // Taken rather than borrowed, so a backup returning early just
+        // leaves the next one to allocate afresh.
+        let mut next = self.next.take();
+        env.states_from_into(from, take, &mut next);
+        let mut total = 0.0;
+        for to in &next {
+            let (p, r) = transition_at(env, from, take, to, &self.rewards)?;
+            total += r + discount_at(env, gamma, to) * p * next_value(env, v, to);
+        }
+        self.next.replace(next);
+        Some(total)
+    }
+}
+
+/// Dynamics read straight from a deterministic environment's
+/// `transition`, with no marginalization over next states or rewards.
+struct Deterministic;
+
+impl<E: IDeterministicEnvironment> Dynamics<E> for Deterministic {
+    fn lookahead(
+        &self,
+        env: &E,
+        from: &E::State,
+        take: &E::Action,
+        v: &ValueFunction<E>,
+        gamma: f32,
+    ) -> Option<f32> {
+        let (to, reward) = env.transition(from, take);
+        Some(reward + discount_at(env, gamma, &to) * next_value(env, v, &to))
+    }
+}
+
+/// Iterative policy evaluation: computes the state-value function
+/// of `policy` by repeatedly sweeping over `env.all_states()`, updating
+/// each state's value in place. Terminal states are skipped and keep
+/// a value of 0.
+///
+/// Sweeps stop once the largest change to any state's value falls
+/// below `theta`, or after `max_iters` sweeps, whichever comes first;
+/// with `gamma` = 1 a policy that never reaches a terminal state may
+/// not converge at all. `gamma` defaults to `env.gamma()`. Returns
+/// `None` if the environment's rewards are not enumerable, as the
+/// transition probabilities are then undefined.
+pub fn evaluate_policy<E, P>(
+    env: &E,
+    policy: &P,
+    gamma: Option<f32>,
+    theta: f32,
+    max_iters: usize,
+) -> Option<ValueFunction<E>>
+where
+    E: IEnvironment,
+    P: Policy<E>,
+{
+    let gamma = gamma.unwrap_or_else(|| env.gamma());
+    let states = env.all_states();
+    let (v, _) = evaluate_with(
+        env,
+        &Direct::new(env),
+        &states,
+        policy,
+        gamma,
+        theta,
+        max_iters,
+    )?;
+    Some(v)
+}
+
+/// As `evaluate_policy`, but reading the dynamics from `cache`.
+pub fn evaluate_policy_cached<E, P>(
+    env: &E,
+    cache: &TransitionCache<E>,
+    policy: &P,
+    gamma: Option<f32>,
+    theta: f32,
+    max_iters: usize,
+) -> Option<ValueFunction<E>>
+where
+    E: IEnvironment,
+    P: Policy<E>,
+{
+    let gamma = gamma.unwrap_or_else(|| env.gamma());
+    let (v, _) = evaluate_with(env, cache, cache.states(), policy, gamma, theta, max_iters)?;
+    Some(v)
+}
+
+/// Evaluates `policy` over `states`, returning its values and whether
+/// the sweeps converged within `max_iters`.
+fn evaluate_with<E, P>(
+    env: &E,
+    dynamics: &impl Dynamics<E>,
+    states: &HashSet<E::State>,
+    policy: &P,
+    gamma: f32,
+    theta: f32,
+    max_iters: usize,
+) -> Option<(ValueFunction<E>, bool)>
+where
+    E: IEnvironment,
+    P: Policy<E>,
+{
+    let mut v: ValueFunction<E> = states.iter().map(|s| (s.clone(), 0.0)).collect();
+    for _ in 0..max_iters {
+        let mut delta: f32 = 0.0;
+        for s in states.iter().filter(|s| !env.is_terminal(s)) {
+            let mut value = 0.0;
+            for (a, p) in policy.action_distribution(env, s) {
+                if p > 0.0 {
+                    value += p * dynamics.lookahead(env, s, &a, &v, gamma)?;
+                }
+            }
+            let old = v.insert(s.clone(), value).unwrap_or(0.0);
+            delta = delta.max((value - old).abs());
+        }
+        if delta < theta {
+            return Some((v, true));
+        }
+    }
+    Some((v, false))
+}
+
+/// The best action from `from` under the state values `v`, along
+/// with its expected return, breaking ties by `tie`.
+fn best_action<E: IEnvironment>(
+    env: &E,
+    dynamics: &impl Dynamics<E>,
+    from: &E::State,
+    v: &ValueFunction<E>,
+    gamma: f32,
+    tie: &TieBreak<E::Action>,
+) -> Option<Option<(E::Action, f32)>> {
+    let mut values = Vec::new();
+    for a in env.action_mask(from) {
+        let q = dynamics.lookahead(env, from, &a, v, gamma)?;
+        values.push((a, q));
+    }
+    Some(argmax::<E>(from, values, tie))
+}
+
+/// The action with the highest value in `values`, breaking ties by
+/// `tie`.
+fn argmax<E: IEnvironment>(
+    state: &E::State,
+    values: Vec<(E::Action, f32)>,
+    tie: &TieBreak<E::Action>,
+) -> Option<(E::Action, f32)> {
+    let max = values.iter().map(|(_, q)| *q).reduce(f32::max)?;
+    let tied = values
+        .into_iter()
+        .filter(|(_, q)| *q == max)
+        .map(|(a, _)| a)
+        .collect();
+    tie.choose(state, tied).map(|a| (a, max))
+}
+
+/// Value iteration: computes the optimal state-value function by
+/// repeatedly applying the Bellman optimality backup to every state
+/// in `env.all_states()`, then extracts the greedy policy from it.
+///
+/// Each sweep computes the new values from the previous sweep's
+/// values. Terminal states are skipped and keep a value of 0. Sweeps
+/// stop once the largest change falls below `theta`, or after
+/// `max_iters` sweeps, whichever comes first. `gamma` defaults to
+/// `env.gamma()`. Returns `None` if the environment's rewards are not
+/// enumerable.
+pub fn value_iteration<E: IEnvironment>(
+    env: &E,
+    gamma: Option<f32>,
+    theta: f32,
+    max_iters: usize,
+) -> Option<(ValueFunction<E>, DeterministicPolicy<E>)> {
+    let (v, policy, _) = value_iteration_with_report(env, gamma, theta, max_iters, 0)?;
+    Some((v, policy))
+}
+
+/// As `value_iteration`, but backing up each state-action pair
+/// through a single call to `transition`, rather than summing over
+/// `states_from` and the rewards as the general backup does.
+///
+/// The values and policy are the same as `value_iteration`'s, and
+/// since a deterministic environment's dynamics are always defined,
+/// there is no failure case.
+pub fn value_iteration_deterministic<E: IDeterministicEnvironment>(
+    env: &E,
+    gamma: Option<f32>,
+    theta: f32,
+    max_iters: usize,
+) -> (ValueFunction<E>, DeterministicPolicy<E>) {
+    let gamma = gamma.unwrap_or_else(|| IEnvironment::gamma(env));
+    let states = IEnvironment::all_states(env);
+    let (v, policy, _) = optimize_with(
+        env,
+        &Deterministic,
+        &states,
+        gamma,
+        theta,
+        max_iters,
+        0,
+        None,
+        None,
+        None,
+    )
+    .expect("deterministic backups are always defined");
+    (v, policy)
+}
+
+/// Why an iterative algorithm's sweeps stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConvergenceStatus {
+    /// The largest change fell below `theta`.
+    Converged,
+    /// `max_iters` sweeps ran without converging.
+    MaxIters,
+    /// The changes stopped shrinking, so more sweeps wouldn't have
+    /// converged either.
+    Oscillating,
+    /// The time budget ran out before the sweeps converged.
+    OutOfTime,
+}
+
+/// How an iterative algorithm's sweeps ended.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConvergenceReport {
+    /// The number of sweeps run.
+    pub iterations: usize,
+    /// The largest change to any value in the last sweep, or infinity
+    /// if no sweeps ran.
+    pub final_delta: f32,
+    /// Whether the sweeps stopped because `final_delta` fell below
+    /// `theta`, rather than by running out of iterations.
+    pub converged: bool,
+    /// Why the sweeps stopped.
+    pub status: ConvergenceStatus,
+    /// The largest change in each sweep, in order, up to the history
+    /// length requested.
+    pub deltas: Vec<f32>,
+    /// How much less the greedy policy returned can be worth than an
+    /// optimal one in any state: `value_suboptimality_bound` of
+    /// `final_delta` at the largest discount of any state.
+    pub suboptimality_bound: f32,
+}
+
+/// The standard bound on how far the greedy policy with respect to a
+/// value function falls short of optimal in any state, `2 * gamma *
+/// delta / (1 - gamma)`, once a sweep of value iteration has changed no
+/// value by more than `delta`. Choosing `theta` below `epsilon * (1 -
+/// gamma) / (2 * gamma)` so guarantees a policy within `epsilon` of
+/// optimal.
+///
+/// Without discounting the bound is infinite, as it is if `delta` is.
+pub fn value_suboptimality_bound(delta: f32, gamma: f32) -> f32 {
+    if gamma >= 1.0 {
+        return f32::INFINITY;
+    }
+    2.0 * gamma * delta / (1.0 - gamma)
+}
+
+/// The largest discount `gamma` applies from any state of `states`.
+fn largest_discount<'a, E: IEnvironment>(
+    env: &E,
+    gamma: f32,
+    states: impl IntoIterator<Item = &'a E::State>,
+) -> f32
+where
+    E::State: 'a,
+{
+    states
+        .into_iter()
+        .map(|s| discount_at(env, gamma, s))
+        .fold(gamma, f32::max)
+}
+
+/// As `value_iteration`, but also reporting how the sweeps converged.
+///
+/// The report's `deltas` keeps the change of at most the first
+/// `history` sweeps, so long runs don't build up a large history;
+/// pass 0 to keep none.
+pub fn value_iteration_with_report<E: IEnvironment>(
+    env: &E,
+    gamma: Option<f32>,
+    theta: f32,
+    max_iters: usize,
+    history: usize,
+) -> Option<(ValueFunction<E>, DeterministicPolicy<E>, ConvergenceReport)> {
+    let gamma = gamma.unwrap_or_else(|| env.gamma());
+    optimize_with(
+        env,
+        &Direct::new(env),
+        &env.all_states(),
+        gamma,
+        theta,
+        max_iters,
+        history,
+        None,
+        None,
+        None,
+    )
+}
+
+/// As `value_iteration_with_report`, but also stopping early, with
+/// status `ConvergenceStatus::Oscillating`, once the sweeps have
+/// stopped converging: when a sweep's change is no smaller than it was
+/// `window` sweeps before.
+///
+/// This catches environments where value iteration can't converge,
+/// such as cycles of nonzero reward with `gamma` = 1, where values
+/// oscillate or grow forever, without running to `max_iters`. The
+/// change can also stay level for a while as values first propagate
+/// out from the rewards, for about as many sweeps as the longest
+/// shortest path between states, so `window` should be longer than
+/// that.
+///
+/// Panics if `window` is 0.
+pub fn value_iteration_with_oscillation_check<E: IEnvironment>(
+    env: &E,
+    gamma: Option<f32>,
+    theta: f32,
+    max_iters: usize,
+    history: usize,
+    window: usize,
+) -> Option<(ValueFunction<E>, DeterministicPolicy<E>, ConvergenceReport)> {
+    assert!(
+        window > 0,
+        "the oscillation window must be at least one sweep"
+    );
+    let gamma = gamma.unwrap_or_else(|| env.gamma());
+    optimize_with(
+        env,
+        &Direct::new(env),
+        &env.all_states(),
+        gamma,
+        theta,
+        max_iters,
+        history,
+        Some(window),
+        None,
+        None,
+    )
+}
+
+/// As `value_iteration_with_report`, keeping no history, but starting
+/// the sweeps from the values `v_init` rather than from 0.
+///
+/// Starting close to the optimal values, such as those of a slightly
+/// different configuration of the same environment, takes fewer
+/// sweeps to converge; starting from them exactly takes one. States
+/// missing from `v_init` start from 0, and terminal states keep 0
+/// whatever `v_init` gives them.
+pub fn value_iteration_from<E: IEnvironment>(
+    env: &E,
+    v_init: ValueFunction<E>,
+    gamma: Option<f32>,
+    theta: f32,
+    max_iters: usize,
+) -> Option<(ValueFunction<E>, DeterministicPolicy<E>, ConvergenceReport)> {
+    let gamma = gamma.unwrap_or_else(|| env.gamma());
+    optimize_with(
+        env,
+        &Direct::new(env),
+        &env.all_states(),
+        gamma,
+        theta,
+        max_iters,
+        0,
+        None,
+        None,
+        Some(&v_init),
+    )
+}
+
+/// Value iteration one sweep at a time, for inspecting or animating
+/// the values as they converge.
+///
+/// Sweeps are `value_iteration`'s, and run on each call to `step`, or
+/// to `next`, which also yields a copy of the values after the sweep:
+/// an `Iterator` can't lend out the values it goes on to change, so
+/// calling `step` and reading `values` avoids the copy. Once a sweep
+/// changes the values by less than `theta`, or `max_iters` sweeps have
+/// run, no more are run. Sweeps also stop if the environment's rewards
+/// are not enumerable, in which case `policy` returns `None`.
+pub struct ValueIterationSolver<'a, E: IEnvironment> {
+    env: &'a E,
+    dynamics: Direct<E>,
+    states: HashSet<E::State>,
+    v: ValueFunction<E>,
+    gamma: f32,
+    theta: f32,
+    max_iters: usize,
+    iterations: usize,
+    converged: bool,
+    failed: bool,
+}
+
+impl<'a, E: IEnvironment> ValueIterationSolver<'a, E> {
+    /// Starts from values of 0. `gamma` defaults to `env.gamma()`.
+    pub fn new(env: &'a E, gamma: Option<f32>, theta: f32, max_iters: usize) -> Self {
+        let states = env.all_states();
+        let v = states.iter().map(|s| (s.clone(), 0.0)).collect();
+        ValueIterationSolver {
+            env,
+            dynamics: Direct::new(env),
+            states,
+            v,
+            gamma: gamma.unwrap_or_else(|| env.gamma()),
+            theta,
+            max_iters,
+            iterations: 0,
+            converged: false,
+            failed: false,
+        }
+    }
+
+    /// Runs one sweep, returning the number of sweeps run so far and
+    /// the largest change, or `None` once sweeps have stopped.
+    pub fn step(&mut self) -> Option<(usize, f32)> {
+        if self.converged || self.failed || self.iterations >= self.max_iters {
+            return None;
+        }
+        let Some(delta) = jacobi_sweep(
+            self.env,
+            &self.dynamics,
+            &self.states,
+            &mut self.v,
+            self.gamma,
+        ) else {
+            self.failed = true;
+            return None;
+        };
+        self.iterations += 1;
+        self.converged = delta < self.theta;
+        Some((self.iterations, delta))
+    }
+
+    /// The values after the sweeps run so far.
+    pub fn values(&self) -> &ValueFunction<E> {
+        &self.v
+    }
+
+    pub fn into_values(self) -> ValueFunction<E> {
+        self.v
+    }
+
+    /// Whether the last sweep changed the values by less than
+    /// `theta`.
+    pub fn is_converged(&self) -> bool {
+        self.converged
+    }
+
+    /// The greedy policy of the current values, breaking ties as
+    /// `value_iteration` does, or `None` if the environment's rewards
+    /// are not enumerable.
+    pub fn policy(&self) -> Option<DeterministicPolicy<E>> {
+        if self.failed {
+            return None;
+        }
+        greedy_policy_with(
+            self.env,
+            &self.dynamics,
+            self.states.iter(),
+            &self.v,
+            self.gamma,
+            &TieBreak::First,
+        )
+    }
+}
+
+impl<E: IEnvironment> Iterator for ValueIterationSolver<'_, E> {
+    /// The number of sweeps run, the largest change in the last, and
+    /// the values after it.
+    type Item = (usize, f32, ValueFunction<E>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (iteration, delta) = self.step()?;
+        Some((iteration, delta, self.v.clone()))
+    }
+}
+
+/// Anytime value iteration: as `value_iteration_with_report`, keeping
+/// no history, but sweeping until the values converge or the wall-clock
+/// `budget` runs out, whichever comes first, rather than for a number
+/// of sweeps, and returning the greedy policy of the values reached
+/// either way.
+///
+/// The clock is checked between sweeps, so the last sweep may overrun
+/// the budget by up to the time of one sweep. If the budget runs out
+/// before the first, the values are all 0 and the policy is greedy
+/// with respect to them; the report's status is then
+/// `ConvergenceStatus::OutOfTime`, with `converged` false, as whenever
+/// the budget runs out first. `gamma` defaults to `env.gamma()`.
+/// Returns `None` if the environment's rewards are not enumerable.
+pub fn value_iteration_timed<E: IEnvironment>(
+    env: &E,
+    budget: Duration,
+    gamma: Option<f32>,
+    theta: f32,
+) -> Option<(ValueFunction<E>, DeterministicPolicy<E>, ConvergenceReport)> {
+    let deadline = Instant::now() + budget;
+    let mut solver = ValueIterationSolver::new(env, gamma, theta, usize::MAX);
+    let mut report = ConvergenceReport {
+        iterations: 0,
+        final_delta: f32::INFINITY,
+        converged: false,
+        status: ConvergenceStatus::OutOfTime,
+        deltas: Vec::new(),
+        suboptimality_bound: f32::INFINITY,
+    };
+    while Instant::now() < deadline {
+        let Some((iterations, delta)) = solver.step() else {
+            break;
+        };
+        report.iterations = iterations;
+        report.final_delta = delta;
+        if solver.is_converged() {
+            report.converged = true;
+            report.status = ConvergenceStatus::Converged;
+            break;
+        }
+    }
+    let policy = solver.policy()?;
+    report.suboptimality_bound = value_suboptimality_bound(
+        report.final_delta,
+        largest_discount(env, solver.gamma, &solver.states),
+    );
+    Some((solver.into_values(), policy, report))
+}
+
+/// As `value_iteration`, but reading the dynamics from `cache`.
+pub fn value_iteration_cached<E: IEnvironment>(
+    env: &E,
+    cache: &TransitionCache<E>,
+    gamma: Option<f32>,
+    theta: f32,
+    max_iters: usize,
+) -> Option<(ValueFunction<E>, DeterministicPolicy<E>)> {
+    let gamma = gamma.unwrap_or_else(|| env.gamma());
+    let (v, policy, _) = optimize_with(
+        env,
+        cache,
+        cache.states(),
+        gamma,
+        theta,
+        max_iters,
+        0,
+        None,
+        None,
+        None,
+    )?;
+    Some((v, policy))
+}
+
+/// How value iteration backs up the states in each sweep.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SweepMode {
+    /// Every backup reads the values of the previous sweep, so the
+    /// order of the states makes no difference.
+    Jacobi,
+    /// Backups update the values in place, so later backups in a sweep
+    /// see the new values of earlier ones. This usually converges in
+    /// fewer sweeps. States are swept in the fixed order of
+    /// `TieBreak::First`; `value_iteration_in_order` takes an order.
+    GaussSeidel,
+}
+
+/// As `value_iteration_with_report`, keeping no history, but sweeping
+/// as `mode` says.
+///
+/// Both modes converge to the same values, within `theta`.
+pub fn value_iteration_with_mode<E: IEnvironment>(
+    env: &E,
+    mode: SweepMode,
+    gamma: Option<f32>,
+    theta: f32,
+    max_iters: usize,
+) -> Option<(ValueFunction<E>, DeterministicPolicy<E>, ConvergenceReport)> {
+    match mode {
+        SweepMode::Jacobi => value_iteration_with_report(env, gamma, theta, max_iters, 0),
+        SweepMode::GaussSeidel => {
+            let order = fixed_order(env.all_states());
+            value_iteration_in_order(env, &order, gamma, theta, max_iters)
+        }
+    }
+}
+
+/// Value iteration with Gauss-Seidel sweeps over the states of
+/// `order`, in that order, rather than over `env.all_states()`.
+///
+/// Sweeping states in the reverse of the order in which rewards
+/// propagate to them, e.g. outwards from the goal, can converge in
+/// far fewer sweeps. States outside `order` keep a value of 0.
+pub fn value_iteration_in_order<E: IEnvironment>(
+    env: &E,
+    order: &[E::State],
+    gamma: Option<f32>,
+    theta: f32,
+    max_iters: usize,
+) -> Option<(ValueFunction<E>, DeterministicPolicy<E>, ConvergenceReport)> {
+    let gamma = gamma.unwrap_or_else(|| env.gamma());
+    let states = order.iter().cloned().collect();
+    optimize_with(
+        env,
+        &Direct::new(env),
+        &states,
+        gamma,
+        theta,
+        max_iters,
+        0,
+        None,
+        Some((order, 1.0)),
+        None,
+    )
+}
+
+/// Value iteration with successive over-relaxation: Gauss-Seidel
+/// sweeps, in the fixed order of `SweepMode::GaussSeidel`, that move
+/// each value `omega` times as far as its backup would, to `V(s) +
+/// omega * (backup(s) - V(s))`.
+///
+/// With `omega` = 1 this is exactly `SweepMode::GaussSeidel`. Above 1
+/// it extrapolates past each backup, which can cut the number of sweeps
+/// many times over where `gamma` is near 1 and states mostly lead back
+/// to themselves, so that plain backups creep towards their values: a
+/// state that stays put with probability `p` under its best action is
+/// solved in a single backup by `omega = 1 / (1 - gamma * p)`. Any
+/// fixed point is that of the plain backups, so the values converged to
+/// are the same, but too large an `omega` overshoots by more each
+/// sweep and diverges; the report's `status` says whether the sweeps
+/// converged. Each sweep's change is measured before relaxation, as
+/// the largest difference between a backup and the value it replaces,
+/// so that an `omega` below 1, taking shorter steps, doesn't stop the
+/// sweeps early or understate the report's `suboptimality_bound`.
+///
+/// Panics if `omega` isn't positive.
+pub fn value_iteration_sor<E: IEnvironment>(
+    env: &E,
+    omega: f32,
+    gamma: Option<f32>,
+    theta: f32,
+    max_iters: usize,
+) -> Option<(ValueFunction<E>, DeterministicPolicy<E>, ConvergenceReport)> {
+    assert!(omega > 0.0, "the relaxation factor must be positive");
+    let gamma = gamma.unwrap_or_else(|| env.gamma());
+    let order = fixed_order(env.all_states());
+    let states = order.iter().cloned().collect();
+    optimize_with(
+        env,
+        &Direct::new(env),
+        &states,
+        gamma,
+        theta,
+        max_iters,
+        0,
+        None,
+        Some((&order, omega)),
+        None,
+    )
+}
+
+/// Runs value iteration sweeps over `states`, stopping early as
+/// `value_iteration_with_oscillation_check` does if `window` is given.
+/// Sweeps are Jacobi sweeps unless an `order` of `states` is given,
+/// with a relaxation factor, in which they are Gauss-Seidel sweeps in
+/// that order, relaxed as `value_iteration_sor` relaxes them. The
+/// values start from `init` where given, and from 0 elsewhere.
+#[allow(clippy::too_many_arguments)]
+fn optimize_with<E: IEnvironment>(
+    env: &E,
+    dynamics: &impl Dynamics<E>,
+    states: &HashSet<E::State>,
+    gamma: f32,
+    theta: f32,
+    max_iters: usize,
+    history: usize,
+    window: Option<usize>,
+    order: Option<(&[E::State], f32)>,
+    init: Option<&ValueFunction<E>>,
+) -> Option<(ValueFunction<E>, DeterministicPolicy<E>, ConvergenceReport)> {
+    let mut v: ValueFunction<E> = states
+        .iter()
+        .map(|s| {
+            let value = match init {
+                Some(init) if !env.is_terminal(s) => init.get(s).copied().unwrap_or(0.0),
+                _ => 0.0,
+            };
+            (s.clone(), value)
+        })
+        .collect();
+    let mut report = ConvergenceReport {
+        iterations: 0,
+        final_delta: f32::INFINITY,
+        converged: false,
+        status: ConvergenceStatus::MaxIters,
+        deltas: Vec::with_capacity(history.min(max_iters)),
+        suboptimality_bound: f32::INFINITY,
+    };
+    // The changes of the last `window` sweeps, oldest first.
+    let mut recent = VecDeque::with_capacity(window.unwrap_or(0));
+    for _ in 0..max_iters {
+        let delta = match order {
+            None => jacobi_sweep(env, dynamics, states, &mut v, gamma)?,
+            Some((order, omega)) => gauss_seidel_sweep(env, dynamics, order, &mut v, gamma, omega)?,
+        };
+        report.iterations += 1;
+        report.final_delta = delta;
+        if report.deltas.len() < history {
+            report.deltas.push(delta);
+        }
+        if delta < theta {
+            report.converged = true;
+            report.status = ConvergenceStatus::Converged;
+            break;
+        }
+        if let Some(window) = window {
+            if recent.len() == window && recent.pop_front().is_some_and(|old| delta >= old) {
+                report.status = ConvergenceStatus::Oscillating;
+                break;
+            }
+            recent.push_back(delta);
+        }
+    }
+
+    report.suboptimality_bound =
+        value_suboptimality_bound(report.final_delta, largest_discount(env, gamma, states));
+    let policy = greedy_policy_with(env, dynamics, states.iter(), &v, gamma, &TieBreak::First)?;
+    Some((v, policy, report))
+}
+
+/// Backs up every state in `states` from the values of the previous
+/// sweep, returning the largest change.
+fn jacobi_sweep<E: IEnvironment>(
+    env: &E,
+    dynamics: &impl Dynamics<E>,
+    states: &HashSet<E::State>,
+    v: &mut ValueFunction<E>,
+    gamma: f32,
+) -> Option<f32> {
+    let mut delta: f32 = 0.0;
+    let mut next = HashMap::with_capacity(states.len());
+    for s in states {
+        if env.is_terminal(s) {
+            next.insert(s.clone(), 0.0);
+            continue;
+        }
+        let value =
+            best_action(env, dynamics, s, v, gamma, &TieBreak::First)?.map_or(0.0, |(_, q)| q);
+        delta = delta.max((value - v[s]).abs());
+        next.insert(s.clone(), value);
+    }
+    *v = next;
+    Some(delta)
+}
+
+/// Backs up each state in `order` in turn, in place, so that later
+/// backups in the sweep see the new values of earlier ones, moving each
+/// value `omega` times as far as its backup would, returning the
+/// largest difference between a backup and the value it backed up.
+///
+/// That is the change an unrelaxed backup would have made, rather than
+/// the relaxed one, which is `omega` times smaller, so that the sweeps
+/// stop, and the suboptimality bound is taken, on the Bellman residual
+/// whatever `omega` is.
+fn gauss_seidel_sweep<E: IEnvironment>(
+    env: &E,
+    dynamics: &impl Dynamics<E>,
+    order: &[E::State],
+    v: &mut ValueFunction<E>,
+    gamma: f32,
+    omega: f32,
+) -> Option<f32> {
+    let mut delta: f32 = 0.0;
+    for s in order.iter().filter(|s| !env.is_terminal(s)) {
+        let backup =
+            best_action(env, dynamics, s, v, gamma, &TieBreak::First)?.map_or(0.0, |(_, q)| q);
+        let old = v.get(s).copied().unwrap_or(0.0);
+        let value = if omega == 1.0 {
+            backup
+        } else {
+            old + omega * (backup - old)
+        };
+        v.insert(s.clone(), value);
+        delta = delta.max((backup - old).abs());
+    }
+    Some(delta)
+}
+
+/// How `value_iteration_with_elimination` ran.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EliminationReport {
+    pub convergence: ConvergenceReport,
+    /// The total number of state-action pairs eliminated by the end of
+    /// each sweep, in order.
+    pub eliminated: Vec<usize>,
+}
+
+/// Value iteration with action elimination: as `value_iteration`, but
+/// permanently dropping from each state's backups the actions proven
+/// suboptimal, so that later sweeps maximize over fewer of them.
+///
+/// After a sweep that changed the values by at most `delta`, every
+/// state's optimal value is within `e = gamma * delta / (1 - gamma)`
+/// of its current one, so each action's optimal value is within
+/// `gamma * e` of its backed-up value. An action whose upper bound
+/// falls below the lower bound of another is then worse than it,
+/// whatever the remaining sweeps do, and is never optimal; elimination
+/// is only ever that sound, with a little slack for rounding. The
+/// bounds need `gamma` < 1, so nothing is eliminated otherwise. The
+/// policy is greedy over the actions left.
+pub fn value_iteration_with_elimination<E: IEnvironment>(
+    env: &E,
+    gamma: Option<f32>,
+    theta: f32,
+    max_iters: usize,
+) -> Option<(ValueFunction<E>, DeterministicPolicy<E>, EliminationReport)> {
+    let gamma = gamma.unwrap_or_else(|| env.gamma());
+    let dynamics = Direct::new(env);
+    let states = env.all_states();
+    let mut remaining: HashMap<E::State, Vec<E::Action>> = states
+        .iter()
+        .filter(|s| !env.is_terminal(s))
+        .map(|s| (s.clone(), fixed_order(env.action_mask(s))))
+        .collect();
+    let mut v: ValueFunction<E> = states.iter().map(|s| (s.clone(), 0.0)).collect();
+    let mut report = EliminationReport {
+        convergence: ConvergenceReport {
+            iterations: 0,
+            final_delta: f32::INFINITY,
+            converged: false,
+            status: ConvergenceStatus::MaxIters,
+            deltas: Vec::new(),
+            suboptimality_bound: f32::INFINITY,
+        },
+        eliminated: Vec::new(),
+    };
+    let mut eliminated = 0;
+    // The bounds hold for the largest discount of any state.
+    let bound = largest_discount(env, gamma, &states);
+    for _ in 0..max_iters {
+        // How far below the best an action's backup must fall for its
+        // upper bound to be below the best's lower bound.
+        let last = report.convergence.final_delta;
+        let margin = if bound < 1.0 && last.is_finite() {
+            2.0 * bound * bound * last / (1.0 - bound)
+        } else {
+            f32::INFINITY
+        };
+        let mut delta: f32 = 0.0;
+        let mut next = HashMap::with_capacity(states.len());
+        for s in &states {
+            let Some(actions) = remaining.get_mut(s) else {
+                next.insert(s.clone(), 0.0);
+                continue;
+            };
+            let mut values = Vec::with_capacity(actions.len());
+            for a in actions.iter() {
+                values.push(dynamics.lookahead(env, s, a, &v, gamma)?);
+            }
+            let value = values.iter().copied().reduce(f32::max).unwrap_or(0.0);
+            let cutoff = value - margin - 1e-6 * (1.0 + value.abs());
+            let before = actions.len();
+            let mut kept = values.iter().map(|q| *q >= cutoff);
+            actions.retain(|_| kept.next().unwrap_or(true));
+            eliminated += before - actions.len();
+            delta = delta.max((value - v[s]).abs());
+            next.insert(s.clone(), value);
+        }
+        v = next;
+        report.convergence.iterations += 1;
+        report.convergence.final_delta = delta;
+        report.eliminated.push(eliminated);
+        if delta < theta {
+            report.convergence.converged = true;
+            report.convergence.status = ConvergenceStatus::Converged;
+            break;
+        }
+    }
+
+    report.convergence.suboptimality_bound =
+        value_suboptimality_bound(report.convergence.final_delta, bound);
+    let mut policy = HashMap::new();
+    for (s, actions) in remaining {
+        let mut values = Vec::with_capacity(actions.len());
+        for a in actions {
+            let q = dynamics.lookahead(env, &s, &a, &v, gamma)?;
+            values.push((a, q));
+        }
+        if let Some((a, _)) = argmax::<E>(&s, values, &TieBreak::First) {
+            policy.insert(s, a);
+        }
+    }
+    Some((v, DeterministicPolicy::new(policy), report))
+}
+
+/// The greedy policy with respect to the state values `v` over the
+/// non-terminal states of `env.all_states()`, breaking ties between
+/// equally good actions by `tie`. `gamma` defaults to `env.gamma()`.
+///
+/// `value_iteration` and its variants extract their policies with
+/// `TieBreak::First`; this allows choosing differently.
+///
+/// Returns `None` if the environment's rewards are not enumerable.
+pub fn greedy_policy<E: IEnvironment>(
+    env: &E,
+    v: &ValueFunction<E>,
+    gamma: Option<f32>,
+    tie: &TieBreak<E::Action>,
+) -> Option<DeterministicPolicy<E>> {
+    let gamma = gamma.unwrap_or_else(|| env.gamma());
+    greedy_policy_with(
+        env,
+        &Direct::new(env),
+        env.all_states().iter(),
+        v,
+        gamma,
+        tie,
+    )
+}
+
+fn greedy_policy_with<'a, E: IEnvironment>(
+    env: &E,
+    dynamics: &impl Dynamics<E>,
+    states: impl Iterator<Item = &'a E::State>,
+    v: &ValueFunction<E>,
+    gamma: f32,
+    tie: &TieBreak<E::Action>,
+) -> Option<DeterministicPolicy<E>>
+where
+    E::State: 'a,
+{
+    let mut actions = HashMap::new();
+    for s in states.filter(|s| !env.is_terminal(s)) {
+        if let Some((a, _)) = best_action(env, dynamics, s, v, gamma, tie)? {
+            actions.insert(s.clone(), a);
+        }
+    }
+    Some(DeterministicPolicy::new(actions))
+}
+
+/// The policy that takes every action within `tolerance` of the best
+/// one-step lookahead under `v` with equal probability, rather than
+/// breaking ties as `greedy_policy` does.
+///
+/// Lookaheads that tie in exact arithmetic can differ by rounding, so
+/// a `tolerance` of 0 may miss ties; around `theta` is a reasonable
+/// choice for values from `value_iteration`. Returns `None` if the
+/// environment's rewards are not enumerable.
+pub fn greedy_stochastic_policy<E: IEnvironment>(
+    env: &E,
+    v: &ValueFunction<E>,
+    gamma: Option<f32>,
+    tolerance: f32,
+) -> Option<TabularStochasticPolicy<E>> {
+    let gamma = gamma.unwrap_or_else(|| env.gamma());
+    let direct = Direct::new(env);
+    let mut probs = HashMap::new();
+    for s in env.all_states().into_iter().filter(|s| !env.is_terminal(s)) {
+        let mut values = Vec::new();
+        for a in env.action_mask(&s) {
+            let q = direct.lookahead(env, &s, &a, v, gamma)?;
+            values.push((a, q));
+        }
+        let Some(max) = values.iter().map(|(_, q)| *q).reduce(f32::max) else {
+            continue;
+        };
+        let tied: Vec<E::Action> = values
+            .into_iter()
+            .filter(|(_, q)| *q >= max - tolerance)
+            .map(|(a, _)| a)
+            .collect();
+        let p = 1.0 / tied.len() as f32;
+        probs.insert(s, tied.into_iter().map(|a| (a, p)).collect());
+    }
+    Some(TabularStochasticPolicy::new(probs))
+}
+
+/// As `value_iteration`, but extracting the policy with
+/// `greedy_stochastic_policy`, so that it spreads its choice evenly
+/// over every optimal action, to within `tolerance`, instead of
+/// picking one.
+pub fn value_iteration_with_ties<E: IEnvironment>(
+    env: &E,
+    gamma: Option<f32>,
+    theta: f32,
+    max_iters: usize,
+    tolerance: f32,
+) -> Option<(ValueFunction<E>, TabularStochasticPolicy<E>)> {
+    let gamma = gamma.unwrap_or_else(|| env.gamma());
+    let (v, _) = value_iteration(env, Some(gamma), theta, max_iters)?;
+    let policy = greedy_stochastic_policy(env, &v, Some(gamma), tolerance)?;
+    Some((v, policy))
+}
+
+/// Finite-horizon dynamic programming: the optimal values and
+/// nonstationary policy of an episode cut off after `horizon` steps,
+/// by backward induction.
+///
+/// Returns the value functions `V_0` to `V_horizon`, `V_t` being the
+/// optimal return from each state of `env.all_states()` with
+/// `horizon - t` steps to go, and the policies for steps 0 to
+/// `horizon - 1`, the one for step `t` acting greedily on `V_{t+1}`. Each is computed
+/// exactly, once, from the one after it, starting from `V_horizon` =
+/// 0; there is no iterating to convergence, so `gamma`, which defaults
+/// to `env.gamma()`, may be 1. Terminal states are worth 0 at every
+/// step. Returns `None` if the environment's rewards are not
+/// enumerable.
+#[allow(clippy::type_complexity)]
+pub fn finite_horizon<E: IEnvironment>(
+    env: &E,
+    horizon: usize,
+    gamma: Option<f32>,
+) -> Option<(Vec<ValueFunction<E>>, Vec<DeterministicPolicy<E>>)> {
+    let gamma = gamma.unwrap_or_else(|| env.gamma());
+    let dynamics = Direct::new(env);
+    let states = env.all_states();
+    let mut values: Vec<ValueFunction<E>> = vec![states.iter().map(|s| (s.clone(), 0.0)).collect()];
+    let mut policies = Vec::with_capacity(horizon);
+    for _ in 0..horizon {
+        let next = values.last().expect("V_horizon is always there");
+        let mut v = HashMap::with_capacity(states.len());
+        let mut actions = HashMap::new();
+        for s in &states {
+            let best = if env.is_terminal(s) {
+                None
+            } else {
+                best_action(env, &dynamics, s, next, gamma, &TieBreak::First)?
+            };
+            let value = match best {
+                Some((a, q)) => {
+                    actions.insert(s.clone(), a);
+                    q
+                }
+                None => 0.0,
+            };
+            v.insert(s.clone(), value);
+        }
+        values.push(v);
+        policies.push(DeterministicPolicy::new(actions));
+    }
+    // Built from the last step back.
+    values.reverse();
+    policies.reverse();
+    Some((values, policies))
+}
+
+/// A parallel variant of `value_iteration`, computing each state's
+/// backup within a sweep on the rayon thread pool.
+///
+/// Like `value_iteration`, each sweep reads only the previous sweep's
+/// values and collects the new ones into a fresh map, so both produce
+/// the same values. Sharing the value function across threads
+/// requires `E: Sync` and `E::State: Send + Sync`, and collecting the greedy
+/// actions requires `E::Action: Send`. The environment is only ever
+/// borrowed, never cloned, so every worker shares one environment and
+/// whatever tables it holds, as does an `Arc` of one.
+#[cfg(feature = "parallel")]
+pub fn par_value_iteration<E>(
+    env: &E,
+    gamma: Option<f32>,
+    theta: f32,
+    max_iters: usize,
+) -> Option<(ValueFunction<E>, DeterministicPolicy<E>)>
+where
+    E: IEnvironment + Sync,
+    E::State: Send + Sync,
+    E::Action: Send,
+{
+    use rayon::prelude::*;
+
+    let gamma = gamma.unwrap_or_else(|| env.gamma());
+    let dynamics = Direct::new(env);
+    let states: Vec<E::State> = env.all_states().into_iter().collect();
+    let mut v: ValueFunction<E> = states.iter().map(|s| (s.clone(), 0.0)).collect();
+    for _ in 0..max_iters {
+        // Each worker gets its own dynamics, and so its own buffer.
+        let next: Option<ValueFunction<E>> = states
+            .par_iter()
+            .map_init(
+                || Direct::new(env),
+                |dynamics, s| {
+                    if env.is_terminal(s) {
+                        return Some((s.clone(), 0.0));
+                    }
+                    let value = best_action(env, &*dynamics, s, &v, gamma, &TieBreak::First)?
+                        .map_or(0.0, |(_, q)| q);
+                    Some((s.clone(), value))
+                },
+            )
+            .collect();
+        let next = next?;
+        let delta = states
+            .iter()
+            .map(|s| (next[s] - v[s]).abs())
+            .fold(0.0, f32::max);
+        v = next;
+        if delta < theta {
+            break;
+        }
+    }
+
+    let policy = greedy_policy_with(env, &dynamics, states.iter(), &v, gamma, &TieBreak::First)?;
+    Some((v, policy))
+}
+
+/// How `policy_iteration` ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PolicyIterationReport {
+    /// The number of rounds of evaluation and improvement run.
+    pub iterations: usize,
+    /// Whether the policy became stable with its last evaluation
+    /// converged within `theta`, rather than a cap being reached.
+    pub converged: bool,
+}
+
+/// Policy iteration: alternates full policy evaluation with greedy
+/// policy improvement until the policy is stable, i.e. an improvement
+/// step changes no state's action.
+///
+/// Starts from an arbitrary policy taking the first action in the
+/// fixed order of `TieBreak::First` in each non-terminal state, and
+/// breaks ties in improvement steps the same way. Each evaluation
+/// runs as `evaluate_policy`, for at most `max_iters` sweeps, and at
+/// most `max_iters` rounds are run, so the call always returns, even
+/// when with `gamma` = 1 an intermediate policy never reaches a
+/// terminal state; such a policy is still improved on from its
+/// truncated values. An action is only replaced when another is
+/// better by more than `theta`, so ties between equally good actions
+/// cannot make the policy oscillate. `gamma` defaults to
+/// `env.gamma()`.
+///
+/// Returns the values from the last evaluation, the last policy, and
+/// how the rounds ended, or `None` if the environment's rewards are
+/// not enumerable.
+pub fn policy_iteration<E: IEnvironment>(
+    env: &E,
+    gamma: Option<f32>,
+    theta: f32,
+    max_iters: usize,
+) -> Option<(
+    ValueFunction<E>,
+    DeterministicPolicy<E>,
+    PolicyIterationReport,
+)> {
+    let gamma = gamma.unwrap_or_else(|| env.gamma());
+    let dynamics = Direct::new(env);
+    let states = env.all_states();
+    let mut actions: HashMap<E::State, E::Action> = states
+        .iter()
+        .filter(|s| !env.is_terminal(s))
+        .filter_map(|s| {
+            TieBreak::First
+                .choose(s, env.action_mask(s).into_iter().collect())
+                .map(|a| (s.clone(), a))
+        })
+        .collect();
+
+    let mut v = ValueFunction::<E>::new();
+    let mut report = PolicyIterationReport {
+        iterations: 0,
+        converged: false,
+    };
+    while report.iterations < max_iters {
+        report.iterations += 1;
+        let policy = DeterministicPolicy::new(actions);
+        let (values, evaluated) =
+            evaluate_with(env, &dynamics, &states, &policy, gamma, theta, max_iters)?;
+        v = values;
+        actions = policy.into_actions();
+
+        let mut stable = true;
+        for (s, current) in actions.iter_mut() {
+            let current_q = dynamics.lookahead(env, s, current, &v, gamma)?;
+            if let Some((a, q)) = best_action(env, &dynamics, s, &v, gamma, &TieBreak::First)? {
+                if q > current_q + theta {
+                    *current = a;
+                    stable = false;
+                }
+            }
+        }
+        if stable {
+            // Evaluating the same policy again would give the same
+            // values, so there's nothing more to do either way.
+            report.converged = evaluated;
+            break;
+        }
+    }
+    Some((v, DeterministicPolicy::new(actions), report))
+}
+
+/// Computes the action-value function implied by the state values
+/// `v`, via a one-step lookahead from every non-terminal state in
+/// `env.all_states()`. `gamma` defaults to `env.gamma()`.
+///
+/// Returns `None` if the environment's rewards are not enumerable.
+pub fn q_from_v<E: IEnvironment>(
+    env: &E,
+    v: &ValueFunction<E>,
+    gamma: Option<f32>,
+) -> Option<QTable<E>> {
+    let gamma = gamma.unwrap_or_else(|| env.gamma());
+    let dynamics = Direct::new(env);
+    let mut q = QTable::new();
+    for s in env.all_states().into_iter().filter(|s| !env.is_terminal(s)) {
+        for a in env.actions_from(&s) {
+            let value = dynamics.lookahead(env, &s, &a, v, gamma)?;
+            q.set(s.clone(), a, value);
+        }
+    }
+    Some(q)
+}
+
+/// The dynamics of following `policy` in `env` as dense arrays: the
+/// state-to-state transition matrix `P`, with `P[i][j]` the
+/// probability of moving from the `i`th state to the `j`th in one
+/// step, the expected one-step rewards `r`, and the states in the
+/// order indexing both.
+///
+/// The states are those of `env.all_states()`, in a fixed order so
+/// that the arrays are reproducible. As in the backups, terminal
+/// states have all-zero rows and rewards, and transitions to states
+/// outside `all_states` are left out, so that `V = (I - gamma P)^-1 r`
+/// is the policy's value function. Returns `None` if the
+/// environment's rewards are not enumerable.
+#[allow(clippy::type_complexity)]
+pub fn policy_transition_matrix<E, P>(
+    env: &E,
+    policy: &P,
+) -> Option<(Vec<Vec<f32>>, Vec<f32>, Vec<E::State>)>
+where
+    E: IEnvironment,
+    P: Policy<E>,
+{
+    let rewards = env.rewards();
+    let states = fixed_order(env.all_states());
+    let index: HashMap<&E::State, usize> = states.iter().enumerate().map(|(i, s)| (s, i)).collect();
+    let mut matrix = vec![vec![0.0; states.len()]; states.len()];
+    let mut reward = vec![0.0; states.len()];
+    for (i, s) in states.iter().enumerate() {
+        if env.is_terminal(s) {
+            continue;
+        }
+        for (a, pa) in policy.action_distribution(env, s) {
+            if pa <= 0.0 {
+                continue;
+            }
+            for to in env.states_from(s, &a) {
+                let (p, r) = transition_at(env, s, &a, &to, &rewards)?;
+                reward[i] += pa * r;
+                if let Some(j) = index.get(&to) {
+                    matrix[i][*j] += pa * p;
+                }
+            }
+        }
+    }
+    Some((matrix, reward, states))
+}
+
+/// Evaluates `policy` exactly, by solving the linear system `(I -
+/// gamma P) V = r` given by `policy_transition_matrix` with Gaussian
+/// elimination, rather than sweeping towards its solution as
+/// `evaluate_policy` does. `gamma` defaults to `env.gamma()`.
+///
+/// Elimination takes time cubic in the number of states, so this
+/// suits small state spaces. The solve is done in `f64`.
+///
+/// With `gamma` < 1 the system always has a solution. With `gamma` =
+/// 1 it has one only if the policy reaches a terminal state, or leaves
+/// `all_states`, with probability 1 from every state; otherwise the
+/// values of the states it cycles among are unbounded, and this
+/// returns `None`, as it does if the environment's rewards are not
+/// enumerable.
+pub fn evaluate_policy_exact<E, P>(
+    env: &E,
+    policy: &P,
+    gamma: Option<f32>,
+) -> Option<ValueFunction<E>>
+where
+    E: IEnvironment,
+    P: Policy<E>,
+{
+    let gamma = gamma.unwrap_or_else(|| env.gamma());
+    let (matrix, reward, states) = policy_transition_matrix(env, policy)?;
+    let discounts = discounts(env, gamma, &states);
+    let system = matrix
+        .iter()
+        .enumerate()
+        .map(|(i, row)| {
+            row.iter()
+                .zip(&discounts)
+                .enumerate()
+                .map(|(j, (p, d))| f64::from(i == j) - d * f64::from(*p))
+                .collect()
+        })
+        .collect();
+    let v = solve_linear(system, reward.into_iter().map(f64::from).collect())?;
+    Some(
+        states
+            .into_iter()
+            .zip(v.into_iter().map(|x| x as f32))
+            .collect(),
+    )
+}
+
+/// The expected number of steps `policy` takes to reach a terminal
+/// state from each state of `env.all_states()`, solving `h(s) = 1 +
+/// sum_s' P(s'|s) h(s')` over `policy_transition_matrix`, with terminal
+/// states pinned at 0.
+///
+/// As in that matrix, transitions to states outside `all_states` are
+/// left out, so taking one counts as ending the episode. A state from
+/// which the policy fails to end with probability 1, because it can
+/// reach states that never lead to an end, such as a cycle or a
+/// non-terminal state without actions, has an infinite hitting time,
+/// reported as `f32::INFINITY` rather than left to the solve. The
+/// solve is done in `f64`. Returns `None` if the environment's rewards
+/// are not enumerable.
+pub fn expected_hitting_time<E, P>(env: &E, policy: &P) -> Option<HashMap<E::State, f32>>
+where
+    E: IEnvironment,
+    P: Policy<E>,
+{
+    let (matrix, _, states) = policy_transition_matrix(env, policy)?;
+    let n = states.len();
+    let mut predecessors = vec![Vec::new(); n];
+    for (i, row) in matrix.iter().enumerate() {
+        for (j, p) in row.iter().enumerate() {
+            if *p > 0.0 {
+                predecessors[j].push(i);
+            }
+        }
+    }
+    // States that end in a step: terminal ones, and those with actions
+    // whose transitions partly or wholly leave `all_states`.
+    let ends = (0..n)
+        .filter(|&i| {
+            let total: f32 = matrix[i].iter().sum();
+            env.is_terminal(&states[i])
+                || (!env.action_mask(&states[i]).is_empty() && total < 1.0 - 1e-6)
+        })
+        .collect();
+    let can_end = search(ends, |&j| predecessors[j].clone());
+    let never_ends = (0..n).filter(|i| !can_end.contains(i)).collect();
+    let infinite = search(never_ends, |&j| predecessors[j].clone());
+
+    let finite: Vec<usize> = (0..n).filter(|i| !infinite.contains(i)).collect();
+    let system = finite
+        .iter()
+        .map(|&i| {
+            finite
+                .iter()
+                .map(|&j| f64::from(i == j) - f64::from(matrix[i][j]))
+                .collect()
+        })
+        .collect();
+    let steps = finite
+        .iter()
+        .map(|&i| f64::from(!env.is_terminal(&states[i])))
+        .collect();
+    let h = solve_linear(system, steps)?;
+    let mut times: HashMap<E::State, f32> = finite
+        .iter()
+        .zip(h)
+        .map(|(&i, t)| (states[i].clone(), t as f32))
+        .collect();
+    times.extend(
+        infinite
+            .into_iter()
+            .map(|i| (states[i].clone(), f32::INFINITY)),
+    );
+    Some(times)
+}
+
+/// The discount into each of `states`, as `discount_at`, in `f64`.
+fn discounts<E: IEnvironment>(env: &E, gamma: f32, states: &[E::State]) -> Vec<f64> {
+    states
+        .iter()
+        .map(|s| f64::from(discount_at(env, gamma, s)))
+        .collect()
+}
+
+/// Solves `a x = b` by Gaussian elimination with partial pivoting, or
+/// returns `None` if `a` is singular.
+pub(crate) fn solve_linear(a: Vec<Vec<f64>>, b: Vec<f64>) -> Option<Vec<f64>> {
+    let x = solve_many(a, b.into_iter().map(|b| vec![b]).collect())?;
+    Some(x.into_iter().map(|row| row[0]).collect())
+}
+
+/// Solves `a x = b` for a matrix `b` of any number of columns at once,
+/// as `solve_linear` does for one.
+fn solve_many(mut a: Vec<Vec<f64>>, mut b: Vec<Vec<f64>>) -> Option<Vec<Vec<f64>>> {
+    let n = b.len();
+    for col in 0..n {
+        let pivot = (col..n).max_by(|i, j| a[*i][col].abs().total_cmp(&a[*j][col].abs()))?;
+        if a[pivot][col].abs() < 1e-12 {
+            return None;
+        }
+        a.swap(col, pivot);
+        b.swap(col, pivot);
+        let (above, below) = a.split_at_mut(col + 1);
+        let pivot_row = &above[col];
+        let (b_above, b_below) = b.split_at_mut(col + 1);
+        let b_col = &b_above[col];
+        for (row, target) in below.iter_mut().zip(b_below) {
+            let factor = row[col] / pivot_row[col];
+            if factor == 0.0 {
+                continue;
+            }
+            for (x, p) in row[col..].iter_mut().zip(&pivot_row[col..]) {
+                *x -= factor * p;
+            }
+            for (t, p) in target.iter_mut().zip(b_col) {
+                *t -= factor * p;
+            }
+        }
+    }
+    let columns = b.first().map_or(0, Vec::len);
+    let mut x = vec![vec![0.0; columns]; n];
+    for row in (0..n).rev() {
+        for c in 0..columns {
+            let known: f64 = (row + 1..n).map(|k| a[row][k] * x[k][c]).sum();
+            x[row][c] = (b[row][c] - known) / a[row][row];
+        }
+    }
+    Some(x)
+}
+
+/// The successor representation of `policy`, `M = (I - gamma P)^-1`
+/// for the transition matrix `P` of `policy_transition_matrix`: the
+/// expected discounted number of visits to each state `to`, counting
+/// the visit at the start, of an episode started from `from`, keyed
+/// by `(from, to)`. `gamma` defaults to `env.gamma()`.
+///
+/// The values of the policy are then `M r` for the rewards `r` of
+/// `policy_transition_matrix`, and since those depend only on `M`
+/// and the rewards, one representation serves any reward on the same
+/// dynamics. A terminal state is visited once from itself and leads
+/// nowhere.
+///
+/// The inverse is computed, in `f64`, as `evaluate_policy_exact`
+/// solves its system. Its entries grow like `1 / (1 - gamma)`, and so
+/// does the error rounding introduces, so as `gamma` nears 1 the
+/// values lose precision, all the more once rounded to `f32`. With
+/// `gamma` = 1 the matrix is singular whenever the policy can cycle
+/// forever, and this returns `None`, as it does if the environment's
+/// rewards are not enumerable.
+#[allow(clippy::type_complexity)]
+pub fn successor_representation<E, P>(
+    env: &E,
+    policy: &P,
+    gamma: Option<f32>,
+) -> Option<HashMap<(E::State, E::State), f32>>
+where
+    E: IEnvironment,
+    P: Policy<E>,
+{
+    let gamma = gamma.unwrap_or_else(|| env.gamma());
+    let (matrix, _, states) = policy_transition_matrix(env, policy)?;
+    let n = states.len();
+    let discounts = discounts(env, gamma, &states);
+    let identity = |i: usize, j: usize| f64::from(i == j);
+    let system = matrix
+        .iter()
+        .enumerate()
+        .map(|(i, row)| {
+            row.iter()
+                .zip(&discounts)
+                .enumerate()
+                .map(|(j, (p, d))| identity(i, j) - d * f64::from(*p))
+                .collect()
+        })
+        .collect();
+    let unit = (0..n)
+        .map(|i| (0..n).map(|j| identity(i, j)).collect())
+        .collect();
+    let inverse = solve_many(system, unit)?;
+    let mut m = HashMap::with_capacity(n * n);
+    for (from, row) in states.iter().zip(inverse) {
+        for (to, visits) in states.iter().zip(row) {
+            m.insert((from.clone(), to.clone()), visits as f32);
+        }
+    }
+    Some(m)
+}
+
+/// The difference in value between policies `p1` and `p2` in every
+/// state of `env.all_states()`: `v1[s] - v2[s]`, positive where `p1`
+/// does better.
+///
+/// Both policies are evaluated over that same set of states, as by
+/// `evaluate_policy`, so every state has a value under each and
+/// terminal states have a gap of 0. `gamma` defaults to `env.gamma()`.
+/// Returns `None` if the environment's rewards are not enumerable.
+pub fn policy_value_gap<E, P1, P2>(
+    env: &E,
+    p1: &P1,
+    p2: &P2,
+    gamma: Option<f32>,
+    theta: f32,
+    max_iters: usize,
+) -> Option<ValueFunction<E>>
+where
+    E: IEnvironment,
+    P1: Policy<E>,
+    P2: Policy<E>,
+{
+    let gamma = gamma.unwrap_or_else(|| env.gamma());
+    let states = env.all_states();
+    let dynamics = Direct::new(env);
+    let (v1, _) = evaluate_with(env, &dynamics, &states, p1, gamma, theta, max_iters)?;
+    let (v2, _) = evaluate_with(env, &dynamics, &states, p2, gamma, theta, max_iters)?;
+    Some(
+        states
+            .into_iter()
+            .map(|s| {
+                let gap = v1[&s] - v2[&s];
+                (s, gap)
+            })
+            .collect(),
+    )
+}
+
+/// Summary statistics of the magnitudes of the gaps in `gap`, as
+/// returned by `policy_value_gap`: its `max` bounds how far apart the
+/// policies are in any state. Returns `None` if `gap` is empty.
+pub fn value_gap_summary<E: IEnvironment>(gap: &ValueFunction<E>) -> Option<Summary> {
+    let magnitudes: Vec<f32> = gap.values().map(|g| g.abs()).collect();
+    Summary::of(&magnitudes)
+}
+
+/// The action from `state` with the highest value in `q`, or `None`
+/// if `state` has no available actions. Missing entries are treated
+/// as 0, and ties are broken by `tie`.
+pub fn greedy_action<E: IEnvironment>(
+    env: &E,
+    q: &QTable<E>,
+    state: &E::State,
+    tie: &TieBreak<E::Action>,
+) -> Option<E::Action> {
+    let values = env
+        .action_mask(state)
+        .into_iter()
+        .map(|a| {
+            let value = q.get(state, &a);
+            (a, value)
+        })
+        .collect();
+    argmax::<E>(state, values, tie).map(|(a, _)| a)
+}
+
+/// The expected value in `q` of the action `policy` takes from
+/// `state`, `sum_a pi(a|s) Q(s,a)`: the bootstrap target of expected
+/// SARSA and its relatives. Missing entries are treated as 0, and
+/// terminal states have value 0.
+pub fn expected_q<E, P>(env: &E, q: &QTable<E>, policy: &P, state: &E::State) -> f32
+where
+    E: IEnvironment,
+    P: Policy<E>,
+{
+    if env.is_terminal(state) {
+        return 0.0;
+    }
+    policy
+        .action_distribution(env, state)
+        .into_iter()
+        .map(|(a, p)| p * q.get(state, &a))
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{policies_equal, values_approx_eq, Chain, Loop, Missing, Trap};
+
+    #[cfg(feature = "examples")]
+    #[test]
+    fn evaluation_of_dull_converges_to_zero() {
+        use crate::env::examples::{Always, DoNothing, Dull};
+
+        let policy = DeterministicPolicy::new(HashMap::from([(Always::Same, DoNothing::Nothing)]));
+        let v = evaluate_policy(&Dull, &policy, Some(0.9), 1e-6, 1000).unwrap();
+        assert_eq!(v, HashMap::from([(Always::Same, 0.0)]));
+    }
+
+    #[cfg(feature = "examples")]
+    #[test]
+    fn dull_stays_put_for_nothing() {
+        use crate::env::examples::{Always, DoNothing, Dull};
+
+        let policy = DeterministicPolicy::new(HashMap::from([(Always::Same, DoNothing::Nothing)]));
+        let (matrix, reward, states) = policy_transition_matrix(&Dull, &policy).unwrap();
+        assert_eq!(matrix, [[1.0]]);
+        assert_eq!(reward, [0.0]);
+        assert_eq!(states, [Always::Same]);
+    }
+
+    #[test]
+    fn transition_matrix_rows_follow_the_policy() {
+        let (matrix, reward, states) =
+            policy_transition_matrix(&Chain, &Chain::wandering()).unwrap();
+        let at = |s: i32| states.iter().position(|t| *t == s).unwrap();
+        assert_eq!(matrix[at(Chain::GOAL)], [0.0; 4]);
+        assert_eq!(reward[at(Chain::GOAL)], 0.0);
+        // Half the time right, otherwise a coin flip between the two.
+        assert_eq!(matrix[at(1)][at(2)], 0.75);
+        assert_eq!(matrix[at(1)][at(0)], 0.25);
+        assert_eq!(reward[at(2)], 0.75);
+        for s in 0..Chain::GOAL {
+            assert_eq!(matrix[at(s)].iter().sum::<f32>(), 1.0);
+        }
+    }
+
+    #[cfg(feature = "examples")]
+    #[test]
+    fn exact_evaluation_matches_sweeps() {
+        use crate::env::examples::GridWorld;
+        use crate::policy::UniformRandomPolicy;
+
+        let grid = GridWorld::new(3, 3, [(1, 1)], (2, 2), 0.1, 0.2);
+        for gamma in [0.9, 1.0] {
+            let exact = evaluate_policy_exact(&grid, &UniformRandomPolicy, Some(gamma)).unwrap();
+            let swept =
+                evaluate_policy(&grid, &UniformRandomPolicy, Some(gamma), 1e-7, 100_000).unwrap();
+            assert_eq!(exact.len(), swept.len());
+            for (s, v) in swept {
+                assert!(
+                    (exact[&s] - v).abs() < 1e-4,
+                    "{:?}: {} vs {}",
+                    s,
+                    exact[&s],
+                    v
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn exact_evaluation_fails_on_endless_undiscounted_returns() {
+        let forever = DeterministicPolicy::new(HashMap::from([((), ())]));
+        assert_eq!(evaluate_policy_exact(&Loop, &forever, Some(1.0)), None);
+        let v = evaluate_policy_exact(&Loop, &forever, None).unwrap();
+        assert!((v[&()] - 10.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn successor_representation_recovers_the_values() {
+        let policy = Chain::wandering();
+        let m = successor_representation(&Chain, &policy, Some(0.9)).unwrap();
+        let (_, reward, states) = policy_transition_matrix(&Chain, &policy).unwrap();
+        let v = evaluate_policy(&Chain, &policy, Some(0.9), 1e-7, 1000).unwrap();
+        for from in &states {
+            let value: f32 = states
+                .iter()
+                .zip(&reward)
+                .map(|(to, r)| m[&(*from, *to)] * r)
+                .sum();
+            assert!((value - v[from]).abs() < 1e-4, "{}: {}", from, value);
+        }
+        assert_eq!(m[&(Chain::GOAL, Chain::GOAL)], 1.0);
+        assert_eq!(m[&(Chain::GOAL, 0)], 0.0);
+
+        // Staying put forever is a visit a step, discounted.
+        let forever = DeterministicPolicy::new(HashMap::from([((), ())]));
+        let m = successor_representation(&Loop, &forever, None).unwrap();
+        assert!((m[&((), ())] - 10.0).abs() < 1e-5);
+        assert_eq!(successor_representation(&Loop, &forever, Some(1.0)), None);
+    }
+
+    #[test]
+    fn evaluation_discounts_the_goal_reward() {
+        let v = evaluate_policy(&Chain, &Chain::rightward(), Some(0.9), 1e-6, 1000).unwrap();
+        assert!((v[&0] - 0.81).abs() < 1e-4);
+        assert!((v[&1] - 0.9).abs() < 1e-4);
+        assert!((v[&2] - 1.0).abs() < 1e-4);
+        assert_eq!(v[&Chain::GOAL], 0.0);
+    }
+
+    #[test]
+    fn evaluation_defaults_to_the_environment_gamma() {
+        // Chain is episodic and undiscounted, Loop continuing with a
+        // discount of 0.9.
+        let v = evaluate_policy(&Chain, &Chain::rightward(), None, 1e-6, 1000).unwrap();
+        assert!((v[&0] - 1.0).abs() < 1e-4);
+        let forever = DeterministicPolicy::new(HashMap::from([((), ())]));
+        let v = evaluate_policy(&Loop, &forever, None, 1e-6, 1000).unwrap();
+        assert!((v[&()] - 10.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn value_iteration_finds_the_optimal_chain_policy() {
+        let (v, policy) = value_iteration(&Chain, Some(0.9), 1e-6, 1000).unwrap();
+        for s in 0..Chain::GOAL {
+            assert_eq!(policy.action(&s), Some(&1));
+        }
+        assert_eq!(policy.action(&Chain::GOAL), None);
+        assert!((v[&0] - 0.81).abs() < 1e-4);
+        assert!((v[&2] - 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn backups_pin_terminal_states_to_zero() {
+        // Moving right from the goal loops back to it with a reward
+        // of 1, which a backup recursing into it would pick up.
+        assert_eq!(Chain.prob(&Chain::GOAL, &1, &Chain::GOAL, &1.0), 1.0);
+        let (v, _) = value_iteration(&Chain, Some(0.9), 1e-6, 1000).unwrap();
+        assert_eq!(v[&Chain::GOAL], 0.0);
+        assert!((v[&2] - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn policies_are_compared_state_by_state() {
+        let rightward = Chain::rightward();
+        let same = policy_value_gap(&Chain, &rightward, &rightward, Some(0.9), 1e-6, 1000).unwrap();
+        assert_eq!(same.len(), 4);
+        assert!(same.values().all(|g| *g == 0.0));
+        assert_eq!(value_gap_summary::<Chain>(&same).unwrap().max, 0.0);
+
+        let wandering = Chain::wandering();
+        let gap = policy_value_gap(&Chain, &wandering, &rightward, Some(0.9), 1e-6, 1000).unwrap();
+        assert_eq!(gap[&Chain::GOAL], 0.0);
+        assert!((0..Chain::GOAL).all(|s| gap[&s] < 0.0));
+        let summary = value_gap_summary::<Chain>(&gap).unwrap();
+        let largest = gap.values().map(|g| g.abs()).fold(0.0, f32::max);
+        assert_eq!(summary.max, largest);
+        assert_eq!(summary.min, 0.0);
+    }
+
+    /// A grid with a cell that either ends episodes or discounts its
+    /// value to nothing.
+    #[cfg(feature = "examples")]
+    struct Cut {
+        grid: crate::env::examples::GridWorld,
+        terminal: bool,
+    }
+
+    #[cfg(feature = "examples")]
+    impl Cut {
+        const CELL: (usize, usize) = (1, 1);
+    }
+
+    #[cfg(feature = "examples")]
+    impl IEnvironment for Cut {
+        type State = (usize, usize);
+        type Action = crate::env::examples::Move;
+
+        fn prob(
+            &self,
+            from: &Self::State,
+            take: &Self::Action,
+            to: &Self::State,
+            with: &f32,
+        ) -> f32 {
+            self.grid.prob(from, take, to, with)
+        }
+
+        fn actions_from(&self, from: &Self::State) -> HashSet<Self::Action> {
+            self.grid.actions_from(from)
+        }
+
+        fn states_from(&self, from: &Self::State, take: &Self::Action) -> HashSet<Self::State> {
+            self.grid.states_from(from, take)
+        }
+
+        fn is_terminal(&self, state: &Self::State) -> bool {
+            self.grid.is_terminal(state) || (self.terminal && *state == Cut::CELL)
+        }
+
+        fn gamma(&self) -> f32 {
+            0.9
+        }
+
+        fn gamma_at(&self, state: &Self::State) -> f32 {
+            if !self.terminal && *state == Cut::CELL {
+                0.0
+            } else {
+                self.gamma()
+            }
+        }
+
+        fn start_states(&self) -> HashSet<Self::State> {
+            self.grid.start_states()
+        }
+
+        fn all_states(&self) -> HashSet<Self::State> {
+            self.grid.all_states()
+        }
+
+        fn rewards(&self) -> Vec<f32> {
+            self.grid.rewards()
+        }
+    }
+
+    #[cfg(feature = "examples")]
+    #[test]
+    fn a_zero_discount_acts_as_a_terminal_state() {
+        use crate::env::examples::GridWorld;
+
+        let grid = GridWorld::new(4, 4, [], (3, 3), 0.1, 0.0);
+        let terminal = Cut {
+            grid: grid.clone(),
+            terminal: true,
+        };
+        let discounted = Cut {
+            grid: grid.clone(),
+            terminal: false,
+        };
+        let (expected, by_terminal) = value_iteration(&terminal, None, 1e-6, 1000).unwrap();
+        let (v, by_discount) = value_iteration(&discounted, None, 1e-6, 1000).unwrap();
+        for s in grid.all_states() {
+            if s == Cut::CELL {
+                // Still a state with its own value, but one that no
+                // other state sees.
+                assert!(v[&s] > expected[&s]);
+                continue;
+            }
+            assert_eq!(v[&s], expected[&s], "{:?}", s);
+            assert_eq!(by_discount.action(&s), by_terminal.action(&s));
+        }
+        // Exact evaluation discounts the same way, and an explicit
+        // discount replaces only the other states'.
+        let exact = evaluate_policy_exact(&discounted, &by_discount, None).unwrap();
+        assert!((exact[&(0, 0)] - expected[&(0, 0)]).abs() < 1e-5);
+        let (v, _) = value_iteration(&discounted, Some(0.5), 1e-6, 1000).unwrap();
+        let (expected, _) = value_iteration(&terminal, Some(0.5), 1e-6, 1000).unwrap();
+        assert_eq!(v[&(0, 1)], expected[&(0, 1)]);
+    }
+
+    #[cfg(feature = "examples")]
+    #[test]
+    fn hitting_times_are_the_distances_along_a_corridor() {
+        use crate::env::examples::{GridWorld, Move};
+
+        let corridor = GridWorld::new(5, 1, [], (4, 0), 0.04, 0.0).with_start((0, 0));
+        let (_, optimal) = value_iteration(&corridor, Some(0.9), 1e-6, 1000).unwrap();
+        let times = expected_hitting_time(&corridor, &optimal).unwrap();
+        assert_eq!(times.len(), 5);
+        for x in 0..5 {
+            assert!((times[&(x, 0)] - (4 - x) as f32).abs() < 1e-5, "{}", x);
+        }
+
+        // Walking into the wall never gets anywhere, except from the
+        // goal itself.
+        let left = DeterministicPolicy::new((0..4).map(|x| ((x, 0), Move::Left)).collect());
+        let times = expected_hitting_time(&corridor, &left).unwrap();
+        assert_eq!(times[&(4, 0)], 0.0);
+        assert!((0..4).all(|x| times[&(x, 0)] == f32::INFINITY));
+    }
+
+    #[test]
+    fn hitting_times_are_infinite_from_states_that_may_get_stuck() {
+        use crate::policy::UniformRandomPolicy;
+
+        // Wandering reaches the goal surely, with the expected time of
+        // a random walk biased to the right.
+        let times = expected_hitting_time(&Chain, &Chain::wandering()).unwrap();
+        assert_eq!(times[&Chain::GOAL], 0.0);
+        assert!(times[&0] > times[&1] && times[&1] > times[&2] && times[&2] > 1.0);
+        // The trap has no actions, and is reachable from everywhere.
+        let times = expected_hitting_time(&Trap, &UniformRandomPolicy).unwrap();
+        assert!(times.values().all(|t| *t == f32::INFINITY));
+    }
+
+    #[test]
+    fn hitting_times_count_leaving_the_states_as_ending() {
+        /// `Chain` with its goal left out of `all_states`, so that
+        /// moving right from 2 leaves the enumerated states entirely.
+        struct Exit;
+
+        impl IEnvironment for Exit {
+            type State = i32;
+            type Action = i32;
+
+            fn prob(&self, from: &i32, take: &i32, to: &i32, with: &f32) -> f32 {
+                Chain.prob(from, take, to, with)
+            }
+
+            fn actions_from(&self, from: &i32) -> HashSet<i32> {
+                Chain.actions_from(from)
+            }
+
+            fn states_from(&self, from: &i32, take: &i32) -> HashSet<i32> {
+                Chain.states_from(from, take)
+            }
+
+            fn start_states(&self) -> HashSet<i32> {
+                Chain.start_states()
+            }
+
+            fn all_states(&self) -> HashSet<i32> {
+                HashSet::from([0, 1, 2])
+            }
+
+            fn rewards(&self) -> Vec<f32> {
+                Chain.rewards()
+            }
+        }
+
+        let policy = DeterministicPolicy::new((0..3).map(|s| (s, 1)).collect());
+        let times = expected_hitting_time(&Exit, &policy).unwrap();
+        assert_eq!(times.len(), 3);
+        for s in 0..3 {
+            assert!((times[&s] - (3 - s) as f32).abs() < 1e-5, "{}", s);
+        }
+    }
+
+    #[test]
+    fn stopping_early_stays_within_the_suboptimality_bound() {
+        let (optimal, _) = value_iteration(&Chain, Some(0.9), 1e-6, 1000).unwrap();
+        let mut suboptimal = false;
+        for sweeps in [1, 2, 3, 10] {
+            let (_, policy, report) =
+                value_iteration_with_report(&Chain, Some(0.9), 0.0, sweeps, 0).unwrap();
+            assert_eq!(
+                report.suboptimality_bound,
+                value_suboptimality_bound(report.final_delta, 0.9)
+            );
+            let v = evaluate_policy_exact(&Chain, &policy, Some(0.9)).unwrap();
+            let gap = optimal.iter().map(|(s, o)| o - v[s]).fold(0.0, f32::max);
+            assert!(
+                gap <= report.suboptimality_bound + 1e-5,
+                "{}: {}",
+                sweeps,
+                gap
+            );
+            suboptimal |= gap > 0.0;
+        }
+        // One sweep only reaches the state next to the goal, leaving
+        // the others to their first action.
+        assert!(suboptimal);
+        assert_eq!(value_suboptimality_bound(0.1, 1.0), f32::INFINITY);
+        let (_, _, report) = value_iteration_with_report(&Chain, None, 1e-6, 1000, 0).unwrap();
+        assert_eq!(report.suboptimality_bound, f32::INFINITY);
+    }
+
+    #[test]
+    fn timed_value_iteration_returns_a_policy_whenever_time_runs_out() {
+        let started = Instant::now();
+        let (v, policy, report) =
+            value_iteration_timed(&Chain, Duration::ZERO, Some(0.9), 1e-6).unwrap();
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert_eq!(report.iterations, 0);
+        assert!(!report.converged);
+        assert_eq!(report.status, ConvergenceStatus::OutOfTime);
+        assert!(v.values().all(|value| *value == 0.0));
+        // Greedy with respect to nothing, but an action everywhere.
+        for s in 0..Chain::GOAL {
+            assert!(policy.action(&s).is_some());
+        }
+
+        let (v, policy, report) =
+            value_iteration_timed(&Chain, Duration::from_secs(60), Some(0.9), 1e-6).unwrap();
+        let (expected, optimal, full) =
+            value_iteration_with_report(&Chain, Some(0.9), 1e-6, 1000, 0).unwrap();
+        assert!(report.converged);
+        assert_eq!(report, full);
+        assert_eq!(v, expected);
+        assert_eq!(policy.into_actions(), optimal.into_actions());
+    }
+
+    #[test]
+    fn stepping_the_solver_matches_value_iteration() {
+        let (expected, _, report) =
+            value_iteration_with_report(&Loop, None, 1e-6, 1000, 1000).unwrap();
+        let sweeps: Vec<_> = ValueIterationSolver::new(&Loop, None, 1e-6, 1000).collect();
+        assert_eq!(sweeps.len(), report.iterations);
+        let deltas: Vec<f32> = sweeps.iter().map(|(_, delta, _)| *delta).collect();
+        assert_eq!(deltas, report.deltas);
+        assert_eq!(sweeps[0].0, 1);
+        let (last, _, values) = sweeps.last().unwrap();
+        assert_eq!(*last, report.iterations);
+        assert_eq!(*values, expected);
+
+        let mut solver = ValueIterationSolver::new(&Chain, Some(0.9), 1e-6, 1000);
+        assert_eq!(solver.by_ref().take(2).count(), 2);
+        assert!(!solver.is_converged());
+        while solver.step().is_some() {}
+        assert!(solver.is_converged());
+        assert_eq!(solver.next(), None);
+        let (expected, greedy) = value_iteration(&Chain, Some(0.9), 1e-6, 1000).unwrap();
+        assert_eq!(
+            solver.policy().unwrap().into_actions(),
+            greedy.into_actions()
+        );
+        assert_eq!(solver.into_values(), expected);
+    }
+
+    #[test]
+    fn warm_starting_from_the_optimum_takes_one_sweep() {
+        let (cold, policy, report) =
+            value_iteration_with_report(&Chain, Some(0.9), 1e-6, 1000, 0).unwrap();
+        assert!(report.iterations > 1);
+        let (warm, again, report) =
+            value_iteration_from(&Chain, cold.clone(), Some(0.9), 1e-6, 1000).unwrap();
+        assert_eq!((report.iterations, report.final_delta), (1, 0.0));
+        assert_eq!(warm, cold);
+        assert_eq!(again.into_actions(), policy.into_actions());
+
+        // Missing states start from 0, and a terminal state's value is
+        // ignored.
+        let partial = HashMap::from([(0, cold[&0]), (Chain::GOAL, 5.0)]);
+        let (v, _, report) = value_iteration_from(&Chain, partial, Some(0.9), 1e-6, 1000).unwrap();
+        assert!(report.converged && report.iterations > 1);
+        assert_eq!(v, cold);
+    }
+
+    #[cfg(feature = "examples")]
+    #[test]
+    fn symmetric_grid_splits_the_start_between_both_ways() {
+        use crate::env::examples::{GridWorld, Move};
+
+        let grid = GridWorld::new(3, 3, [], (2, 2), 0.1, 0.0).with_start((0, 0));
+        let (v, policy) = value_iteration_with_ties(&grid, Some(0.9), 1e-6, 1000, 1e-5).unwrap();
+        let (greedy, _) = value_iteration(&grid, Some(0.9), 1e-6, 1000).unwrap();
+        assert_eq!(v, greedy);
+        assert_eq!(
+            policy.distribution(&(0, 0)),
+            Some(&HashMap::from([(Move::Down, 0.5), (Move::Right, 0.5)]))
+        );
+        assert_eq!(
+            policy.distribution(&(2, 1)),
+            Some(&HashMap::from([(Move::Down, 1.0)]))
+        );
+        assert_eq!(policy.distribution(&(2, 2)), None);
+
+        // A wide enough tolerance also admits the actions that waste a
+        // step against the wall, a step cost and a tenth of the start's
+        // value, 0.146, worse.
+        let loose = greedy_stochastic_policy(&grid, &v, Some(0.9), 0.2).unwrap();
+        assert_eq!(loose.distribution(&(0, 0)).unwrap().len(), 4);
+        let splits = policy.action_distribution(&grid, &(0, 0));
+        assert_eq!(splits.values().sum::<f32>(), 1.0);
+    }
+
+    #[test]
+    fn states_without_actions_are_worth_zero() {
+        let (v, policy) = value_iteration(&Trap, Some(0.9), 1e-6, 1000).unwrap();
+        assert_eq!(v[&Chain::GOAL], 0.0);
+        assert!((v[&0] - 0.81).abs() < 1e-4);
+        assert_eq!(policy.action(&Chain::GOAL), None);
+        let (_, improved, _) = policy_iteration(&Trap, Some(0.9), 1e-6, 1000).unwrap();
+        assert_eq!(improved.into_actions(), policy.into_actions());
+
+        let q = q_from_v(&Trap, &v, Some(0.9)).unwrap();
+        assert_eq!(
+            greedy_action(&Trap, &q, &Chain::GOAL, &TieBreak::First),
+            None
+        );
+        assert_eq!(q.greedy_action(&Trap, &Chain::GOAL), None);
+        assert_eq!(q.max_action_value(&Trap, &Chain::GOAL), 0.0);
+    }
+
+    #[test]
+    fn greedy_actions_from_q_match_value_iteration() {
+        let (v, policy) = value_iteration(&Chain, Some(0.9), 1e-6, 1000).unwrap();
+        let q = q_from_v(&Chain, &v, Some(0.9)).unwrap();
+        assert!((q.get(&0, &1) - 0.81).abs() < 1e-4);
+        assert!((q.get(&0, &-1) - 0.729).abs() < 1e-4);
+        assert!(!q.contains(&Chain::GOAL, &1));
+        for s in 0..Chain::GOAL {
+            let greedy = greedy_action(&Chain, &q, &s, &TieBreak::First);
+            assert_eq!(greedy.as_ref(), policy.action(&s));
+        }
+    }
+
+    #[test]
+    fn policy_iteration_agrees_with_value_iteration() {
+        let (v, policy, report) = policy_iteration(&Chain, Some(0.9), 1e-6, 1000).unwrap();
+        let (_, optimal) = value_iteration(&Chain, Some(0.9), 1e-6, 1000).unwrap();
+        assert_eq!(policy.into_actions(), optimal.into_actions());
+        assert!((v[&0] - 0.81).abs() < 1e-4);
+        assert!(report.converged);
+        // From a policy moving left everywhere, each round turns right
+        // one more state back from the goal, and a last one finds the
+        // policy stable.
+        assert!(report.iterations >= 1);
+        assert!(report.iterations <= Chain::GOAL as usize + 1);
+    }
+
+    #[cfg(feature = "examples")]
+    #[test]
+    fn policy_iteration_returns_when_a_policy_never_terminates() {
+        use crate::env::examples::GridWorld;
+
+        // The first policy moves down everywhere, so along the bottom
+        // row it bumps into the edge forever at a cost of 0.1 a step.
+        let env = GridWorld::new(3, 3, [], (2, 2), 0.1, 0.0);
+        let (v, policy, report) = policy_iteration(&env, None, 1e-6, 1000).unwrap();
+        let (v_star, optimal) = value_iteration(&env, None, 1e-6, 1000).unwrap();
+        assert!(report.converged);
+        assert!((v[&(0, 0)] - 0.7).abs() < 1e-4);
+        assert!((v_star[&(0, 0)] - 0.7).abs() < 1e-4);
+        assert!(values_approx_eq(&v, &v_star, 1e-4, Missing::Mismatch));
+        for s in env.all_states() {
+            if let Some(a) = policy.action(&s) {
+                let q = Direct::new(&env)
+                    .lookahead(&env, &s, a, &v_star, 1.0)
+                    .unwrap();
+                let best = optimal.action(&s).unwrap();
+                let q_best = Direct::new(&env)
+                    .lookahead(&env, &s, best, &v_star, 1.0)
+                    .unwrap();
+                assert!((q - q_best).abs() < 1e-4);
+            }
+        }
+    }
+
+    #[cfg(feature = "examples")]
+    #[test]
+    fn evaluation_stops_after_max_iters() {
+        use crate::env::examples::{GridWorld, Move};
+
+        let env = GridWorld::new(3, 3, [], (2, 2), 0.1, 0.0);
+        let down = DeterministicPolicy::new(
+            env.all_states()
+                .into_iter()
+                .map(|s| (s, Move::Down))
+                .collect(),
+        );
+        let v = evaluate_policy(&env, &down, None, 1e-6, 50).unwrap();
+        assert!((v[&(0, 2)] + 5.0).abs() < 1e-4);
+        let (_, _, report) = policy_iteration(&env, None, 1e-6, 1).unwrap();
+        assert_eq!(report.iterations, 1);
+        assert!(!report.converged);
+    }
+
+    #[test]
+    fn value_iteration_reports_running_out_of_sweeps() {
+        // Loop's value climbs towards 10 by 0.9^k a sweep, so it is
+        // far from converged after three.
+        let (v, _, report) = value_iteration_with_report(&Loop, None, 1e-6, 3, 2).unwrap();
+        assert!(!report.converged);
+        assert_eq!(report.iterations, 3);
+        assert!((v[&()] - 2.71).abs() < 1e-5);
+        assert!((report.final_delta - 0.81).abs() < 1e-5);
+        assert_eq!(report.deltas.len(), 2);
+        assert!((report.deltas[1] - 0.9).abs() < 1e-6);
+
+        let (_, _, report) = value_iteration_with_report(&Chain, None, 1e-6, 100, 100).unwrap();
+        assert!(report.converged);
+        assert!(report.final_delta < 1e-6);
+        assert_eq!(report.deltas.len(), report.iterations);
+    }
+
+    #[test]
+    fn gauss_seidel_sweeps_reach_the_same_values_sooner() {
+        let (v, policy, jacobi) =
+            value_iteration_with_mode(&Chain, SweepMode::Jacobi, Some(0.9), 1e-6, 100).unwrap();
+        // Each Jacobi sweep carries the goal's reward one state further.
+        assert_eq!(jacobi.iterations, 4);
+
+        let (gs_v, gs_policy, gs) =
+            value_iteration_in_order(&Chain, &[3, 2, 1, 0], Some(0.9), 1e-6, 100).unwrap();
+        // Backwards from the goal, one sweep gets there and one checks.
+        assert_eq!(gs.iterations, 2);
+        assert!(gs.converged);
+        assert_eq!(gs_v, v);
+        assert_eq!(gs_policy.into_actions(), policy.into_actions());
+
+        let (forward, _, report) =
+            value_iteration_in_order(&Chain, &[0, 1, 2, 3], Some(0.9), 1e-6, 100).unwrap();
+        assert_eq!(report.iterations, 4);
+        assert_eq!(forward, v);
+    }
+
+    /// A corridor of states 0 to 5 along which action 1 only advances
+    /// with probability 0.1, staying put otherwise, and action 0
+    /// always stays put. Entering the terminal state 5 gives 1.
+    struct Sticky;
+
+    impl IEnvironment for Sticky {
+        type State = i32;
+        type Action = i32;
+
+        fn prob(&self, from: &i32, take: &i32, to: &i32, with: &f32) -> f32 {
+            let reward = if *to == 5 { 1.0 } else { 0.0 };
+            if *with != reward {
+                0.0
+            } else if *take == 0 {
+                f32::from(to == from)
+            } else if *to == from + 1 {
+                0.1
+            } else {
+                0.9 * f32::from(to == from)
+            }
+        }
+
+        fn actions_from(&self, _: &i32) -> HashSet<i32> {
+            HashSet::from([0, 1])
+        }
+
+        fn states_from(&self, from: &i32, _: &i32) -> HashSet<i32> {
+            HashSet::from([*from, from + 1])
+        }
+
+        fn is_terminal(&self, state: &i32) -> bool {
+            *state == 5
+        }
+
+        fn start_states(&self) -> HashSet<i32> {
+            HashSet::from([0])
+        }
+
+        fn rewards(&self) -> Vec<f32> {
+            vec![0.0, 1.0]
+        }
+    }
+
+    #[test]
+    fn over_relaxation_converges_in_fewer_sweeps_to_the_same_values() {
+        let (v, policy, plain) =
+            value_iteration_with_mode(&Sticky, SweepMode::GaussSeidel, Some(0.999), 1e-6, 10_000)
+                .unwrap();
+        let (unrelaxed, _, report) =
+            value_iteration_sor(&Sticky, 1.0, Some(0.999), 1e-6, 10_000).unwrap();
+        assert_eq!(unrelaxed, v);
+        assert_eq!(report, plain);
+
+        // Staying put with probability 0.9 each step.
+        let omega = 1.0 / (1.0 - 0.999 * 0.9);
+        let (relaxed, relaxed_policy, report) =
+            value_iteration_sor(&Sticky, omega, Some(0.999), 1e-6, 10_000).unwrap();
+        assert!(report.converged && plain.converged);
+        assert!(
+            report.iterations * 10 < plain.iterations,
+            "{} vs {}",
+            report.iterations,
+            plain.iterations
+        );
+        assert!(values_approx_eq(&relaxed, &v, 1e-4, Missing::Mismatch));
+        assert!(policies_equal(
+            &relaxed_policy,
+            &policy,
+            &Sticky.all_states()
+        ));
+    }
+
+    #[test]
+    fn under_relaxation_is_measured_by_the_unrelaxed_change() {
+        let (v, _) = value_iteration(&Sticky, Some(0.9), 1e-7, 10_000).unwrap();
+        // Short steps change the values little each sweep, though
+        // they're still far from converged.
+        let (relaxed, _, report) =
+            value_iteration_sor(&Sticky, 0.1, Some(0.9), 1e-2, 10_000).unwrap();
+        assert!(report.converged);
+        for s in 0..5 {
+            let error = (relaxed[&s] - v[&s]).abs();
+            assert!(error <= report.suboptimality_bound, "{s}: {error}");
+        }
+    }
+
+    #[cfg(feature = "examples")]
+    #[test]
+    fn gauss_seidel_converges_in_fewer_sweeps_on_a_maze() {
+        use crate::testing::maze;
+
+        let maze = maze();
+        let (v, _, jacobi) =
+            value_iteration_with_mode(&maze, SweepMode::Jacobi, Some(0.95), 1e-6, 1000).unwrap();
+        let (gs_v, _, gs) =
+            value_iteration_with_mode(&maze, SweepMode::GaussSeidel, Some(0.95), 1e-6, 1000)
+                .unwrap();
+        assert!(jacobi.converged && gs.converged);
+        assert!(
+            gs.iterations <= jacobi.iterations,
+            "{} vs {}",
+            gs.iterations,
+            jacobi.iterations
+        );
+        assert!(values_approx_eq(&v, &gs_v, 1e-4, Missing::Mismatch));
+    }
+
+    #[cfg(feature = "examples")]
+    #[test]
+    fn eliminated_actions_are_never_optimal() {
+        use crate::env::examples::GridWorld;
+
+        // Slipping makes the values converge only geometrically, giving
+        // the bounds sweeps in which to tighten.
+        let grid = GridWorld::new(4, 4, [(1, 1), (2, 2)], (3, 3), 0.05, 0.2);
+        let (v, policy, report) =
+            value_iteration_with_elimination(&grid, Some(0.9), 1e-6, 1000).unwrap();
+        assert!(report.convergence.converged);
+        assert_eq!(report.eliminated.len(), report.convergence.iterations);
+        assert!(report.eliminated.windows(2).all(|w| w[0] <= w[1]));
+        assert_eq!(report.eliminated[0], 0);
+        // Most moves are provably worse than the best one.
+        let pairs = 4 * (grid.all_states().len() - 1);
+        assert!(*report.eliminated.last().unwrap() > pairs / 2);
+
+        let (v_star, _) = value_iteration(&grid, Some(0.9), 1e-6, 1000).unwrap();
+        let q_star = q_from_v(&grid, &v_star, Some(0.9)).unwrap();
+        assert!(values_approx_eq(&v, &v_star, 1e-5, Missing::Mismatch));
+        for s in grid
+            .all_states()
+            .into_iter()
+            .filter(|s| !grid.is_terminal(s))
+        {
+            let a = policy.action(&s).unwrap();
+            assert!((q_star.get(&s, a) - v_star[&s]).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn nothing_is_eliminated_undiscounted() {
+        let (v, _, report) =
+            value_iteration_with_elimination(&Chain, Some(1.0), 1e-6, 100).unwrap();
+        assert!(report.eliminated.iter().all(|n| *n == 0));
+        assert_eq!(v[&0], 1.0);
+    }
+
+    #[test]
+    fn oscillating_sweeps_stop_early() {
+        // Undiscounted, Loop's value grows by 1 every sweep forever.
+        let (_, _, report) = value_iteration_with_report(&Loop, Some(1.0), 1e-6, 100, 0).unwrap();
+        assert_eq!(report.status, ConvergenceStatus::MaxIters);
+        assert_eq!(report.iterations, 100);
+
+        let (v, _, report) =
+            value_iteration_with_oscillation_check(&Loop, Some(1.0), 1e-6, 100, 10, 5).unwrap();
+        assert_eq!(report.status, ConvergenceStatus::Oscillating);
+        assert!(!report.converged);
+        assert_eq!(report.iterations, 6);
+        assert_eq!(report.deltas, [1.0; 6]);
+        assert_eq!(v[&()], 6.0);
+
+        // Converging sweeps shrink every time, and are left to finish.
+        let (_, _, report) =
+            value_iteration_with_oscillation_check(&Loop, None, 1e-4, 1000, 0, 5).unwrap();
+        assert_eq!(report.status, ConvergenceStatus::Converged);
+        assert!(report.converged);
+    }
+
+    #[test]
+    fn finite_horizons_induct_backwards() {
+        // One step to go: the best immediate reward, so only the state
+        // next to the goal is worth anything.
+        let (values, policies) = finite_horizon(&Chain, 1, Some(1.0)).unwrap();
+        assert_eq!(values.len(), 2);
+        assert_eq!(policies.len(), 1);
+        assert_eq!(
+            values[0],
+            HashMap::from([(0, 0.0), (1, 0.0), (2, 1.0), (3, 0.0)])
+        );
+        assert_eq!(values[1][&2], 0.0);
+        assert_eq!(policies[0].action(&2), Some(&1));
+
+        // With three steps to go from the start, the goal is just in
+        // reach, and with fewer steps later on the values fall.
+        let (values, policies) = finite_horizon(&Chain, 3, Some(1.0)).unwrap();
+        assert_eq!(values[0][&0], 1.0);
+        assert_eq!(values[1][&0], 0.0);
+        assert_eq!(values[1][&1], 1.0);
+        assert_eq!(policies[0].action(&0), Some(&1));
+
+        // Looping pays 1 a step, so longer horizons are worth more.
+        let mut last = -1.0;
+        for horizon in 0..10 {
+            let (values, _) = finite_horizon(&Loop, horizon, None).unwrap();
+            let expected = (1.0 - 0.9f32.powi(horizon as i32)) / (1.0 - 0.9);
+            assert!((values[0][&()] - expected).abs() < 1e-5);
+            assert!(values[0][&()] > last);
+            last = values[0][&()];
+        }
+    }
+
+    #[test]
+    fn expected_q_weighs_the_actions_by_the_policy() {
+        use crate::policy::UniformRandomPolicy;
+
+        let q: QTable<Chain> = [((1, -1), 2.0), ((1, 1), 4.0)].into_iter().collect();
+        let greedy = q.to_policy(&Chain);
+        assert_eq!(expected_q(&Chain, &q, &greedy, &1), 4.0);
+        assert_eq!(expected_q(&Chain, &q, &UniformRandomPolicy, &1), 3.0);
+        assert_eq!(
+            expected_q(&Chain, &q, &UniformRandomPolicy, &Chain::GOAL),
+            0.0
+        );
+    }
+
+    #[cfg(all(feature = "examples", feature = "parallel"))]
+    #[test]
+    fn parallel_sweeps_agree_with_serial_ones() {
+        use crate::env::examples::GridWorld;
+
+        let theta = 1e-5;
+        let env = GridWorld::new(20, 20, [(3, 3), (4, 3), (10, 12)], (19, 19), 0.01, 0.2);
+        let (v, policy) = value_iteration(&env, Some(0.9), theta, 10_000).unwrap();
+        let (par_v, par_policy) = par_value_iteration(&env, Some(0.9), theta, 10_000).unwrap();
+        // Summing in a different order can break near-ties between
+        // actions differently, so compare the actions by their values.
+        let dynamics = Direct::new(&env);
+        assert!(values_approx_eq(&v, &par_v, theta, Missing::Mismatch));
+        for s in env.all_states() {
+            if let Some(a) = policy.action(&s) {
+                let value = |a| dynamics.lookahead(&env, &s, a, &v, 0.9).unwrap();
+                assert!((value(a) - value(par_policy.action(&s).unwrap())).abs() < theta);
+            }
+        }
+    }
+}
@@ -0,0 +1,143 @@
+use std::collections::{HashMap, HashSet};
+
+use super::{discount_at, next_value, Dynamics, ValueFunction};
+use crate::env::{reachable_states, transition_at, IEnvironment};
+
+/// The transitions out of one state-action pair, as `(next_state,
+/// prob, expected_reward)` triples.
+type Transitions<E> = Vec<(<E as IEnvironment>::State, f32, f32)>;
+
+/// The one-step dynamics of every reachable state-action pair,
+/// computed once so that repeated sweeps don't re-evaluate `prob`.
+///
+/// For each non-terminal state in `env.all_states()` and each action in
+/// `actions_from`, the cache stores the `(next_state, prob,
+/// expected_reward)` triples of the transitions with positive
+/// probability, where `expected_reward` is conditional on arriving
+/// in `next_state`. Memory use is one cloned state and two `f32`s per
+/// transition, i.e. proportional to the total number of
+/// `states_from` entries over all state-action pairs.
+pub struct TransitionCache<E: IEnvironment> {
+    states: HashSet<E::State>,
+    transitions: HashMap<E::State, HashMap<E::Action, Transitions<E>>>,
+}
+
+impl<E: IEnvironment> TransitionCache<E> {
+    /// Enumerates the environment's dynamics via `all_states`,
+    /// `actions_from` and `states_from`. Returns `None` if the
+    /// environment's rewards are not enumerable.
+    pub fn new(env: &E) -> Option<Self> {
+        Self::over(env, env.all_states())
+    }
+
+    /// As `new`, but enumerating only the states reachable from
+    /// `starts`, so that `value_iteration_cached` and
+    /// `evaluate_policy_cached` sweep just those states.
+    pub fn reachable_from(env: &E, starts: &HashSet<E::State>) -> Option<Self> {
+        Self::over(env, reachable_states(env, starts))
+    }
+
+    fn over(env: &E, states: HashSet<E::State>) -> Option<Self> {
+        let rewards = env.rewards();
+        let mut transitions = HashMap::with_capacity(states.len());
+        for s in states.iter().filter(|s| !env.is_terminal(s)) {
+            let mut by_action = HashMap::new();
+            for a in env.actions_from(s) {
+                let mut to = Vec::new();
+                for next in env.states_from(s, &a) {
+                    let (p, r) = transition_at(env, s, &a, &next, &rewards)?;
+                    if p > 0.0 {
+                        to.push((next, p, r / p));
+                    }
+                }
+                by_action.insert(a, to);
+            }
+            transitions.insert(s.clone(), by_action);
+        }
+        Some(TransitionCache {
+            states,
+            transitions,
+        })
+    }
+
+    /// Every state the cache was built over: `all_states`, or the
+    /// reachable set for `reachable_from`.
+    pub fn states(&self) -> &HashSet<E::State> {
+        &self.states
+    }
+
+    /// The cached transitions taking action `a` from state `s`, or an
+    /// empty slice if the pair wasn't enumerated.
+    pub fn transitions(&self, s: &E::State, a: &E::Action) -> &[(E::State, f32, f32)] {
+        self.transitions
+            .get(s)
+            .and_then(|by_action| by_action.get(a))
+            .map_or(&[], |to| to.as_slice())
+    }
+}
+
+impl<E: IEnvironment> Dynamics<E> for TransitionCache<E> {
+    fn lookahead(
+        &self,
+        env: &E,
+        from: &E::State,
+        take: &E::Action,
+        v: &ValueFunction<E>,
+        gamma: f32,
+    ) -> Option<f32> {
+        Some(
+            self.transitions(from, take)
+                .iter()
+                .map(|(to, p, r)| p * (r + discount_at(env, gamma, to) * next_value(env, v, to)))
+                .sum(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Chain;
+
+    #[test]
+    fn stores_only_possible_transitions() {
+        let cache = TransitionCache::new(&Chain).unwrap();
+        assert_eq!(cache.transitions(&2, &1), &[(3, 1.0, 1.0)]);
+        assert_eq!(cache.transitions(&0, &-1), &[(0, 1.0, 0.0)]);
+        assert!(cache.transitions(&Chain::GOAL, &1).is_empty());
+    }
+
+    #[cfg(feature = "examples")]
+    #[test]
+    fn cached_and_uncached_sweeps_agree() {
+        use crate::dp::{
+            evaluate_policy, evaluate_policy_cached, value_iteration, value_iteration_cached,
+        };
+        use crate::env::examples::GridWorld;
+        use crate::testing::{values_approx_eq, Missing};
+
+        let grid = GridWorld::new(4, 3, [(1, 1)], (3, 0), 0.04, 0.2);
+        let cache = TransitionCache::new(&grid).unwrap();
+        let (v, policy) = value_iteration(&grid, Some(0.9), 1e-6, 1000).unwrap();
+        let (cached_v, _) = value_iteration_cached(&grid, &cache, Some(0.9), 1e-6, 1000).unwrap();
+        assert!(values_approx_eq(&v, &cached_v, 1e-5, Missing::Mismatch));
+        let v = evaluate_policy(&grid, &policy, Some(0.9), 1e-6, 1000).unwrap();
+        let cached_v =
+            evaluate_policy_cached(&grid, &cache, &policy, Some(0.9), 1e-6, 1000).unwrap();
+        assert!(values_approx_eq(&v, &cached_v, 1e-5, Missing::Mismatch));
+    }
+
+    #[cfg(feature = "examples")]
+    #[test]
+    fn reachable_cache_sweeps_only_reachable_states() {
+        use crate::dp::value_iteration_cached;
+        use crate::env::examples::GridWorld;
+
+        let grid = GridWorld::new(6, 1, [(2, 0)], (5, 0), 0.0, 0.0).with_start((3, 0));
+        let cache = TransitionCache::reachable_from(&grid, &grid.start_states()).unwrap();
+        let (v, _) = value_iteration_cached(&grid, &cache, Some(0.9), 1e-6, 1000).unwrap();
+        assert_eq!(v.len(), 3);
+        assert!((v[&(3, 0)] - 0.9).abs() < 1e-6);
+        assert!(!v.contains_key(&(0, 0)));
+    }
+}
@@ -0,0 +1,288 @@
+use std::collections::HashSet;
+
+use super::{
+    discount_at, evaluate_with, next_value, optimize_with, Direct, Dynamics, ValueFunction,
+};
+use crate::env::{IConstrainedEnvironment, IEnvironment};
+use crate::policy::DeterministicPolicy;
+
+/// Dynamics with each reward `r` replaced by `r - lambda * cost`.
+struct Lagrangian {
+    rewards: Vec<f32>,
+    lambda: f32,
+}
+
+impl<E: IConstrainedEnvironment> Dynamics<E> for Lagrangian {
+    fn lookahead(
+        &self,
+        env: &E,
+        from: &E::State,
+        take: &E::Action,
+        v: &ValueFunction<E>,
+        gamma: f32,
+    ) -> Option<f32> {
+        let mut total = 0.0;
+        for to in env.states_from(from, take) {
+            let next = discount_at(env, gamma, &to) * next_value(env, v, &to);
+            for r in &self.rewards {
+                let p = env.prob(from, take, &to, r);
+                if p > 0.0 {
+                    let penalized = r - self.lambda * env.cost(from, take, &to, r);
+                    total += p * (penalized + next);
+                }
+            }
+        }
+        Some(total)
+    }
+}
+
+/// Dynamics with each reward replaced by its cost.
+struct Costs {
+    rewards: Vec<f32>,
+}
+
+impl<E: IConstrainedEnvironment> Dynamics<E> for Costs {
+    fn lookahead(
+        &self,
+        env: &E,
+        from: &E::State,
+        take: &E::Action,
+        v: &ValueFunction<E>,
+        gamma: f32,
+    ) -> Option<f32> {
+        let mut total = 0.0;
+        for to in env.states_from(from, take) {
+            let next = discount_at(env, gamma, &to) * next_value(env, v, &to);
+            for r in &self.rewards {
+                let p = env.prob(from, take, &to, r);
+                if p > 0.0 {
+                    total += p * (env.cost(from, take, &to, r) + next);
+                }
+            }
+        }
+        Some(total)
+    }
+}
+
+/// Value iteration on the Lagrangian relaxation of a constrained MDP:
+/// the ordinary MDP whose reward for each outcome is `r - lambda *
+/// cost`, so that `lambda` prices cost in units of reward.
+///
+/// Otherwise as `value_iteration`, returning the Lagrangian values and
+/// their greedy policy. Returns `None` if the environment's rewards
+/// are not enumerable, as costs can't then be weighted.
+pub fn lagrangian_value_iteration<E: IConstrainedEnvironment>(
+    env: &E,
+    lambda: f32,
+    gamma: Option<f32>,
+    theta: f32,
+    max_iters: usize,
+) -> Option<(ValueFunction<E>, DeterministicPolicy<E>)> {
+    let gamma = gamma.unwrap_or_else(|| env.gamma());
+    let rewards = env.rewards();
+    if rewards.is_empty() {
+        return None;
+    }
+    let dynamics = Lagrangian { rewards, lambda };
+    let (v, policy, _) = optimize_with(
+        env,
+        &dynamics,
+        &env.all_states(),
+        gamma,
+        theta,
+        max_iters,
+        0,
+        None,
+        None,
+        None,
+    )?;
+    Some((v, policy))
+}
+
+/// A policy found by `solve_cmdp`, with the expected discounted reward
+/// and cost of following it from the start states.
+pub struct CmdpSolution<E: IEnvironment> {
+    pub policy: DeterministicPolicy<E>,
+    /// The price on cost the policy is optimal for.
+    pub lambda: f32,
+    pub reward: f32,
+    pub cost: f32,
+}
+
+impl<E: IEnvironment> CmdpSolution<E> {
+    pub fn is_feasible(&self, cost_limit: f32) -> bool {
+        self.cost <= cost_limit
+    }
+}
+
+/// The mean of `v` over the start states, as episodes start from each
+/// with equal probability.
+fn start_value<E: IEnvironment>(env: &E, v: &ValueFunction<E>) -> f32 {
+    let starts = env.start_states();
+    if starts.is_empty() {
+        return 0.0;
+    }
+    let total: f32 = starts.iter().map(|s| next_value(env, v, s)).sum();
+    total / starts.len() as f32
+}
+
+/// Solves the constrained MDP of maximizing expected discounted reward
+/// subject to expected discounted cost at most `cost_limit`, both from
+/// the start states, by dual ascent on the price `lambda`.
+///
+/// Starting from `lambda` = 0, each of `steps` rounds (at least one)
+/// solves the Lagrangian MDP with `lagrangian_value_iteration`,
+/// evaluates its policy's reward and cost, and moves `lambda` by
+/// `rate` times the constraint violation, `cost - cost_limit`, never
+/// below 0. The result is the policy with the most reward among those
+/// that met the limit, or, if none did, the one with the least cost;
+/// check `is_feasible`.
+///
+/// The optimal policy of a constrained MDP may need to randomize
+/// between actions, which no deterministic policy found here does, so
+/// this can fall short of the optimum, erring on the side of meeting
+/// the limit. `theta` and `max_iters` are passed on to each solve and
+/// evaluation, and `gamma` defaults to `env.gamma()`. Returns `None` if
+/// the environment's rewards are not enumerable.
+#[allow(clippy::too_many_arguments)]
+pub fn solve_cmdp<E: IConstrainedEnvironment>(
+    env: &E,
+    cost_limit: f32,
+    gamma: Option<f32>,
+    theta: f32,
+    max_iters: usize,
+    rate: f32,
+    steps: usize,
+) -> Option<CmdpSolution<E>> {
+    let gamma = gamma.unwrap_or_else(|| env.gamma());
+    let rewards = env.rewards();
+    if rewards.is_empty() {
+        return None;
+    }
+    let states: HashSet<E::State> = env.all_states();
+    let direct = Direct::new(env);
+    let costs = Costs { rewards };
+    let mut lambda = 0.0;
+    let mut best: Option<CmdpSolution<E>> = None;
+    for _ in 0..steps.max(1) {
+        let (_, policy) = lagrangian_value_iteration(env, lambda, Some(gamma), theta, max_iters)?;
+        let (reward, _) = evaluate_with(env, &direct, &states, &policy, gamma, theta, max_iters)?;
+        let (cost, _) = evaluate_with(env, &costs, &states, &policy, gamma, theta, max_iters)?;
+        let candidate = CmdpSolution {
+            policy,
+            lambda,
+            reward: start_value(env, &reward),
+            cost: start_value(env, &cost),
+        };
+        lambda = (lambda + rate * (candidate.cost - cost_limit)).max(0.0);
+        let better = match &best {
+            None => true,
+            Some(best) => match (
+                candidate.is_feasible(cost_limit),
+                best.is_feasible(cost_limit),
+            ) {
+                (true, true) => candidate.reward > best.reward,
+                (false, false) => candidate.cost < best.cost,
+                (feasible, _) => feasible,
+            },
+        };
+        if better {
+            best = Some(candidate);
+        }
+    }
+    best
+}
+
+#[cfg(all(test, feature = "examples"))]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::env::examples::{GridWorld, Move};
+
+    type Cell = (usize, usize);
+
+    /// A 5x2 grid whose shortest path, along the top row to the goal in
+    /// its right corner, crosses three cells of lava, each costing 1 to
+    /// enter; the way around the bottom row is two steps longer.
+    struct Lava {
+        grid: GridWorld,
+        lava: HashSet<Cell>,
+    }
+
+    impl Lava {
+        fn new() -> Self {
+            Lava {
+                grid: GridWorld::new(5, 2, [], (4, 0), 1.0, 0.0),
+                lava: HashSet::from([(1, 0), (2, 0), (3, 0)]),
+            }
+        }
+    }
+
+    impl IEnvironment for Lava {
+        type State = Cell;
+        type Action = Move;
+
+        fn prob(&self, from: &Cell, take: &Move, to: &Cell, with: &f32) -> f32 {
+            self.grid.prob(from, take, to, with)
+        }
+
+        fn actions_from(&self, from: &Cell) -> HashSet<Move> {
+            self.grid.actions_from(from)
+        }
+
+        fn states_from(&self, from: &Cell, take: &Move) -> HashSet<Cell> {
+            self.grid.states_from(from, take)
+        }
+
+        fn is_terminal(&self, state: &Cell) -> bool {
+            self.grid.is_terminal(state)
+        }
+
+        fn start_states(&self) -> HashSet<Cell> {
+            self.grid.start_states()
+        }
+
+        fn all_states(&self) -> HashSet<Cell> {
+            self.grid.all_states()
+        }
+
+        fn rewards(&self) -> Vec<f32> {
+            self.grid.rewards()
+        }
+    }
+
+    impl IConstrainedEnvironment for Lava {
+        fn cost(&self, _: &Cell, _: &Move, to: &Cell, _: &f32) -> f32 {
+            if self.lava.contains(to) {
+                1.0
+            } else {
+                0.0
+            }
+        }
+    }
+
+    #[test]
+    fn pricing_cost_steers_around_the_lava() {
+        let env = Lava::new();
+        let (_, reckless) = lagrangian_value_iteration(&env, 0.0, None, 1e-6, 100).unwrap();
+        assert_eq!(reckless.action(&(0, 0)), Some(&Move::Right));
+        let (_, careful) = lagrangian_value_iteration(&env, 1.0, None, 1e-6, 100).unwrap();
+        assert_eq!(careful.action(&(0, 0)), Some(&Move::Down));
+    }
+
+    #[test]
+    fn tightening_the_budget_avoids_the_lava() {
+        let env = Lava::new();
+        let loose = solve_cmdp(&env, 3.0, None, 1e-6, 100, 0.5, 20).unwrap();
+        assert!(loose.is_feasible(3.0));
+        assert_eq!(loose.policy.action(&(0, 0)), Some(&Move::Right));
+        assert_eq!((loose.reward, loose.cost, loose.lambda), (-2.0, 3.0, 0.0));
+
+        let tight = solve_cmdp(&env, 0.5, None, 1e-6, 100, 0.5, 20).unwrap();
+        assert!(tight.is_feasible(0.5));
+        assert_eq!(tight.policy.action(&(0, 0)), Some(&Move::Down));
+        assert_eq!((tight.reward, tight.cost), (-4.0, 0.0));
+        assert!(tight.lambda > 2.0 / 3.0);
+    }
+}
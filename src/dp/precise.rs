@@ -0,0 +1,245 @@
+use std::collections::HashMap;
+
+use crate::env::IEnvironment;
+use crate::policy::{DeterministicPolicy, Policy, TieBreak};
+
+/// A state-value function held in `f64`, as computed by
+/// `value_iteration_f64` and `evaluate_policy_f64`.
+pub type PreciseValueFunction<E> = HashMap<<E as IEnvironment>::State, f64>;
+
+/// The expected return of taking `take` from `from` and continuing
+/// with the values `v`, as `Direct`'s backup but summed in `f64`.
+fn lookahead<E: IEnvironment>(
+    env: &E,
+    rewards: &[f32],
+    from: &E::State,
+    take: &E::Action,
+    v: &PreciseValueFunction<E>,
+    gamma: f64,
+) -> Option<f64> {
+    let mut total = 0.0;
+    for to in env.states_from(from, take) {
+        let (p, r) = if rewards.is_empty() {
+            (
+                f64::from(env.prob_transition(from, take, &to)?),
+                f64::from(env.expected_reward_at(from, take, &to)?),
+            )
+        } else {
+            let mut p = 0.0;
+            let mut expected = 0.0;
+            for r in rewards {
+                let q = f64::from(env.prob(from, take, &to, r));
+                p += q;
+                expected += q * f64::from(*r);
+            }
+            (p, expected)
+        };
+        let next = if env.is_terminal(&to) {
+            0.0
+        } else {
+            v.get(&to).copied().unwrap_or(0.0)
+        };
+        // As `discount_at`.
+        let own = env.gamma_at(&to);
+        let discount = if own == env.gamma() {
+            gamma
+        } else {
+            f64::from(own)
+        };
+        total += r + discount * p * next;
+    }
+    Some(total)
+}
+
+/// As `value_iteration`, but computing the values in `f64`.
+///
+/// The environment still reports its probabilities and rewards as
+/// `f32`, but each is widened before it is summed or discounted, so
+/// rounding no longer swamps small differences between the returns
+/// of actions: with values near 1000, `f32` can't tell apart returns
+/// closer than about 1e-4. `gamma` defaults to `env.gamma()`. Returns
+/// `None` if the environment's rewards are not enumerable.
+pub fn value_iteration_f64<E: IEnvironment>(
+    env: &E,
+    gamma: Option<f64>,
+    theta: f64,
+    max_iters: usize,
+) -> Option<(PreciseValueFunction<E>, DeterministicPolicy<E>)> {
+    let gamma = gamma.unwrap_or_else(|| f64::from(env.gamma()));
+    let rewards = env.rewards();
+    let states = env.all_states();
+    let mut v: PreciseValueFunction<E> = states.iter().map(|s| (s.clone(), 0.0)).collect();
+    for _ in 0..max_iters {
+        let mut delta: f64 = 0.0;
+        let mut next = HashMap::with_capacity(states.len());
+        for s in &states {
+            let value = if env.is_terminal(s) {
+                0.0
+            } else {
+                best_action(env, &rewards, s, &v, gamma)?.map_or(0.0, |(_, q)| q)
+            };
+            delta = delta.max((value - v[s]).abs());
+            next.insert(s.clone(), value);
+        }
+        v = next;
+        if delta < theta {
+            break;
+        }
+    }
+
+    let mut policy = HashMap::new();
+    for s in states.iter().filter(|s| !env.is_terminal(s)) {
+        if let Some((a, _)) = best_action(env, &rewards, s, &v, gamma)? {
+            policy.insert(s.clone(), a);
+        }
+    }
+    Some((v, DeterministicPolicy::new(policy)))
+}
+
+/// The best action from `from` under `v`, breaking ties as
+/// `TieBreak::First` does.
+fn best_action<E: IEnvironment>(
+    env: &E,
+    rewards: &[f32],
+    from: &E::State,
+    v: &PreciseValueFunction<E>,
+    gamma: f64,
+) -> Option<Option<(E::Action, f64)>> {
+    let mut values = Vec::new();
+    for a in env.action_mask(from) {
+        let q = lookahead(env, rewards, from, &a, v, gamma)?;
+        values.push((a, q));
+    }
+    let Some(max) = values.iter().map(|(_, q)| *q).reduce(f64::max) else {
+        return Some(None);
+    };
+    let tied = values
+        .into_iter()
+        .filter(|(_, q)| *q == max)
+        .map(|(a, _)| a)
+        .collect();
+    Some(TieBreak::First.choose(from, tied).map(|a| (a, max)))
+}
+
+/// As `evaluate_policy`, but computing the values in `f64`; see
+/// `value_iteration_f64`.
+pub fn evaluate_policy_f64<E, P>(
+    env: &E,
+    policy: &P,
+    gamma: Option<f64>,
+    theta: f64,
+    max_iters: usize,
+) -> Option<PreciseValueFunction<E>>
+where
+    E: IEnvironment,
+    P: Policy<E>,
+{
+    let gamma = gamma.unwrap_or_else(|| f64::from(env.gamma()));
+    let rewards = env.rewards();
+    let states = env.all_states();
+    let mut v: PreciseValueFunction<E> = states.iter().map(|s| (s.clone(), 0.0)).collect();
+    for _ in 0..max_iters {
+        let mut delta: f64 = 0.0;
+        for s in states.iter().filter(|s| !env.is_terminal(s)) {
+            let mut value = 0.0;
+            for (a, p) in policy.action_distribution(env, s) {
+                if p > 0.0 {
+                    value += f64::from(p) * lookahead(env, &rewards, s, &a, &v, gamma)?;
+                }
+            }
+            let old = v.insert(s.clone(), value).unwrap_or(0.0);
+            delta = delta.max((value - old).abs());
+        }
+        if delta < theta {
+            break;
+        }
+    }
+    Some(v)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::dp::{evaluate_policy, value_iteration};
+    use crate::policy::fixed_order;
+    use crate::testing::Chain;
+
+    /// From state 0 both actions lead to state 1, which pays 1 a step
+    /// forever, but `better` also pays 1e-5 on the way.
+    struct Nearly {
+        better: usize,
+    }
+
+    impl IEnvironment for Nearly {
+        type State = usize;
+        type Action = usize;
+
+        fn prob(&self, from: &usize, take: &usize, to: &usize, with: &f32) -> f32 {
+            let reward = match from {
+                0 if *take == self.better => 1e-5,
+                0 => 0.0,
+                _ => 1.0,
+            };
+            if *to == 1 && *with == reward {
+                1.0
+            } else {
+                0.0
+            }
+        }
+
+        fn actions_from(&self, _: &usize) -> HashSet<usize> {
+            HashSet::from([0, 1])
+        }
+
+        fn states_from(&self, _: &usize, _: &usize) -> HashSet<usize> {
+            HashSet::from([1])
+        }
+
+        fn is_terminal(&self, _: &usize) -> bool {
+            false
+        }
+
+        fn gamma(&self) -> f32 {
+            0.999
+        }
+
+        fn start_states(&self) -> HashSet<usize> {
+            HashSet::from([0])
+        }
+
+        fn all_states(&self) -> HashSet<usize> {
+            HashSet::from([0, 1])
+        }
+
+        fn rewards(&self) -> Vec<f32> {
+            vec![0.0, 1e-5, 1.0]
+        }
+    }
+
+    #[test]
+    fn f64_tells_apart_returns_that_f32_rounds_together() {
+        // Make `f32`'s tie go to the worse action.
+        let order = fixed_order([0, 1]);
+        let env = Nearly { better: order[1] };
+
+        let (_, rounded) = value_iteration(&env, None, 1e-4, 100_000).unwrap();
+        assert_eq!(rounded.action(&0), Some(&order[0]));
+
+        let (v, precise) = value_iteration_f64(&env, None, 1e-9, 100_000).unwrap();
+        assert_eq!(precise.action(&0), Some(&order[1]));
+        let gamma = f64::from(env.gamma());
+        assert!((v[&1] - 1.0 / (1.0 - gamma)).abs() < 1e-5);
+    }
+
+    #[test]
+    fn f64_evaluation_agrees_with_f32() {
+        let policy = Chain::wandering();
+        let v32 = evaluate_policy(&Chain, &policy, Some(0.9), 1e-6, 1000).unwrap();
+        let v64 = evaluate_policy_f64(&Chain, &policy, Some(0.9), 1e-12, 1000).unwrap();
+        for (s, v) in v32 {
+            assert!((f64::from(v) - v64[&s]).abs() < 1e-5);
+        }
+    }
+}
@@ -0,0 +1,461 @@
+use std::collections::hash_map::{self, HashMap};
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::dp::greedy_action;
+use crate::env::IEnvironment;
+use crate::policy::{fixed_order, DeterministicPolicy, TieBreak};
+use crate::td::VisitCounts;
+
+/// How `QTable::merge` combines the values two tables have for a pair.
+pub enum MergeStrategy<'a, E: IEnvironment> {
+    /// The mean of the two values.
+    Average,
+    /// The larger of the two values.
+    Max,
+    /// The mean of the two values weighted by how many times each
+    /// table's learner visited the pair, `own` counting the visits of
+    /// the table merged into and `other` those of the table merged in.
+    /// Pairs neither visited are averaged.
+    WeightedByVisits {
+        own: &'a VisitCounts<E>,
+        other: &'a VisitCounts<E>,
+    },
+}
+
+/// An action-value function: the expected return of taking each
+/// action from each state.
+///
+/// Pairs that have never been set have a default value, 0 unless
+/// the table was made by `filled`, so a fresh table is the all-zero
+/// function.
+pub struct QTable<E: IEnvironment> {
+    values: HashMap<(E::State, E::Action), f32>,
+    default: f32,
+}
+
+impl<E: IEnvironment> QTable<E> {
+    pub fn new() -> Self {
+        Self::filled(0.0)
+    }
+
+    /// A table in which every pair has the value `value` until set,
+    /// e.g. an optimistic one for learners to explore by trying the
+    /// pairs not yet disappointing them. Every method treats an unset
+    /// pair as having this value, `max_action_value` and
+    /// `greedy_action` included, except that terminal states are
+    /// still worth 0.
+    pub fn filled(value: f32) -> Self {
+        QTable {
+            values: HashMap::new(),
+            default: value,
+        }
+    }
+
+    /// The value of pairs that haven't been set.
+    pub fn default_value(&self) -> f32 {
+        self.default
+    }
+
+    /// The value of taking `action` from `state`, or the default value
+    /// if it hasn't been set.
+    pub fn get(&self, state: &E::State, action: &E::Action) -> f32 {
+        self.values
+            .get(&(state.clone(), action.clone()))
+            .copied()
+            .unwrap_or(self.default)
+    }
+
+    pub fn set(&mut self, state: E::State, action: E::Action, value: f32) {
+        self.values.insert((state, action), value);
+    }
+
+    /// A mutable reference to the value of taking `action` from
+    /// `state`, set to the default value first if it hasn't been set.
+    pub fn get_mut(&mut self, state: E::State, action: E::Action) -> &mut f32 {
+        self.values.entry((state, action)).or_insert(self.default)
+    }
+
+    /// Whether the value of taking `action` from `state` has been set.
+    pub fn contains(&self, state: &E::State, action: &E::Action) -> bool {
+        self.values.contains_key(&(state.clone(), action.clone()))
+    }
+
+    /// The number of state-action pairs that have been set.
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// The state-action pairs that have been set, with their values.
+    pub fn iter(&self) -> hash_map::Iter<'_, (E::State, E::Action), f32> {
+        self.values.iter()
+    }
+
+    /// The largest value of any action available from `state` in
+    /// `env`; 0 for terminal states and states without actions.
+    pub fn max_action_value(&self, env: &E, state: &E::State) -> f32 {
+        if env.is_terminal(state) {
+            return 0.0;
+        }
+        env.action_mask(state)
+            .iter()
+            .map(|a| self.get(state, a))
+            .reduce(f32::max)
+            .unwrap_or(0.0)
+    }
+
+    /// The available action from `state` with the largest value, ties
+    /// going to the first in a fixed order, as `TieBreak::First`; None
+    /// for terminal states and states without actions. For other
+    /// tie-breaking, see `dp::greedy_action`.
+    pub fn greedy_action(&self, env: &E, state: &E::State) -> Option<E::Action> {
+        if env.is_terminal(state) {
+            return None;
+        }
+        greedy_action(env, self, state, &TieBreak::First)
+    }
+
+    /// Combines `other` into this table, pair by pair, as `strategy`
+    /// says, e.g. to pool the tables learned by independent workers.
+    /// Pairs missing from either table have that table's default
+    /// value there, as they do everywhere. This only approximates
+    /// learning from the workers' experience together.
+    pub fn merge(&mut self, other: &QTable<E>, strategy: MergeStrategy<'_, E>) {
+        for key in other.values.keys() {
+            self.values.entry(key.clone()).or_insert(self.default);
+        }
+        for ((s, a), value) in &mut self.values {
+            let theirs = other.get(s, a);
+            *value = match &strategy {
+                MergeStrategy::Average => (*value + theirs) / 2.0,
+                MergeStrategy::Max => value.max(theirs),
+                MergeStrategy::WeightedByVisits { own, other } => {
+                    let (n, m) = (own.count(s, a) as f32, other.count(s, a) as f32);
+                    if n + m > 0.0 {
+                        (n * *value + m * theirs) / (n + m)
+                    } else {
+                        (*value + theirs) / 2.0
+                    }
+                }
+            };
+        }
+    }
+
+    /// The greedy policy over the non-terminal states of
+    /// `env.all_states()`.
+    pub fn to_policy(&self, env: &E) -> DeterministicPolicy<E> {
+        DeterministicPolicy::new(
+            env.all_states()
+                .into_iter()
+                .filter_map(|s| self.greedy_action(env, &s).map(|a| (s, a)))
+                .collect(),
+        )
+    }
+}
+
+impl<E: IEnvironment> Default for QTable<E> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<E: IEnvironment> Clone for QTable<E> {
+    fn clone(&self) -> Self {
+        QTable {
+            values: self.values.clone(),
+            default: self.default,
+        }
+    }
+}
+
+impl<E: IEnvironment> PartialEq for QTable<E> {
+    fn eq(&self, other: &Self) -> bool {
+        self.values == other.values && self.default == other.default
+    }
+}
+
+impl<E> fmt::Debug for QTable<E>
+where
+    E: IEnvironment,
+    E::State: fmt::Debug,
+    E::Action: fmt::Debug,
+{
+    /// Pairs are listed in a fixed order, so the output is the same on
+    /// every run.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let keys = fixed_order(self.values.keys());
+        f.debug_map()
+            .entries(keys.into_iter().map(|k| (k, &self.values[k])))
+            .finish()
+    }
+}
+
+impl<E: IEnvironment> FromIterator<((E::State, E::Action), f32)> for QTable<E> {
+    fn from_iter<I: IntoIterator<Item = ((E::State, E::Action), f32)>>(iter: I) -> Self {
+        QTable {
+            values: iter.into_iter().collect(),
+            default: 0.0,
+        }
+    }
+}
+
+impl<E: IEnvironment> IntoIterator for QTable<E> {
+    type Item = ((E::State, E::Action), f32);
+    type IntoIter = hash_map::IntoIter<(E::State, E::Action), f32>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.values.into_iter()
+    }
+}
+
+impl<'a, E: IEnvironment> IntoIterator for &'a QTable<E> {
+    type Item = (&'a (E::State, E::Action), &'a f32);
+    type IntoIter = hash_map::Iter<'a, (E::State, E::Action), f32>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.values.iter()
+    }
+}
+
+/// The pairs whose values differ between the tables `a` and `b`, e.g.
+/// two checkpoints of the same learner, with the change from `a` to
+/// `b`, the largest changes first, and ties in a fixed order.
+///
+/// A pair set in only one table is compared against the other's
+/// default value, 0 unless it was made by `filled`, as `get` reads it.
+pub fn q_diff<E: IEnvironment>(a: &QTable<E>, b: &QTable<E>) -> Vec<(E::State, E::Action, f32)> {
+    let keys: HashSet<&(E::State, E::Action)> = a.values.keys().chain(b.values.keys()).collect();
+    let mut changed: Vec<(E::State, E::Action, f32)> = fixed_order(keys)
+        .into_iter()
+        .filter_map(|(s, action)| {
+            let delta = b.get(s, action) - a.get(s, action);
+            (delta != 0.0).then(|| (s.clone(), action.clone(), delta))
+        })
+        .collect();
+    changed.sort_by(|x, y| y.2.abs().total_cmp(&x.2.abs()));
+    changed
+}
+
+/// An overview of how the tables `a` and `b` differ, as `q_diff_summary`
+/// measures it.
+pub struct QDiffSummary<E: IEnvironment> {
+    /// The number of pairs whose values differ.
+    pub changed: usize,
+    /// The largest absolute change to any pair, or 0 if none changed.
+    pub max_delta: f32,
+    /// The non-terminal states set in either table whose greedy action
+    /// differs between them, in a fixed order.
+    pub flipped: Vec<E::State>,
+}
+
+impl<E: IEnvironment> Clone for QDiffSummary<E> {
+    fn clone(&self) -> Self {
+        QDiffSummary {
+            changed: self.changed,
+            max_delta: self.max_delta,
+            flipped: self.flipped.clone(),
+        }
+    }
+}
+
+impl<E: IEnvironment> PartialEq for QDiffSummary<E> {
+    fn eq(&self, other: &Self) -> bool {
+        self.changed == other.changed
+            && self.max_delta == other.max_delta
+            && self.flipped == other.flipped
+    }
+}
+
+impl<E> fmt::Debug for QDiffSummary<E>
+where
+    E: IEnvironment,
+    E::State: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("QDiffSummary")
+            .field("changed", &self.changed)
+            .field("max_delta", &self.max_delta)
+            .field("flipped", &self.flipped)
+            .finish()
+    }
+}
+
+/// Summarizes `q_diff(a, b)`, along with the states whose greedy
+/// action in `env`, as `QTable::greedy_action` picks it, `b` changed
+/// from `a`'s, which are usually what explains a checkpoint
+/// performing differently.
+pub fn q_diff_summary<E: IEnvironment>(env: &E, a: &QTable<E>, b: &QTable<E>) -> QDiffSummary<E> {
+    let changed = q_diff(a, b);
+    let states: HashSet<&E::State> = a
+        .values
+        .keys()
+        .chain(b.values.keys())
+        .map(|(s, _)| s)
+        .collect();
+    let flipped = fixed_order(states)
+        .into_iter()
+        .filter(|s| a.greedy_action(env, s) != b.greedy_action(env, s))
+        .cloned()
+        .collect();
+    QDiffSummary {
+        changed: changed.len(),
+        max_delta: changed.first().map_or(0.0, |(_, _, delta)| delta.abs()),
+        flipped,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Chain;
+
+    #[test]
+    fn accessors_default_unseen_pairs_to_zero() {
+        let mut q = QTable::<Chain>::new();
+        assert!(q.is_empty());
+        q.set(0, 1, 0.5);
+        q.set(0, -1, -0.5);
+        *q.get_mut(1, -1) += 2.0;
+        assert_eq!(q.len(), 3);
+        assert_eq!(q.get(&0, &1), 0.5);
+        assert_eq!(q.get(&1, &-1), 2.0);
+        assert_eq!(q.get(&2, &1), 0.0);
+        assert!(!q.contains(&2, &1));
+
+        assert_eq!(q.max_action_value(&Chain, &0), 0.5);
+        assert_eq!(q.greedy_action(&Chain, &0), Some(1));
+        assert_eq!(q.max_action_value(&Chain, &1), 2.0);
+        assert_eq!(q.greedy_action(&Chain, &1), Some(-1));
+        // An unseen state has both actions tied at 0.
+        assert_eq!(q.max_action_value(&Chain, &2), 0.0);
+        assert!(q.greedy_action(&Chain, &2).is_some());
+        q.set(Chain::GOAL, 1, 5.0);
+        assert_eq!(q.max_action_value(&Chain, &Chain::GOAL), 0.0);
+        assert_eq!(q.greedy_action(&Chain, &Chain::GOAL), None);
+    }
+
+    #[test]
+    fn the_policy_is_greedy_in_every_non_terminal_state() {
+        let q: QTable<Chain> = [((0, 1), 1.0), ((1, -1), 1.0), ((2, 1), 1.0)]
+            .into_iter()
+            .collect();
+        let policy = q.to_policy(&Chain);
+        assert_eq!(policy.action(&0), Some(&1));
+        assert_eq!(policy.action(&1), Some(&-1));
+        assert_eq!(policy.action(&2), Some(&1));
+        assert_eq!(policy.action(&Chain::GOAL), None);
+        assert_eq!(q.clone().into_iter().count(), 3);
+        assert_eq!(q, q.iter().map(|(k, v)| (*k, *v)).collect());
+    }
+
+    #[test]
+    fn merging_combines_pair_by_pair() {
+        let q: QTable<Chain> = [((0, 1), 1.0), ((1, -1), -2.0)].into_iter().collect();
+        let mut same = q.clone();
+        same.merge(&q, MergeStrategy::Average);
+        assert_eq!(same, q);
+
+        let other: QTable<Chain> = [((0, 1), 3.0), ((1, -1), -4.0), ((2, 1), 1.0)]
+            .into_iter()
+            .collect();
+        let mut max = q.clone();
+        max.merge(&other, MergeStrategy::Max);
+        assert_eq!(max.get(&0, &1), 3.0);
+        assert_eq!(max.get(&1, &-1), -2.0);
+        assert_eq!(max.get(&2, &1), 1.0);
+
+        let mut own = VisitCounts::new();
+        own.increment(0, 1);
+        let mut theirs = VisitCounts::new();
+        for _ in 0..3 {
+            theirs.increment(0, 1);
+        }
+        let mut weighted = q.clone();
+        weighted.merge(
+            &other,
+            MergeStrategy::WeightedByVisits {
+                own: &own,
+                other: &theirs,
+            },
+        );
+        assert_eq!(weighted.get(&0, &1), 2.5);
+        assert_eq!(weighted.get(&1, &-1), -3.0);
+    }
+
+    #[test]
+    fn diffs_list_the_largest_changes_first() {
+        let before: QTable<Chain> = [((0, 1), 1.0), ((0, -1), 0.5), ((1, 1), 2.0)]
+            .into_iter()
+            .collect();
+        assert!(q_diff(&before, &before).is_empty());
+        let same = q_diff_summary(&Chain, &before, &before);
+        assert_eq!(same.changed, 0);
+        assert_eq!(same.max_delta, 0.0);
+        assert!(same.flipped.is_empty());
+
+        // Setting a pair to the value it had by default is no change.
+        let after: QTable<Chain> = [((0, 1), 0.25), ((0, -1), 0.5), ((1, 1), 2.5), ((2, 1), 0.0)]
+            .into_iter()
+            .collect();
+        assert_eq!(q_diff(&before, &after), [(0, 1, -0.75), (1, 1, 0.5)]);
+        // Only in one table, so against 0 in the other.
+        let mut later = after.clone();
+        later.set(1, -1, -1.0);
+        assert_eq!(q_diff(&after, &later), [(1, -1, -1.0)]);
+
+        let summary = q_diff_summary(&Chain, &before, &later);
+        assert_eq!(summary.changed, 3);
+        assert_eq!(summary.max_delta, 1.0);
+        // State 0 now prefers moving left, while state 1 still prefers
+        // moving right.
+        assert_eq!(summary.flipped, [0]);
+    }
+
+    #[test]
+    fn summaries_need_only_the_states_to_be_debug() {
+        /// `Chain`, with neither `Debug` nor `Clone` itself.
+        struct Opaque;
+
+        impl IEnvironment for Opaque {
+            type State = i32;
+            type Action = i32;
+
+            fn prob(&self, from: &i32, take: &i32, to: &i32, with: &f32) -> f32 {
+                Chain.prob(from, take, to, with)
+            }
+
+            fn actions_from(&self, from: &i32) -> HashSet<i32> {
+                Chain.actions_from(from)
+            }
+
+            fn states_from(&self, from: &i32, take: &i32) -> HashSet<i32> {
+                Chain.states_from(from, take)
+            }
+
+            fn is_terminal(&self, state: &i32) -> bool {
+                Chain.is_terminal(state)
+            }
+
+            fn start_states(&self) -> HashSet<i32> {
+                Chain.start_states()
+            }
+
+            fn rewards(&self) -> Vec<f32> {
+                Chain.rewards()
+            }
+        }
+
+        let before: QTable<Opaque> = [((0, 1), 1.0)].into_iter().collect();
+        let after: QTable<Opaque> = [((0, -1), 2.0)].into_iter().collect();
+        let summary = q_diff_summary(&Opaque, &before, &after);
+        assert_eq!(summary.clone(), summary);
+        assert_eq!(
+            format!("{:?}", summary),
+            "QDiffSummary { changed: 2, max_delta: 2.0, flipped: [0] }"
+        );
+    }
+}
@@ -0,0 +1,214 @@
+use std::collections::HashSet;
+
+use super::{optimize_with, Direct, Dynamics, ValueFunction};
+use crate::env::IEnvironment;
+use crate::policy::DeterministicPolicy;
+
+/// How `robust_value_iteration` combines the backups of an ensemble's
+/// members.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Robustness {
+    /// The weighted average of the members' backups: the Bayes-optimal
+    /// objective if the weights are the probabilities of each member
+    /// being the true environment.
+    Average,
+    /// The smallest of the members' backups, ignoring the weights: the
+    /// minimax objective, as though an adversary chose the member at
+    /// every step.
+    WorstCase,
+}
+
+/// Dynamics backing up through every member of an ensemble, combined
+/// as `robustness` says.
+struct Ensemble<'a, E: IEnvironment> {
+    members: Vec<(&'a E, Direct<E>, f32)>,
+    robustness: Robustness,
+}
+
+impl<E: IEnvironment> Dynamics<E> for Ensemble<'_, E> {
+    fn lookahead(
+        &self,
+        _env: &E,
+        from: &E::State,
+        take: &E::Action,
+        v: &ValueFunction<E>,
+        gamma: f32,
+    ) -> Option<f32> {
+        let mut combined = match self.robustness {
+            Robustness::Average => 0.0,
+            Robustness::WorstCase => f32::INFINITY,
+        };
+        for (env, direct, weight) in &self.members {
+            let q = direct.lookahead(*env, from, take, v, gamma)?;
+            combined = match self.robustness {
+                Robustness::Average => combined + weight * q,
+                Robustness::WorstCase => combined.min(q),
+            };
+        }
+        Some(combined)
+    }
+}
+
+/// Value iteration over an ensemble of environments, such as one
+/// environment under several slip probabilities, for a policy robust
+/// to not knowing which of them it will face.
+///
+/// Each backup of a state-action pair is backed up in every member of
+/// `envs`, and the results combined as `robustness` says: averaged by
+/// `weights`, which are normalized to sum to 1, or their worst case.
+/// The members must share their states and actions; the states swept
+/// are every member's, and the actions, terminal states and discounts
+/// are taken from the first. Otherwise as `value_iteration`, with
+/// `gamma` defaulting to the first member's `gamma()`. Returns `None`
+/// if any member's rewards are not enumerable.
+///
+/// Panics if `envs` is empty, if `weights` isn't as long as it, or
+/// if any weight is negative or they are all 0.
+pub fn robust_value_iteration<E: IEnvironment>(
+    envs: &[E],
+    weights: &[f32],
+    robustness: Robustness,
+    gamma: Option<f32>,
+    theta: f32,
+    max_iters: usize,
+) -> Option<(ValueFunction<E>, DeterministicPolicy<E>)> {
+    assert!(!envs.is_empty(), "an ensemble needs at least one member");
+    assert_eq!(envs.len(), weights.len(), "each member needs a weight");
+    assert!(
+        weights.iter().all(|w| *w >= 0.0),
+        "ensemble weights can't be negative"
+    );
+    let total: f32 = weights.iter().sum();
+    assert!(total > 0.0, "ensemble weights can't all be 0");
+
+    let env = &envs[0];
+    let gamma = gamma.unwrap_or_else(|| env.gamma());
+    let ensemble = Ensemble {
+        members: envs
+            .iter()
+            .zip(weights)
+            .map(|(member, w)| (member, Direct::new(member), w / total))
+            .collect(),
+        robustness,
+    };
+    let states: HashSet<E::State> = envs.iter().flat_map(|e| e.all_states()).collect();
+    let (v, policy, _) = optimize_with(
+        env, &ensemble, &states, gamma, theta, max_iters, 0, None, None, None,
+    )?;
+    Some((v, policy))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dp::evaluate_policy;
+
+    /// A single choice between a sure 0.5 and a gamble paying 1 with
+    /// probability `win`.
+    struct Gamble {
+        win: f32,
+    }
+
+    const SAFE: u8 = 0;
+    const RISKY: u8 = 1;
+
+    impl IEnvironment for Gamble {
+        type State = bool;
+        type Action = u8;
+
+        fn prob(&self, from: &bool, take: &u8, to: &bool, with: &f32) -> f32 {
+            if *from || !*to {
+                return 0.0;
+            }
+            let (sure, win, lose) = match *take {
+                SAFE => (1.0, 0.0, 0.0),
+                _ => (0.0, self.win, 1.0 - self.win),
+            };
+            if *with == 0.5 {
+                sure
+            } else if *with == 1.0 {
+                win
+            } else {
+                lose
+            }
+        }
+
+        fn actions_from(&self, from: &bool) -> HashSet<u8> {
+            if *from {
+                HashSet::new()
+            } else {
+                HashSet::from([SAFE, RISKY])
+            }
+        }
+
+        fn states_from(&self, from: &bool, _take: &u8) -> HashSet<bool> {
+            if *from {
+                HashSet::new()
+            } else {
+                HashSet::from([true])
+            }
+        }
+
+        fn is_terminal(&self, state: &bool) -> bool {
+            *state
+        }
+
+        fn start_states(&self) -> HashSet<bool> {
+            HashSet::from([false])
+        }
+
+        fn rewards(&self) -> Vec<f32> {
+            vec![0.0, 0.5, 1.0]
+        }
+    }
+
+    /// The value of `policy` from the start in each of `envs`.
+    fn values(envs: &[Gamble], policy: &DeterministicPolicy<Gamble>) -> Vec<f32> {
+        envs.iter()
+            .map(|env| evaluate_policy(env, policy, Some(1.0), 1e-6, 100).unwrap()[&false])
+            .collect()
+    }
+
+    #[test]
+    fn the_worst_case_policy_maximizes_the_worst_member() {
+        let envs = [Gamble { win: 0.9 }, Gamble { win: 0.3 }];
+        let weights = [1.0, 1.0];
+
+        let (v, average) =
+            robust_value_iteration(&envs, &weights, Robustness::Average, Some(1.0), 1e-6, 100)
+                .unwrap();
+        assert_eq!(average.action(&false), Some(&RISKY));
+        assert!((v[&false] - 0.6).abs() < 1e-6);
+
+        let (v, worst) =
+            robust_value_iteration(&envs, &weights, Robustness::WorstCase, Some(1.0), 1e-6, 100)
+                .unwrap();
+        assert_eq!(worst.action(&false), Some(&SAFE));
+        assert_eq!(v[&false], 0.5);
+
+        // The average policy does better on average, but the worst
+        // case one does better in the worst member.
+        let (average, worst) = (values(&envs, &average), values(&envs, &worst));
+        let mean = |values: &[f32]| values.iter().sum::<f32>() / 2.0;
+        let min = |values: &[f32]| values.iter().copied().fold(f32::INFINITY, f32::min);
+        assert!(mean(&average) > mean(&worst));
+        assert!(min(&worst) > min(&average));
+    }
+
+    #[test]
+    fn weights_shift_the_average() {
+        let envs = [Gamble { win: 0.9 }, Gamble { win: 0.3 }];
+        let (v, policy) = robust_value_iteration(
+            &envs,
+            &[1.0, 3.0],
+            Robustness::Average,
+            Some(1.0),
+            1e-6,
+            100,
+        )
+        .unwrap();
+        // 0.9 / 4 + 0.3 * 3 / 4 = 0.45, less than the sure 0.5.
+        assert_eq!(policy.action(&false), Some(&SAFE));
+        assert_eq!(v[&false], 0.5);
+    }
+}
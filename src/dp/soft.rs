@@ -0,0 +1,108 @@
+use std::collections::HashMap;
+
+use super::{q_from_v, Direct, Dynamics, ValueFunction};
+use crate::env::IEnvironment;
+use crate::policy::Softmax;
+
+/// `tau * ln(sum(exp(q / tau)))` over `values`, computed relative to
+/// their maximum so that no exponential overflows however large the
+/// values, or `None` if there are none. With `tau` = 0 this is the
+/// maximum itself.
+fn soft_max(values: &[f32], tau: f32) -> Option<f32> {
+    let max = values.iter().copied().reduce(f32::max)?;
+    if tau <= 0.0 {
+        return Some(max);
+    }
+    let total: f32 = values.iter().map(|q| ((q - max) / tau).exp()).sum();
+    Some(max + tau * total.ln())
+}
+
+/// Soft value iteration: value iteration for the entropy-regularized
+/// objective of maximum-entropy reinforcement learning, which rewards
+/// a policy for the entropy of its choices, weighted by the
+/// temperature `tau`, as well as for its return.
+///
+/// Each backup replaces the maximum over actions with its smooth
+/// counterpart, `V(s) = tau * ln(sum_a exp(Q(s, a) / tau))`, and the
+/// optimal policy of the objective is the Boltzmann distribution over
+/// the resulting action values, returned as a `Softmax` policy at the
+/// same `tau`. The soft values exceed the hard ones by at most `tau *
+/// ln |A|` a step, so as `tau` goes to 0 they approach
+/// `value_iteration`'s, and at 0 they are the same.
+///
+/// Sweeps are as `value_iteration`'s, stopping once the largest change
+/// falls below `theta`, or after `max_iters` sweeps. `gamma` defaults
+/// to `env.gamma()`. Returns `None` if the environment's rewards are
+/// not enumerable.
+pub fn soft_value_iteration<E: IEnvironment>(
+    env: &E,
+    tau: f32,
+    gamma: Option<f32>,
+    theta: f32,
+    max_iters: usize,
+) -> Option<(ValueFunction<E>, Softmax<E>)> {
+    let gamma = gamma.unwrap_or_else(|| env.gamma());
+    let direct = Direct::new(env);
+    let states = env.all_states();
+    let mut v: ValueFunction<E> = states.iter().map(|s| (s.clone(), 0.0)).collect();
+    for _ in 0..max_iters {
+        let mut delta: f32 = 0.0;
+        let mut next = HashMap::with_capacity(states.len());
+        for s in &states {
+            let mut values = Vec::new();
+            if !env.is_terminal(s) {
+                for a in env.action_mask(s) {
+                    values.push(direct.lookahead(env, s, &a, &v, gamma)?);
+                }
+            }
+            let value = soft_max(&values, tau).unwrap_or(0.0);
+            delta = delta.max((value - v[s]).abs());
+            next.insert(s.clone(), value);
+        }
+        v = next;
+        if delta < theta {
+            break;
+        }
+    }
+    let q = q_from_v(env, &v, Some(gamma))?;
+    Some((v, Softmax::new(q, tau)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dp::value_iteration;
+    use crate::policy::Policy;
+    use crate::testing::Chain;
+
+    #[test]
+    fn soft_max_is_stable_for_large_values() {
+        let soft = soft_max(&[1000.0, 1000.0], 1.0).unwrap();
+        assert!((soft - (1000.0 + 2f32.ln())).abs() < 1e-3);
+        assert_eq!(soft_max(&[1000.0, 1.0], 0.0), Some(1000.0));
+        assert_eq!(soft_max(&[], 1.0), None);
+    }
+
+    #[test]
+    fn cooling_approaches_the_hard_values() {
+        let (hard, _) = value_iteration(&Chain, Some(0.9), 1e-6, 1000).unwrap();
+        let mut last = f32::INFINITY;
+        for tau in [1.0, 0.1, 0.01, 0.001] {
+            let (soft, _) = soft_value_iteration(&Chain, tau, Some(0.9), 1e-6, 1000).unwrap();
+            let gap = hard.iter().map(|(s, v)| soft[s] - v).fold(0.0, f32::max);
+            // Two actions a step, discounted by 0.9 forever.
+            assert!(gap <= tau * 2f32.ln() * 10.0, "{}: {}", tau, gap);
+            assert!(gap < last);
+            last = gap;
+        }
+        let (cold, _) = soft_value_iteration(&Chain, 0.0, Some(0.9), 1e-6, 1000).unwrap();
+        assert_eq!(cold, hard);
+    }
+
+    #[test]
+    fn the_soft_policy_prefers_the_better_action() {
+        let (_, policy) = soft_value_iteration(&Chain, 0.1, Some(0.9), 1e-6, 1000).unwrap();
+        assert!(policy.action_prob(&Chain, &0, &1) > 0.5);
+        assert_eq!(policy.tau(), 0.1);
+    }
+}
@@ -0,0 +1,619 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::hash::Hash;
+
+use crate::policy::fixed_order;
+use crate::rng::StdRng;
+use crate::simulate::sample_weighted;
+
+pub mod action_noise;
+pub mod boxed;
+pub mod constrained;
+pub mod deterministic;
+pub mod discretized;
+#[cfg(feature = "examples")]
+pub mod examples;
+pub mod frame_stack;
+pub mod goal_conditioned;
+pub mod multi;
+pub mod multi_agent;
+pub mod normalized;
+pub mod partial;
+pub mod reward_transform;
+pub mod sequential;
+pub mod shaped;
+pub mod shared;
+pub mod space;
+pub mod tabular;
+pub mod time_limited;
+
+pub use action_noise::{ActionNoise, Noise};
+pub use boxed::{BoxedEnv, DynEnvironment};
+pub use constrained::IConstrainedEnvironment;
+pub use deterministic::{derive_rewards, IDeterministicEnvironment};
+pub use discretized::{Bins, Discretized};
+pub use frame_stack::FrameStack;
+pub use goal_conditioned::GoalConditioned;
+pub use multi::{IMultiRewardEnvironment, Scalarized};
+pub use multi_agent::{IMultiAgentEnvironment, JointAction};
+pub use normalized::NormalizedEnv;
+pub use partial::{BeliefState, FullyObservable, IPartialEnvironment};
+pub use reward_transform::{RewardTransform, Transform};
+pub use sequential::{Sequential, Stage};
+pub use shaped::Shaped;
+pub use space::Space;
+pub use tabular::{random_mdp, TabularError, TabularMDP};
+pub use time_limited::TimeLimited;
+
+/// A Markov decision process, described by its full dynamics.
+///
+/// Every method takes `&self`, so an environment can carry its own
+/// configuration, such as a grid's size or slip probability.
+pub trait IEnvironment {
+    type Action: Hash + Eq + Clone;
+    type State: Hash + Eq + Clone;
+
+    /// The full dynamics of the environment: the probability of
+    /// transitioning from state `from` to state `to`, taking action
+    /// `take` and receiving rewards `with`.
+    fn prob(&self, from: &Self::State, take: &Self::Action, to: &Self::State, with: &f32) -> f32;
+
+    // Expectations
+
+    /// Calculates the probability of transitioning from state `from`
+    /// to state `to`, taking action `take`.
+    ///
+    /// This is formally calcuated as the marginal probability over
+    /// different rewards values. If `rewards` is implemented, then
+    /// this probability can be calculated exactly, and is provided
+    /// through the trait's default implementation.
+    ///
+    /// If `rewards` is not or cannot be implemented (e.g. for an
+    /// infinite range), then this function must be defined explitly
+    fn prob_transition(
+        &self,
+        from: &Self::State,
+        take: &Self::Action,
+        to: &Self::State,
+    ) -> Option<f32> {
+        let rewards = self.rewards();
+        if !rewards.is_empty() {
+            Some(rewards.iter().map(|s| self.prob(from, take, to, s)).sum())
+        } else {
+            None
+        }
+    }
+
+    /// Calculate the expected reward taking action `take` from state
+    /// `from`, marginalizing over all possible resulting states.
+    fn expected_reward(&self, from: &Self::State, take: &Self::Action) -> Option<f32> {
+        let rewards = self.rewards();
+        if !rewards.is_empty() {
+            let to = self.states_from(from, take);
+            Some(
+                rewards
+                    .iter()
+                    .flat_map(|r| to.iter().map(move |t| (r, t)))
+                    .map(|(r, t)| self.prob(from, take, t, r) * r)
+                    .sum(),
+            )
+        } else {
+            None
+        }
+    }
+
+    /// Calculate the expected reward taking action `take` from state
+    /// `from`, arriving at state `to`.
+    fn expected_reward_at(
+        &self,
+        from: &Self::State,
+        take: &Self::Action,
+        to: &Self::State,
+    ) -> Option<f32> {
+        let rewards = self.rewards();
+        if !rewards.is_empty() {
+            Some(
+                rewards
+                    .iter()
+                    .map(|r| self.prob(from, take, to, r) * r)
+                    .sum(),
+            )
+        } else {
+            None
+        }
+    }
+
+    // Space enumeration functions:
+
+    /// The actions available from state `from`.
+    ///
+    /// A non-terminal state may have none, making it a trap: episodes
+    /// end there as if it were terminal, and algorithms value it at 0.
+    fn actions_from(&self, from: &Self::State) -> HashSet<Self::Action>;
+
+    /// The actions an agent may choose from state `from`: by default,
+    /// all of `actions_from`.
+    ///
+    /// An environment whose agents act over a fixed action set, such
+    /// as function approximators with one output per action, can keep
+    /// `actions_from` fixed and mask out the actions illegal in each
+    /// state here instead, as a subset of `actions_from`. The policies
+    /// and learners choose only among these, giving the rest
+    /// probability 0. A non-terminal state whose actions are all
+    /// masked out is an error, which `validate_masks` reports.
+    fn action_mask(&self, from: &Self::State) -> HashSet<Self::Action> {
+        self.actions_from(from)
+    }
+
+    /// Whether `take` is among the `actions_from` state `from`.
+    ///
+    /// The dynamics are undefined for any other action, and usually
+    /// give it probability 0 of going anywhere; `Episodic::step`
+    /// checks this first. Environments that can answer without
+    /// building the whole set may override it.
+    fn is_valid_action(&self, from: &Self::State, take: &Self::Action) -> bool {
+        self.actions_from(from).contains(take)
+    }
+
+    fn states_from(&self, from: &Self::State, take: &Self::Action) -> HashSet<Self::State>;
+
+    /// Replaces the contents of `buf` with the states of
+    /// `states_from(from, take)`, each once, in any order.
+    ///
+    /// The planners' backups call this for every state-action pair,
+    /// reusing one buffer throughout, so environments that override it
+    /// to write their next states straight into `buf` save allocating
+    /// a `HashSet` on every backup. The default drains `states_from`.
+    fn states_from_into(
+        &self,
+        from: &Self::State,
+        take: &Self::Action,
+        buf: &mut Vec<Self::State>,
+    ) {
+        buf.clear();
+        buf.extend(self.states_from(from, take));
+    }
+
+    /// The next states reachable by taking action `take` from state
+    /// `from`, with their positive transition probabilities.
+    ///
+    /// The default pairs each of `states_from` with its
+    /// `prob_transition`, dropping those with probability 0, and
+    /// returns `None` if `prob_transition` is undefined. The default
+    /// `sample_transition` samples from this, so environments that can
+    /// produce the distribution more cheaply should override it; the
+    /// order of the result must not vary between calls, for
+    /// reproducibility.
+    fn transition_distribution(
+        &self,
+        from: &Self::State,
+        take: &Self::Action,
+    ) -> Option<Vec<(Self::State, f32)>> {
+        let rewards = self.rewards();
+        let mut distribution = Vec::new();
+        for to in fixed_order(self.states_from(from, take)) {
+            let (p, _) = transition_at(self, from, take, &to, &rewards)?;
+            if p > 0.0 {
+                distribution.push((to, p));
+            }
+        }
+        Some(distribution)
+    }
+
+    /// Samples a next state and reward for taking action `take` from
+    /// state `from`, as every simulation in the crate does, or `None`
+    /// if there is no next state to take.
+    ///
+    /// The default draws the next state from `transition_distribution`,
+    /// and the reward from `rewards` weighted by `prob`. If the rewards
+    /// are not enumerable, the reward is instead the expected reward
+    /// given the sampled next state, recovered from
+    /// `expected_reward_at`. Environments that can sample more cheaply
+    /// than by building the distributions, or that can sample but not
+    /// enumerate them, should override it.
+    ///
+    /// The default panics if `transition_distribution` is undefined for
+    /// the environment.
+    fn sample_transition(
+        &self,
+        from: &Self::State,
+        take: &Self::Action,
+        rng: &mut StdRng,
+    ) -> Option<(Self::State, f32)> {
+        let rewards = self.rewards();
+        let candidates = self
+            .transition_distribution(from, take)
+            .expect("prob_transition must be defined to simulate the environment");
+        let to = sample_weighted(candidates, rng)?;
+
+        let reward = if !rewards.is_empty() {
+            let weighted = rewards
+                .iter()
+                .map(|r| (*r, self.prob(from, take, &to, r)))
+                .collect();
+            sample_weighted(weighted, rng).unwrap_or(0.0)
+        } else {
+            let p = self.prob_transition(from, take, &to).unwrap_or(0.0);
+            match self.expected_reward_at(from, take, &to) {
+                Some(r) if p > 0.0 => r / p,
+                _ => 0.0,
+            }
+        };
+        Some((to, reward))
+    }
+
+    /// Whether `state` ends an episode.
+    ///
+    /// Terminal states are absorbing: simulation stops on reaching
+    /// one, and dynamic-programming algorithms pin their value to 0.
+    fn is_terminal(&self, _state: &Self::State) -> bool {
+        false
+    }
+
+    /// The environment's natural discount factor, used by algorithms
+    /// when the caller doesn't provide one.
+    ///
+    /// Episodic environments can keep the default of 1; continuing
+    /// environments should declare a discount below 1.
+    fn gamma(&self) -> f32 {
+        1.0
+    }
+
+    /// The discount applied to the value of `state` by a backup of a
+    /// transition into it, by default `gamma()`.
+    ///
+    /// A state-dependent discount can model a chance of the episode
+    /// ending on entering a state: a discount of 0 makes the state's
+    /// value count for nothing, as a terminal state's does. The
+    /// dynamic-programming backups discount by this wherever it differs
+    /// from `gamma()`, even when given an explicit `gamma`, which only
+    /// replaces the discount of the other states; with the default,
+    /// they discount by the single `gamma` as before. Sample-based
+    /// algorithms use the single discount throughout.
+    fn gamma_at(&self, _state: &Self::State) -> f32 {
+        self.gamma()
+    }
+
+    /// The states an episode may begin in (optional)
+    fn start_states(&self) -> HashSet<Self::State> {
+        HashSet::new()
+    }
+
+    /// Enumerates every state in the environment.
+    ///
+    /// Dynamic-programming algorithms sweep over this set. The
+    /// default implementation is `reachable_states` from
+    /// `start_states`, so it only finds states reachable from a start
+    /// state and is empty if `start_states` is not implemented.
+    fn all_states(&self) -> HashSet<Self::State> {
+        reachable_states(self, &self.start_states())
+    }
+
+    /// Enumerates every action in the environment: the union of
+    /// `actions_from` over `all_states`.
+    fn all_actions(&self) -> HashSet<Self::Action> {
+        self.all_states()
+            .iter()
+            .flat_map(|s| self.actions_from(s))
+            .collect()
+    }
+
+    /// A description of the actions, for tools that need their shape.
+    ///
+    /// The default is `Space::Discrete` over `all_actions`, so it
+    /// enumerates every state; environments whose actions have more
+    /// structure, or are too many to enumerate, should say so here.
+    fn action_space(&self) -> Space {
+        Space::Discrete(self.all_actions().len())
+    }
+
+    /// A description of the states, for tools that need their shape.
+    ///
+    /// The default is `Space::Discrete` over `all_states`.
+    fn observation_space(&self) -> Space {
+        Space::Discrete(self.all_states().len())
+    }
+
+    /// Enumerates the possible reward values in the environment
+    /// (optional)
+    ///
+    /// This may be called repeatedly: each default expectation method
+    /// calls it once per invocation, so it should be cheap to
+    /// construct. Algorithms evaluating many transitions fetch it once
+    /// up front and reuse it via `transition_at`.
+    fn rewards(&self) -> Vec<f32> {
+        vec![]
+    }
+
+    /// The reward for taking `take` from `from` to `to`, split into
+    /// named components, such as a movement cost and a goal bonus
+    /// (optional).
+    ///
+    /// The components must sum to the reward received, which must then
+    /// be the same whatever reward the transition is sampled with; the
+    /// default, with none, says nothing. `simulate::rollout_attributed`
+    /// uses these to attribute a return to its components.
+    fn reward_components(
+        &self,
+        _from: &Self::State,
+        _take: &Self::Action,
+        _to: &Self::State,
+    ) -> HashMap<&'static str, f32> {
+        HashMap::new()
+    }
+}
+
+/// Every state reachable from `starts`, the starts included, by a
+/// breadth-first search over `actions_from` and `states_from`.
+///
+/// Each state is expanded once, so cycles and self-loops are fine.
+/// Terminal states are reached but not expanded, since episodes end
+/// there.
+pub fn reachable_states<E: IEnvironment + ?Sized>(
+    env: &E,
+    starts: &HashSet<E::State>,
+) -> HashSet<E::State> {
+    search(starts.clone(), |from| {
+        if env.is_terminal(from) {
+            return Vec::new();
+        }
+        env.actions_from(from)
+            .into_iter()
+            .flat_map(|take| env.states_from(from, &take))
+            .collect()
+    })
+}
+
+/// Every state reachable from `starts` by repeatedly following
+/// `successors`, breadth first, expanding each state once.
+pub(crate) fn search<S: Hash + Eq + Clone>(
+    starts: HashSet<S>,
+    successors: impl Fn(&S) -> Vec<S>,
+) -> HashSet<S> {
+    let mut seen = starts;
+    let mut frontier: VecDeque<S> = seen.iter().cloned().collect();
+    while let Some(from) = frontier.pop_front() {
+        for to in successors(&from) {
+            if seen.insert(to.clone()) {
+                frontier.push_back(to);
+            }
+        }
+    }
+    seen
+}
+
+/// The actions available from `state`, in the fixed order of
+/// `fixed_order`, so that anything built by iterating over them comes
+/// out the same on every run.
+pub(crate) fn sorted_actions<E: IEnvironment + ?Sized>(
+    env: &E,
+    state: &E::State,
+) -> Vec<E::Action> {
+    fixed_order(env.actions_from(state))
+}
+
+/// The states reachable by taking `take` from `from`, in the fixed
+/// order of `fixed_order`, as `sorted_actions`.
+pub(crate) fn sorted_states<E: IEnvironment + ?Sized>(
+    env: &E,
+    from: &E::State,
+    take: &E::Action,
+) -> Vec<E::State> {
+    fixed_order(env.states_from(from, take))
+}
+
+/// Calculates `prob_transition` and `expected_reward_at` for the
+/// transition from `from` to `to` taking `take` together, in a single
+/// pass over `rewards`, which must be the environment's `rewards()`.
+///
+/// This lets callers enumerate the rewards once rather than on every
+/// transition. If `rewards` is empty, the environment's own
+/// `prob_transition` and `expected_reward_at` are used instead.
+pub(crate) fn transition_at<E: IEnvironment + ?Sized>(
+    env: &E,
+    from: &E::State,
+    take: &E::Action,
+    to: &E::State,
+    rewards: &[f32],
+) -> Option<(f32, f32)> {
+    if rewards.is_empty() {
+        return Some((
+            env.prob_transition(from, take, to)?,
+            env.expected_reward_at(from, take, to)?,
+        ));
+    }
+    let mut p = 0.0;
+    let mut expected = 0.0;
+    for r in rewards {
+        let q = env.prob(from, take, to, r);
+        p += q;
+        expected += q * r;
+    }
+    Some((p, expected))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Chain;
+
+    #[cfg(feature = "examples")]
+    #[test]
+    fn enumeration_terminates_on_self_loops() {
+        use examples::{Always, Dull};
+
+        assert_eq!(Dull.all_states(), HashSet::from([Always::Same]));
+    }
+
+    #[test]
+    fn enumeration_finds_every_reachable_state() {
+        assert_eq!(Chain.all_states(), HashSet::from([0, 1, 2, 3]));
+        assert_eq!(Chain.all_actions(), HashSet::from([-1, 1]));
+    }
+
+    #[test]
+    fn reachability_stops_at_terminal_states() {
+        assert_eq!(
+            reachable_states(&Chain, &HashSet::from([Chain::GOAL])),
+            HashSet::from([Chain::GOAL])
+        );
+        assert_eq!(reachable_states(&Chain, &HashSet::new()), HashSet::new());
+    }
+
+    #[cfg(feature = "examples")]
+    #[test]
+    fn reachability_finds_the_cells_on_this_side_of_a_wall() {
+        use examples::GridWorld;
+
+        let corridor = GridWorld::new(6, 1, [(2, 0)], (5, 0), 0.0, 0.0);
+        assert_eq!(corridor.all_states().len(), 5);
+        assert_eq!(
+            reachable_states(&corridor, &HashSet::from([(0, 0)])),
+            HashSet::from([(0, 0), (1, 0)])
+        );
+        assert_eq!(
+            reachable_states(&corridor, &HashSet::from([(3, 0)])),
+            HashSet::from([(3, 0), (4, 0), (5, 0)])
+        );
+    }
+
+    #[cfg(feature = "examples")]
+    #[test]
+    fn transition_distributions_sum_to_one() {
+        use examples::{Always, DoNothing, Dull, GridWorld};
+
+        assert_eq!(
+            Dull.transition_distribution(&Always::Same, &DoNothing::Nothing),
+            Some(vec![(Always::Same, 1.0)])
+        );
+        let grid = GridWorld::new(4, 3, [(1, 1)], (3, 0), 0.04, 0.2);
+        for s in grid.all_states() {
+            for a in grid.actions_from(&s) {
+                let distribution = grid.transition_distribution(&s, &a).unwrap();
+                assert!(distribution.iter().all(|(_, p)| *p > 0.0));
+                let total: f32 = distribution.iter().map(|(_, p)| p).sum();
+                assert!((total - 1.0).abs() < 1e-6, "{s:?} {a:?}");
+            }
+        }
+    }
+
+    #[cfg(feature = "examples")]
+    #[test]
+    fn sampled_transitions_follow_the_dynamics() {
+        use examples::{GridWorld, Move};
+
+        let grid = GridWorld::new(3, 3, [(1, 1)], (2, 2), 0.04, 0.2);
+        let mut rng = crate::rng::make_rng(0);
+        let draws = 20_000;
+        for from in [(0, 0), (2, 1)] {
+            let mut counts: HashMap<(usize, usize), usize> = HashMap::new();
+            for _ in 0..draws {
+                let (to, reward) = grid
+                    .sample_transition(&from, &Move::Right, &mut rng)
+                    .unwrap();
+                assert_eq!(reward, if to == grid.goal() { 1.0 } else { -0.04 });
+                *counts.entry(to).or_insert(0) += 1;
+            }
+            for to in grid.states_from(&from, &Move::Right) {
+                let p = grid.prob_transition(&from, &Move::Right, &to).unwrap();
+                let frequency = counts.get(&to).copied().unwrap_or(0) as f32 / draws as f32;
+                assert!((frequency - p).abs() < 0.01, "{from:?} {to:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn wrappers_sample_through_a_sample_only_environment() {
+        use crate::rng::make_rng;
+        use crate::td::q_learning;
+
+        /// `Chain`, walked only rightward, that can be sampled but
+        /// whose probabilities are undefined.
+        struct Opaque;
+
+        impl IEnvironment for Opaque {
+            type State = i32;
+            type Action = i32;
+
+            fn prob(&self, _: &i32, _: &i32, _: &i32, _: &f32) -> f32 {
+                0.0
+            }
+
+            fn prob_transition(&self, _: &i32, _: &i32, _: &i32) -> Option<f32> {
+                None
+            }
+
+            fn actions_from(&self, _: &i32) -> HashSet<i32> {
+                HashSet::from([1])
+            }
+
+            fn states_from(&self, from: &i32, take: &i32) -> HashSet<i32> {
+                Chain.states_from(from, take)
+            }
+
+            fn sample_transition(&self, from: &i32, _: &i32, _: &mut StdRng) -> Option<(i32, f32)> {
+                Some((from + 1, 1.0))
+            }
+
+            fn is_terminal(&self, state: &i32) -> bool {
+                Chain.is_terminal(state)
+            }
+
+            fn start_states(&self) -> HashSet<i32> {
+                Chain.start_states()
+            }
+        }
+
+        let mut rng = make_rng(0);
+        let limited = TimeLimited::new(RewardTransform::new(Opaque, Transform::Scale(2.0)), 5);
+        assert_eq!(
+            limited.sample_transition(&(0, 5), &1, &mut rng),
+            Some(((1, 4), 2.0))
+        );
+        assert_eq!(limited.sample_transition(&(0, 0), &1, &mut rng), None);
+        let shaped = Shaped::new(Opaque, |s: &i32| *s as f32);
+        assert_eq!(shaped.sample_transition(&0, &1, &mut rng), Some((1, 2.0)));
+        let stacked = FrameStack::<_, 2>::new(NormalizedEnv::new(Opaque));
+        assert_eq!(
+            stacked.sample_transition(&[0, 1], &1, &mut rng),
+            Some(([1, 2], 1.0))
+        );
+        let noisy = ActionNoise::new(FullyObservable::new(Opaque), 0.5, Noise::Sticky);
+        assert_eq!(
+            noisy.sample_transition(&(0, None), &1, &mut rng),
+            Some(((1, Some(1)), 1.0))
+        );
+
+        // Learning runs on the samples alone.
+        let q = q_learning(&limited, 20, 0.5, Some(1.0), 0.0, &mut rng);
+        assert!((q.get(&(2, 3), &1) - 2.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn transition_distributions_drop_impossible_states() {
+        /// `Chain`, but listing both neighbours as possible next states.
+        struct Loose;
+
+        impl IEnvironment for Loose {
+            type State = i32;
+            type Action = i32;
+
+            fn prob(&self, from: &i32, take: &i32, to: &i32, with: &f32) -> f32 {
+                Chain.prob(from, take, to, with)
+            }
+
+            fn actions_from(&self, from: &i32) -> HashSet<i32> {
+                Chain.actions_from(from)
+            }
+
+            fn states_from(&self, from: &i32, _: &i32) -> HashSet<i32> {
+                HashSet::from([from - 1, from + 1])
+            }
+
+            fn rewards(&self) -> Vec<f32> {
+                Chain.rewards()
+            }
+        }
+
+        assert_eq!(Loose.transition_distribution(&1, &1), Some(vec![(2, 1.0)]));
+    }
+}
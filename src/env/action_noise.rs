@@ -0,0 +1,291 @@
+use std::collections::HashSet;
+
+use crate::env::IEnvironment;
+use crate::policy::fixed_order;
+use crate::rng::StdRng;
+use crate::simulate::sample_weighted;
+
+/// How `ActionNoise` perturbs the agent's actions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Noise {
+    /// Repeats the action taken on the previous step instead, or
+    /// takes the agent's own on the first step of an episode, or if
+    /// the previous action isn't available any more.
+    Sticky,
+    /// Takes an action drawn uniformly from the available ones, which
+    /// may be the agent's own.
+    Random,
+}
+
+/// The environment `E` with the agent's actions perturbed: with
+/// probability `p` the action taken is replaced as `noise` describes
+/// before the base environment's dynamics apply, so that a policy's
+/// robustness to imprecise control can be measured.
+///
+/// Sticky actions depend on the action last taken, so each state
+/// carries it along with the base state, as `(state, previous)`,
+/// `previous` being `None` at the start of an episode. Random actions
+/// don't, so with `Noise::Random` the previous action is always
+/// `None`, and the state space is the base one's.
+pub struct ActionNoise<E> {
+    env: E,
+    p: f32,
+    noise: Noise,
+}
+
+impl<E: IEnvironment> ActionNoise<E> {
+    /// Perturbs the actions taken in `env` by `noise` with probability
+    /// `p`.
+    ///
+    /// Panics if `p` is not between 0 and 1.
+    pub fn new(env: E, p: f32, noise: Noise) -> Self {
+        assert!((0.0..=1.0).contains(&p), "p must be a probability");
+        ActionNoise { env, p, noise }
+    }
+
+    /// The environment being perturbed.
+    pub fn env(&self) -> &E {
+        &self.env
+    }
+
+    pub fn p(&self) -> f32 {
+        self.p
+    }
+
+    pub fn noise(&self) -> Noise {
+        self.noise
+    }
+
+    /// The actions actually taken, and their probabilities, when the
+    /// agent takes `take` from `from`, each at most once.
+    fn effective(
+        &self,
+        from: &(E::State, Option<E::Action>),
+        take: &E::Action,
+    ) -> Vec<(E::Action, f32)> {
+        let mut effective = vec![(take.clone(), 1.0 - self.p)];
+        match self.noise {
+            Noise::Sticky => {
+                let repeated = match &from.1 {
+                    Some(previous) if self.env.is_valid_action(&from.0, previous) => previous,
+                    _ => take,
+                };
+                effective.push((repeated.clone(), self.p));
+            }
+            Noise::Random => {
+                let actions = fixed_order(self.env.action_mask(&from.0));
+                let each = self.p / actions.len() as f32;
+                effective.extend(actions.into_iter().map(|a| (a, each)));
+            }
+        }
+        let mut merged: Vec<(E::Action, f32)> = Vec::with_capacity(effective.len());
+        for (a, p) in effective {
+            match merged.iter_mut().find(|(b, _)| *b == a) {
+                Some((_, total)) => *total += p,
+                None => merged.push((a, p)),
+            }
+        }
+        merged.retain(|(_, p)| *p > 0.0);
+        merged
+    }
+
+    /// The previous action recorded in the next state after taking
+    /// `effective`.
+    fn recorded(&self, effective: &E::Action) -> Option<E::Action> {
+        match self.noise {
+            Noise::Sticky => Some(effective.clone()),
+            Noise::Random => None,
+        }
+    }
+}
+
+impl<E: IEnvironment> IEnvironment for ActionNoise<E> {
+    type State = (E::State, Option<E::Action>);
+    type Action = E::Action;
+
+    fn prob(&self, from: &Self::State, take: &E::Action, to: &Self::State, with: &f32) -> f32 {
+        self.effective(from, take)
+            .into_iter()
+            .filter(|(a, _)| self.recorded(a) == to.1)
+            .map(|(a, p)| p * self.env.prob(&from.0, &a, &to.0, with))
+            .sum()
+    }
+
+    fn prob_transition(
+        &self,
+        from: &Self::State,
+        take: &E::Action,
+        to: &Self::State,
+    ) -> Option<f32> {
+        let mut total = 0.0;
+        for (a, p) in self.effective(from, take) {
+            if self.recorded(&a) == to.1 {
+                total += p * self.env.prob_transition(&from.0, &a, &to.0)?;
+            }
+        }
+        Some(total)
+    }
+
+    fn transition_distribution(
+        &self,
+        from: &Self::State,
+        take: &E::Action,
+    ) -> Option<Vec<(Self::State, f32)>> {
+        let mut distribution: Vec<(Self::State, f32)> = Vec::new();
+        for (a, p) in self.effective(from, take) {
+            for (to, q) in self.env.transition_distribution(&from.0, &a)? {
+                let to = (to, self.recorded(&a));
+                match distribution.iter_mut().find(|(s, _)| *s == to) {
+                    Some((_, total)) => *total += p * q,
+                    None => distribution.push((to, p * q)),
+                }
+            }
+        }
+        Some(distribution)
+    }
+
+    fn expected_reward(&self, from: &Self::State, take: &E::Action) -> Option<f32> {
+        let mut total = 0.0;
+        for (a, p) in self.effective(from, take) {
+            total += p * self.env.expected_reward(&from.0, &a)?;
+        }
+        Some(total)
+    }
+
+    fn expected_reward_at(
+        &self,
+        from: &Self::State,
+        take: &E::Action,
+        to: &Self::State,
+    ) -> Option<f32> {
+        let mut total = 0.0;
+        for (a, p) in self.effective(from, take) {
+            if self.recorded(&a) == to.1 {
+                total += p * self.env.expected_reward_at(&from.0, &a, &to.0)?;
+            }
+        }
+        Some(total)
+    }
+
+    fn actions_from(&self, from: &Self::State) -> HashSet<E::Action> {
+        self.env.actions_from(&from.0)
+    }
+
+    fn action_mask(&self, from: &Self::State) -> HashSet<E::Action> {
+        self.env.action_mask(&from.0)
+    }
+
+    fn is_valid_action(&self, from: &Self::State, take: &E::Action) -> bool {
+        self.env.is_valid_action(&from.0, take)
+    }
+
+    fn states_from(&self, from: &Self::State, take: &E::Action) -> HashSet<Self::State> {
+        self.effective(from, take)
+            .into_iter()
+            .flat_map(|(a, _)| {
+                let recorded = self.recorded(&a);
+                self.env
+                    .states_from(&from.0, &a)
+                    .into_iter()
+                    .map(move |to| (to, recorded.clone()))
+            })
+            .collect()
+    }
+
+    /// Draws the action actually taken, then samples the base
+    /// environment taking it.
+    fn sample_transition(
+        &self,
+        from: &Self::State,
+        take: &E::Action,
+        rng: &mut StdRng,
+    ) -> Option<(Self::State, f32)> {
+        let taken = sample_weighted(self.effective(from, take), rng)?;
+        let (to, reward) = self.env.sample_transition(&from.0, &taken, rng)?;
+        Some(((to, self.recorded(&taken)), reward))
+    }
+
+    fn is_terminal(&self, state: &Self::State) -> bool {
+        self.env.is_terminal(&state.0)
+    }
+
+    fn gamma(&self) -> f32 {
+        self.env.gamma()
+    }
+
+    fn gamma_at(&self, state: &Self::State) -> f32 {
+        self.env.gamma_at(&state.0)
+    }
+
+    /// Each base start state, with no action taken yet.
+    fn start_states(&self) -> HashSet<Self::State> {
+        self.env
+            .start_states()
+            .into_iter()
+            .map(|s| (s, None))
+            .collect()
+    }
+
+    fn rewards(&self) -> Vec<f32> {
+        self.env.rewards()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dp::{evaluate_policy, value_iteration};
+    use crate::policy::UniformRandomPolicy;
+    use crate::testing::Chain;
+
+    #[test]
+    fn without_noise_the_values_are_the_base_ones() {
+        let (base, policy) = value_iteration(&Chain, Some(0.9), 1e-6, 1000).unwrap();
+        for noise in [Noise::Sticky, Noise::Random] {
+            let noisy = ActionNoise::new(Chain, 0.0, noise);
+            let (v, noisy_policy) = value_iteration(&noisy, Some(0.9), 1e-6, 1000).unwrap();
+            for ((s, previous), value) in v {
+                assert_eq!(value, base[&s], "{:?}", (s, previous));
+                assert_eq!(noisy_policy.action(&(s, previous)), policy.action(&s));
+            }
+        }
+    }
+
+    #[test]
+    fn random_actions_degrade_the_value_toward_random_play() {
+        let (optimal, _) = value_iteration(&Chain, Some(0.9), 1e-6, 1000).unwrap();
+        let random =
+            evaluate_policy(&Chain, &UniformRandomPolicy, Some(0.9), 1e-7, 10_000).unwrap();
+        let mut last = optimal[&0];
+        for p in [0.25, 0.5, 1.0] {
+            let noisy = ActionNoise::new(Chain, p, Noise::Random);
+            let (v, _) = value_iteration(&noisy, Some(0.9), 1e-7, 10_000).unwrap();
+            let value = v[&(0, None)];
+            assert!(
+                value < last && value >= random[&0] - 1e-5,
+                "{}: {}",
+                p,
+                value
+            );
+            last = value;
+        }
+        assert!((last - random[&0]).abs() < 1e-5);
+    }
+
+    #[test]
+    fn sticky_actions_carry_the_previous_action() {
+        let noisy = ActionNoise::new(Chain, 0.5, Noise::Sticky);
+        assert_eq!(noisy.start_states(), HashSet::from([(0, None)]));
+        // With nothing to repeat, the agent's own action is taken.
+        assert_eq!(
+            noisy.states_from(&(0, None), &1),
+            HashSet::from([(1, Some(1))])
+        );
+        assert_eq!(
+            noisy.states_from(&(1, Some(1)), &-1),
+            HashSet::from([(0, Some(-1)), (2, Some(1))])
+        );
+        assert_eq!(noisy.prob(&(1, Some(1)), &-1, &(2, Some(1)), &0.0), 0.5);
+        assert_eq!(noisy.prob(&(1, Some(1)), &-1, &(2, None), &0.0), 0.0);
+    }
+}
@@ -0,0 +1,146 @@
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+
+use crate::env::{IEnvironment, Space};
+use crate::rng::StdRng;
+
+/// An environment behind a trait object, with states `S` and actions
+/// `A`.
+pub type DynEnvironment<'a, S, A> = dyn IEnvironment<State = S, Action = A> + 'a;
+
+/// A boxed environment of any type with states `S` and actions `A`,
+/// so that different environments can be kept in one collection and
+/// run through the same algorithms.
+///
+/// `Box<DynEnvironment>` can't implement `IEnvironment` itself, as it
+/// would overlap with the implementation for every
+/// `IDeterministicEnvironment`; this wrapper forwards every method,
+/// overridden or not, to the boxed environment instead.
+pub struct BoxedEnv<'a, S, A> {
+    env: Box<DynEnvironment<'a, S, A>>,
+}
+
+impl<'a, S, A> BoxedEnv<'a, S, A> {
+    pub fn new(env: impl IEnvironment<State = S, Action = A> + 'a) -> Self {
+        BoxedEnv { env: Box::new(env) }
+    }
+
+    /// The boxed environment.
+    pub fn env(&self) -> &DynEnvironment<'a, S, A> {
+        self.env.as_ref()
+    }
+}
+
+impl<S, A> IEnvironment for BoxedEnv<'_, S, A>
+where
+    S: Hash + Eq + Clone,
+    A: Hash + Eq + Clone,
+{
+    type State = S;
+    type Action = A;
+
+    fn prob(&self, from: &S, take: &A, to: &S, with: &f32) -> f32 {
+        self.env.prob(from, take, to, with)
+    }
+
+    fn prob_transition(&self, from: &S, take: &A, to: &S) -> Option<f32> {
+        self.env.prob_transition(from, take, to)
+    }
+
+    fn transition_distribution(&self, from: &S, take: &A) -> Option<Vec<(S, f32)>> {
+        self.env.transition_distribution(from, take)
+    }
+
+    fn expected_reward(&self, from: &S, take: &A) -> Option<f32> {
+        self.env.expected_reward(from, take)
+    }
+
+    fn expected_reward_at(&self, from: &S, take: &A, to: &S) -> Option<f32> {
+        self.env.expected_reward_at(from, take, to)
+    }
+
+    fn actions_from(&self, from: &S) -> HashSet<A> {
+        self.env.actions_from(from)
+    }
+
+    fn action_mask(&self, from: &S) -> HashSet<A> {
+        self.env.action_mask(from)
+    }
+
+    fn is_valid_action(&self, from: &S, take: &A) -> bool {
+        self.env.is_valid_action(from, take)
+    }
+
+    fn states_from(&self, from: &S, take: &A) -> HashSet<S> {
+        self.env.states_from(from, take)
+    }
+
+    fn states_from_into(&self, from: &S, take: &A, buf: &mut Vec<S>) {
+        self.env.states_from_into(from, take, buf)
+    }
+
+    fn sample_transition(&self, from: &S, take: &A, rng: &mut StdRng) -> Option<(S, f32)> {
+        self.env.sample_transition(from, take, rng)
+    }
+
+    fn is_terminal(&self, state: &S) -> bool {
+        self.env.is_terminal(state)
+    }
+
+    fn gamma(&self) -> f32 {
+        self.env.gamma()
+    }
+
+    fn gamma_at(&self, state: &S) -> f32 {
+        self.env.gamma_at(state)
+    }
+
+    fn start_states(&self) -> HashSet<S> {
+        self.env.start_states()
+    }
+
+    fn all_states(&self) -> HashSet<S> {
+        self.env.all_states()
+    }
+
+    fn all_actions(&self) -> HashSet<A> {
+        self.env.all_actions()
+    }
+
+    fn action_space(&self) -> Space {
+        self.env.action_space()
+    }
+
+    fn observation_space(&self) -> Space {
+        self.env.observation_space()
+    }
+
+    fn rewards(&self) -> Vec<f32> {
+        self.env.rewards()
+    }
+
+    fn reward_components(&self, from: &S, take: &A, to: &S) -> HashMap<&'static str, f32> {
+        self.env.reward_components(from, take, to)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dp::value_iteration;
+    use crate::testing::{Chain, Trap};
+
+    #[test]
+    fn boxed_and_unboxed_value_iteration_agree() {
+        let envs = [BoxedEnv::new(Chain), BoxedEnv::new(Trap)];
+        let (chain, _) = value_iteration(&Chain, Some(0.9), 1e-6, 1000).unwrap();
+        let (trap, _) = value_iteration(&Trap, Some(0.9), 1e-6, 1000).unwrap();
+        let boxed: Vec<_> = envs
+            .iter()
+            .map(|env| value_iteration(env, Some(0.9), 1e-6, 1000).unwrap().0)
+            .collect();
+        assert_eq!(boxed, [chain, trap]);
+        assert!(envs[0].is_terminal(&Chain::GOAL));
+        assert!(!envs[1].is_terminal(&Chain::GOAL));
+    }
+}
@@ -0,0 +1,13 @@
+use crate::env::IEnvironment;
+
+/// An environment with a cost signal alongside its reward, for
+/// constrained MDPs: the aim is the most reward whose expected
+/// discounted cost stays within a budget, as `dp::solve_cmdp` finds.
+///
+/// Costs are attached to the same outcomes as rewards, so the
+/// environment's rewards must be enumerable for them to be weighted.
+pub trait IConstrainedEnvironment: IEnvironment {
+    /// The cost incurred transitioning from state `from` to state
+    /// `to`, taking action `take` and receiving reward `with`.
+    fn cost(&self, from: &Self::State, take: &Self::Action, to: &Self::State, with: &f32) -> f32;
+}
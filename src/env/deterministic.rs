@@ -0,0 +1,215 @@
+use std::collections::HashSet;
+use std::hash::Hash;
+
+use crate::env::{search, IEnvironment};
+
+/// An environment in which every action leads to a single next state
+/// with a single reward, described by its `transition` function alone.
+///
+/// Every implementor is an `IEnvironment`, with `prob` 1 for the
+/// outcome of `transition` and 0 otherwise, and `states_from` the
+/// single next state. The remaining methods mirror those of
+/// `IEnvironment`, defaults included; with both traits in scope, call
+/// them through one trait explicitly, as in
+/// `IEnvironment::actions_from(&env, &s)`.
+pub trait IDeterministicEnvironment {
+    type Action: Hash + Eq + Clone;
+    type State: Hash + Eq + Clone;
+
+    /// The next state and reward of taking `take` from `from`.
+    fn transition(&self, from: &Self::State, take: &Self::Action) -> (Self::State, f32);
+
+    fn actions_from(&self, from: &Self::State) -> HashSet<Self::Action>;
+
+    fn is_terminal(&self, _state: &Self::State) -> bool {
+        false
+    }
+
+    fn gamma(&self) -> f32 {
+        1.0
+    }
+
+    fn start_states(&self) -> HashSet<Self::State> {
+        HashSet::new()
+    }
+
+    /// Every state in the environment. By default, those reachable
+    /// from `start_states` by following `transition`, without
+    /// expanding terminal states, as for `reachable_states`.
+    fn all_states(&self) -> HashSet<Self::State> {
+        search(self.start_states(), |from| {
+            if self.is_terminal(from) {
+                return Vec::new();
+            }
+            self.actions_from(from)
+                .iter()
+                .map(|take| self.transition(from, take).0)
+                .collect()
+        })
+    }
+
+    /// The possible rewards (optional).
+    ///
+    /// Unlike for a general `IEnvironment`, nothing needs these: the
+    /// transition probabilities and expected rewards are exact without
+    /// them. `derive_rewards` can enumerate them, at a cost.
+    fn rewards(&self) -> Vec<f32> {
+        vec![]
+    }
+}
+
+/// Every reward `env` can give, found by taking every action from
+/// every non-terminal state in `all_states`.
+///
+/// This walks the whole state space on every call, so an environment
+/// returning it from `rewards` should only do so when the state space
+/// is small or when it caches the result.
+pub fn derive_rewards<E: IDeterministicEnvironment>(env: &E) -> Vec<f32> {
+    let mut rewards: Vec<f32> = Vec::new();
+    for from in env.all_states() {
+        if env.is_terminal(&from) {
+            continue;
+        }
+        for take in env.actions_from(&from) {
+            let (_, r) = env.transition(&from, &take);
+            if !rewards.contains(&r) {
+                rewards.push(r);
+            }
+        }
+    }
+    rewards
+}
+
+impl<E: IDeterministicEnvironment> IEnvironment for E {
+    type State = E::State;
+    type Action = E::Action;
+
+    fn prob(&self, from: &E::State, take: &E::Action, to: &E::State, with: &f32) -> f32 {
+        let (next, reward) = self.transition(from, take);
+        if next == *to && reward == *with {
+            1.0
+        } else {
+            0.0
+        }
+    }
+
+    fn prob_transition(&self, from: &E::State, take: &E::Action, to: &E::State) -> Option<f32> {
+        let (next, _) = self.transition(from, take);
+        Some(if next == *to { 1.0 } else { 0.0 })
+    }
+
+    fn transition_distribution(
+        &self,
+        from: &E::State,
+        take: &E::Action,
+    ) -> Option<Vec<(E::State, f32)>> {
+        Some(vec![(self.transition(from, take).0, 1.0)])
+    }
+
+    fn expected_reward(&self, from: &E::State, take: &E::Action) -> Option<f32> {
+        Some(self.transition(from, take).1)
+    }
+
+    fn expected_reward_at(&self, from: &E::State, take: &E::Action, to: &E::State) -> Option<f32> {
+        let (next, reward) = self.transition(from, take);
+        Some(if next == *to { reward } else { 0.0 })
+    }
+
+    fn actions_from(&self, from: &E::State) -> HashSet<E::Action> {
+        IDeterministicEnvironment::actions_from(self, from)
+    }
+
+    fn states_from(&self, from: &E::State, take: &E::Action) -> HashSet<E::State> {
+        HashSet::from([self.transition(from, take).0])
+    }
+
+    fn states_from_into(&self, from: &E::State, take: &E::Action, buf: &mut Vec<E::State>) {
+        buf.clear();
+        buf.push(self.transition(from, take).0);
+    }
+
+    fn is_terminal(&self, state: &E::State) -> bool {
+        IDeterministicEnvironment::is_terminal(self, state)
+    }
+
+    fn gamma(&self) -> f32 {
+        IDeterministicEnvironment::gamma(self)
+    }
+
+    fn start_states(&self) -> HashSet<E::State> {
+        IDeterministicEnvironment::start_states(self)
+    }
+
+    fn all_states(&self) -> HashSet<E::State> {
+        IDeterministicEnvironment::all_states(self)
+    }
+
+    fn rewards(&self) -> Vec<f32> {
+        IDeterministicEnvironment::rewards(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{dp, validate};
+
+    /// A line of cells 0 to 4, starting at 0, where each step costs 1
+    /// and entering the terminal cell 4 pays 10.
+    struct Line;
+
+    impl IDeterministicEnvironment for Line {
+        type State = i32;
+        type Action = i32;
+
+        fn transition(&self, from: &i32, take: &i32) -> (i32, f32) {
+            let to = (from + take).clamp(0, 4);
+            (to, if to == 4 { 10.0 } else { -1.0 })
+        }
+
+        fn actions_from(&self, _: &i32) -> HashSet<i32> {
+            HashSet::from([-1, 1])
+        }
+
+        fn is_terminal(&self, state: &i32) -> bool {
+            *state == 4
+        }
+
+        fn start_states(&self) -> HashSet<i32> {
+            HashSet::from([0])
+        }
+    }
+
+    #[test]
+    fn the_blanket_impl_has_valid_dynamics() {
+        assert!(validate::validate_dynamics(&Line, &2, &1, 1e-6).is_ok());
+        assert!(validate::validate_all(&Line, 1e-6).is_ok());
+        assert_eq!(IEnvironment::prob(&Line, &3, &1, &4, &10.0), 1.0);
+        assert_eq!(IEnvironment::prob(&Line, &3, &1, &4, &-1.0), 0.0);
+        assert_eq!(IEnvironment::all_states(&Line).len(), 5);
+        assert!(IEnvironment::rewards(&Line).is_empty());
+    }
+
+    #[test]
+    fn the_deterministic_fast_path_matches_value_iteration() {
+        let (v, policy) = dp::value_iteration(&Line, Some(0.9), 1e-6, 1000).unwrap();
+        let (fast_v, fast_policy) = dp::value_iteration_deterministic(&Line, Some(0.9), 1e-6, 1000);
+        assert_eq!(v, fast_v);
+        assert_eq!(policy.into_actions(), fast_policy.into_actions());
+    }
+
+    #[test]
+    fn rewards_can_be_derived() {
+        let mut rewards = derive_rewards(&Line);
+        rewards.sort_by(f32::total_cmp);
+        assert_eq!(rewards, [-1.0, 10.0]);
+    }
+
+    #[test]
+    fn value_iteration_needs_no_reward_list() {
+        let (v, policy) = dp::value_iteration(&Line, Some(0.9), 1e-6, 100).unwrap();
+        assert_eq!(policy.action(&0), Some(&1));
+        assert!((v[&3] - 10.0).abs() < 1e-5);
+        assert!((v[&2] - (-1.0 + 0.9 * 10.0)).abs() < 1e-5);
+    }
+}
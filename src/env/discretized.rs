@@ -0,0 +1,337 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+
+use rand::Rng;
+
+use crate::env::IEnvironment;
+use crate::rng::{make_rng, StdRng};
+use crate::simulate::sample_weighted;
+
+/// The bins of a point, one index for each dimension, or `None` once
+/// the dynamics have ended the episode.
+pub type Bins = Option<Vec<usize>>;
+
+/// The estimated outcomes of one state-action pair: each next state
+/// once, with its probability and mean reward, in the order sampled.
+type Outcomes = Vec<(Bins, f32, f32)>;
+
+/// A continuous environment, given only as a sampler of its
+/// `dynamics`, made tabular by binning each dimension of its state
+/// into a grid.
+///
+/// `dynamics(state, action, rng)` takes a point and one of `actions`
+/// actions, numbered from 0, and samples the next point, the reward,
+/// and whether the episode ends there. Each dimension is split into
+/// equal bins between its bounds, points outside them falling into the
+/// outermost bins, and the states are the bins points fall into, or
+/// `None` once an episode ends.
+///
+/// The dynamics can't be enumerated, so neither can `prob`: the first
+/// time a state-action pair is asked about, `samples` points are drawn
+/// uniformly from within its bins and stepped through `dynamics`, and
+/// the transitions and rewards are estimated from them once and for
+/// all. The rewards are then those estimates' means, and `rewards` is
+/// empty. Only the sample-based algorithms, e.g. `q_learning`, apply;
+/// the planners, sweeping every state, would sample the whole grid,
+/// and solve the estimate rather than the continuous problem.
+pub struct Discretized<F> {
+    dynamics: F,
+    bounds: Vec<(f32, f32)>,
+    bins: Vec<usize>,
+    actions: usize,
+    starts: Vec<Vec<f32>>,
+    samples: usize,
+    gamma: f32,
+    rng: RefCell<StdRng>,
+    model: RefCell<HashMap<(Vec<usize>, usize), Outcomes>>,
+}
+
+impl<F> Discretized<F>
+where
+    F: Fn(&[f32], usize, &mut StdRng) -> (Vec<f32>, f32, bool),
+{
+    /// Bins the state of `dynamics` into `bins[d]` bins between
+    /// `bounds[d]` along each dimension `d`, with `actions` actions.
+    /// There are no start states until `with_start` adds some.
+    ///
+    /// Panics if `bounds` and `bins` differ in length, if a dimension
+    /// has no bins, or if a lower bound isn't below its upper one.
+    pub fn new(dynamics: F, bounds: Vec<(f32, f32)>, bins: Vec<usize>, actions: usize) -> Self {
+        assert_eq!(bounds.len(), bins.len(), "one bin count per dimension");
+        assert!(bins.iter().all(|&n| n > 0), "every dimension needs a bin");
+        assert!(bounds.iter().all(|(lo, hi)| lo < hi), "empty bounds");
+        Discretized {
+            dynamics,
+            bounds,
+            bins,
+            actions,
+            starts: Vec::new(),
+            samples: 100,
+            gamma: 1.0,
+            rng: RefCell::new(make_rng(0)),
+            model: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Starts episodes from the bins of `point`, too.
+    pub fn with_start(mut self, point: Vec<f32>) -> Self {
+        self.starts.push(point);
+        self
+    }
+
+    /// Estimates each state-action pair from `samples` draws, rather
+    /// than 100.
+    pub fn with_samples(mut self, samples: usize) -> Self {
+        assert!(samples > 0, "at least one sample is needed");
+        self.samples = samples;
+        self
+    }
+
+    /// Draws the estimates' samples from a generator seeded with `seed`,
+    /// rather than 0.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = RefCell::new(make_rng(seed));
+        self
+    }
+
+    pub fn with_gamma(mut self, gamma: f32) -> Self {
+        self.gamma = gamma;
+        self
+    }
+
+    /// The bins `point` falls into.
+    pub fn bins_of(&self, point: &[f32]) -> Vec<usize> {
+        point
+            .iter()
+            .zip(self.bounds.iter().zip(&self.bins))
+            .map(|(x, ((lo, hi), &n))| {
+                let at = ((x - lo) / (hi - lo) * n as f32).floor();
+                (at.max(0.0) as usize).min(n - 1)
+            })
+            .collect()
+    }
+
+    /// Samples `dynamics` from points drawn uniformly within `bins`,
+    /// taking `take`.
+    fn estimate(&self, bins: &[usize], take: usize) -> Outcomes {
+        let mut rng = self.rng.borrow_mut();
+        let mut outcomes: Outcomes = Vec::new();
+        for _ in 0..self.samples {
+            let point: Vec<f32> = bins
+                .iter()
+                .zip(self.bounds.iter().zip(&self.bins))
+                .map(|(&i, ((lo, hi), &n))| {
+                    let width = (hi - lo) / n as f32;
+                    lo + (i as f32 + rng.gen::<f32>()) * width
+                })
+                .collect();
+            let (next, reward, done) = (self.dynamics)(&point, take, &mut rng);
+            let to = if done {
+                None
+            } else {
+                Some(self.bins_of(&next))
+            };
+            match outcomes.iter_mut().find(|(s, _, _)| *s == to) {
+                Some((_, count, total)) => {
+                    *count += 1.0;
+                    *total += reward;
+                }
+                None => outcomes.push((to, 1.0, reward)),
+            }
+        }
+        for (_, count, total) in &mut outcomes {
+            *total /= *count;
+            *count /= self.samples as f32;
+        }
+        outcomes
+    }
+
+    /// The estimated outcomes of taking `take` from `from`, estimating
+    /// them first if this is the first time they're needed.
+    fn outcomes(&self, from: &Bins, take: &usize) -> Outcomes {
+        let Some(bins) = from else {
+            return Vec::new();
+        };
+        if *take >= self.actions {
+            return Vec::new();
+        }
+        let key = (bins.clone(), *take);
+        if let Some(outcomes) = self.model.borrow().get(&key) {
+            return outcomes.clone();
+        }
+        let outcomes = self.estimate(bins, *take);
+        self.model.borrow_mut().insert(key, outcomes.clone());
+        outcomes
+    }
+}
+
+impl<F> IEnvironment for Discretized<F>
+where
+    F: Fn(&[f32], usize, &mut StdRng) -> (Vec<f32>, f32, bool),
+{
+    type State = Bins;
+    type Action = usize;
+
+    /// The estimated probability of `to`, if `with` is its mean reward,
+    /// and 0 for any other reward.
+    fn prob(&self, from: &Bins, take: &usize, to: &Bins, with: &f32) -> f32 {
+        self.outcomes(from, take)
+            .into_iter()
+            .find(|(s, _, r)| s == to && r == with)
+            .map_or(0.0, |(_, p, _)| p)
+    }
+
+    fn prob_transition(&self, from: &Bins, take: &usize, to: &Bins) -> Option<f32> {
+        let outcomes = self.outcomes(from, take);
+        Some(
+            outcomes
+                .iter()
+                .find(|(s, _, _)| s == to)
+                .map_or(0.0, |o| o.1),
+        )
+    }
+
+    fn transition_distribution(&self, from: &Bins, take: &usize) -> Option<Vec<(Bins, f32)>> {
+        Some(
+            self.outcomes(from, take)
+                .into_iter()
+                .map(|(s, p, _)| (s, p))
+                .collect(),
+        )
+    }
+
+    fn expected_reward(&self, from: &Bins, take: &usize) -> Option<f32> {
+        Some(
+            self.outcomes(from, take)
+                .iter()
+                .map(|(_, p, r)| p * r)
+                .sum(),
+        )
+    }
+
+    fn expected_reward_at(&self, from: &Bins, take: &usize, to: &Bins) -> Option<f32> {
+        let outcomes = self.outcomes(from, take);
+        Some(
+            outcomes
+                .iter()
+                .find(|(s, _, _)| s == to)
+                .map_or(0.0, |(_, p, r)| p * r),
+        )
+    }
+
+    fn actions_from(&self, from: &Bins) -> HashSet<usize> {
+        match from {
+            Some(_) => (0..self.actions).collect(),
+            None => HashSet::new(),
+        }
+    }
+
+    fn is_valid_action(&self, from: &Bins, take: &usize) -> bool {
+        from.is_some() && *take < self.actions
+    }
+
+    fn states_from(&self, from: &Bins, take: &usize) -> HashSet<Bins> {
+        self.outcomes(from, take)
+            .into_iter()
+            .map(|(s, _, _)| s)
+            .collect()
+    }
+
+    /// Draws one of the estimated outcomes, with its mean reward,
+    /// without building the distribution first.
+    fn sample_transition(
+        &self,
+        from: &Bins,
+        take: &usize,
+        rng: &mut StdRng,
+    ) -> Option<(Bins, f32)> {
+        let outcomes = self
+            .outcomes(from, take)
+            .into_iter()
+            .map(|(s, p, r)| ((s, r), p))
+            .collect();
+        sample_weighted(outcomes, rng)
+    }
+
+    fn is_terminal(&self, state: &Bins) -> bool {
+        state.is_none()
+    }
+
+    fn gamma(&self) -> f32 {
+        self.gamma
+    }
+
+    fn start_states(&self) -> HashSet<Bins> {
+        self.starts
+            .iter()
+            .map(|point| Some(self.bins_of(point)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+
+    use super::*;
+    use crate::td::q_learning;
+
+    /// A pendulum reduced to its angle, 0 upright: gravity pulls it
+    /// away from upright, a weaker pull than either push, and it
+    /// counts as balanced, ending the episode, within 0.15 of it.
+    fn pendulum(state: &[f32], action: usize, rng: &mut StdRng) -> (Vec<f32>, f32, bool) {
+        let push = if action == 0 { -0.3 } else { 0.3 };
+        let noise = rng.gen_range(-0.05..0.05);
+        let theta = state[0] + push + 0.1 * state[0].sin() + noise;
+        let theta = (theta + PI).rem_euclid(2.0 * PI) - PI;
+        if theta.abs() < 0.15 {
+            (vec![theta], 0.0, true)
+        } else {
+            (vec![theta], -1.0, false)
+        }
+    }
+
+    #[test]
+    fn points_fall_into_the_bins_between_the_bounds() {
+        let env = Discretized::new(pendulum, vec![(-PI, PI)], vec![20], 2);
+        assert_eq!(env.bins_of(&[-PI]), vec![0]);
+        assert_eq!(env.bins_of(&[0.01]), vec![10]);
+        assert_eq!(env.bins_of(&[PI]), vec![19]);
+        assert_eq!(env.bins_of(&[-10.0]), vec![0]);
+        assert!(env.is_terminal(&None));
+        let distribution = env.transition_distribution(&Some(vec![5]), &1).unwrap();
+        let total: f32 = distribution.iter().map(|(_, p)| p).sum();
+        assert!((total - 1.0).abs() < 1e-5);
+        // Estimated once, so asking again gives the same answer.
+        assert_eq!(
+            env.transition_distribution(&Some(vec![5]), &1).unwrap(),
+            distribution
+        );
+    }
+
+    #[test]
+    fn a_discretized_pendulum_is_learnable() {
+        let env = Discretized::new(pendulum, vec![(-PI, PI)], vec![20], 2)
+            .with_start(vec![3.0])
+            .with_gamma(0.95);
+        let q = q_learning(&env, 500, 0.2, None, 0.2, &mut make_rng(0));
+
+        // The greedy policy, run on the continuous dynamics themselves,
+        // balances the pendulum in about the 10 steps it needs.
+        let mut rng = make_rng(1);
+        for _ in 0..10 {
+            let mut theta = vec![3.0];
+            let mut steps = 0;
+            loop {
+                let action = q.greedy_action(&env, &Some(env.bins_of(&theta))).unwrap();
+                let (next, _, done) = pendulum(&theta, action, &mut rng);
+                steps += 1;
+                if done {
+                    break;
+                }
+                assert!(steps < 20, "not balanced after {steps} steps");
+                theta = next;
+            }
+        }
+    }
+}
@@ -0,0 +1,57 @@
+//! Example interface implementations.
+
+use std::collections::HashSet;
+
+use crate::env::IEnvironment;
+
+pub mod bandit;
+pub mod cliff_walking;
+pub mod frozen_lake;
+pub mod grid_world;
+pub mod maximization_bias;
+pub mod random_walk;
+
+pub use bandit::{Arm, Bandit};
+pub use cliff_walking::CliffWalking;
+pub use frozen_lake::FrozenLake;
+pub use grid_world::{render_trajectory, GridRenderable, GridWorld, Move};
+pub use maximization_bias::{BiasAction, BiasState, MaximizationBias};
+pub use random_walk::RandomWalk;
+
+#[derive(Hash, Eq, PartialEq, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DoNothing {
+    Nothing,
+}
+#[derive(Debug, Hash, Eq, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Always {
+    Same,
+}
+
+#[derive(Debug)]
+pub struct Dull;
+impl IEnvironment for Dull {
+    type State = Always;
+    type Action = DoNothing;
+
+    fn actions_from(&self, _: &Self::State) -> HashSet<Self::Action> {
+        HashSet::from_iter(vec![DoNothing::Nothing])
+    }
+    fn states_from(&self, _: &Self::State, _: &Self::Action) -> HashSet<Self::State> {
+        HashSet::from_iter(vec![Always::Same])
+    }
+    fn start_states(&self) -> HashSet<Self::State> {
+        HashSet::from_iter(vec![Always::Same])
+    }
+    fn rewards(&self) -> Vec<f32> {
+        vec![0.0]
+    }
+    fn prob(&self, _: &Self::State, _: &Self::Action, _: &Self::State, with: &f32) -> f32 {
+        if *with == 0.0 {
+            1.0
+        } else {
+            0.0
+        }
+    }
+}
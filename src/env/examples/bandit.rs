@@ -0,0 +1,111 @@
+use std::collections::HashSet;
+
+use crate::env::IEnvironment;
+
+/// The number of reward values a `Gaussian` arm is discretized onto.
+pub const GAUSSIAN_ATOMS: usize = 21;
+
+/// The reward distribution of one bandit arm.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Arm {
+    /// A reward of 1 with probability `p`, and 0 otherwise.
+    Bernoulli(f32),
+    /// A normally distributed reward, discretized onto
+    /// `GAUSSIAN_ATOMS` evenly spaced values spanning three standard
+    /// deviations either side of the mean so that the rewards remain
+    /// enumerable.
+    Gaussian { mean: f32, std: f32 },
+}
+
+impl Arm {
+    pub fn mean(&self) -> f32 {
+        match *self {
+            Arm::Bernoulli(p) => p,
+            Arm::Gaussian { mean, .. } => mean,
+        }
+    }
+
+    /// The reward values this arm can produce, with their
+    /// probabilities.
+    pub fn atoms(&self) -> Vec<(f32, f32)> {
+        match *self {
+            Arm::Bernoulli(p) => vec![(1.0, p), (0.0, 1.0 - p)],
+            Arm::Gaussian { mean, std } if std <= 0.0 => vec![(mean, 1.0)],
+            Arm::Gaussian { mean, std } => {
+                let half = (GAUSSIAN_ATOMS / 2) as f32;
+                let z: Vec<f32> = (0..GAUSSIAN_ATOMS)
+                    .map(|i| 3.0 * (i as f32 - half) / half)
+                    .collect();
+                let weights: Vec<f32> = z.iter().map(|z| (-z * z / 2.0).exp()).collect();
+                let total: f32 = weights.iter().sum();
+                z.iter()
+                    .zip(weights)
+                    .map(|(z, w)| (mean + z * std, w / total))
+                    .collect()
+            }
+        }
+    }
+}
+
+/// A multi-armed bandit: a single, never-terminal state in which each
+/// action pulls one of the arms, identified by index.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bandit {
+    arms: Vec<Arm>,
+}
+
+impl Bandit {
+    pub fn new(arms: Vec<Arm>) -> Self {
+        Bandit { arms }
+    }
+
+    pub fn arms(&self) -> &[Arm] {
+        &self.arms
+    }
+
+    /// The highest expected reward of any arm, against which regret
+    /// is measured.
+    pub fn best_mean(&self) -> f32 {
+        self.arms
+            .iter()
+            .map(Arm::mean)
+            .fold(f32::NEG_INFINITY, f32::max)
+    }
+}
+
+impl IEnvironment for Bandit {
+    type State = ();
+    type Action = usize;
+
+    fn prob(&self, _: &(), take: &usize, _: &(), with: &f32) -> f32 {
+        self.arms.get(*take).map_or(0.0, |arm| {
+            arm.atoms()
+                .into_iter()
+                .filter(|(r, _)| r == with)
+                .map(|(_, p)| p)
+                .sum()
+        })
+    }
+
+    fn actions_from(&self, _: &()) -> HashSet<usize> {
+        (0..self.arms.len()).collect()
+    }
+
+    fn states_from(&self, _: &(), _: &usize) -> HashSet<()> {
+        HashSet::from([()])
+    }
+
+    fn start_states(&self) -> HashSet<()> {
+        HashSet::from([()])
+    }
+
+    fn rewards(&self) -> Vec<f32> {
+        let mut rewards: Vec<f32> = Vec::new();
+        for (r, _) in self.arms.iter().flat_map(Arm::atoms) {
+            if !rewards.contains(&r) {
+                rewards.push(r);
+            }
+        }
+        rewards
+    }
+}
@@ -0,0 +1,154 @@
+use std::collections::HashSet;
+
+use crate::env::examples::grid_world::Cell;
+use crate::env::examples::Move;
+use crate::env::IEnvironment;
+
+/// The cliff walking grid from Sutton and Barto's Example 6.6, on
+/// which SARSA and Q-learning learn different paths.
+///
+/// A 12 by 4 grid whose bottom row runs from the start, in its left
+/// corner, to the goal, in its right, along a cliff. Every step costs
+/// 1, entering the goal included, except that stepping off the cliff
+/// costs 100 and sends the agent back to the start. Moving off the
+/// edge of the grid leaves the agent in place.
+///
+/// The shortest path runs along the edge of the cliff, and is what
+/// Q-learning learns. While exploring though, a step along it
+/// sometimes falls off, so SARSA, which learns the values of the
+/// exploring policy it follows, learns a safer path further away.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CliffWalking;
+
+impl CliffWalking {
+    pub const WIDTH: usize = 12;
+    pub const HEIGHT: usize = 4;
+    pub const START: Cell = (0, Self::HEIGHT - 1);
+    pub const GOAL: Cell = (Self::WIDTH - 1, Self::HEIGHT - 1);
+
+    pub fn is_cliff(&self, (x, y): &Cell) -> bool {
+        *y == Self::HEIGHT - 1 && *x > 0 && *x < Self::WIDTH - 1
+    }
+
+    /// The cell reached by moving `m` from `from`, and the reward for
+    /// it.
+    pub fn moved(&self, from: &Cell, m: Move) -> (Cell, f32) {
+        let (x, y) = *from;
+        let to = match m {
+            Move::Up if y > 0 => (x, y - 1),
+            Move::Down if y + 1 < Self::HEIGHT => (x, y + 1),
+            Move::Left if x > 0 => (x - 1, y),
+            Move::Right if x + 1 < Self::WIDTH => (x + 1, y),
+            _ => *from,
+        };
+        if self.is_cliff(&to) {
+            (Self::START, -100.0)
+        } else {
+            (to, -1.0)
+        }
+    }
+}
+
+impl IEnvironment for CliffWalking {
+    type State = Cell;
+    type Action = Move;
+
+    fn prob(&self, from: &Cell, take: &Move, to: &Cell, with: &f32) -> f32 {
+        if self.is_terminal(from) {
+            return 0.0;
+        }
+        if self.moved(from, *take) == (*to, *with) {
+            1.0
+        } else {
+            0.0
+        }
+    }
+
+    fn actions_from(&self, from: &Cell) -> HashSet<Move> {
+        if self.is_terminal(from) {
+            HashSet::new()
+        } else {
+            HashSet::from(Move::ALL)
+        }
+    }
+
+    fn states_from(&self, from: &Cell, take: &Move) -> HashSet<Cell> {
+        if self.is_terminal(from) {
+            return HashSet::new();
+        }
+        HashSet::from([self.moved(from, *take).0])
+    }
+
+    fn is_terminal(&self, state: &Cell) -> bool {
+        *state == Self::GOAL
+    }
+
+    fn start_states(&self) -> HashSet<Cell> {
+        HashSet::from([Self::START])
+    }
+
+    /// Every cell but the cliff's, which are never occupied.
+    fn all_states(&self) -> HashSet<Cell> {
+        (0..Self::HEIGHT)
+            .flat_map(|y| (0..Self::WIDTH).map(move |x| (x, y)))
+            .filter(|c| !self.is_cliff(c))
+            .collect()
+    }
+
+    fn rewards(&self) -> Vec<f32> {
+        vec![-1.0, -100.0]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dp::{value_iteration, QTable};
+    use crate::rng::make_rng;
+    use crate::td;
+
+    /// The cells visited following `q` greedily from the start, if it
+    /// reaches the goal.
+    fn greedy_path(q: &QTable<CliffWalking>) -> Option<Vec<Cell>> {
+        let mut state = CliffWalking::START;
+        let mut path = vec![state];
+        while state != CliffWalking::GOAL {
+            if path.len() > 50 {
+                return None;
+            }
+            state = CliffWalking
+                .moved(&state, q.greedy_action(&CliffWalking, &state)?)
+                .0;
+            path.push(state);
+        }
+        Some(path)
+    }
+
+    #[test]
+    fn falling_off_the_cliff_returns_to_the_start() {
+        let env = CliffWalking;
+        assert!(crate::validate::validate_all(&env, 1e-6).is_ok());
+        assert_eq!(env.moved(&(0, 3), Move::Right), ((0, 3), -100.0));
+        assert_eq!(env.moved(&(5, 2), Move::Down), ((0, 3), -100.0));
+        assert_eq!(env.moved(&(11, 2), Move::Down), ((11, 3), -1.0));
+        assert_eq!(env.all_states().len(), 48 - 10);
+
+        // Up, along the edge and down again.
+        let (v, _) = value_iteration(&env, None, 1e-6, 1000).unwrap();
+        assert_eq!(v[&CliffWalking::START], -13.0);
+    }
+
+    #[test]
+    fn q_learning_walks_the_edge_and_sarsa_keeps_away() {
+        let mut rng = make_rng(0);
+        let q = td::q_learning(&CliffWalking, 500, 0.5, None, 0.1, &mut rng);
+        let path = greedy_path(&q).unwrap();
+        assert_eq!(path.len(), 14);
+        assert!(path.iter().all(|(_, y)| *y >= 2));
+
+        let q = td::sarsa(&CliffWalking, 500, 0.5, None, 0.1, &mut rng);
+        let path = greedy_path(&q).unwrap();
+        assert!(path.len() > 14);
+        assert!(path.iter().any(|(_, y)| *y < 2));
+    }
+}
@@ -0,0 +1,197 @@
+use std::collections::HashSet;
+
+use crate::env::examples::grid_world::{Cell, Move};
+use crate::env::IEnvironment;
+
+/// The classic 4x4 map, for `FrozenLake::new`.
+pub const FOUR_BY_FOUR: &str = "SFFF FHFH FFFH HFFG";
+
+/// The classic 8x8 map, for `FrozenLake::new`.
+pub const EIGHT_BY_EIGHT: &str = "
+    SFFFFFFF
+    FFFFFFFF
+    FFFHFFFF
+    FFFFFHFF
+    FFFHFFFF
+    FHHFFFHF
+    FHFFHFHF
+    FFFHFFFG
+";
+
+/// The FrozenLake grid from Gym: the agent crosses a frozen lake from
+/// the start to the goal, and falls into the water on entering a
+/// hole.
+///
+/// Holes and the goal are terminal. Entering the goal gives a reward
+/// of 1; every other step, including into a hole, gives 0. Moving off
+/// the edge of the map leaves the agent in place.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrozenLake {
+    rows: Vec<Vec<u8>>,
+    slippery: bool,
+}
+
+impl FrozenLake {
+    /// The lake drawn by `map`, which lists the rows from top to
+    /// bottom, separated by whitespace, with one character per tile:
+    /// `S` for the start, `F` for frozen ice, `H` for a hole and `G`
+    /// for the goal.
+    ///
+    /// On slippery ice, the intended move and the two moves
+    /// perpendicular to it each happen with probability 1/3. Returns
+    /// `None` if the map is empty, its rows differ in length, or it
+    /// has any other character.
+    pub fn new(map: &str, slippery: bool) -> Option<Self> {
+        let rows: Vec<Vec<u8>> = map
+            .split_whitespace()
+            .map(|row| row.as_bytes().to_vec())
+            .collect();
+        let width = rows.first()?.len();
+        let valid = rows.iter().all(|row| {
+            row.len() == width && row.iter().all(|t| matches!(t, b'S' | b'F' | b'H' | b'G'))
+        });
+        valid.then_some(FrozenLake { rows, slippery })
+    }
+
+    /// The classic 4x4 lake.
+    pub fn four_by_four(slippery: bool) -> Self {
+        Self::new(FOUR_BY_FOUR, slippery).expect("the 4x4 map is valid")
+    }
+
+    /// The classic 8x8 lake.
+    pub fn eight_by_eight(slippery: bool) -> Self {
+        Self::new(EIGHT_BY_EIGHT, slippery).expect("the 8x8 map is valid")
+    }
+
+    pub fn width(&self) -> usize {
+        self.rows[0].len()
+    }
+
+    pub fn height(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn is_slippery(&self) -> bool {
+        self.slippery
+    }
+
+    /// The character of the tile at `cell`, or `None` off the map.
+    pub fn tile(&self, cell: &Cell) -> Option<u8> {
+        let (x, y) = *cell;
+        self.rows.get(y)?.get(x).copied()
+    }
+
+    fn cells(&self) -> impl Iterator<Item = (Cell, u8)> + '_ {
+        self.rows
+            .iter()
+            .enumerate()
+            .flat_map(|(y, row)| row.iter().enumerate().map(move |(x, t)| ((x, y), *t)))
+    }
+
+    /// The cell reached by moving `m` from `from`.
+    fn moved(&self, from: &Cell, m: Move) -> Cell {
+        let (x, y) = *from;
+        match m {
+            Move::Up if y > 0 => (x, y - 1),
+            Move::Down if y + 1 < self.height() => (x, y + 1),
+            Move::Left if x > 0 => (x - 1, y),
+            Move::Right if x + 1 < self.width() => (x + 1, y),
+            _ => *from,
+        }
+    }
+
+    /// The moves actually taken when attempting `take`, with their
+    /// probabilities.
+    fn outcomes(&self, take: &Move) -> Vec<(Move, f32)> {
+        if self.slippery {
+            let [a, b] = take.perpendicular();
+            vec![(*take, 1.0 / 3.0), (a, 1.0 / 3.0), (b, 1.0 / 3.0)]
+        } else {
+            vec![(*take, 1.0)]
+        }
+    }
+
+    fn reward(&self, to: &Cell) -> f32 {
+        if self.tile(to) == Some(b'G') {
+            1.0
+        } else {
+            0.0
+        }
+    }
+}
+
+impl IEnvironment for FrozenLake {
+    type State = Cell;
+    type Action = Move;
+
+    fn prob(&self, from: &Cell, take: &Move, to: &Cell, with: &f32) -> f32 {
+        if self.reward(to) != *with {
+            return 0.0;
+        }
+        self.outcomes(take)
+            .into_iter()
+            .filter(|(m, _)| self.moved(from, *m) == *to)
+            .map(|(_, p)| p)
+            .sum()
+    }
+
+    fn actions_from(&self, _: &Cell) -> HashSet<Move> {
+        HashSet::from(Move::ALL)
+    }
+
+    fn states_from(&self, from: &Cell, take: &Move) -> HashSet<Cell> {
+        self.outcomes(take)
+            .into_iter()
+            .map(|(m, _)| self.moved(from, m))
+            .collect()
+    }
+
+    fn is_terminal(&self, state: &Cell) -> bool {
+        matches!(self.tile(state), Some(b'H' | b'G'))
+    }
+
+    fn start_states(&self) -> HashSet<Cell> {
+        self.cells()
+            .filter(|(_, t)| *t == b'S')
+            .map(|(c, _)| c)
+            .collect()
+    }
+
+    fn all_states(&self) -> HashSet<Cell> {
+        self.cells().map(|(c, _)| c).collect()
+    }
+
+    fn rewards(&self) -> Vec<f32> {
+        vec![0.0, 1.0]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dp;
+    use crate::validate;
+
+    #[test]
+    fn the_start_is_six_steps_from_the_goal_on_bare_ice() {
+        let lake = FrozenLake::four_by_four(false);
+        let (v, _) = dp::value_iteration(&lake, Some(0.9), 1e-8, 1000).unwrap();
+        assert!((v[&(0, 0)] - 0.9f32.powi(5)).abs() < 1e-5);
+    }
+
+    #[test]
+    fn slippery_ice_matches_the_published_values() {
+        let lake = FrozenLake::four_by_four(true);
+        assert!(validate::validate_all(&lake, 1e-5).is_ok());
+        let (v, _) = dp::value_iteration(&lake, Some(0.99), 1e-8, 10_000).unwrap();
+        assert!((v[&(0, 0)] - 0.542).abs() < 1e-3);
+    }
+
+    #[test]
+    fn maps_must_be_rectangular_and_known() {
+        assert!(FrozenLake::new("SF FG", false).is_some());
+        assert!(FrozenLake::new("SFF FG", false).is_none());
+        assert!(FrozenLake::new("SX FG", false).is_none());
+        assert!(FrozenLake::new("", false).is_none());
+    }
+}
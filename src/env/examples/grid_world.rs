@@ -0,0 +1,502 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::dp::ValueFunction;
+use crate::env::{IEnvironment, Space};
+use crate::policy::DeterministicPolicy;
+
+/// A cell of a grid, as `(column, row)` with row 0 at the top.
+pub type Cell = (usize, usize);
+
+#[derive(Debug, Hash, Eq, PartialEq, Ord, PartialOrd, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Move {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+impl Move {
+    pub const ALL: [Move; 4] = [Move::Up, Move::Down, Move::Left, Move::Right];
+
+    /// The two moves at right angles to this one.
+    pub fn perpendicular(&self) -> [Move; 2] {
+        match self {
+            Move::Up | Move::Down => [Move::Left, Move::Right],
+            Move::Left | Move::Right => [Move::Up, Move::Down],
+        }
+    }
+
+    fn arrow(&self) -> char {
+        match self {
+            Move::Up => '^',
+            Move::Down => 'v',
+            Move::Left => '<',
+            Move::Right => '>',
+        }
+    }
+}
+
+/// Environments laid out on a grid, whose value functions and
+/// policies can be drawn in it.
+pub trait GridRenderable: IEnvironment + Sized {
+    /// Lays out `v` in the grid, one row per line, with the values
+    /// right-aligned in columns of equal width. Cells that aren't
+    /// states, such as walls, and terminal states are drawn as glyphs
+    /// rather than numbers, and states missing from `v` as `.`.
+    fn render_values(&self, v: &ValueFunction<Self>) -> String;
+
+    /// Draws `policy`'s action in each cell as an arrow.
+    fn render_policy(&self, policy: &DeterministicPolicy<Self>) -> String;
+
+    /// Draws the grid with the agent in `state`.
+    fn render_state(&self, state: &Self::State) -> String;
+}
+
+/// A rectangular grid in which the agent moves up, down, left or
+/// right towards a goal cell.
+///
+/// Moving off the edge of the grid or into a wall leaves the agent
+/// in place. Entering the goal ends the episode with a reward of +1;
+/// every other step gives a reward of `-step_cost`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GridWorld {
+    width: usize,
+    height: usize,
+    start: Cell,
+    goal: Cell,
+    walls: HashSet<Cell>,
+    step_cost: f32,
+    slip: f32,
+}
+
+impl GridWorld {
+    /// A `width` by `height` grid with the given walls, which can't be
+    /// entered, and terminal `goal`, starting from the top-left cell.
+    ///
+    /// With probability `slip` a move slips: instead of the intended
+    /// move, one of the two perpendicular moves is taken with equal
+    /// probability.
+    pub fn new(
+        width: usize,
+        height: usize,
+        walls: impl IntoIterator<Item = Cell>,
+        goal: Cell,
+        step_cost: f32,
+        slip: f32,
+    ) -> Self {
+        GridWorld {
+            width,
+            height,
+            start: (0, 0),
+            goal,
+            walls: walls.into_iter().collect(),
+            step_cost,
+            slip,
+        }
+    }
+
+    /// This grid, with episodes starting from `start` instead.
+    pub fn with_start(mut self, start: Cell) -> Self {
+        self.start = start;
+        self
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn start(&self) -> Cell {
+        self.start
+    }
+
+    pub fn goal(&self) -> Cell {
+        self.goal
+    }
+
+    pub fn is_wall(&self, cell: &Cell) -> bool {
+        self.walls.contains(cell)
+    }
+
+    /// The cell reached by moving `m` from `from`.
+    pub fn moved(&self, from: &Cell, m: Move) -> Cell {
+        let (x, y) = *from;
+        let to = match m {
+            Move::Up if y > 0 => (x, y - 1),
+            Move::Down if y + 1 < self.height => (x, y + 1),
+            Move::Left if x > 0 => (x - 1, y),
+            Move::Right if x + 1 < self.width => (x + 1, y),
+            _ => *from,
+        };
+        if self.is_wall(&to) {
+            *from
+        } else {
+            to
+        }
+    }
+
+    /// The moves actually taken when attempting `take`, with their
+    /// probabilities.
+    fn outcomes(&self, take: &Move) -> impl Iterator<Item = (Move, f32)> {
+        let [a, b] = take.perpendicular();
+        [
+            (*take, 1.0 - self.slip),
+            (a, self.slip / 2.0),
+            (b, self.slip / 2.0),
+        ]
+        .into_iter()
+        .filter(|(_, p)| *p > 0.0)
+    }
+
+    fn reward(&self, to: &Cell) -> f32 {
+        if *to == self.goal {
+            1.0
+        } else {
+            -self.step_cost
+        }
+    }
+
+    /// Renders the grid with `policy`'s action in each cell drawn as
+    /// an arrow.
+    pub fn display_policy<'a>(
+        &'a self,
+        policy: &'a DeterministicPolicy<Self>,
+    ) -> PolicyDisplay<'a> {
+        PolicyDisplay { grid: self, policy }
+    }
+
+    fn render(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        mut glyph: impl FnMut(&Cell) -> Option<char>,
+    ) -> fmt::Result {
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let cell = (x, y);
+                let c = if self.is_wall(&cell) {
+                    '#'
+                } else if cell == self.goal {
+                    'G'
+                } else {
+                    glyph(&cell).unwrap_or('.')
+                };
+                write!(f, "{}", c)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+impl IEnvironment for GridWorld {
+    type State = Cell;
+    type Action = Move;
+
+    fn prob(&self, from: &Cell, take: &Move, to: &Cell, with: &f32) -> f32 {
+        if self.reward(to) != *with {
+            return 0.0;
+        }
+        self.outcomes(take)
+            .filter(|(m, _)| self.moved(from, *m) == *to)
+            .map(|(_, p)| p)
+            .sum()
+    }
+
+    fn actions_from(&self, _: &Cell) -> HashSet<Move> {
+        HashSet::from(Move::ALL)
+    }
+
+    fn states_from(&self, from: &Cell, take: &Move) -> HashSet<Cell> {
+        self.outcomes(take)
+            .map(|(m, _)| self.moved(from, m))
+            .collect()
+    }
+
+    /// Slipping into a wall or the edge can land several outcomes on
+    /// the same cell, which is written once.
+    fn states_from_into(&self, from: &Cell, take: &Move, buf: &mut Vec<Cell>) {
+        buf.clear();
+        for (m, _) in self.outcomes(take) {
+            let to = self.moved(from, m);
+            if !buf.contains(&to) {
+                buf.push(to);
+            }
+        }
+    }
+
+    fn is_terminal(&self, state: &Cell) -> bool {
+        *state == self.goal
+    }
+
+    fn start_states(&self) -> HashSet<Cell> {
+        HashSet::from([self.start])
+    }
+
+    fn action_space(&self) -> Space {
+        Space::Discrete(Move::ALL.len())
+    }
+
+    /// A column and a row, walls included.
+    fn observation_space(&self) -> Space {
+        Space::MultiDiscrete(vec![self.width, self.height])
+    }
+
+    fn all_states(&self) -> HashSet<Cell> {
+        (0..self.height)
+            .flat_map(|y| (0..self.width).map(move |x| (x, y)))
+            .filter(|c| !self.is_wall(c))
+            .collect()
+    }
+
+    fn rewards(&self) -> Vec<f32> {
+        // Listing the same reward twice would double-count it.
+        if self.step_cost == -1.0 {
+            vec![1.0]
+        } else {
+            vec![1.0, -self.step_cost]
+        }
+    }
+
+    /// The step `cost`, `-step_cost` or 0 on entering the goal, and
+    /// the goal `bonus`, 1 on entering it or 0.
+    fn reward_components(&self, _: &Cell, _: &Move, to: &Cell) -> HashMap<&'static str, f32> {
+        let goal = *to == self.goal;
+        HashMap::from([
+            ("cost", if goal { 0.0 } else { -self.step_cost }),
+            ("bonus", if goal { 1.0 } else { 0.0 }),
+        ])
+    }
+}
+
+/// Draws the grid: `#` for walls, `G` for the goal, `S` for the start
+/// and `.` for other cells.
+impl fmt::Display for GridWorld {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.render(f, |c| (*c == self.start).then_some('S'))
+    }
+}
+
+/// A `GridWorld` rendered with a policy's actions; see
+/// `GridWorld::display_policy`.
+pub struct PolicyDisplay<'a> {
+    grid: &'a GridWorld,
+    policy: &'a DeterministicPolicy<GridWorld>,
+}
+
+impl fmt::Display for PolicyDisplay<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.grid
+            .render(f, |c| self.policy.action(c).map(Move::arrow))
+    }
+}
+
+impl GridRenderable for GridWorld {
+    /// Walls are drawn as `#` and the goal as `G`.
+    fn render_values(&self, v: &ValueFunction<Self>) -> String {
+        let cells: Vec<Vec<String>> = (0..self.height)
+            .map(|y| {
+                (0..self.width)
+                    .map(|x| {
+                        let cell = (x, y);
+                        if self.is_wall(&cell) {
+                            "#".to_string()
+                        } else if cell == self.goal {
+                            "G".to_string()
+                        } else {
+                            v.get(&cell)
+                                .map_or_else(|| ".".to_string(), |value| format!("{:.2}", value))
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+        let width = cells.iter().flatten().map(String::len).max().unwrap_or(0);
+        let mut out = String::new();
+        for row in cells {
+            let row: Vec<String> = row.iter().map(|c| format!("{:>width$}", c)).collect();
+            out.push_str(&row.join(" "));
+            out.push('\n');
+        }
+        out
+    }
+
+    fn render_policy(&self, policy: &DeterministicPolicy<Self>) -> String {
+        self.display_policy(policy).to_string()
+    }
+
+    /// The agent is drawn as `A`, including on the goal.
+    fn render_state(&self, state: &Cell) -> String {
+        let mut out = String::new();
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let cell = (x, y);
+                out.push(if cell == *state {
+                    'A'
+                } else if self.is_wall(&cell) {
+                    '#'
+                } else if cell == self.goal {
+                    'G'
+                } else {
+                    '.'
+                });
+            }
+            out.push('\n');
+        }
+        out
+    }
+}
+
+/// One frame per step of `trajectory`, as from `rollout`, ending in
+/// state `end`, for animating it in a terminal: each the grid with the
+/// agent where the step left it, captioned with the step's number,
+/// action and reward.
+///
+/// The last frame also says how the episode ended: in success, at a
+/// terminal state entered with a positive reward, such as a goal, in
+/// failure, at any other terminal state, such as a hole, or cut off
+/// before reaching one. An empty trajectory has no frames.
+pub fn render_trajectory<E>(
+    env: &E,
+    trajectory: &[(E::State, E::Action, f32)],
+    end: &E::State,
+) -> Vec<String>
+where
+    E: GridRenderable,
+    E::Action: fmt::Debug,
+{
+    let mut frames = Vec::with_capacity(trajectory.len());
+    for (t, (_, action, reward)) in trajectory.iter().enumerate() {
+        let at = trajectory.get(t + 1).map_or(end, |(s, _, _)| s);
+        let mut frame = env.render_state(at);
+        frame.push_str(&format!(
+            "step {}/{}: {:?}, reward {}\n",
+            t + 1,
+            trajectory.len(),
+            action,
+            reward
+        ));
+        if t + 1 == trajectory.len() {
+            frame.push_str(if !env.is_terminal(end) {
+                "stopped before the end\n"
+            } else if *reward > 0.0 {
+                "success\n"
+            } else {
+                "failure\n"
+            });
+        }
+        frames.push(frame);
+    }
+    frames
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buffered_next_states_match_the_set() {
+        let grid = GridWorld::new(3, 3, [(1, 1)], (2, 2), 0.04, 0.2);
+        let mut buf = vec![(9, 9)];
+        for s in grid.all_states() {
+            for a in Move::ALL {
+                grid.states_from_into(&s, &a, &mut buf);
+                assert_eq!(buf.len(), grid.states_from(&s, &a).len());
+                assert_eq!(
+                    buf.iter().copied().collect::<HashSet<_>>(),
+                    grid.states_from(&s, &a)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn instances_carry_their_own_layout() {
+        let small = GridWorld::new(2, 2, [], (1, 1), 0.0, 0.0);
+        let walled = GridWorld::new(3, 2, [(1, 0)], (2, 1), 0.5, 0.0);
+        assert_eq!(small.all_states().len(), 4);
+        assert_eq!(walled.all_states().len(), 5);
+        assert_eq!(walled.moved(&(0, 0), Move::Right), (0, 0));
+        assert_eq!(walled.rewards(), vec![1.0, -0.5]);
+        assert!(walled.is_terminal(&(2, 1)));
+        assert!(!small.is_terminal(&(2, 1)));
+    }
+
+    #[test]
+    fn spaces_describe_the_moves_and_the_grid() {
+        let grid = GridWorld::new(3, 2, [(1, 0)], (2, 1), 0.5, 0.0);
+        assert_eq!(grid.action_space(), Space::Discrete(4));
+        assert_eq!(
+            grid.action_space().cardinality(),
+            Some(grid.all_actions().len())
+        );
+        assert_eq!(grid.observation_space(), Space::MultiDiscrete(vec![3, 2]));
+        // The wall is a cell, though not a state.
+        assert_eq!(grid.observation_space().cardinality(), Some(6));
+        assert_eq!(
+            crate::env::BoxedEnv::new(grid).action_space(),
+            Space::Discrete(4)
+        );
+    }
+
+    #[test]
+    fn slipping_keeps_the_dynamics_normalized() {
+        let grid = GridWorld::new(3, 3, [(1, 1)], (2, 2), 0.1, 0.2);
+        assert!(crate::validate::validate_all(&grid, 1e-5).is_ok());
+        assert_eq!(grid.all_states().len(), 8);
+        assert!((grid.prob(&(0, 0), &Move::Right, &(1, 0), &-0.1) - 0.8).abs() < 1e-6);
+        // Slipping up off the edge leaves the agent in place.
+        assert!((grid.prob(&(0, 0), &Move::Right, &(0, 0), &-0.1) - 0.1).abs() < 1e-6);
+        assert_eq!(grid.prob(&(2, 1), &Move::Down, &(2, 2), &1.0), 0.8);
+    }
+
+    #[test]
+    fn displays_the_grid_and_a_policy() {
+        let grid = GridWorld::new(3, 2, [(1, 1)], (2, 0), 0.0, 0.0).with_start((0, 1));
+        assert_eq!(grid.to_string(), "..G\nS#.\n");
+        let policy = DeterministicPolicy::new(
+            [
+                ((0, 0), Move::Right),
+                ((1, 0), Move::Right),
+                ((0, 1), Move::Up),
+            ]
+            .into(),
+        );
+        assert_eq!(grid.display_policy(&policy).to_string(), ">>G\n^#.\n");
+    }
+
+    #[test]
+    fn renders_a_solved_grid() {
+        let grid = GridWorld::new(2, 2, [(1, 1)], (1, 0), 0.5, 0.0).with_start((0, 1));
+        let (v, policy) = crate::dp::value_iteration(&grid, Some(1.0), 1e-6, 100).unwrap();
+        assert_eq!(grid.render_values(&v), "1.00    G\n0.50    #\n");
+        assert_eq!(grid.render_policy(&policy), ">G\n^#\n");
+        assert_eq!(grid.render_state(&(0, 1)), ".G\nA#\n");
+    }
+
+    #[test]
+    fn animates_a_rollout_frame_by_frame() {
+        use crate::simulate::rollout_greedy;
+
+        let grid = GridWorld::new(3, 2, [(1, 1)], (2, 1), 0.1, 0.0).with_start((0, 1));
+        let (_, policy) = crate::dp::value_iteration(&grid, Some(0.9), 1e-6, 100).unwrap();
+        let trajectory = rollout_greedy(&grid, &policy, grid.start(), 10);
+        let frames = render_trajectory(&grid, &trajectory, &grid.goal());
+        assert_eq!(frames.len(), trajectory.len());
+        assert_eq!(frames[0], "A..\n.#G\nstep 1/4: Up, reward -0.1\n");
+        assert_eq!(
+            frames.last().unwrap(),
+            "...\n.#A\nstep 4/4: Down, reward 1\nsuccess\n"
+        );
+
+        // Cut off short of the goal.
+        let frames = render_trajectory(&grid, &trajectory[..2], &(1, 0));
+        assert_eq!(frames.len(), 2);
+        assert!(frames[1].starts_with(".A.\n"));
+        assert!(frames[1].ends_with("stopped before the end\n"));
+    }
+}
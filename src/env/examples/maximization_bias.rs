@@ -0,0 +1,105 @@
+use std::collections::HashSet;
+
+use crate::env::examples::bandit::Arm;
+use crate::env::IEnvironment;
+
+/// The states of `MaximizationBias`.
+#[derive(Hash, Eq, PartialEq, Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BiasState {
+    A,
+    B,
+    End,
+}
+
+/// The actions of `MaximizationBias`: `Left` and `Right` from `A`,
+/// and one of the noisy `Arm`s from `B`.
+#[derive(Hash, Eq, PartialEq, Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BiasAction {
+    Left,
+    Right,
+    Arm(usize),
+}
+
+/// The MDP from Sutton and Barto's Example 6.7, on which Q-learning
+/// suffers from maximization bias.
+///
+/// Episodes start in `A`. Going `Right` ends the episode with reward
+/// 0; going `Left` leads to `B`, also with reward 0, from which each
+/// of `ARMS` actions ends the episode with a reward drawn from a
+/// normal distribution with mean -0.1 and variance 1. `Right` is
+/// optimal, but the maximum over the noisy estimates in `B` is
+/// usually positive early on, which makes `Left` look better.
+pub struct MaximizationBias;
+
+impl MaximizationBias {
+    /// The number of actions from `B`.
+    pub const ARMS: usize = 10;
+
+    /// The reward distribution of every action from `B`.
+    pub const ARM: Arm = Arm::Gaussian {
+        mean: -0.1,
+        std: 1.0,
+    };
+}
+
+impl IEnvironment for MaximizationBias {
+    type State = BiasState;
+    type Action = BiasAction;
+
+    fn prob(&self, from: &BiasState, take: &BiasAction, to: &BiasState, with: &f32) -> f32 {
+        match (from, take, to) {
+            (BiasState::A, BiasAction::Left, BiasState::B)
+            | (BiasState::A, BiasAction::Right, BiasState::End)
+                if *with == 0.0 =>
+            {
+                1.0
+            }
+            (BiasState::B, BiasAction::Arm(i), BiasState::End) if *i < Self::ARMS => Self::ARM
+                .atoms()
+                .into_iter()
+                .filter(|(r, _)| r == with)
+                .map(|(_, p)| p)
+                .sum(),
+            _ => 0.0,
+        }
+    }
+
+    fn actions_from(&self, from: &BiasState) -> HashSet<BiasAction> {
+        match from {
+            BiasState::A => HashSet::from([BiasAction::Left, BiasAction::Right]),
+            BiasState::B => (0..Self::ARMS).map(BiasAction::Arm).collect(),
+            BiasState::End => HashSet::new(),
+        }
+    }
+
+    fn states_from(&self, from: &BiasState, take: &BiasAction) -> HashSet<BiasState> {
+        match (from, take) {
+            (BiasState::A, BiasAction::Left) => HashSet::from([BiasState::B]),
+            (BiasState::A, BiasAction::Right) => HashSet::from([BiasState::End]),
+            (BiasState::B, BiasAction::Arm(i)) if *i < Self::ARMS => {
+                HashSet::from([BiasState::End])
+            }
+            _ => HashSet::new(),
+        }
+    }
+
+    fn is_terminal(&self, state: &BiasState) -> bool {
+        *state == BiasState::End
+    }
+
+    fn start_states(&self) -> HashSet<BiasState> {
+        HashSet::from([BiasState::A])
+    }
+
+    fn rewards(&self) -> Vec<f32> {
+        let mut rewards = vec![0.0];
+        for (r, _) in Self::ARM.atoms() {
+            if !rewards.contains(&r) {
+                rewards.push(r);
+            }
+        }
+        rewards
+    }
+}
@@ -0,0 +1,146 @@
+use std::collections::HashSet;
+
+use crate::dp::ValueFunction;
+use crate::env::examples::Move;
+use crate::env::IEnvironment;
+
+/// The random walk from Sutton and Barto's Example 6.2, the standard
+/// benchmark for prediction.
+///
+/// States `1..=length` lie in a row between the terminal states `0`
+/// and `length + 1`. Each step moves one state `Left` or `Right`, and
+/// entering the right end pays 1, every other step 0. Episodes start
+/// in the middle. Under the uniformly random policy the walk is fair,
+/// so the value of state `i` is its chance of leaving by the right
+/// end, `i / (length + 1)`; see `true_values`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RandomWalk {
+    length: usize,
+}
+
+impl RandomWalk {
+    /// A walk over `length` non-terminal states.
+    ///
+    /// Panics if `length` is 0.
+    pub fn new(length: usize) -> Self {
+        assert!(length > 0, "a random walk needs a state to walk from");
+        RandomWalk { length }
+    }
+
+    pub fn length(&self) -> usize {
+        self.length
+    }
+
+    /// The middle state, rounding down for an even `length`.
+    pub fn start(&self) -> usize {
+        self.length.div_ceil(2)
+    }
+
+    /// The values of the uniformly random policy without discounting:
+    /// `i / (length + 1)` for each non-terminal state `i`, and 0 at
+    /// both ends.
+    pub fn true_values(&self) -> ValueFunction<Self> {
+        let n = (self.length + 1) as f32;
+        self.all_states()
+            .into_iter()
+            .map(|s| {
+                let v = if self.is_terminal(&s) {
+                    0.0
+                } else {
+                    s as f32 / n
+                };
+                (s, v)
+            })
+            .collect()
+    }
+
+    fn moved(&self, from: usize, take: &Move) -> Option<usize> {
+        match take {
+            Move::Left => Some(from - 1),
+            Move::Right => Some(from + 1),
+            Move::Up | Move::Down => None,
+        }
+    }
+}
+
+impl IEnvironment for RandomWalk {
+    type State = usize;
+    type Action = Move;
+
+    fn prob(&self, from: &usize, take: &Move, to: &usize, with: &f32) -> f32 {
+        if self.is_terminal(from) || self.moved(*from, take) != Some(*to) {
+            return 0.0;
+        }
+        let reward = if *to == self.length + 1 { 1.0 } else { 0.0 };
+        if *with == reward {
+            1.0
+        } else {
+            0.0
+        }
+    }
+
+    /// `Left` and `Right`, except at the ends, which are absorbing.
+    fn actions_from(&self, from: &usize) -> HashSet<Move> {
+        if self.is_terminal(from) {
+            HashSet::new()
+        } else {
+            HashSet::from([Move::Left, Move::Right])
+        }
+    }
+
+    fn states_from(&self, from: &usize, take: &Move) -> HashSet<usize> {
+        if self.is_terminal(from) {
+            return HashSet::new();
+        }
+        self.moved(*from, take).into_iter().collect()
+    }
+
+    fn is_terminal(&self, state: &usize) -> bool {
+        *state == 0 || *state > self.length
+    }
+
+    fn start_states(&self) -> HashSet<usize> {
+        HashSet::from([self.start()])
+    }
+
+    fn all_states(&self) -> HashSet<usize> {
+        (0..=self.length + 1).collect()
+    }
+
+    fn rewards(&self) -> Vec<f32> {
+        vec![0.0, 1.0]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dp::evaluate_policy;
+    use crate::policy::UniformRandomPolicy;
+    use crate::testing::{values_approx_eq, Missing};
+
+    #[test]
+    fn uniform_evaluation_recovers_the_true_values() {
+        for length in [1, 5, 19] {
+            let walk = RandomWalk::new(length);
+            let v = evaluate_policy(&walk, &UniformRandomPolicy, None, 1e-7, 100_000).unwrap();
+            let expected = walk.true_values();
+            assert_eq!(v.len(), length + 2);
+            assert!(values_approx_eq(&v, &expected, 1e-4, Missing::Mismatch));
+        }
+    }
+
+    #[test]
+    fn both_ends_are_absorbing() {
+        let walk = RandomWalk::new(5);
+        assert_eq!(walk.start(), 3);
+        assert!(crate::validate::validate_all(&walk, 1e-6).is_ok());
+        for end in [0, 6] {
+            assert!(walk.is_terminal(&end));
+            assert!(walk.actions_from(&end).is_empty());
+            assert!(walk.states_from(&end, &Move::Left).is_empty());
+        }
+        assert_eq!(walk.prob(&5, &Move::Right, &6, &1.0), 1.0);
+        assert_eq!(walk.prob(&1, &Move::Left, &0, &0.0), 1.0);
+    }
+}
@@ -0,0 +1,207 @@
+use std::collections::HashSet;
+
+use crate::env::IEnvironment;
+use crate::rng::StdRng;
+
+/// The environment `E` with each state replaced by the last `N` base
+/// states visited, oldest first, so that a policy can act on recent
+/// history in environments whose base states alone aren't Markov.
+///
+/// Each transition shifts the window along by one, dropping the
+/// oldest state and appending the base environment's next state.
+/// Episodes start from a window filled with the start state, standing
+/// in for the history before it, and end once the newest state is
+/// terminal.
+///
+/// The stacked state space can be as large as `|S|^N`, so
+/// `all_states` is left to its default, the windows reachable from
+/// the start states, rather than every combination of base states;
+/// even so it grows quickly with `N`.
+pub struct FrameStack<E, const N: usize> {
+    env: E,
+}
+
+impl<E: IEnvironment, const N: usize> FrameStack<E, N> {
+    /// Stacks the last `N` states of `env`.
+    ///
+    /// Panics if `N` is 0.
+    pub fn new(env: E) -> Self {
+        assert!(N > 0, "a frame stack must keep at least one state");
+        FrameStack { env }
+    }
+
+    /// The environment being stacked.
+    pub fn env(&self) -> &E {
+        &self.env
+    }
+}
+
+/// The most recent state of `window`.
+fn newest<S, const N: usize>(window: &[S; N]) -> &S {
+    &window[N - 1]
+}
+
+/// `window` shifted along by one, with `to` appended.
+fn shifted<S: Clone, const N: usize>(window: &[S; N], to: S) -> [S; N] {
+    let mut to = Some(to);
+    std::array::from_fn(|i| match window.get(i + 1) {
+        Some(s) => s.clone(),
+        None => to.take().expect("only the last slot is new"),
+    })
+}
+
+/// Whether `to` is `from` shifted along by one.
+fn follows<S: PartialEq, const N: usize>(from: &[S; N], to: &[S; N]) -> bool {
+    from[1..] == to[..N - 1]
+}
+
+impl<E: IEnvironment, const N: usize> IEnvironment for FrameStack<E, N> {
+    type State = [E::State; N];
+    type Action = E::Action;
+
+    fn prob(&self, from: &Self::State, take: &E::Action, to: &Self::State, with: &f32) -> f32 {
+        if follows(from, to) {
+            self.env.prob(newest(from), take, newest(to), with)
+        } else {
+            0.0
+        }
+    }
+
+    fn prob_transition(
+        &self,
+        from: &Self::State,
+        take: &E::Action,
+        to: &Self::State,
+    ) -> Option<f32> {
+        if follows(from, to) {
+            self.env.prob_transition(newest(from), take, newest(to))
+        } else {
+            Some(0.0)
+        }
+    }
+
+    fn transition_distribution(
+        &self,
+        from: &Self::State,
+        take: &E::Action,
+    ) -> Option<Vec<(Self::State, f32)>> {
+        let distribution = self.env.transition_distribution(newest(from), take)?;
+        Some(
+            distribution
+                .into_iter()
+                .map(|(to, p)| (shifted(from, to), p))
+                .collect(),
+        )
+    }
+
+    fn expected_reward(&self, from: &Self::State, take: &E::Action) -> Option<f32> {
+        self.env.expected_reward(newest(from), take)
+    }
+
+    fn expected_reward_at(
+        &self,
+        from: &Self::State,
+        take: &E::Action,
+        to: &Self::State,
+    ) -> Option<f32> {
+        if follows(from, to) {
+            self.env.expected_reward_at(newest(from), take, newest(to))
+        } else {
+            Some(0.0)
+        }
+    }
+
+    fn actions_from(&self, from: &Self::State) -> HashSet<E::Action> {
+        self.env.actions_from(newest(from))
+    }
+
+    fn action_mask(&self, from: &Self::State) -> HashSet<E::Action> {
+        self.env.action_mask(newest(from))
+    }
+
+    fn is_valid_action(&self, from: &Self::State, take: &E::Action) -> bool {
+        self.env.is_valid_action(newest(from), take)
+    }
+
+    fn states_from(&self, from: &Self::State, take: &E::Action) -> HashSet<Self::State> {
+        self.env
+            .states_from(newest(from), take)
+            .into_iter()
+            .map(|to| shifted(from, to))
+            .collect()
+    }
+
+    fn sample_transition(
+        &self,
+        from: &Self::State,
+        take: &E::Action,
+        rng: &mut StdRng,
+    ) -> Option<(Self::State, f32)> {
+        let (to, reward) = self.env.sample_transition(newest(from), take, rng)?;
+        Some((shifted(from, to), reward))
+    }
+
+    fn is_terminal(&self, state: &Self::State) -> bool {
+        self.env.is_terminal(newest(state))
+    }
+
+    fn gamma(&self) -> f32 {
+        self.env.gamma()
+    }
+
+    fn gamma_at(&self, state: &Self::State) -> f32 {
+        self.env.gamma_at(newest(state))
+    }
+
+    /// Each base start state, repeated to fill the window.
+    fn start_states(&self) -> HashSet<Self::State> {
+        self.env
+            .start_states()
+            .into_iter()
+            .map(|s| std::array::from_fn(|_| s.clone()))
+            .collect()
+    }
+
+    fn rewards(&self) -> Vec<f32> {
+        self.env.rewards()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dp::value_iteration;
+    use crate::testing::Chain;
+
+    #[test]
+    fn a_single_frame_is_the_base_environment() {
+        let stacked = FrameStack::<_, 1>::new(Chain);
+        let (base, policy) = value_iteration(&Chain, Some(0.9), 1e-6, 1000).unwrap();
+        let (v, stacked_policy) = value_iteration(&stacked, Some(0.9), 1e-6, 1000).unwrap();
+        assert_eq!(v.len(), base.len());
+        for (s, value) in base {
+            assert_eq!(v[&[s]], value);
+            assert_eq!(stacked_policy.action(&[s]), policy.action(&s));
+        }
+    }
+
+    #[test]
+    fn windows_shift_along_from_a_padded_start() {
+        let stacked = FrameStack::<_, 3>::new(Chain);
+        assert_eq!(stacked.start_states(), HashSet::from([[0, 0, 0]]));
+        assert_eq!(
+            stacked.states_from(&[0, 0, 0], &1),
+            HashSet::from([[0, 0, 1]])
+        );
+        assert_eq!(stacked.prob(&[0, 0, 1], &1, &[0, 1, 2], &0.0), 1.0);
+        assert_eq!(stacked.prob(&[0, 0, 1], &1, &[1, 1, 2], &0.0), 0.0);
+        assert!(stacked.is_terminal(&[1, 2, Chain::GOAL]));
+
+        // Values don't depend on the history, only on the newest state.
+        let (v, _) = value_iteration(&stacked, Some(0.9), 1e-6, 1000).unwrap();
+        let (base, _) = value_iteration(&Chain, Some(0.9), 1e-6, 1000).unwrap();
+        for (window, value) in v {
+            assert!((value - base[newest(&window)]).abs() < 1e-6);
+        }
+    }
+}
@@ -0,0 +1,274 @@
+use std::collections::HashSet;
+use std::hash::Hash;
+
+use crate::env::IEnvironment;
+use crate::replay::Transition;
+use crate::rng::StdRng;
+
+/// The environment `E` as a goal-conditioned task: each state is paired
+/// with a goal `G`, and every step gives a reward of -1 until one
+/// reaches a state whose achieved goal, as `achieved` extracts it,
+/// matches, which gives 0 and ends the episode.
+///
+/// The base environment's own rewards are dropped, though its terminal
+/// states still end episodes, goal reached or not. The goal never
+/// changes within an episode; episodes start from every pairing of a
+/// base start state with one of `goals`. The base environment's
+/// transition probabilities must be defined, as for simulating it.
+pub struct GoalConditioned<E, G, F> {
+    env: E,
+    goals: Vec<G>,
+    achieved: F,
+}
+
+impl<E, G, F> GoalConditioned<E, G, F>
+where
+    E: IEnvironment,
+    G: Hash + Eq + Clone,
+    F: Fn(&E::State) -> G,
+{
+    /// Conditions `env` on reaching one of `goals`, as measured by
+    /// `achieved`.
+    pub fn new(env: E, goals: Vec<G>, achieved: F) -> Self {
+        GoalConditioned {
+            env,
+            goals,
+            achieved,
+        }
+    }
+
+    /// The environment being conditioned.
+    pub fn env(&self) -> &E {
+        &self.env
+    }
+
+    pub fn goals(&self) -> &[G] {
+        &self.goals
+    }
+
+    /// The goal achieved in the base state `state`.
+    pub fn achieved(&self, state: &E::State) -> G {
+        (self.achieved)(state)
+    }
+
+    /// The reward for arriving in the base state `to` while pursuing
+    /// `goal`.
+    pub fn reward(&self, to: &E::State, goal: &G) -> f32 {
+        if self.achieved(to) == *goal {
+            0.0
+        } else {
+            -1.0
+        }
+    }
+
+    /// Hindsight relabeling: the transitions of `episode` again, once
+    /// for each distinct goal achieved at the same step or later, with
+    /// that goal in place of the one pursued, and the reward and `done`
+    /// recomputed for it.
+    ///
+    /// However badly the episode missed its goal, each step is then
+    /// one that moved towards some goal it did achieve, so learning
+    /// from the relabeled transitions, e.g. through a `ReplayBuffer`,
+    /// sees successes even while the real goals are never reached. A
+    /// step from a state that already achieves a goal gets no copy for
+    /// it, as it couldn't have been taken from a terminal state.
+    #[allow(clippy::type_complexity)]
+    pub fn relabel(
+        &self,
+        episode: &[Transition<(E::State, G), E::Action>],
+    ) -> Vec<Transition<(E::State, G), E::Action>> {
+        let mut relabeled = Vec::new();
+        for (t, transition) in episode.iter().enumerate() {
+            let mut goals: Vec<G> = Vec::new();
+            for later in &episode[t..] {
+                let goal = self.achieved(&later.next.0);
+                if !goals.contains(&goal) {
+                    goals.push(goal);
+                }
+            }
+            let (state, _) = &transition.state;
+            for goal in goals {
+                if self.achieved(state) == goal {
+                    continue;
+                }
+                let (next, _) = &transition.next;
+                let next = (next.clone(), goal.clone());
+                relabeled.push(Transition {
+                    state: (state.clone(), goal.clone()),
+                    action: transition.action.clone(),
+                    reward: self.reward(&next.0, &goal),
+                    done: self.is_terminal(&next),
+                    next,
+                });
+            }
+        }
+        relabeled
+    }
+}
+
+impl<E, G, F> IEnvironment for GoalConditioned<E, G, F>
+where
+    E: IEnvironment,
+    G: Hash + Eq + Clone,
+    F: Fn(&E::State) -> G,
+{
+    type State = (E::State, G);
+    type Action = E::Action;
+
+    fn prob(&self, from: &Self::State, take: &E::Action, to: &Self::State, with: &f32) -> f32 {
+        if from.1 != to.1 || self.reward(&to.0, &to.1) != *with {
+            return 0.0;
+        }
+        self.env
+            .prob_transition(&from.0, take, &to.0)
+            .expect("prob_transition must be defined to condition the environment")
+    }
+
+    fn prob_transition(
+        &self,
+        from: &Self::State,
+        take: &E::Action,
+        to: &Self::State,
+    ) -> Option<f32> {
+        if from.1 != to.1 {
+            return Some(0.0);
+        }
+        self.env.prob_transition(&from.0, take, &to.0)
+    }
+
+    fn actions_from(&self, from: &Self::State) -> HashSet<E::Action> {
+        self.env.actions_from(&from.0)
+    }
+
+    fn action_mask(&self, from: &Self::State) -> HashSet<E::Action> {
+        self.env.action_mask(&from.0)
+    }
+
+    fn is_valid_action(&self, from: &Self::State, take: &E::Action) -> bool {
+        self.env.is_valid_action(&from.0, take)
+    }
+
+    fn states_from(&self, from: &Self::State, take: &E::Action) -> HashSet<Self::State> {
+        self.env
+            .states_from(&from.0, take)
+            .into_iter()
+            .map(|to| (to, from.1.clone()))
+            .collect()
+    }
+
+    /// The base environment's sample, with the goal carried along and
+    /// the reward for pursuing it in place of the base one.
+    fn sample_transition(
+        &self,
+        from: &Self::State,
+        take: &E::Action,
+        rng: &mut StdRng,
+    ) -> Option<(Self::State, f32)> {
+        let (to, _) = self.env.sample_transition(&from.0, take, rng)?;
+        let reward = self.reward(&to, &from.1);
+        Some(((to, from.1.clone()), reward))
+    }
+
+    fn is_terminal(&self, state: &Self::State) -> bool {
+        self.env.is_terminal(&state.0) || self.achieved(&state.0) == state.1
+    }
+
+    fn gamma(&self) -> f32 {
+        self.env.gamma()
+    }
+
+    fn gamma_at(&self, state: &Self::State) -> f32 {
+        self.env.gamma_at(&state.0)
+    }
+
+    /// Each base start state, paired with each goal.
+    fn start_states(&self) -> HashSet<Self::State> {
+        let mut starts = HashSet::new();
+        for s in self.env.start_states() {
+            for goal in &self.goals {
+                starts.insert((s.clone(), goal.clone()));
+            }
+        }
+        starts
+    }
+
+    fn rewards(&self) -> Vec<f32> {
+        vec![-1.0, 0.0]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dp::value_iteration;
+    use crate::replay::ReplayBuffer;
+    use crate::testing::Chain;
+
+    fn step(from: i32, action: i32, to: i32, goal: i32) -> Transition<(i32, i32), i32> {
+        Transition {
+            state: (from, goal),
+            action,
+            reward: -1.0,
+            next: (to, goal),
+            done: false,
+        }
+    }
+
+    #[test]
+    fn each_goal_is_reached_by_the_shortest_path() {
+        let env = GoalConditioned::new(Chain, vec![1, 2, 3], |s: &i32| *s);
+        assert_eq!(env.start_states().len(), 3);
+        let (v, policy) = value_iteration(&env, Some(1.0), 1e-6, 1000).unwrap();
+        for goal in [1, 2, 3] {
+            // Every step but the last costs 1.
+            assert_eq!(v[&(0, goal)], 1.0 - goal as f32);
+            assert_eq!(policy.action(&(0, goal)), Some(&1));
+            assert!(env.is_terminal(&(goal, goal)));
+        }
+    }
+
+    #[test]
+    fn relabeling_a_failed_episode_gives_successes() {
+        let env = GoalConditioned::new(Chain, vec![3], |s: &i32| *s);
+        // Aiming for 3, but only getting as far as 2.
+        let episode = [
+            step(0, 1, 1, 3),
+            step(1, -1, 0, 3),
+            step(0, 1, 1, 3),
+            step(1, 1, 2, 3),
+        ];
+        assert!(episode
+            .iter()
+            .all(|t| t.reward == env.reward(&t.next.0, &t.next.1)));
+        let relabeled = env.relabel(&episode);
+        let successes: Vec<_> = relabeled.iter().filter(|t| t.done).collect();
+        assert!(successes
+            .iter()
+            .all(|t| t.reward == 0.0 && t.next.0 == t.next.1));
+        assert!(successes.contains(&&Transition {
+            state: (1, 2),
+            action: 1,
+            reward: 0.0,
+            next: (2, 2),
+            done: true,
+        }));
+        // The first step achieves 1, 0 and 2 later, but 0 is where it
+        // starts.
+        let first: Vec<_> = relabeled
+            .iter()
+            .filter(|t| t.state.0 == 0)
+            .take(2)
+            .collect();
+        assert_eq!(first[0].state.1, 1);
+        assert!(first[0].done);
+        assert_eq!(first[1].state.1, 2);
+        assert!(!first[1].done);
+        assert!(relabeled.iter().all(|t| t.state.0 != t.state.1));
+
+        let mut buffer = ReplayBuffer::new(16);
+        for t in episode.into_iter().chain(relabeled) {
+            buffer.push(t);
+        }
+        assert_eq!(buffer.len(), 4 + 7);
+    }
+}
@@ -0,0 +1,220 @@
+use std::collections::HashSet;
+use std::hash::Hash;
+
+use crate::env::{search, IEnvironment};
+
+/// An environment with vector-valued rewards, one component per
+/// objective, such as performance and safety.
+///
+/// The methods mirror those of `IEnvironment`, except that rewards are
+/// vectors, and must be enumerable. `Scalarized` turns a multi-reward
+/// environment into an ordinary `IEnvironment` by weighting the
+/// objectives, so that every algorithm applies unchanged.
+pub trait IMultiRewardEnvironment {
+    type Action: Hash + Eq + Clone;
+    type State: Hash + Eq + Clone;
+
+    /// The probability of transitioning from state `from` to state
+    /// `to`, taking action `take` and receiving the reward vector
+    /// `with`.
+    fn prob(&self, from: &Self::State, take: &Self::Action, to: &Self::State, with: &[f32]) -> f32;
+
+    /// Every possible reward vector.
+    fn rewards(&self) -> Vec<Vec<f32>>;
+
+    fn actions_from(&self, from: &Self::State) -> HashSet<Self::Action>;
+    fn states_from(&self, from: &Self::State, take: &Self::Action) -> HashSet<Self::State>;
+
+    fn is_terminal(&self, _state: &Self::State) -> bool {
+        false
+    }
+
+    fn gamma(&self) -> f32 {
+        1.0
+    }
+
+    fn start_states(&self) -> HashSet<Self::State> {
+        HashSet::new()
+    }
+
+    /// Every state in the environment. By default, those reachable
+    /// from `start_states`, as for `reachable_states`.
+    fn all_states(&self) -> HashSet<Self::State> {
+        search(self.start_states(), |from| {
+            if self.is_terminal(from) {
+                return Vec::new();
+            }
+            self.actions_from(from)
+                .into_iter()
+                .flat_map(|take| self.states_from(from, &take))
+                .collect()
+        })
+    }
+}
+
+/// The multi-reward environment `E` as an ordinary environment, whose
+/// reward is the weighted sum `weights . r` of each reward vector `r`.
+///
+/// Solving it, e.g. with `value_iteration`, finds the policy optimal
+/// for that trade-off between the objectives.
+pub struct Scalarized<E> {
+    env: E,
+    weights: Vec<f32>,
+    rewards: Vec<f32>,
+}
+
+impl<E: IMultiRewardEnvironment> Scalarized<E> {
+    /// Scalarizes `env` by `weights`, one per objective.
+    ///
+    /// Panics if a reward vector doesn't have one component per weight.
+    pub fn new(env: E, weights: Vec<f32>) -> Self {
+        let mut scalarized = Scalarized {
+            env,
+            weights,
+            rewards: Vec::new(),
+        };
+        let mut rewards: Vec<f32> = scalarized
+            .env
+            .rewards()
+            .iter()
+            .map(|r| scalarized.scalarize(r))
+            .collect();
+        rewards.sort_by(f32::total_cmp);
+        rewards.dedup();
+        scalarized.rewards = rewards;
+        scalarized
+    }
+
+    /// The environment being scalarized.
+    pub fn env(&self) -> &E {
+        &self.env
+    }
+
+    pub fn weights(&self) -> &[f32] {
+        &self.weights
+    }
+
+    /// The weighted sum of the reward vector `reward`.
+    pub fn scalarize(&self, reward: &[f32]) -> f32 {
+        assert_eq!(
+            reward.len(),
+            self.weights.len(),
+            "reward vector has the wrong number of objectives"
+        );
+        self.weights.iter().zip(reward).map(|(w, r)| w * r).sum()
+    }
+}
+
+impl<E: IMultiRewardEnvironment> IEnvironment for Scalarized<E> {
+    type State = E::State;
+    type Action = E::Action;
+
+    /// The total probability of the reward vectors that scalarize to
+    /// `with`.
+    fn prob(&self, from: &E::State, take: &E::Action, to: &E::State, with: &f32) -> f32 {
+        self.env
+            .rewards()
+            .iter()
+            .filter(|r| self.scalarize(r) == *with)
+            .map(|r| self.env.prob(from, take, to, r))
+            .sum()
+    }
+
+    fn actions_from(&self, from: &E::State) -> HashSet<E::Action> {
+        self.env.actions_from(from)
+    }
+
+    fn states_from(&self, from: &E::State, take: &E::Action) -> HashSet<E::State> {
+        self.env.states_from(from, take)
+    }
+
+    fn is_terminal(&self, state: &E::State) -> bool {
+        self.env.is_terminal(state)
+    }
+
+    fn gamma(&self) -> f32 {
+        self.env.gamma()
+    }
+
+    fn start_states(&self) -> HashSet<E::State> {
+        self.env.start_states()
+    }
+
+    fn all_states(&self) -> HashSet<E::State> {
+        self.env.all_states()
+    }
+
+    /// Every scalarized reward, as enumerated by `Scalarized::new`.
+    fn rewards(&self) -> Vec<f32> {
+        self.rewards.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dp::value_iteration;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    enum Pace {
+        Fast,
+        Slow,
+    }
+
+    /// A single decision from state 0 into the terminal state 1,
+    /// rewarding performance and safety. `Slow` scores 1 for
+    /// performance, safely; `Fast` scores 2, but half the time costs 2
+    /// in safety.
+    struct Race;
+
+    impl IMultiRewardEnvironment for Race {
+        type State = u8;
+        type Action = Pace;
+
+        fn prob(&self, from: &u8, take: &Pace, to: &u8, with: &[f32]) -> f32 {
+            if (*from, *to) != (0, 1) {
+                return 0.0;
+            }
+            match (take, with) {
+                (Pace::Slow, [1.0, 0.0]) => 1.0,
+                (Pace::Fast, [2.0, 0.0] | [2.0, -2.0]) => 0.5,
+                _ => 0.0,
+            }
+        }
+
+        fn rewards(&self) -> Vec<Vec<f32>> {
+            vec![vec![1.0, 0.0], vec![2.0, 0.0], vec![2.0, -2.0]]
+        }
+
+        fn actions_from(&self, _: &u8) -> HashSet<Pace> {
+            HashSet::from([Pace::Fast, Pace::Slow])
+        }
+
+        fn states_from(&self, _: &u8, _: &Pace) -> HashSet<u8> {
+            HashSet::from([1])
+        }
+
+        fn is_terminal(&self, state: &u8) -> bool {
+            *state == 1
+        }
+
+        fn start_states(&self) -> HashSet<u8> {
+            HashSet::from([0])
+        }
+    }
+
+    #[test]
+    fn weights_trade_off_the_objectives() {
+        let reckless = Scalarized::new(Race, vec![1.0, 0.5]);
+        assert_eq!(reckless.rewards(), vec![1.0, 2.0]);
+        assert_eq!(reckless.prob(&0, &Pace::Fast, &1, &1.0), 0.5);
+        let (v, policy) = value_iteration(&reckless, None, 1e-6, 100).unwrap();
+        assert_eq!(policy.action(&0), Some(&Pace::Fast));
+        assert_eq!(v[&0], 1.5);
+
+        let careful = Scalarized::new(Race, vec![1.0, 2.0]);
+        let (v, policy) = value_iteration(&careful, None, 1e-6, 100).unwrap();
+        assert_eq!(policy.action(&0), Some(&Pace::Slow));
+        assert_eq!(v[&0], 1.0);
+    }
+}
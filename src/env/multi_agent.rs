@@ -0,0 +1,83 @@
+use std::collections::HashSet;
+use std::hash::Hash;
+
+use crate::env::search;
+
+/// The action two agents take together in a simultaneous-move game:
+/// the first agent's and the second's.
+pub type JointAction<E> = (
+    <E as IMultiAgentEnvironment>::Action1,
+    <E as IMultiAgentEnvironment>::Action2,
+);
+
+/// A Markov game between two agents, who each choose an action at
+/// once from every state, and each receive their own reward.
+///
+/// The methods mirror those of `IEnvironment`, except that actions are
+/// joint, one for each agent, and rewards belong to an agent, so that
+/// the transition to a next state is the same for both. Agent 0 takes
+/// the actions of type `Action1`, and agent 1 those of type `Action2`.
+/// In a zero-sum game agent 1's reward is the negative of agent 0's.
+pub trait IMultiAgentEnvironment {
+    type State: Hash + Eq + Clone;
+    type Action1: Hash + Eq + Clone;
+    type Action2: Hash + Eq + Clone;
+
+    /// The probability of transitioning from state `from` to state
+    /// `to` when the agents take the joint action `take`.
+    fn prob(
+        &self,
+        from: &Self::State,
+        take: &(Self::Action1, Self::Action2),
+        to: &Self::State,
+    ) -> f32;
+
+    /// The reward agent `agent`, 0 or 1, receives on transitioning
+    /// from `from` to `to` under the joint action `take`.
+    fn rewards_for(
+        &self,
+        agent: usize,
+        from: &Self::State,
+        take: &(Self::Action1, Self::Action2),
+        to: &Self::State,
+    ) -> f32;
+
+    /// The actions available to each agent in state `from`.
+    fn actions_from(&self, from: &Self::State) -> (HashSet<Self::Action1>, HashSet<Self::Action2>);
+
+    fn states_from(
+        &self,
+        from: &Self::State,
+        take: &(Self::Action1, Self::Action2),
+    ) -> HashSet<Self::State>;
+
+    fn is_terminal(&self, _state: &Self::State) -> bool {
+        false
+    }
+
+    fn gamma(&self) -> f32 {
+        1.0
+    }
+
+    fn start_states(&self) -> HashSet<Self::State> {
+        HashSet::new()
+    }
+
+    /// Every state in the environment. By default, those reachable
+    /// from `start_states` under any joint action.
+    fn all_states(&self) -> HashSet<Self::State> {
+        search(self.start_states(), |from| {
+            if self.is_terminal(from) {
+                return Vec::new();
+            }
+            let (first, second) = self.actions_from(from);
+            let mut next = Vec::new();
+            for a in &first {
+                for o in &second {
+                    next.extend(self.states_from(from, &(a.clone(), o.clone())));
+                }
+            }
+            next
+        })
+    }
+}
@@ -0,0 +1,221 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::env::IEnvironment;
+use crate::rng::StdRng;
+use crate::validate::total_probability;
+
+/// The environment `E` with its dynamics renormalized: for each
+/// state-action pair, `prob` is divided by its total over
+/// `states_from` and `rewards`, the total `validate_dynamics` checks,
+/// so that it sums to exactly 1.
+///
+/// This is meant for prototyping with dynamics that are slightly off,
+/// not as a substitute for fixing them. Pairs with a total of 0 are
+/// left alone, since there is nothing to rescale; `total` reports
+/// them, as it does the original total of every pair.
+pub struct NormalizedEnv<E: IEnvironment> {
+    env: E,
+    totals: HashMap<(E::State, E::Action), f32>,
+}
+
+impl<E: IEnvironment> NormalizedEnv<E> {
+    /// Normalizes `env`.
+    ///
+    /// The totals are computed here, once, for every action from every
+    /// non-terminal state in the environment's `all_states`; pairs
+    /// outside those have theirs recomputed whenever they're needed.
+    pub fn new(env: E) -> Self {
+        let mut totals = HashMap::new();
+        for s in env.all_states().into_iter().filter(|s| !env.is_terminal(s)) {
+            for a in env.actions_from(&s) {
+                let total = total_probability(&env, &s, &a);
+                totals.insert((s.clone(), a), total);
+            }
+        }
+        NormalizedEnv { env, totals }
+    }
+
+    /// The environment being normalized.
+    pub fn env(&self) -> &E {
+        &self.env
+    }
+
+    /// The total probability of taking `take` from `from` in the
+    /// original environment.
+    pub fn total(&self, from: &E::State, take: &E::Action) -> f32 {
+        self.totals
+            .get(&(from.clone(), take.clone()))
+            .copied()
+            .unwrap_or_else(|| total_probability(&self.env, from, take))
+    }
+
+    /// What the probabilities of taking `take` from `from` are divided
+    /// by: their total, or 1 if that is 0.
+    fn scale(&self, from: &E::State, take: &E::Action) -> f32 {
+        let total = self.total(from, take);
+        if total > 0.0 {
+            total
+        } else {
+            1.0
+        }
+    }
+}
+
+impl<E: IEnvironment> IEnvironment for NormalizedEnv<E> {
+    type State = E::State;
+    type Action = E::Action;
+
+    fn prob(&self, from: &E::State, take: &E::Action, to: &E::State, with: &f32) -> f32 {
+        self.env.prob(from, take, to, with) / self.scale(from, take)
+    }
+
+    fn prob_transition(&self, from: &E::State, take: &E::Action, to: &E::State) -> Option<f32> {
+        Some(self.env.prob_transition(from, take, to)? / self.scale(from, take))
+    }
+
+    fn transition_distribution(
+        &self,
+        from: &E::State,
+        take: &E::Action,
+    ) -> Option<Vec<(E::State, f32)>> {
+        let scale = self.scale(from, take);
+        let distribution = self.env.transition_distribution(from, take)?;
+        Some(
+            distribution
+                .into_iter()
+                .map(|(to, p)| (to, p / scale))
+                .collect(),
+        )
+    }
+
+    fn expected_reward(&self, from: &E::State, take: &E::Action) -> Option<f32> {
+        Some(self.env.expected_reward(from, take)? / self.scale(from, take))
+    }
+
+    fn expected_reward_at(&self, from: &E::State, take: &E::Action, to: &E::State) -> Option<f32> {
+        Some(self.env.expected_reward_at(from, take, to)? / self.scale(from, take))
+    }
+
+    fn actions_from(&self, from: &E::State) -> HashSet<E::Action> {
+        self.env.actions_from(from)
+    }
+
+    fn action_mask(&self, from: &E::State) -> HashSet<E::Action> {
+        self.env.action_mask(from)
+    }
+
+    fn is_valid_action(&self, from: &E::State, take: &E::Action) -> bool {
+        self.env.is_valid_action(from, take)
+    }
+
+    fn states_from(&self, from: &E::State, take: &E::Action) -> HashSet<E::State> {
+        self.env.states_from(from, take)
+    }
+
+    fn states_from_into(&self, from: &E::State, take: &E::Action, buf: &mut Vec<E::State>) {
+        self.env.states_from_into(from, take, buf)
+    }
+
+    /// The base environment's sample, since sampling already draws in
+    /// proportion to the probabilities, whatever their total.
+    fn sample_transition(
+        &self,
+        from: &E::State,
+        take: &E::Action,
+        rng: &mut StdRng,
+    ) -> Option<(E::State, f32)> {
+        self.env.sample_transition(from, take, rng)
+    }
+
+    fn is_terminal(&self, state: &E::State) -> bool {
+        self.env.is_terminal(state)
+    }
+
+    fn gamma(&self) -> f32 {
+        self.env.gamma()
+    }
+
+    fn gamma_at(&self, state: &E::State) -> f32 {
+        self.env.gamma_at(state)
+    }
+
+    fn start_states(&self) -> HashSet<E::State> {
+        self.env.start_states()
+    }
+
+    fn all_states(&self) -> HashSet<E::State> {
+        self.env.all_states()
+    }
+
+    fn rewards(&self) -> Vec<f32> {
+        self.env.rewards()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dp::value_iteration;
+    use crate::testing::Chain;
+    use crate::validate::{validate_all, validate_dynamics};
+
+    /// `Chain`, but losing a fifth of the probability of every
+    /// transition, and all of it moving left from state 2.
+    struct Leaky;
+
+    impl IEnvironment for Leaky {
+        type State = i32;
+        type Action = i32;
+
+        fn prob(&self, from: &i32, take: &i32, to: &i32, with: &f32) -> f32 {
+            if (*from, *take) == (2, -1) {
+                0.0
+            } else {
+                0.8 * Chain.prob(from, take, to, with)
+            }
+        }
+
+        fn actions_from(&self, from: &i32) -> HashSet<i32> {
+            Chain.actions_from(from)
+        }
+
+        fn states_from(&self, from: &i32, take: &i32) -> HashSet<i32> {
+            Chain.states_from(from, take)
+        }
+
+        fn is_terminal(&self, state: &i32) -> bool {
+            Chain.is_terminal(state)
+        }
+
+        fn start_states(&self) -> HashSet<i32> {
+            Chain.start_states()
+        }
+
+        fn rewards(&self) -> Vec<f32> {
+            Chain.rewards()
+        }
+    }
+
+    #[test]
+    fn normalized_dynamics_validate() {
+        assert!(validate_dynamics(&Leaky, &0, &1, 1e-6).is_err());
+        let normalized = NormalizedEnv::new(Leaky);
+        for s in 0..Chain::GOAL {
+            assert_eq!(validate_dynamics(&normalized, &s, &1, 1e-6), Ok(()));
+        }
+        assert_eq!(normalized.prob_transition(&1, &1, &2), Some(1.0));
+        assert_eq!(normalized.total(&1, &1), 0.8);
+
+        // Nothing to rescale moving left from 2.
+        assert_eq!(normalized.total(&2, &-1), 0.0);
+        assert_eq!(validate_all(&normalized, 1e-6), Err((2, -1, 0.0)));
+    }
+
+    #[test]
+    fn normalizing_valid_dynamics_changes_nothing() {
+        let (v, _) = value_iteration(&Chain, Some(0.9), 1e-6, 1000).unwrap();
+        let (normalized, _) =
+            value_iteration(&NormalizedEnv::new(Chain), Some(0.9), 1e-6, 1000).unwrap();
+        assert_eq!(v, normalized);
+    }
+}
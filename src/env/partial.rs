@@ -0,0 +1,370 @@
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+
+use crate::env::{IEnvironment, Space};
+use crate::rng::StdRng;
+
+/// A partially observable environment: the dynamics are those of the
+/// underlying `IEnvironment`, but an agent only sees an observation
+/// emitted from each state, not the state itself.
+pub trait IPartialEnvironment: IEnvironment {
+    type Observation: Hash + Eq + Clone;
+
+    /// Samples the observation emitted on arriving in `state`.
+    fn observe(&self, state: &Self::State, rng: &mut StdRng) -> Self::Observation;
+
+    /// The probability that `state` emits `obs`.
+    fn obs_prob(&self, state: &Self::State, obs: &Self::Observation) -> f32;
+}
+
+/// A belief state: a probability distribution over the true states of
+/// a partially observable environment, updated by Bayesian filtering
+/// as actions are taken and observations arrive.
+///
+/// The probabilities always sum to 1, and states with probability 0
+/// aren't stored.
+#[derive(Debug, Clone)]
+pub struct BeliefState<E: IEnvironment> {
+    probs: HashMap<E::State, f32>,
+}
+
+impl<E: IPartialEnvironment> BeliefState<E> {
+    /// The belief given by `weights`, normalized to sum to 1, or
+    /// `None` if no weight is positive.
+    pub fn new(weights: HashMap<E::State, f32>) -> Option<Self> {
+        normalized(weights).map(|probs| BeliefState { probs })
+    }
+
+    /// The uniform belief over `env.start_states()`, or `None` if
+    /// there are none.
+    pub fn from_start(env: &E) -> Option<Self> {
+        Self::new(env.start_states().into_iter().map(|s| (s, 1.0)).collect())
+    }
+
+    /// The probability of the true state being `state`.
+    pub fn prob(&self, state: &E::State) -> f32 {
+        self.probs.get(state).copied().unwrap_or(0.0)
+    }
+
+    pub fn probs(&self) -> &HashMap<E::State, f32> {
+        &self.probs
+    }
+
+    /// The most probable state, ties broken arbitrarily.
+    pub fn most_likely(&self) -> Option<&E::State> {
+        self.probs
+            .iter()
+            .max_by(|a, b| a.1.total_cmp(b.1))
+            .map(|(s, _)| s)
+    }
+
+    /// Filters the belief through taking `action` and then observing
+    /// `obs`: `b'(s') ∝ O(obs|s') * sum_s P(s'|s,action) * b(s)`, with
+    /// the transition probabilities from `prob_transition`.
+    ///
+    /// If the prediction gives `obs` no probability at all, the belief
+    /// is reset to `O(obs|s')` over `env.all_states()` instead, and if
+    /// no state can emit `obs` it is left unchanged. Returns `None`,
+    /// leaving the belief unchanged, if `prob_transition` isn't
+    /// available for some transition.
+    pub fn update(&mut self, env: &E, action: &E::Action, obs: &E::Observation) -> Option<()> {
+        let mut predicted: HashMap<E::State, f32> = HashMap::new();
+        for (s, p) in &self.probs {
+            for next in env.states_from(s, action) {
+                let t = env.prob_transition(s, action, &next)?;
+                *predicted.entry(next).or_insert(0.0) += p * t;
+            }
+        }
+        let filtered = predicted
+            .into_iter()
+            .map(|(s, p)| {
+                let o = env.obs_prob(&s, obs);
+                (s, p * o)
+            })
+            .collect();
+        if let Some(probs) = normalized(filtered) {
+            self.probs = probs;
+        } else if let Some(probs) = normalized(
+            env.all_states()
+                .into_iter()
+                .map(|s| {
+                    let o = env.obs_prob(&s, obs);
+                    (s, o)
+                })
+                .collect(),
+        ) {
+            self.probs = probs;
+        }
+        Some(())
+    }
+}
+
+/// `weights` without its non-positive entries, scaled to sum to 1, or
+/// `None` if nothing is left.
+fn normalized<S: Hash + Eq>(weights: HashMap<S, f32>) -> Option<HashMap<S, f32>> {
+    let positive: HashMap<S, f32> = weights.into_iter().filter(|(_, w)| *w > 0.0).collect();
+    let total: f32 = positive.values().sum();
+    if total <= 0.0 || !total.is_finite() {
+        return None;
+    }
+    Some(positive.into_iter().map(|(s, w)| (s, w / total)).collect())
+}
+
+/// The environment `E` as a trivially partially observable one, whose
+/// observation of each state is the state itself.
+pub struct FullyObservable<E>(E);
+
+impl<E: IEnvironment> FullyObservable<E> {
+    pub fn new(env: E) -> Self {
+        FullyObservable(env)
+    }
+
+    /// The environment being observed.
+    pub fn env(&self) -> &E {
+        &self.0
+    }
+}
+
+impl<E: IEnvironment + Default> Default for FullyObservable<E> {
+    fn default() -> Self {
+        Self::new(E::default())
+    }
+}
+
+impl<E: IEnvironment> IEnvironment for FullyObservable<E> {
+    type State = E::State;
+    type Action = E::Action;
+
+    fn prob(&self, from: &E::State, take: &E::Action, to: &E::State, with: &f32) -> f32 {
+        self.0.prob(from, take, to, with)
+    }
+
+    fn prob_transition(&self, from: &E::State, take: &E::Action, to: &E::State) -> Option<f32> {
+        self.0.prob_transition(from, take, to)
+    }
+
+    fn transition_distribution(
+        &self,
+        from: &E::State,
+        take: &E::Action,
+    ) -> Option<Vec<(E::State, f32)>> {
+        self.0.transition_distribution(from, take)
+    }
+
+    fn expected_reward(&self, from: &E::State, take: &E::Action) -> Option<f32> {
+        self.0.expected_reward(from, take)
+    }
+
+    fn expected_reward_at(&self, from: &E::State, take: &E::Action, to: &E::State) -> Option<f32> {
+        self.0.expected_reward_at(from, take, to)
+    }
+
+    fn actions_from(&self, from: &E::State) -> HashSet<E::Action> {
+        self.0.actions_from(from)
+    }
+
+    fn action_mask(&self, from: &E::State) -> HashSet<E::Action> {
+        self.0.action_mask(from)
+    }
+
+    fn is_valid_action(&self, from: &E::State, take: &E::Action) -> bool {
+        self.0.is_valid_action(from, take)
+    }
+
+    fn states_from(&self, from: &E::State, take: &E::Action) -> HashSet<E::State> {
+        self.0.states_from(from, take)
+    }
+
+    fn states_from_into(&self, from: &E::State, take: &E::Action, buf: &mut Vec<E::State>) {
+        self.0.states_from_into(from, take, buf)
+    }
+
+    fn sample_transition(
+        &self,
+        from: &E::State,
+        take: &E::Action,
+        rng: &mut StdRng,
+    ) -> Option<(E::State, f32)> {
+        self.0.sample_transition(from, take, rng)
+    }
+
+    fn is_terminal(&self, state: &E::State) -> bool {
+        self.0.is_terminal(state)
+    }
+
+    fn gamma(&self) -> f32 {
+        self.0.gamma()
+    }
+
+    fn gamma_at(&self, state: &E::State) -> f32 {
+        self.0.gamma_at(state)
+    }
+
+    fn start_states(&self) -> HashSet<E::State> {
+        self.0.start_states()
+    }
+
+    fn all_states(&self) -> HashSet<E::State> {
+        self.0.all_states()
+    }
+
+    fn all_actions(&self) -> HashSet<E::Action> {
+        self.0.all_actions()
+    }
+
+    fn action_space(&self) -> Space {
+        self.0.action_space()
+    }
+
+    fn observation_space(&self) -> Space {
+        self.0.observation_space()
+    }
+
+    fn rewards(&self) -> Vec<f32> {
+        self.0.rewards()
+    }
+}
+
+impl<E: IEnvironment> IPartialEnvironment for FullyObservable<E> {
+    type Observation = E::State;
+
+    fn observe(&self, state: &E::State, _: &mut StdRng) -> E::State {
+        state.clone()
+    }
+
+    fn obs_prob(&self, state: &E::State, obs: &E::State) -> f32 {
+        if state == obs {
+            1.0
+        } else {
+            0.0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::Rng;
+
+    use super::*;
+    use crate::rng::make_rng;
+    use crate::simulate::Episodic;
+    use crate::testing::Chain;
+    use crate::validate;
+
+    #[test]
+    fn full_observations_are_the_states() {
+        let env = FullyObservable::new(Chain);
+        assert!(validate::validate_all(&env, 1e-5).is_ok());
+        let mut rng = make_rng(0);
+        for s in env.all_states() {
+            assert_eq!(env.observe(&s, &mut rng), s);
+            assert_eq!(env.obs_prob(&s, &s), 1.0);
+            assert_eq!(env.obs_prob(&s, &(s + 1)), 0.0);
+        }
+
+        let mut episode = Episodic::new(env, rng);
+        assert_eq!(episode.reset_observed(), 0);
+        for expected in 1..=Chain::GOAL {
+            let (obs, _, done) = episode.step_observed(&1).unwrap();
+            assert_eq!(obs, expected);
+            assert_eq!(episode.state(), Some(&obs));
+            assert_eq!(done, expected == Chain::GOAL);
+        }
+    }
+
+    /// A ring of six cells, around which the single action moves one
+    /// cell with probability 0.8. Each cell is observed correctly with
+    /// probability 0.6, and otherwise as one of its neighbours.
+    struct Ring;
+
+    impl IEnvironment for Ring {
+        type State = u8;
+        type Action = ();
+
+        fn prob(&self, from: &u8, _: &(), to: &u8, with: &f32) -> f32 {
+            if *with != 0.0 {
+                0.0
+            } else if *to == (from + 1) % 6 {
+                0.8
+            } else if to == from {
+                0.2
+            } else {
+                0.0
+            }
+        }
+
+        fn actions_from(&self, _: &u8) -> HashSet<()> {
+            HashSet::from([()])
+        }
+
+        fn states_from(&self, from: &u8, _: &()) -> HashSet<u8> {
+            HashSet::from([*from, (from + 1) % 6])
+        }
+
+        fn start_states(&self) -> HashSet<u8> {
+            (0..6).collect()
+        }
+
+        fn rewards(&self) -> Vec<f32> {
+            vec![0.0]
+        }
+    }
+
+    impl IPartialEnvironment for Ring {
+        type Observation = u8;
+
+        fn observe(&self, state: &u8, rng: &mut StdRng) -> u8 {
+            let u: f32 = rng.gen();
+            if u < 0.6 {
+                *state
+            } else if u < 0.8 {
+                (state + 1) % 6
+            } else {
+                (state + 5) % 6
+            }
+        }
+
+        fn obs_prob(&self, state: &u8, obs: &u8) -> f32 {
+            if obs == state {
+                0.6
+            } else if *obs == (state + 1) % 6 || *obs == (state + 5) % 6 {
+                0.2
+            } else {
+                0.0
+            }
+        }
+    }
+
+    #[test]
+    fn beliefs_track_the_true_state() {
+        let mut episode = Episodic::new(Ring, make_rng(9));
+        episode.reset();
+        let mut rng = make_rng(4);
+        let mut belief = BeliefState::from_start(&Ring).unwrap();
+        assert!((belief.prob(&0) - 1.0 / 6.0).abs() < 1e-6);
+        let mut on_truth = 0.0;
+        for t in 0..200 {
+            let (state, _, _) = episode.step(&()).unwrap();
+            let obs = Ring.observe(&state, &mut rng);
+            belief.update(&Ring, &(), &obs).unwrap();
+            assert!((belief.probs().values().sum::<f32>() - 1.0).abs() < 1e-5);
+            if t >= 100 {
+                on_truth += belief.prob(&state);
+            }
+        }
+        // Far more than the 1/6 of an uninformed belief.
+        assert!(on_truth / 100.0 > 0.5);
+    }
+
+    #[test]
+    fn impossible_observations_reset_the_belief() {
+        // From cell 0 only cells 0 and 1 are reachable, and neither
+        // can be observed as 3.
+        let mut belief = BeliefState::<Ring>::new(HashMap::from([(0, 1.0)])).unwrap();
+        belief.update(&Ring, &(), &3).unwrap();
+        assert!(belief.probs().values().all(|p| p.is_finite()));
+        assert!((belief.prob(&3) - 0.6).abs() < 1e-5);
+        assert!((belief.prob(&2) - 0.2).abs() < 1e-5);
+        assert_eq!(belief.most_likely(), Some(&3));
+    }
+}
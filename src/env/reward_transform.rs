@@ -0,0 +1,324 @@
+use std::cell::Cell;
+use std::collections::HashSet;
+
+use crate::env::IEnvironment;
+use crate::rng::StdRng;
+
+/// How `RewardTransform` changes rewards.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Transform {
+    /// Clamps every reward to between `low` and `high`, inclusive.
+    Clip { low: f32, high: f32 },
+    /// Multiplies every reward by a constant.
+    Scale(f32),
+    /// Standardizes rewards by the running mean and standard deviation
+    /// of those sampled so far. This depends on the rewards seen, not
+    /// on the dynamics, so it only applies to rewards passed through
+    /// `RewardTransform::observe`; the model of the dynamics is left
+    /// unchanged.
+    RunningStandardize,
+}
+
+/// The environment `E` with its rewards transformed, e.g. clipped to
+/// [-1, 1] for stability.
+///
+/// `Clip` and `Scale` change the dynamics themselves: with enumerable
+/// rewards, each base reward is mapped through the transform, and the
+/// probabilities of base rewards mapped to the same value are summed.
+/// Without them, only `Scale` can be applied to the expectations;
+/// clipped expectations are then undefined, as clipping an average
+/// isn't averaging the clipped rewards. `RunningStandardize` leaves
+/// the dynamics alone, and applies only to rewards sampled elsewhere,
+/// e.g. by `Episodic`, and passed through `observe`.
+pub struct RewardTransform<E> {
+    env: E,
+    transform: Transform,
+    /// The base environment's rewards, enumerated once.
+    base_rewards: Vec<f32>,
+    rewards: Vec<f32>,
+    /// The count, mean and sum of squared deviations of the rewards
+    /// observed so far, for `RunningStandardize`.
+    stats: Cell<(usize, f64, f64)>,
+}
+
+impl<E: IEnvironment> RewardTransform<E> {
+    /// Transforms the rewards of `env` by `transform`.
+    pub fn new(env: E, transform: Transform) -> Self {
+        let base_rewards = env.rewards();
+        let mut rewards: Vec<f32> = base_rewards.iter().map(|r| map(transform, *r)).collect();
+        rewards.sort_by(f32::total_cmp);
+        rewards.dedup();
+        RewardTransform {
+            env,
+            transform,
+            base_rewards,
+            rewards,
+            stats: Cell::new((0, 0.0, 0.0)),
+        }
+    }
+
+    /// The environment whose rewards are transformed.
+    pub fn env(&self) -> &E {
+        &self.env
+    }
+
+    pub fn transform(&self) -> Transform {
+        self.transform
+    }
+
+    /// The transform of a sampled `reward`. For `RunningStandardize`
+    /// this counts `reward` into the running statistics first, and
+    /// then standardizes it by them; the first reward, having no
+    /// spread yet, becomes 0.
+    pub fn observe(&self, reward: f32) -> f32 {
+        if self.transform != Transform::RunningStandardize {
+            return map(self.transform, reward);
+        }
+        // Welford's update.
+        let (n, mean, m2) = self.stats.get();
+        let n = n + 1;
+        let delta = reward as f64 - mean;
+        let mean = mean + delta / n as f64;
+        let m2 = m2 + delta * (reward as f64 - mean);
+        self.stats.set((n, mean, m2));
+        let std = (m2 / n as f64).sqrt();
+        if std > 0.0 {
+            ((reward as f64 - mean) / std) as f32
+        } else {
+            0.0
+        }
+    }
+}
+
+/// `reward` under the dynamics transformed by `transform`.
+fn map(transform: Transform, reward: f32) -> f32 {
+    match transform {
+        Transform::Clip { low, high } => reward.clamp(low, high),
+        Transform::Scale(c) => c * reward,
+        Transform::RunningStandardize => reward,
+    }
+}
+
+impl<E: IEnvironment> IEnvironment for RewardTransform<E> {
+    type State = E::State;
+    type Action = E::Action;
+
+    /// The probability of the base rewards transformed to `with`. If
+    /// the base rewards aren't enumerable, that of the base reward
+    /// scaled to `with`, or, when clipping, of `with` itself strictly
+    /// between the bounds and 0 at them.
+    fn prob(&self, from: &E::State, take: &E::Action, to: &E::State, with: &f32) -> f32 {
+        if !self.base_rewards.is_empty() {
+            return self
+                .base_rewards
+                .iter()
+                .filter(|r| map(self.transform, **r) == *with)
+                .map(|r| self.env.prob(from, take, to, r))
+                .sum();
+        }
+        match self.transform {
+            Transform::Clip { low, high } if *with <= low || *with >= high => 0.0,
+            Transform::Scale(c) if c != 0.0 => self.env.prob(from, take, to, &(with / c)),
+            Transform::Scale(_) => 0.0,
+            _ => self.env.prob(from, take, to, with),
+        }
+    }
+
+    fn prob_transition(&self, from: &E::State, take: &E::Action, to: &E::State) -> Option<f32> {
+        self.env.prob_transition(from, take, to)
+    }
+
+    fn transition_distribution(
+        &self,
+        from: &E::State,
+        take: &E::Action,
+    ) -> Option<Vec<(E::State, f32)>> {
+        self.env.transition_distribution(from, take)
+    }
+
+    fn expected_reward(&self, from: &E::State, take: &E::Action) -> Option<f32> {
+        match self.transform {
+            Transform::Clip { .. } => self
+                .env
+                .states_from(from, take)
+                .iter()
+                .map(|to| self.expected_reward_at(from, take, to))
+                .sum(),
+            Transform::Scale(c) => Some(c * self.env.expected_reward(from, take)?),
+            Transform::RunningStandardize => self.env.expected_reward(from, take),
+        }
+    }
+
+    fn expected_reward_at(&self, from: &E::State, take: &E::Action, to: &E::State) -> Option<f32> {
+        match self.transform {
+            Transform::Clip { .. } => {
+                if self.base_rewards.is_empty() {
+                    return None;
+                }
+                Some(
+                    self.base_rewards
+                        .iter()
+                        .map(|r| map(self.transform, *r) * self.env.prob(from, take, to, r))
+                        .sum(),
+                )
+            }
+            Transform::Scale(c) => Some(c * self.env.expected_reward_at(from, take, to)?),
+            Transform::RunningStandardize => self.env.expected_reward_at(from, take, to),
+        }
+    }
+
+    fn actions_from(&self, from: &E::State) -> HashSet<E::Action> {
+        self.env.actions_from(from)
+    }
+
+    fn action_mask(&self, from: &E::State) -> HashSet<E::Action> {
+        self.env.action_mask(from)
+    }
+
+    fn is_valid_action(&self, from: &E::State, take: &E::Action) -> bool {
+        self.env.is_valid_action(from, take)
+    }
+
+    fn states_from(&self, from: &E::State, take: &E::Action) -> HashSet<E::State> {
+        self.env.states_from(from, take)
+    }
+
+    fn states_from_into(&self, from: &E::State, take: &E::Action, buf: &mut Vec<E::State>) {
+        self.env.states_from_into(from, take, buf)
+    }
+
+    /// The base environment's sample, with its reward mapped through
+    /// the transform; `RunningStandardize` leaves it as it is, for
+    /// `observe` to standardize.
+    fn sample_transition(
+        &self,
+        from: &E::State,
+        take: &E::Action,
+        rng: &mut StdRng,
+    ) -> Option<(E::State, f32)> {
+        let (to, reward) = self.env.sample_transition(from, take, rng)?;
+        Some((to, map(self.transform, reward)))
+    }
+
+    fn is_terminal(&self, state: &E::State) -> bool {
+        self.env.is_terminal(state)
+    }
+
+    fn gamma(&self) -> f32 {
+        self.env.gamma()
+    }
+
+    fn gamma_at(&self, state: &E::State) -> f32 {
+        self.env.gamma_at(state)
+    }
+
+    fn start_states(&self) -> HashSet<E::State> {
+        self.env.start_states()
+    }
+
+    fn all_states(&self) -> HashSet<E::State> {
+        self.env.all_states()
+    }
+
+    /// The base rewards transformed, as enumerated by
+    /// `RewardTransform::new`, or nothing if the base rewards aren't
+    /// enumerable.
+    fn rewards(&self) -> Vec<f32> {
+        self.rewards.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dp::value_iteration;
+    use crate::validate::validate_all;
+
+    /// From the start, a sure 1, or 1 and then a tenth's chance of
+    /// -100 instead of another 1.
+    struct Tail;
+
+    const START: u8 = 0;
+    const MIDDLE: u8 = 1;
+    const END: u8 = 2;
+    const SAFE: u8 = 0;
+    const RISKY: u8 = 1;
+
+    impl IEnvironment for Tail {
+        type State = u8;
+        type Action = u8;
+
+        fn prob(&self, from: &u8, take: &u8, to: &u8, with: &f32) -> f32 {
+            match (*from, *take, *to) {
+                (START, SAFE, END) | (START, RISKY, MIDDLE) if *with == 1.0 => 1.0,
+                (MIDDLE, _, END) if *with == 1.0 => 0.9,
+                (MIDDLE, _, END) if *with == -100.0 => 0.1,
+                _ => 0.0,
+            }
+        }
+
+        fn actions_from(&self, from: &u8) -> HashSet<u8> {
+            match *from {
+                START => HashSet::from([SAFE, RISKY]),
+                MIDDLE => HashSet::from([SAFE]),
+                _ => HashSet::new(),
+            }
+        }
+
+        fn states_from(&self, from: &u8, take: &u8) -> HashSet<u8> {
+            match (*from, *take) {
+                (START, RISKY) => HashSet::from([MIDDLE]),
+                (END, _) => HashSet::new(),
+                _ => HashSet::from([END]),
+            }
+        }
+
+        fn is_terminal(&self, state: &u8) -> bool {
+            *state == END
+        }
+
+        fn start_states(&self) -> HashSet<u8> {
+            HashSet::from([START])
+        }
+
+        fn rewards(&self) -> Vec<f32> {
+            vec![-100.0, 1.0]
+        }
+    }
+
+    #[test]
+    fn clipping_the_tail_makes_the_risk_worth_taking() {
+        let (v, policy) = value_iteration(&Tail, Some(1.0), 1e-6, 100).unwrap();
+        assert_eq!(policy.action(&START), Some(&SAFE));
+        assert_eq!(v[&START], 1.0);
+
+        let clipped = RewardTransform::new(
+            Tail,
+            Transform::Clip {
+                low: -1.0,
+                high: 1.0,
+            },
+        );
+        assert_eq!(clipped.rewards(), vec![-1.0, 1.0]);
+        assert!(validate_all(&clipped, 1e-6).is_ok());
+        let tail = clipped.expected_reward(&MIDDLE, &SAFE).unwrap();
+        assert!((tail - 0.8).abs() < 1e-6);
+        let (v, policy) = value_iteration(&clipped, Some(1.0), 1e-6, 100).unwrap();
+        assert_eq!(policy.action(&START), Some(&RISKY));
+        assert!((v[&START] - 1.8).abs() < 1e-6);
+
+        let scaled = RewardTransform::new(Tail, Transform::Scale(0.5));
+        assert_eq!(scaled.prob(&MIDDLE, &SAFE, &END, &-50.0), 0.1);
+        assert_eq!(scaled.expected_reward_at(&START, &SAFE, &END), Some(0.5));
+    }
+
+    #[test]
+    fn running_standardization_applies_to_observed_rewards() {
+        let standardized = RewardTransform::new(Tail, Transform::RunningStandardize);
+        assert_eq!(standardized.rewards(), Tail.rewards());
+        assert_eq!(standardized.observe(1.0), 0.0);
+        // Mean 2 and standard deviation 1 once 3 is seen.
+        assert_eq!(standardized.observe(3.0), 1.0);
+        assert_eq!(standardized.observe(2.0), 0.0);
+    }
+}
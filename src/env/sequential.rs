@@ -0,0 +1,408 @@
+use std::collections::HashSet;
+
+use crate::env::IEnvironment;
+use crate::rng::StdRng;
+use crate::simulate::sample_start;
+
+/// A state or action of a `Sequential` environment, from either its
+/// first or its second stage.
+#[derive(Hash, Eq, PartialEq, Clone, Copy, Debug)]
+pub enum Stage<X, Y> {
+    First(X),
+    Second(Y),
+}
+
+/// The environment `A` followed by the environment `B`: on reaching a
+/// terminal state of `A`, the episode continues from one of `B`'s
+/// start states, chosen uniformly, and ends at a terminal state of
+/// `B`.
+///
+/// `A`'s terminal states are bridged over rather than visited: a
+/// transition into one goes straight to a start of `B`, with `A`'s
+/// reward for it plus a fixed handoff reward, 0 unless set by
+/// `with_handoff`. `B` must have start states for the handoff to
+/// happen.
+///
+/// The composite discounts with `A`'s `gamma` throughout, so the
+/// value of `B` as seen from `A` is discounted by the steps taken in
+/// `A`, and `B`'s own `gamma` isn't used; stages meant to be chained
+/// should share a discount.
+pub struct Sequential<A, B> {
+    first: A,
+    second: B,
+    handoff: f32,
+}
+
+impl<A: IEnvironment, B: IEnvironment> Sequential<A, B> {
+    pub fn new(first: A, second: B) -> Self {
+        Sequential {
+            first,
+            second,
+            handoff: 0.0,
+        }
+    }
+
+    /// This composite, with `reward` added to the reward of every
+    /// transition that completes the first stage.
+    pub fn with_handoff(mut self, reward: f32) -> Self {
+        self.handoff = reward;
+        self
+    }
+
+    pub fn first(&self) -> &A {
+        &self.first
+    }
+
+    pub fn second(&self) -> &B {
+        &self.second
+    }
+
+    /// The terminal states of `A` reachable from `from` taking
+    /// `take`, each of which hands off to `B`.
+    fn handoffs(&self, from: &A::State, take: &A::Action) -> Vec<A::State> {
+        self.first
+            .states_from(from, take)
+            .into_iter()
+            .filter(|s| self.first.is_terminal(s))
+            .collect()
+    }
+
+    /// The probability of each start of `B` being chosen at the
+    /// handoff, or `None` if `to` isn't one.
+    fn start_prob(&self, to: &B::State) -> Option<f32> {
+        let starts = self.second.start_states();
+        if starts.contains(to) {
+            Some(1.0 / starts.len() as f32)
+        } else {
+            None
+        }
+    }
+}
+
+impl<A, B> Default for Sequential<A, B>
+where
+    A: IEnvironment + Default,
+    B: IEnvironment + Default,
+{
+    fn default() -> Self {
+        Self::new(A::default(), B::default())
+    }
+}
+
+type State<A, B> = Stage<<A as IEnvironment>::State, <B as IEnvironment>::State>;
+type Action<A, B> = Stage<<A as IEnvironment>::Action, <B as IEnvironment>::Action>;
+
+impl<A: IEnvironment, B: IEnvironment> IEnvironment for Sequential<A, B> {
+    type State = State<A, B>;
+    type Action = Action<A, B>;
+
+    /// Within a stage, the stage's own dynamics. At the handoff, the
+    /// probability of `A` reaching any of its terminal states with a
+    /// reward that, plus the handoff reward, is `with`, split evenly
+    /// over `B`'s start states.
+    fn prob(&self, from: &State<A, B>, take: &Action<A, B>, to: &State<A, B>, with: &f32) -> f32 {
+        match (from, take, to) {
+            (Stage::First(s), Stage::First(a), Stage::First(t)) => {
+                if self.first.is_terminal(t) {
+                    0.0
+                } else {
+                    self.first.prob(s, a, t, with)
+                }
+            }
+            (Stage::First(s), Stage::First(a), Stage::Second(t)) => {
+                let Some(start) = self.start_prob(t) else {
+                    return 0.0;
+                };
+                let rewards = self.first.rewards();
+                let p: f32 = self
+                    .handoffs(s, a)
+                    .iter()
+                    .map(|end| {
+                        if rewards.is_empty() {
+                            return self.first.prob(s, a, end, &(with - self.handoff));
+                        }
+                        rewards
+                            .iter()
+                            .filter(|r| *r + self.handoff == *with)
+                            .map(|r| self.first.prob(s, a, end, r))
+                            .sum()
+                    })
+                    .sum();
+                p * start
+            }
+            (Stage::Second(s), Stage::Second(a), Stage::Second(t)) => {
+                self.second.prob(s, a, t, with)
+            }
+            _ => 0.0,
+        }
+    }
+
+    fn prob_transition(
+        &self,
+        from: &State<A, B>,
+        take: &Action<A, B>,
+        to: &State<A, B>,
+    ) -> Option<f32> {
+        match (from, take, to) {
+            (Stage::First(s), Stage::First(a), Stage::First(t)) => {
+                if self.first.is_terminal(t) {
+                    Some(0.0)
+                } else {
+                    self.first.prob_transition(s, a, t)
+                }
+            }
+            (Stage::First(s), Stage::First(a), Stage::Second(t)) => {
+                let Some(start) = self.start_prob(t) else {
+                    return Some(0.0);
+                };
+                let mut p = 0.0;
+                for end in self.handoffs(s, a) {
+                    p += self.first.prob_transition(s, a, &end)?;
+                }
+                Some(p * start)
+            }
+            (Stage::Second(s), Stage::Second(a), Stage::Second(t)) => {
+                self.second.prob_transition(s, a, t)
+            }
+            _ => Some(0.0),
+        }
+    }
+
+    fn expected_reward_at(
+        &self,
+        from: &State<A, B>,
+        take: &Action<A, B>,
+        to: &State<A, B>,
+    ) -> Option<f32> {
+        match (from, take, to) {
+            (Stage::First(s), Stage::First(a), Stage::First(t)) => {
+                if self.first.is_terminal(t) {
+                    Some(0.0)
+                } else {
+                    self.first.expected_reward_at(s, a, t)
+                }
+            }
+            (Stage::First(s), Stage::First(a), Stage::Second(t)) => {
+                let Some(start) = self.start_prob(t) else {
+                    return Some(0.0);
+                };
+                let mut expected = 0.0;
+                for end in self.handoffs(s, a) {
+                    let p = self.first.prob_transition(s, a, &end)?;
+                    expected += self.first.expected_reward_at(s, a, &end)? + p * self.handoff;
+                }
+                Some(expected * start)
+            }
+            (Stage::Second(s), Stage::Second(a), Stage::Second(t)) => {
+                self.second.expected_reward_at(s, a, t)
+            }
+            _ => Some(0.0),
+        }
+    }
+
+    fn actions_from(&self, from: &State<A, B>) -> HashSet<Action<A, B>> {
+        match from {
+            Stage::First(s) => self
+                .first
+                .actions_from(s)
+                .into_iter()
+                .map(Stage::First)
+                .collect(),
+            Stage::Second(s) => self
+                .second
+                .actions_from(s)
+                .into_iter()
+                .map(Stage::Second)
+                .collect(),
+        }
+    }
+
+    fn action_mask(&self, from: &State<A, B>) -> HashSet<Action<A, B>> {
+        match from {
+            Stage::First(s) => self
+                .first
+                .action_mask(s)
+                .into_iter()
+                .map(Stage::First)
+                .collect(),
+            Stage::Second(s) => self
+                .second
+                .action_mask(s)
+                .into_iter()
+                .map(Stage::Second)
+                .collect(),
+        }
+    }
+
+    fn is_valid_action(&self, from: &State<A, B>, take: &Action<A, B>) -> bool {
+        match (from, take) {
+            (Stage::First(s), Stage::First(a)) => self.first.is_valid_action(s, a),
+            (Stage::Second(s), Stage::Second(a)) => self.second.is_valid_action(s, a),
+            _ => false,
+        }
+    }
+
+    fn states_from(&self, from: &State<A, B>, take: &Action<A, B>) -> HashSet<State<A, B>> {
+        match (from, take) {
+            (Stage::First(s), Stage::First(a)) => {
+                let mut to = HashSet::new();
+                let mut handoff = false;
+                for t in self.first.states_from(s, a) {
+                    if self.first.is_terminal(&t) {
+                        handoff = true;
+                    } else {
+                        to.insert(Stage::First(t));
+                    }
+                }
+                if handoff {
+                    to.extend(self.second.start_states().into_iter().map(Stage::Second));
+                }
+                to
+            }
+            (Stage::Second(s), Stage::Second(a)) => self
+                .second
+                .states_from(s, a)
+                .into_iter()
+                .map(Stage::Second)
+                .collect(),
+            _ => HashSet::new(),
+        }
+    }
+
+    /// Within a stage, the stage's own sample. A sample reaching a
+    /// terminal state of `A` hands off to a start of `B`, drawn
+    /// uniformly, with the handoff reward added.
+    fn sample_transition(
+        &self,
+        from: &State<A, B>,
+        take: &Action<A, B>,
+        rng: &mut StdRng,
+    ) -> Option<(State<A, B>, f32)> {
+        match (from, take) {
+            (Stage::First(s), Stage::First(a)) => {
+                let (to, reward) = self.first.sample_transition(s, a, rng)?;
+                if !self.first.is_terminal(&to) {
+                    return Some((Stage::First(to), reward));
+                }
+                let start = sample_start(&self.second, rng)?;
+                Some((Stage::Second(start), reward + self.handoff))
+            }
+            (Stage::Second(s), Stage::Second(a)) => {
+                let (to, reward) = self.second.sample_transition(s, a, rng)?;
+                Some((Stage::Second(to), reward))
+            }
+            _ => None,
+        }
+    }
+
+    /// Only `B`'s terminal states end an episode.
+    fn is_terminal(&self, state: &State<A, B>) -> bool {
+        match state {
+            Stage::First(_) => false,
+            Stage::Second(s) => self.second.is_terminal(s),
+        }
+    }
+
+    fn gamma(&self) -> f32 {
+        self.first.gamma()
+    }
+
+    /// `A`'s discounts; `B`'s are ignored, as its `gamma` is.
+    fn gamma_at(&self, state: &State<A, B>) -> f32 {
+        match state {
+            Stage::First(s) => self.first.gamma_at(s),
+            Stage::Second(_) => self.gamma(),
+        }
+    }
+
+    /// `A`'s start states, except that any terminal ones are replaced
+    /// by `B`'s.
+    fn start_states(&self) -> HashSet<State<A, B>> {
+        let mut starts = HashSet::new();
+        let mut handoff = false;
+        for s in self.first.start_states() {
+            if self.first.is_terminal(&s) {
+                handoff = true;
+            } else {
+                starts.insert(Stage::First(s));
+            }
+        }
+        if handoff {
+            starts.extend(self.second.start_states().into_iter().map(Stage::Second));
+        }
+        starts
+    }
+
+    /// The non-terminal states of `A` and all the states of `B`.
+    fn all_states(&self) -> HashSet<State<A, B>> {
+        self.first
+            .all_states()
+            .into_iter()
+            .filter(|s| !self.first.is_terminal(s))
+            .map(Stage::First)
+            .chain(self.second.all_states().into_iter().map(Stage::Second))
+            .collect()
+    }
+
+    /// The rewards of both stages, and `A`'s plus the handoff reward, or
+    /// nothing if either stage's rewards aren't enumerable.
+    fn rewards(&self) -> Vec<f32> {
+        let first = self.first.rewards();
+        let second = self.second.rewards();
+        if first.is_empty() || second.is_empty() {
+            return Vec::new();
+        }
+        let mut rewards: Vec<f32> = first
+            .iter()
+            .copied()
+            .chain(first.iter().map(|r| r + self.handoff))
+            .chain(second)
+            .collect();
+        rewards.sort_by(f32::total_cmp);
+        rewards.dedup();
+        rewards
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dp;
+    use crate::testing::Chain;
+    use crate::validate;
+
+    #[test]
+    fn the_first_stage_hands_off_to_the_second() {
+        let chained = Sequential::new(Chain, Chain).with_handoff(2.0);
+        let (last, right) = (Stage::First(2), Stage::First(1));
+        assert_eq!(chained.all_states().len(), 3 + 4);
+        assert_eq!(
+            chained.states_from(&last, &right),
+            HashSet::from([Stage::Second(0)])
+        );
+        assert_eq!(chained.prob(&last, &right, &Stage::Second(0), &3.0), 1.0);
+        assert_eq!(chained.prob(&last, &right, &Stage::Second(0), &1.0), 0.0);
+        assert_eq!(
+            chained.expected_reward_at(&last, &right, &Stage::Second(0)),
+            Some(3.0)
+        );
+        assert!(!chained.is_terminal(&Stage::First(Chain::GOAL)));
+        assert!(chained.is_terminal(&Stage::Second(Chain::GOAL)));
+        assert!(validate::validate_all(&chained, 1e-5).is_ok());
+    }
+
+    #[test]
+    fn value_iteration_discounts_the_second_stage() {
+        // Three steps reach the handoff, after which the second chain
+        // is worth what it is alone, discounted by those steps.
+        let (alone, _) = dp::value_iteration(&Chain, Some(0.9), 1e-6, 1000).unwrap();
+        let expected = alone[&0] + 0.9f32.powi(3) * alone[&0];
+        let plain = Sequential::new(Chain, Chain);
+        let (v, _) = dp::value_iteration(&plain, Some(0.9), 1e-6, 1000).unwrap();
+        assert!((v[&Stage::First(0)] - expected).abs() < 1e-4);
+
+        let bonus = Sequential::new(Chain, Chain).with_handoff(2.0);
+        let (v, _) = dp::value_iteration(&bonus, Some(0.9), 1e-6, 1000).unwrap();
+        assert!((v[&Stage::First(0)] - expected - 0.81 * 2.0).abs() < 1e-4);
+    }
+}
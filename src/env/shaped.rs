@@ -0,0 +1,223 @@
+use std::collections::HashSet;
+
+use crate::env::IEnvironment;
+use crate::rng::StdRng;
+
+/// The environment `E` with potential-based reward shaping: every
+/// transition from `s` to `s'` has `gamma * phi(s') - phi(s)` added
+/// to its reward, where `phi` is a potential function over states and
+/// `gamma` is the environment's own.
+///
+/// Terminal states are taken to have potential 0. Shaping of this
+/// form leaves the optimal policy unchanged, provided algorithms run
+/// with the same `gamma` the shaping uses.
+pub struct Shaped<E, F> {
+    env: E,
+    phi: F,
+    rewards: Vec<f32>,
+}
+
+impl<E: IEnvironment, F: Fn(&E::State) -> f32> Shaped<E, F> {
+    /// Shapes `env` by the potential `phi`.
+    ///
+    /// The shaped rewards are enumerated here, once, by walking every
+    /// transition between the environment's `all_states`, so that
+    /// `rewards` stays cheap; this is expensive on large environments.
+    pub fn new(env: E, phi: F) -> Self {
+        let mut shaped = Shaped {
+            env,
+            phi,
+            rewards: Vec::new(),
+        };
+        shaped.rewards = shaped.shaped_rewards();
+        shaped
+    }
+
+    /// The environment being shaped.
+    pub fn env(&self) -> &E {
+        &self.env
+    }
+
+    fn potential(&self, state: &E::State) -> f32 {
+        if self.env.is_terminal(state) {
+            0.0
+        } else {
+            (self.phi)(state)
+        }
+    }
+
+    /// The term added to the reward of a transition from `from` to
+    /// `to`.
+    pub fn shaping(&self, from: &E::State, to: &E::State) -> f32 {
+        self.env.gamma() * self.potential(to) - self.potential(from)
+    }
+
+    /// Every base reward plus every shaping term over the transitions
+    /// between the environment's `all_states`, or nothing if the base
+    /// rewards aren't enumerable.
+    fn shaped_rewards(&self) -> Vec<f32> {
+        let base = self.env.rewards();
+        if base.is_empty() {
+            return base;
+        }
+        let mut rewards: Vec<f32> = Vec::new();
+        for from in self.env.all_states() {
+            for take in self.env.actions_from(&from) {
+                for to in self.env.states_from(&from, &take) {
+                    let shaping = self.shaping(&from, &to);
+                    rewards.extend(base.iter().map(|r| r + shaping));
+                }
+            }
+        }
+        rewards.sort_by(f32::total_cmp);
+        rewards.dedup();
+        rewards
+    }
+}
+
+impl<E: IEnvironment, F: Fn(&E::State) -> f32> IEnvironment for Shaped<E, F> {
+    type State = E::State;
+    type Action = E::Action;
+
+    /// The probability of the base rewards that shape to `with`. If
+    /// the base rewards aren't enumerable, the shaping term is
+    /// subtracted from `with` instead.
+    fn prob(&self, from: &E::State, take: &E::Action, to: &E::State, with: &f32) -> f32 {
+        let shaping = self.shaping(from, to);
+        let rewards = self.env.rewards();
+        if rewards.is_empty() {
+            return self.env.prob(from, take, to, &(with - shaping));
+        }
+        rewards
+            .iter()
+            .filter(|r| *r + shaping == *with)
+            .map(|r| self.env.prob(from, take, to, r))
+            .sum()
+    }
+
+    fn prob_transition(&self, from: &E::State, take: &E::Action, to: &E::State) -> Option<f32> {
+        self.env.prob_transition(from, take, to)
+    }
+
+    fn transition_distribution(
+        &self,
+        from: &E::State,
+        take: &E::Action,
+    ) -> Option<Vec<(E::State, f32)>> {
+        self.env.transition_distribution(from, take)
+    }
+
+    fn expected_reward_at(&self, from: &E::State, take: &E::Action, to: &E::State) -> Option<f32> {
+        let p = self.env.prob_transition(from, take, to)?;
+        Some(self.env.expected_reward_at(from, take, to)? + p * self.shaping(from, to))
+    }
+
+    fn actions_from(&self, from: &E::State) -> HashSet<E::Action> {
+        self.env.actions_from(from)
+    }
+
+    fn action_mask(&self, from: &E::State) -> HashSet<E::Action> {
+        self.env.action_mask(from)
+    }
+
+    fn is_valid_action(&self, from: &E::State, take: &E::Action) -> bool {
+        self.env.is_valid_action(from, take)
+    }
+
+    fn states_from(&self, from: &E::State, take: &E::Action) -> HashSet<E::State> {
+        self.env.states_from(from, take)
+    }
+
+    fn states_from_into(&self, from: &E::State, take: &E::Action, buf: &mut Vec<E::State>) {
+        self.env.states_from_into(from, take, buf)
+    }
+
+    /// The base environment's sample, with its reward shaped.
+    fn sample_transition(
+        &self,
+        from: &E::State,
+        take: &E::Action,
+        rng: &mut StdRng,
+    ) -> Option<(E::State, f32)> {
+        let (to, reward) = self.env.sample_transition(from, take, rng)?;
+        let shaping = self.shaping(from, &to);
+        Some((to, reward + shaping))
+    }
+
+    fn is_terminal(&self, state: &E::State) -> bool {
+        self.env.is_terminal(state)
+    }
+
+    fn gamma(&self) -> f32 {
+        self.env.gamma()
+    }
+
+    fn gamma_at(&self, state: &E::State) -> f32 {
+        self.env.gamma_at(state)
+    }
+
+    fn start_states(&self) -> HashSet<E::State> {
+        self.env.start_states()
+    }
+
+    fn all_states(&self) -> HashSet<E::State> {
+        self.env.all_states()
+    }
+
+    /// Every base reward plus every shaping term, as enumerated by
+    /// `Shaped::new`, or nothing if the base rewards aren't
+    /// enumerable.
+    fn rewards(&self) -> Vec<f32> {
+        self.rewards.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+    use crate::testing::Chain;
+
+    #[test]
+    fn shapes_rewards_by_the_change_in_potential() {
+        let shaped = Shaped::new(Chain, |s: &i32| *s as f32);
+        // Chain has gamma 1: 1 * 2 - 1 on moving right from 1, and the
+        // goal's potential is 0 whatever phi says.
+        assert_eq!(shaped.prob(&1, &1, &2, &1.0), 1.0);
+        assert_eq!(shaped.prob(&1, &1, &2, &0.0), 0.0);
+        assert_eq!(shaped.prob(&2, &1, &3, &-1.0), 1.0);
+        assert_eq!(shaped.expected_reward_at(&2, &1, &3), Some(-1.0));
+        assert_eq!(shaped.rewards(), vec![-2.0, -1.0, 0.0, 1.0, 2.0, 3.0]);
+    }
+
+    #[test]
+    fn rewards_are_enumerated_once() {
+        let calls = Cell::new(0);
+        let shaped = Shaped::new(Chain, |s: &i32| {
+            calls.set(calls.get() + 1);
+            *s as f32
+        });
+        let after_new = calls.get();
+        for _ in 0..10 {
+            shaped.rewards();
+        }
+        assert_eq!(calls.get(), after_new);
+    }
+
+    #[cfg(feature = "examples")]
+    #[test]
+    fn value_iteration_finds_the_same_policy() {
+        use crate::dp::value_iteration;
+        use crate::env::examples::GridWorld;
+
+        let grid = GridWorld::new(4, 3, [(1, 1)], (3, 0), 0.04, 0.2).with_start((0, 2));
+        let goal = grid.goal();
+        let shaped = Shaped::new(grid.clone(), move |&(x, y): &(usize, usize)| {
+            -((goal.0.abs_diff(x) + goal.1.abs_diff(y)) as f32)
+        });
+        let (_, base) = value_iteration(&grid, None, 1e-6, 10_000).unwrap();
+        let (_, shaped) = value_iteration(&shaped, None, 1e-6, 10_000).unwrap();
+        assert_eq!(base.into_actions(), shaped.into_actions());
+    }
+}
@@ -0,0 +1,225 @@
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+use std::sync::Arc;
+
+use crate::env::{IEnvironment, Space};
+use crate::rng::StdRng;
+
+/// Forwards every method of `IEnvironment`, overridden or not, from a
+/// shared pointer to the environment it points to.
+macro_rules! forward_shared {
+    ($pointer:ident) => {
+        /// The shared environment itself, so that one environment,
+        /// along with any large tables it holds, can be handed to
+        /// several algorithms, or threads, without being cloned.
+        impl<E: IEnvironment + ?Sized> IEnvironment for $pointer<E> {
+            type State = E::State;
+            type Action = E::Action;
+
+            fn prob(&self, from: &E::State, take: &E::Action, to: &E::State, with: &f32) -> f32 {
+                (**self).prob(from, take, to, with)
+            }
+
+            fn prob_transition(
+                &self,
+                from: &E::State,
+                take: &E::Action,
+                to: &E::State,
+            ) -> Option<f32> {
+                (**self).prob_transition(from, take, to)
+            }
+
+            fn transition_distribution(
+                &self,
+                from: &E::State,
+                take: &E::Action,
+            ) -> Option<Vec<(E::State, f32)>> {
+                (**self).transition_distribution(from, take)
+            }
+
+            fn expected_reward(&self, from: &E::State, take: &E::Action) -> Option<f32> {
+                (**self).expected_reward(from, take)
+            }
+
+            fn expected_reward_at(
+                &self,
+                from: &E::State,
+                take: &E::Action,
+                to: &E::State,
+            ) -> Option<f32> {
+                (**self).expected_reward_at(from, take, to)
+            }
+
+            fn actions_from(&self, from: &E::State) -> HashSet<E::Action> {
+                (**self).actions_from(from)
+            }
+
+            fn action_mask(&self, from: &E::State) -> HashSet<E::Action> {
+                (**self).action_mask(from)
+            }
+
+            fn is_valid_action(&self, from: &E::State, take: &E::Action) -> bool {
+                (**self).is_valid_action(from, take)
+            }
+
+            fn states_from(&self, from: &E::State, take: &E::Action) -> HashSet<E::State> {
+                (**self).states_from(from, take)
+            }
+
+            fn states_from_into(&self, from: &E::State, take: &E::Action, buf: &mut Vec<E::State>) {
+                (**self).states_from_into(from, take, buf)
+            }
+
+            fn sample_transition(
+                &self,
+                from: &E::State,
+                take: &E::Action,
+                rng: &mut StdRng,
+            ) -> Option<(E::State, f32)> {
+                (**self).sample_transition(from, take, rng)
+            }
+
+            fn is_terminal(&self, state: &E::State) -> bool {
+                (**self).is_terminal(state)
+            }
+
+            fn gamma(&self) -> f32 {
+                (**self).gamma()
+            }
+
+            fn gamma_at(&self, state: &E::State) -> f32 {
+                (**self).gamma_at(state)
+            }
+
+            fn start_states(&self) -> HashSet<E::State> {
+                (**self).start_states()
+            }
+
+            fn all_states(&self) -> HashSet<E::State> {
+                (**self).all_states()
+            }
+
+            fn all_actions(&self) -> HashSet<E::Action> {
+                (**self).all_actions()
+            }
+
+            fn action_space(&self) -> Space {
+                (**self).action_space()
+            }
+
+            fn observation_space(&self) -> Space {
+                (**self).observation_space()
+            }
+
+            fn rewards(&self) -> Vec<f32> {
+                (**self).rewards()
+            }
+
+            fn reward_components(
+                &self,
+                from: &E::State,
+                take: &E::Action,
+                to: &E::State,
+            ) -> HashMap<&'static str, f32> {
+                (**self).reward_components(from, take, to)
+            }
+        }
+    };
+}
+
+forward_shared!(Arc);
+forward_shared!(Rc);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dp::value_iteration;
+    use crate::testing::Chain;
+
+    /// `Chain`, but with its rewards looked up in a large table that
+    /// is shared rather than owned, and that the environment can't be
+    /// cloned to copy.
+    struct Lookup {
+        table: Arc<Vec<f32>>,
+    }
+
+    impl Lookup {
+        const SIZE: usize = 1 << 20;
+
+        fn new() -> Self {
+            let mut table = vec![0.0; Self::SIZE];
+            table[Chain::GOAL as usize] = 1.0;
+            Lookup {
+                table: Arc::new(table),
+            }
+        }
+    }
+
+    impl IEnvironment for Lookup {
+        type State = i32;
+        type Action = i32;
+
+        fn prob(&self, from: &i32, take: &i32, to: &i32, with: &f32) -> f32 {
+            let moved = Chain.states_from(from, take).contains(to);
+            f32::from(moved && self.table[*to as usize] == *with)
+        }
+
+        fn actions_from(&self, from: &i32) -> HashSet<i32> {
+            Chain.actions_from(from)
+        }
+
+        fn states_from(&self, from: &i32, take: &i32) -> HashSet<i32> {
+            Chain.states_from(from, take)
+        }
+
+        fn is_terminal(&self, state: &i32) -> bool {
+            Chain.is_terminal(state)
+        }
+
+        fn start_states(&self) -> HashSet<i32> {
+            Chain.start_states()
+        }
+
+        fn rewards(&self) -> Vec<f32> {
+            vec![0.0, 1.0]
+        }
+    }
+
+    #[test]
+    fn shared_environments_are_solved_without_copies() {
+        let env = Arc::new(Lookup::new());
+        let (expected, _) = value_iteration(&Chain, Some(0.9), 1e-6, 1000).unwrap();
+        std::thread::scope(|scope| {
+            let solves: Vec<_> = (0..4)
+                .map(|_| {
+                    let env = Arc::clone(&env);
+                    scope.spawn(move || value_iteration(&env, Some(0.9), 1e-6, 1000).unwrap().0)
+                })
+                .collect();
+            for solve in solves {
+                assert_eq!(solve.join().unwrap(), expected);
+            }
+        });
+        // Only the one table, however many threads solved it.
+        assert_eq!(Arc::strong_count(&env), 1);
+        assert_eq!(Arc::strong_count(&env.table), 1);
+
+        let env = Rc::new(Lookup::new());
+        assert_eq!(
+            value_iteration(&env, Some(0.9), 1e-6, 1000).unwrap().0,
+            expected
+        );
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn parallel_sweeps_borrow_the_shared_environment() {
+        use crate::dp::par_value_iteration;
+
+        let env = Arc::new(Lookup::new());
+        let (v, _) = par_value_iteration(&env, Some(0.9), 1e-6, 1000).unwrap();
+        let (expected, _) = value_iteration(&Chain, Some(0.9), 1e-6, 1000).unwrap();
+        assert_eq!(v, expected);
+        assert_eq!(Arc::strong_count(&env.table), 1);
+    }
+}
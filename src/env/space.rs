@@ -0,0 +1,60 @@
+/// A description of an environment's states or actions, for tools
+/// that need to know their shape without enumerating them, following
+/// the spaces of Gym.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Space {
+    /// `n` distinct values.
+    Discrete(usize),
+    /// A vector of discrete values, the `i`-th taking one of
+    /// `nvec[i]`, like a grid's cells as a column and a row.
+    MultiDiscrete(Vec<usize>),
+    /// A vector of reals, each between its `low` and `high` bounds,
+    /// inclusive.
+    Box { low: Vec<f32>, high: Vec<f32> },
+}
+
+impl Space {
+    /// The number of values in the space, as a product over its
+    /// dimensions, or `None` for a `Box`, which has infinitely many.
+    pub fn cardinality(&self) -> Option<usize> {
+        match self {
+            Space::Discrete(n) => Some(*n),
+            Space::MultiDiscrete(nvec) => Some(nvec.iter().product()),
+            Space::Box { .. } => None,
+        }
+    }
+
+    /// Whether the space's values are discrete.
+    pub fn is_discrete(&self) -> bool {
+        !matches!(self, Space::Box { .. })
+    }
+
+    /// The number of dimensions of a value: 1 for `Discrete`, and the
+    /// length of the vector otherwise.
+    pub fn dimensions(&self) -> usize {
+        match self {
+            Space::Discrete(_) => 1,
+            Space::MultiDiscrete(nvec) => nvec.len(),
+            Space::Box { low, .. } => low.len(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cardinality_multiplies_the_dimensions() {
+        assert_eq!(Space::Discrete(4).cardinality(), Some(4));
+        assert_eq!(Space::MultiDiscrete(vec![3, 5]).cardinality(), Some(15));
+        let continuous = Space::Box {
+            low: vec![-1.0, 0.0],
+            high: vec![1.0, 2.0],
+        };
+        assert_eq!(continuous.cardinality(), None);
+        assert!(!continuous.is_discrete());
+        assert_eq!(continuous.dimensions(), 2);
+    }
+}
@@ -0,0 +1,313 @@
+use std::collections::HashSet;
+
+use rand::seq::index;
+use rand::Rng;
+
+use crate::env::IEnvironment;
+use crate::rng::make_rng;
+
+/// Why `TabularMDP::from_matrix` rejected its matrices.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TabularError {
+    /// There are no states or no actions.
+    Empty,
+    /// The transition entry for this state and action, or the reward
+    /// entry if `reward` is set, doesn't have one row per state and
+    /// one column per action and next state.
+    Shape {
+        state: usize,
+        action: Option<usize>,
+        reward: bool,
+    },
+    /// The transition probabilities out of this state and action are
+    /// negative or don't sum to 1, totalling `total`.
+    NotNormalized {
+        state: usize,
+        action: usize,
+        total: f32,
+    },
+}
+
+/// A finite MDP given by its matrices, as MDPs are in textbooks: states
+/// and actions are indices, every action is available in every state,
+/// and each transition has a fixed reward.
+///
+/// Episodes start from state 0, and no state is terminal unless made
+/// so by `with_terminals`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TabularMDP {
+    transition: Vec<Vec<Vec<f32>>>,
+    reward: Vec<Vec<Vec<f32>>>,
+    rewards: Vec<f32>,
+    terminals: HashSet<usize>,
+    gamma: f32,
+}
+
+impl TabularMDP {
+    /// The MDP in which taking action `a` from state `s` leads to state
+    /// `t` with probability `transition[s][a][t]` and reward
+    /// `reward[s][a][t]`, discounted by `gamma`.
+    ///
+    /// Both matrices must be states by actions by states, and each
+    /// row `transition[s][a]` must be non-negative and sum to within
+    /// 1e-5 of 1.
+    pub fn from_matrix(
+        transition: Vec<Vec<Vec<f32>>>,
+        reward: Vec<Vec<Vec<f32>>>,
+        gamma: f32,
+    ) -> Result<Self, TabularError> {
+        let states = transition.len();
+        let actions = transition.first().map_or(0, Vec::len);
+        if states == 0 || actions == 0 {
+            return Err(TabularError::Empty);
+        }
+        for (matrix, is_reward) in [(&transition, false), (&reward, true)] {
+            if matrix.len() != states {
+                let state = matrix.len().min(states);
+                return Err(TabularError::Shape {
+                    state,
+                    action: None,
+                    reward: is_reward,
+                });
+            }
+            for (s, row) in matrix.iter().enumerate() {
+                let shape = |action| TabularError::Shape {
+                    state: s,
+                    action,
+                    reward: is_reward,
+                };
+                if row.len() != actions {
+                    return Err(shape(None));
+                }
+                if let Some(a) = row.iter().position(|next| next.len() != states) {
+                    return Err(shape(Some(a)));
+                }
+            }
+        }
+        for (s, row) in transition.iter().enumerate() {
+            for (a, next) in row.iter().enumerate() {
+                let total: f32 = next.iter().sum();
+                if next.iter().any(|p| *p < 0.0) || (total - 1.0).abs() > 1e-5 {
+                    return Err(TabularError::NotNormalized {
+                        state: s,
+                        action: a,
+                        total,
+                    });
+                }
+            }
+        }
+
+        let mut rewards: Vec<f32> = reward.iter().flatten().flatten().copied().collect();
+        rewards.sort_by(f32::total_cmp);
+        rewards.dedup();
+        Ok(TabularMDP {
+            transition,
+            reward,
+            rewards,
+            terminals: HashSet::new(),
+            gamma,
+        })
+    }
+
+    /// This MDP, with `terminals` as its terminal states.
+    pub fn with_terminals(mut self, terminals: impl IntoIterator<Item = usize>) -> Self {
+        self.terminals = terminals.into_iter().collect();
+        self
+    }
+
+    pub fn num_states(&self) -> usize {
+        self.transition.len()
+    }
+
+    pub fn num_actions(&self) -> usize {
+        self.transition[0].len()
+    }
+}
+
+/// A random MDP over `states` states and `actions` actions, for
+/// stress-testing algorithms, generated from `seed`.
+///
+/// Each state-action pair leads to `branching` distinct next states,
+/// or every state if there are fewer, drawn uniformly, with random
+/// probabilities normalized to sum to 1 and a random reward in
+/// [-1, 1) for each. Like any `TabularMDP`, episodes start from state
+/// 0 and no state is terminal; the discount is 0.9.
+///
+/// Panics if `states` or `actions` is 0.
+pub fn random_mdp(states: usize, actions: usize, branching: usize, seed: u64) -> TabularMDP {
+    assert!(
+        states > 0 && actions > 0,
+        "a random MDP needs states and actions"
+    );
+    let mut rng = make_rng(seed);
+    let branching = branching.clamp(1, states);
+    let mut transition = vec![vec![vec![0.0; states]; actions]; states];
+    let mut reward = vec![vec![vec![0.0; states]; actions]; states];
+    for s in 0..states {
+        for a in 0..actions {
+            let next = index::sample(&mut rng, states, branching).into_vec();
+            // Bounded away from 0, so no drawn next state is impossible.
+            let weights: Vec<f32> = next.iter().map(|_| rng.gen_range(0.1..1.0)).collect();
+            let total: f32 = weights.iter().sum();
+            for (t, w) in next.into_iter().zip(weights) {
+                transition[s][a][t] = w / total;
+                reward[s][a][t] = rng.gen_range(-1.0..1.0);
+            }
+        }
+    }
+    TabularMDP::from_matrix(transition, reward, 0.9).expect("random matrices are well formed")
+}
+
+impl IEnvironment for TabularMDP {
+    type State = usize;
+    type Action = usize;
+
+    fn prob(&self, from: &usize, take: &usize, to: &usize, with: &f32) -> f32 {
+        if self.reward[*from][*take][*to] == *with {
+            self.transition[*from][*take][*to]
+        } else {
+            0.0
+        }
+    }
+
+    fn prob_transition(&self, from: &usize, take: &usize, to: &usize) -> Option<f32> {
+        Some(self.transition[*from][*take][*to])
+    }
+
+    fn expected_reward_at(&self, from: &usize, take: &usize, to: &usize) -> Option<f32> {
+        Some(self.reward[*from][*take][*to])
+    }
+
+    fn actions_from(&self, _: &usize) -> HashSet<usize> {
+        (0..self.num_actions()).collect()
+    }
+
+    fn states_from(&self, from: &usize, take: &usize) -> HashSet<usize> {
+        let next = &self.transition[*from][*take];
+        (0..next.len()).filter(|to| next[*to] > 0.0).collect()
+    }
+
+    fn is_terminal(&self, state: &usize) -> bool {
+        self.terminals.contains(state)
+    }
+
+    fn gamma(&self) -> f32 {
+        self.gamma
+    }
+
+    fn start_states(&self) -> HashSet<usize> {
+        HashSet::from([0])
+    }
+
+    fn all_states(&self) -> HashSet<usize> {
+        (0..self.num_states()).collect()
+    }
+
+    fn rewards(&self) -> Vec<f32> {
+        self.rewards.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dp::value_iteration;
+    use crate::testing::{values_approx_eq, Missing};
+    use crate::validate::validate_all;
+
+    /// Action 0 stays put and action 1 tries to switch states, which
+    /// from state 0 only works 80% of the time. Switching into state 1
+    /// pays 1, and staying there pays 2 a step.
+    fn two_state() -> Result<TabularMDP, TabularError> {
+        TabularMDP::from_matrix(
+            vec![
+                vec![vec![1.0, 0.0], vec![0.2, 0.8]],
+                vec![vec![0.0, 1.0], vec![1.0, 0.0]],
+            ],
+            vec![
+                vec![vec![0.0, 0.0], vec![0.0, 1.0]],
+                vec![vec![0.0, 2.0], vec![0.0, 0.0]],
+            ],
+            0.9,
+        )
+    }
+
+    #[test]
+    fn matrices_solve_to_the_known_optimum() {
+        let env = two_state().unwrap();
+        assert_eq!(validate_all(&env, 1e-6), Ok(()));
+        assert_eq!(env.rewards(), [0.0, 1.0, 2.0]);
+        let (v, policy) = value_iteration(&env, None, 1e-6, 1000).unwrap();
+        // V(1) = 2 / (1 - 0.9), and V(0) = 0.8 (1 + 0.9 V(1)) + 0.2 (0.9 V(0)).
+        assert!((v[&1] - 20.0).abs() < 1e-3);
+        assert!((v[&0] - 15.2 / 0.82).abs() < 1e-3);
+        assert_eq!(policy.action(&0), Some(&1));
+        assert_eq!(policy.action(&1), Some(&0));
+    }
+
+    #[test]
+    fn random_mdps_are_valid_and_solved_alike() {
+        use crate::dp::policy_iteration;
+
+        for seed in 0..20 {
+            let env = random_mdp(6, 3, 2, seed);
+            assert_eq!(validate_all(&env, 1e-5), Ok(()), "seed {seed}");
+            for s in 0..6 {
+                for a in 0..3 {
+                    assert_eq!(env.states_from(&s, &a).len(), 2);
+                }
+            }
+            let (v, _) = value_iteration(&env, None, 1e-6, 10_000).unwrap();
+            let (w, _, _) = policy_iteration(&env, None, 1e-6, 10_000).unwrap();
+            assert!(
+                values_approx_eq(&v, &w, 1e-3, Missing::Mismatch),
+                "seed {seed}"
+            );
+        }
+        assert_eq!(random_mdp(4, 2, 3, 7), random_mdp(4, 2, 3, 7));
+        assert_eq!(random_mdp(3, 1, 10, 0).states_from(&0, &0).len(), 3);
+    }
+
+    #[test]
+    fn malformed_matrices_are_rejected() {
+        let reward = two_state().unwrap().reward;
+        let unnormalized = vec![
+            vec![vec![1.0, 0.0], vec![0.5, 0.4]],
+            vec![vec![0.0, 1.0], vec![1.0, 0.0]],
+        ];
+        assert_eq!(
+            TabularMDP::from_matrix(unnormalized, reward.clone(), 0.9),
+            Err(TabularError::NotNormalized {
+                state: 0,
+                action: 1,
+                total: 0.9,
+            })
+        );
+
+        let ragged = vec![
+            vec![vec![1.0, 0.0], vec![0.2, 0.8]],
+            vec![vec![0.0, 1.0], vec![1.0]],
+        ];
+        assert_eq!(
+            TabularMDP::from_matrix(ragged, reward.clone(), 0.9),
+            Err(TabularError::Shape {
+                state: 1,
+                action: Some(1),
+                reward: false,
+            })
+        );
+        let transition = two_state().unwrap().transition;
+        assert_eq!(
+            TabularMDP::from_matrix(transition, reward[..1].to_vec(), 0.9),
+            Err(TabularError::Shape {
+                state: 1,
+                action: None,
+                reward: true,
+            })
+        );
+        assert_eq!(
+            TabularMDP::from_matrix(vec![], vec![], 0.9),
+            Err(TabularError::Empty)
+        );
+    }
+}
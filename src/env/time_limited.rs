@@ -0,0 +1,241 @@
+use std::collections::HashSet;
+
+use crate::env::IEnvironment;
+use crate::rng::StdRng;
+
+/// The environment `E` with a finite horizon: each state carries the
+/// number of steps remaining, which every transition decrements, and
+/// states with no steps remaining are terminal.
+///
+/// Since the time remaining is part of the state, dynamic programming
+/// over this environment finds the optimal nonstationary policy for
+/// the horizon, which may act differently in the same base state
+/// depending on how much time is left. The price is the size
+/// of the state space: `all_states` is `horizon + 1` copies of the
+/// base environment's, and each sweep costs that much more.
+pub struct TimeLimited<E> {
+    env: E,
+    horizon: usize,
+}
+
+impl<E: IEnvironment> TimeLimited<E> {
+    /// Limits episodes of `env` to `horizon` steps.
+    pub fn new(env: E, horizon: usize) -> Self {
+        TimeLimited { env, horizon }
+    }
+
+    /// The environment being limited.
+    pub fn env(&self) -> &E {
+        &self.env
+    }
+
+    pub fn horizon(&self) -> usize {
+        self.horizon
+    }
+}
+
+/// Whether `to` has one step fewer remaining than `from`.
+fn ticks(from: usize, to: usize) -> bool {
+    from > 0 && to == from - 1
+}
+
+impl<E: IEnvironment> IEnvironment for TimeLimited<E> {
+    type State = (E::State, usize);
+    type Action = E::Action;
+
+    fn prob(
+        &self,
+        (from, left): &Self::State,
+        take: &E::Action,
+        (to, then): &Self::State,
+        with: &f32,
+    ) -> f32 {
+        if ticks(*left, *then) {
+            self.env.prob(from, take, to, with)
+        } else {
+            0.0
+        }
+    }
+
+    fn prob_transition(
+        &self,
+        (from, left): &Self::State,
+        take: &E::Action,
+        (to, then): &Self::State,
+    ) -> Option<f32> {
+        if ticks(*left, *then) {
+            self.env.prob_transition(from, take, to)
+        } else {
+            Some(0.0)
+        }
+    }
+
+    fn transition_distribution(
+        &self,
+        (from, left): &Self::State,
+        take: &E::Action,
+    ) -> Option<Vec<(Self::State, f32)>> {
+        if *left == 0 {
+            return Some(Vec::new());
+        }
+        let distribution = self.env.transition_distribution(from, take)?;
+        Some(
+            distribution
+                .into_iter()
+                .map(|(to, p)| ((to, left - 1), p))
+                .collect(),
+        )
+    }
+
+    fn expected_reward(&self, (from, left): &Self::State, take: &E::Action) -> Option<f32> {
+        if *left == 0 {
+            return Some(0.0);
+        }
+        self.env.expected_reward(from, take)
+    }
+
+    fn expected_reward_at(
+        &self,
+        (from, left): &Self::State,
+        take: &E::Action,
+        (to, then): &Self::State,
+    ) -> Option<f32> {
+        if ticks(*left, *then) {
+            self.env.expected_reward_at(from, take, to)
+        } else {
+            Some(0.0)
+        }
+    }
+
+    fn actions_from(&self, (from, _): &Self::State) -> HashSet<E::Action> {
+        self.env.actions_from(from)
+    }
+
+    fn action_mask(&self, (from, _): &Self::State) -> HashSet<E::Action> {
+        self.env.action_mask(from)
+    }
+
+    fn is_valid_action(&self, (from, _): &Self::State, take: &E::Action) -> bool {
+        self.env.is_valid_action(from, take)
+    }
+
+    fn states_from(&self, (from, left): &Self::State, take: &E::Action) -> HashSet<Self::State> {
+        if *left == 0 {
+            return HashSet::new();
+        }
+        self.env
+            .states_from(from, take)
+            .into_iter()
+            .map(|to| (to, left - 1))
+            .collect()
+    }
+
+    fn sample_transition(
+        &self,
+        (from, left): &Self::State,
+        take: &E::Action,
+        rng: &mut StdRng,
+    ) -> Option<(Self::State, f32)> {
+        if *left == 0 {
+            return None;
+        }
+        let (to, reward) = self.env.sample_transition(from, take, rng)?;
+        Some(((to, left - 1), reward))
+    }
+
+    /// Terminal in the base environment, or out of time.
+    fn is_terminal(&self, (state, left): &Self::State) -> bool {
+        *left == 0 || self.env.is_terminal(state)
+    }
+
+    fn gamma(&self) -> f32 {
+        self.env.gamma()
+    }
+
+    fn gamma_at(&self, (state, _): &Self::State) -> f32 {
+        self.env.gamma_at(state)
+    }
+
+    fn start_states(&self) -> HashSet<Self::State> {
+        self.env
+            .start_states()
+            .into_iter()
+            .map(|s| (s, self.horizon))
+            .collect()
+    }
+
+    /// Every base state paired with every number of steps remaining,
+    /// from 0 to `horizon`.
+    fn all_states(&self) -> HashSet<Self::State> {
+        let states = self.env.all_states();
+        (0..=self.horizon)
+            .flat_map(|left| states.iter().map(move |s| (s.clone(), left)))
+            .collect()
+    }
+
+    fn rewards(&self) -> Vec<f32> {
+        self.env.rewards()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dp::value_iteration;
+    use crate::env::IDeterministicEnvironment;
+
+    /// A corridor of cells 0 to 4, starting at 1, with an exit at each
+    /// end: entering 0 pays 1 and entering 4 pays 10.
+    struct Exits;
+
+    impl IDeterministicEnvironment for Exits {
+        type State = i32;
+        type Action = i32;
+
+        fn transition(&self, from: &i32, take: &i32) -> (i32, f32) {
+            let to = from + take;
+            let reward = match to {
+                0 => 1.0,
+                4 => 10.0,
+                _ => 0.0,
+            };
+            (to, reward)
+        }
+
+        fn actions_from(&self, _: &i32) -> HashSet<i32> {
+            HashSet::from([-1, 1])
+        }
+
+        fn is_terminal(&self, state: &i32) -> bool {
+            *state == 0 || *state == 4
+        }
+
+        fn start_states(&self) -> HashSet<i32> {
+            HashSet::from([1])
+        }
+    }
+
+    #[test]
+    fn steps_count_down_to_a_terminal_state() {
+        let limited = TimeLimited::new(Exits, 2);
+        assert_eq!(limited.start_states(), HashSet::from([(1, 2)]));
+        assert_eq!(limited.states_from(&(1, 2), &1), HashSet::from([(2, 1)]));
+        assert_eq!(limited.prob(&(1, 2), &1, &(2, 1), &0.0), 1.0);
+        assert_eq!(limited.prob(&(1, 2), &1, &(2, 2), &0.0), 0.0);
+        assert!(limited.is_terminal(&(2, 0)));
+        assert!(!limited.is_terminal(&(2, 1)));
+        assert_eq!(limited.all_states().len(), 3 * 5);
+    }
+
+    #[test]
+    fn a_short_horizon_settles_for_the_nearer_exit() {
+        let (_, unlimited) = value_iteration(&Exits, None, 1e-6, 1000).unwrap();
+        assert_eq!(unlimited.action(&1), Some(&1));
+
+        let (v, limited) = value_iteration(&TimeLimited::new(Exits, 3), None, 1e-6, 1000).unwrap();
+        assert_eq!(limited.action(&(1, 3)), Some(&1));
+        assert_eq!(v[&(1, 3)], 10.0);
+        assert_eq!(limited.action(&(1, 2)), Some(&-1));
+        assert_eq!(v[&(1, 2)], 1.0);
+    }
+}
@@ -0,0 +1,13 @@
+//! The errors of driving an environment step by step.
+
+/// An error from stepping an environment, carrying the offending
+/// state and action where there are some.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReinforceError<S, A> {
+    /// `action` is not among the `actions_from` `state`. Environments
+    /// generally give such actions probability 0 of going anywhere,
+    /// rather than failing themselves.
+    InvalidAction { state: S, action: A },
+    /// A step was taken before the first `reset`.
+    NotReset,
+}
@@ -0,0 +1,200 @@
+//! CSV and JSON export of Q-tables for analysis in other tools.
+//!
+//! States and actions are written as their `Debug` form, so unlike
+//! `persist` nothing here needs serde, and nothing is read back.
+
+use std::fmt::Debug;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::dp::QTable;
+use crate::env::IEnvironment;
+
+/// The entries of `q` as `(state, action, value)` rows, sorted by
+/// state and then action so that the output is reproducible.
+fn rows<E>(q: &QTable<E>) -> Vec<(String, String, f32)>
+where
+    E: IEnvironment,
+    E::State: Debug,
+    E::Action: Debug,
+{
+    let mut rows: Vec<_> = q
+        .iter()
+        .map(|((s, a), v)| (format!("{:?}", s), format!("{:?}", a), *v))
+        .collect();
+    rows.sort_by(|x, y| (&x.0, &x.1).cmp(&(&y.0, &y.1)));
+    rows
+}
+
+/// Writes `q` to `path` as CSV with a `state,action,value` header and
+/// one row per entry.
+///
+/// Fields containing commas, quotes or line breaks, such as the
+/// `Debug` form of a tuple state, are quoted, with any quotes inside
+/// doubled.
+pub fn export_q_csv<E>(q: &QTable<E>, path: impl AsRef<Path>) -> io::Result<()>
+where
+    E: IEnvironment,
+    E::State: Debug,
+    E::Action: Debug,
+{
+    let mut writer = BufWriter::new(File::create(path)?);
+    writeln!(writer, "state,action,value")?;
+    for (s, a, v) in rows(q) {
+        writeln!(writer, "{},{},{}", csv_field(&s), csv_field(&a), v)?;
+    }
+    writer.flush()
+}
+
+/// Writes `q` to `path` as a JSON array of `{"state", "action",
+/// "value"}` objects, with states and actions as strings.
+///
+/// JSON has no representation of non-finite numbers, so those values
+/// are written as `null`.
+pub fn export_q_json<E>(q: &QTable<E>, path: impl AsRef<Path>) -> io::Result<()>
+where
+    E: IEnvironment,
+    E::State: Debug,
+    E::Action: Debug,
+{
+    let mut writer = BufWriter::new(File::create(path)?);
+    write!(writer, "[")?;
+    for (i, (s, a, v)) in rows(q).into_iter().enumerate() {
+        let separator = if i == 0 { "" } else { "," };
+        let value = if v.is_finite() {
+            v.to_string()
+        } else {
+            "null".to_string()
+        };
+        write!(
+            writer,
+            "{}\n  {{\"state\": \"{}\", \"action\": \"{}\", \"value\": {}}}",
+            separator,
+            json_escape(&s),
+            json_escape(&a),
+            value
+        )?;
+    }
+    writeln!(writer, "\n]")?;
+    writer.flush()
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn json_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(any(feature = "examples", feature = "serde"))]
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("reinforce-{}-{name}", std::process::id()))
+    }
+
+    /// Splits one line of CSV into its fields, undoing `csv_field`.
+    fn parse_csv_line(line: &str) -> Vec<String> {
+        let mut fields = vec![String::new()];
+        let mut quoted = false;
+        let mut chars = line.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' if quoted && chars.peek() == Some(&'"') => {
+                    chars.next();
+                    fields.last_mut().unwrap().push('"');
+                }
+                '"' => quoted = !quoted,
+                ',' if !quoted => fields.push(String::new()),
+                c => fields.last_mut().unwrap().push(c),
+            }
+        }
+        fields
+    }
+
+    #[cfg(feature = "examples")]
+    #[test]
+    fn csv_rows_read_back_as_written() {
+        use crate::env::examples::{GridWorld, Move};
+
+        let q: QTable<GridWorld> = [
+            (((0, 0), Move::Right), 0.5),
+            (((1, 0), Move::Up), -0.125),
+            (((0, 1), Move::Left), 1.0 / 3.0),
+        ]
+        .into_iter()
+        .collect();
+        let path = temp_path("q.csv");
+        export_q_csv(&q, &path).unwrap();
+        let csv = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let mut lines = csv.lines();
+        assert_eq!(lines.next(), Some("state,action,value"));
+        assert!(csv.contains("\"(0, 0)\",Right,0.5\n"));
+        let read: Vec<(String, String, f32)> = lines
+            .map(|line| {
+                let fields = parse_csv_line(line);
+                assert_eq!(fields.len(), 3, "{line}");
+                (
+                    fields[0].clone(),
+                    fields[1].clone(),
+                    fields[2].parse().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(read, rows(&q));
+    }
+
+    #[test]
+    fn fields_are_quoted_and_escaped() {
+        assert_eq!(csv_field("Right"), "Right");
+        assert_eq!(csv_field("(0, 1)"), "\"(0, 1)\"");
+        assert_eq!(csv_field("\"a\""), "\"\"\"a\"\"\"");
+        assert_eq!(
+            parse_csv_line(&csv_field("say \"hi\", then")),
+            ["say \"hi\", then"]
+        );
+        assert_eq!(json_escape("\"a\"\\\n"), "\\\"a\\\"\\\\\\n");
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn json_output_parses() {
+        use crate::testing::Chain;
+
+        let q: QTable<Chain> = [((0, 1), 0.25), ((2, -1), f32::NAN)].into_iter().collect();
+        let path = temp_path("q.json");
+        export_q_json(&q, &path).unwrap();
+        let json: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!([
+                {"state": "0", "action": "1", "value": 0.25},
+                {"state": "2", "action": "-1", "value": null},
+            ])
+        );
+    }
+}
@@ -0,0 +1,295 @@
+//! Inverse reinforcement learning: recovering the reward an expert
+//! acted on from its demonstrations.
+
+use std::collections::HashSet;
+
+use crate::dataset::Dataset;
+use crate::dp::soft_value_iteration;
+use crate::env::IEnvironment;
+use crate::rng::StdRng;
+use crate::simulate::state_visitation;
+
+/// The temperature of the soft-optimal policy each iteration of
+/// `max_ent_irl` fits to the current reward.
+const TEMPERATURE: f32 = 1.0;
+
+fn dot(w: &[f32], x: &[f32]) -> f32 {
+    w.iter().zip(x).map(|(a, b)| a * b).sum()
+}
+
+/// The environment `E` with its rewards replaced by a linear one of
+/// the state acted from, `weights . features(from)`, whatever the
+/// action and next state.
+struct LinearReward<'a, E, F> {
+    env: &'a E,
+    features: &'a F,
+    weights: Vec<f32>,
+}
+
+impl<E, F> LinearReward<'_, E, F>
+where
+    E: IEnvironment,
+    F: Fn(&E::State) -> Vec<f32>,
+{
+    fn reward(&self, from: &E::State) -> f32 {
+        dot(&self.weights, &(self.features)(from))
+    }
+}
+
+impl<E, F> IEnvironment for LinearReward<'_, E, F>
+where
+    E: IEnvironment,
+    F: Fn(&E::State) -> Vec<f32>,
+{
+    type State = E::State;
+    type Action = E::Action;
+
+    fn prob(&self, from: &E::State, take: &E::Action, to: &E::State, with: &f32) -> f32 {
+        if self.reward(from) != *with {
+            return 0.0;
+        }
+        self.env
+            .prob_transition(from, take, to)
+            .expect("prob_transition must be defined to replace the rewards")
+    }
+
+    fn prob_transition(&self, from: &E::State, take: &E::Action, to: &E::State) -> Option<f32> {
+        self.env.prob_transition(from, take, to)
+    }
+
+    fn transition_distribution(
+        &self,
+        from: &E::State,
+        take: &E::Action,
+    ) -> Option<Vec<(E::State, f32)>> {
+        self.env.transition_distribution(from, take)
+    }
+
+    fn expected_reward(&self, from: &E::State, _: &E::Action) -> Option<f32> {
+        Some(self.reward(from))
+    }
+
+    fn expected_reward_at(&self, from: &E::State, take: &E::Action, to: &E::State) -> Option<f32> {
+        Some(self.env.prob_transition(from, take, to)? * self.reward(from))
+    }
+
+    fn actions_from(&self, from: &E::State) -> HashSet<E::Action> {
+        self.env.actions_from(from)
+    }
+
+    fn action_mask(&self, from: &E::State) -> HashSet<E::Action> {
+        self.env.action_mask(from)
+    }
+
+    fn is_valid_action(&self, from: &E::State, take: &E::Action) -> bool {
+        self.env.is_valid_action(from, take)
+    }
+
+    fn states_from(&self, from: &E::State, take: &E::Action) -> HashSet<E::State> {
+        self.env.states_from(from, take)
+    }
+
+    fn states_from_into(&self, from: &E::State, take: &E::Action, buf: &mut Vec<E::State>) {
+        self.env.states_from_into(from, take, buf)
+    }
+
+    fn sample_transition(
+        &self,
+        from: &E::State,
+        take: &E::Action,
+        rng: &mut StdRng,
+    ) -> Option<(E::State, f32)> {
+        let (to, _) = self.env.sample_transition(from, take, rng)?;
+        Some((to, self.reward(from)))
+    }
+
+    fn is_terminal(&self, state: &E::State) -> bool {
+        self.env.is_terminal(state)
+    }
+
+    fn gamma(&self) -> f32 {
+        self.env.gamma()
+    }
+
+    fn gamma_at(&self, state: &E::State) -> f32 {
+        self.env.gamma_at(state)
+    }
+
+    fn start_states(&self) -> HashSet<E::State> {
+        self.env.start_states()
+    }
+
+    fn all_states(&self) -> HashSet<E::State> {
+        self.env.all_states()
+    }
+}
+
+/// Maximum-entropy inverse reinforcement learning: the weights of the
+/// linear reward `weights . features(s)`, for acting from state `s`,
+/// under which the expert that collected `dataset` in `env` is most
+/// likely, assuming it acted soft-optimally, at temperature 1.
+///
+/// Starting from 0, each of `iterations` steps of gradient ascent
+/// solves for the soft-optimal policy under the current reward with
+/// `soft_value_iteration`, estimates its feature expectations from
+/// its `state_visitation` over as many episodes, and of at most as
+/// many steps, as `dataset` holds, and moves the weights by `lr`
+/// times the expert's feature expectations less the policy's. Both
+/// are expectations under the same discounted state distribution,
+/// each visit `t` steps into an episode counting `gamma^t` and the
+/// weights normalized to sum to 1, so that the gradient vanishes
+/// only when the two match; `gamma` defaults to `env.gamma()`.
+///
+/// The policy's episodes start uniformly from `env.start_states()`,
+/// so these should be where the demonstrations start, and as often:
+/// otherwise the two feature expectations differ by where the
+/// episodes begin as well as how they were acted, and the weights
+/// are fitted to explain both.
+///
+/// The environment's own rewards are ignored, but its transition
+/// probabilities must be defined. Returns `None` if `dataset` is
+/// empty, or if `soft_value_iteration` fails.
+pub fn max_ent_irl<E, F>(
+    env: &E,
+    dataset: &Dataset<E>,
+    features: F,
+    iterations: usize,
+    lr: f32,
+    gamma: Option<f32>,
+    rng: &mut StdRng,
+) -> Option<Vec<f32>>
+where
+    E: IEnvironment,
+    F: Fn(&E::State) -> Vec<f32>,
+{
+    let gamma = gamma.unwrap_or_else(|| env.gamma());
+    let first = dataset.transitions().next()?;
+    let dimensions = features(&first.state).len();
+
+    let mut expert = vec![0.0; dimensions];
+    let mut total = 0.0;
+    let mut episodes = 0;
+    let mut max_steps = 0;
+    for episode in dataset.records().chunk_by(|a, b| a.episode == b.episode) {
+        let mut discount = 1.0;
+        for record in episode {
+            let phi = features(&record.transition.state);
+            for (e, x) in expert.iter_mut().zip(&phi) {
+                *e += discount * x;
+            }
+            total += discount;
+            discount *= gamma;
+        }
+        episodes += 1;
+        max_steps = max_steps.max(episode.len());
+    }
+    for e in &mut expert {
+        *e /= total;
+    }
+
+    let mut learned = LinearReward {
+        env,
+        features: &features,
+        weights: vec![0.0; dimensions],
+    };
+    for _ in 0..iterations {
+        let (_, policy) = soft_value_iteration(&learned, TEMPERATURE, Some(gamma), 1e-4, 1000)?;
+        let visitation = state_visitation(&learned, &policy, episodes, max_steps, Some(gamma), rng);
+        let mut expected = vec![0.0; dimensions];
+        for (s, p) in &visitation {
+            for (e, x) in expected.iter_mut().zip(features(s)) {
+                *e += p * x;
+            }
+        }
+        for ((w, e), x) in learned.weights.iter_mut().zip(&expert).zip(&expected) {
+            *w += lr * (e - x);
+        }
+    }
+    Some(learned.weights)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dataset::collect_dataset;
+    use crate::rng::make_rng;
+
+    /// A 5-by-5 grid without end, moving deterministically in the
+    /// four directions, and staying put at the edges.
+    struct Plane;
+
+    impl IEnvironment for Plane {
+        type State = (i32, i32);
+        type Action = (i32, i32);
+
+        fn prob(&self, from: &(i32, i32), take: &(i32, i32), to: &(i32, i32), with: &f32) -> f32 {
+            f32::from(*with == 0.0 && self.states_from(from, take).contains(to))
+        }
+
+        fn actions_from(&self, _: &(i32, i32)) -> HashSet<(i32, i32)> {
+            HashSet::from([(0, 1), (0, -1), (1, 0), (-1, 0)])
+        }
+
+        fn states_from(&self, from: &(i32, i32), take: &(i32, i32)) -> HashSet<(i32, i32)> {
+            let to = ((from.0 + take.0).clamp(0, 4), (from.1 + take.1).clamp(0, 4));
+            HashSet::from([to])
+        }
+
+        fn start_states(&self) -> HashSet<(i32, i32)> {
+            HashSet::from([(0, 0)])
+        }
+
+        fn rewards(&self) -> Vec<f32> {
+            vec![0.0]
+        }
+    }
+
+    fn features(s: &(i32, i32)) -> Vec<f32> {
+        vec![s.0 as f32 / 4.0, s.1 as f32 / 4.0]
+    }
+
+    #[test]
+    fn the_recovered_reward_points_the_experts_way() {
+        let truth = vec![1.0, 0.5];
+        let rewarded = LinearReward {
+            env: &Plane,
+            features: &features,
+            weights: truth.clone(),
+        };
+        let (_, expert) =
+            soft_value_iteration(&rewarded, TEMPERATURE, Some(0.9), 1e-4, 1000).unwrap();
+        // Demonstrated under the true reward, but learned from without.
+        let demos = collect_dataset(&rewarded, &expert, 200, 30, &mut make_rng(0));
+        let dataset = Dataset::from_records(demos.records().to_vec());
+
+        let weights = max_ent_irl(
+            &Plane,
+            &dataset,
+            features,
+            20,
+            0.5,
+            Some(0.9),
+            &mut make_rng(1),
+        )
+        .unwrap();
+        let cosine = dot(&weights, &truth) / (dot(&weights, &weights) * dot(&truth, &truth)).sqrt();
+        assert!(cosine > 0.99, "{weights:?}");
+    }
+
+    #[test]
+    fn nothing_is_learned_from_an_empty_dataset() {
+        let dataset = Dataset::from_records(Vec::new());
+        assert_eq!(
+            max_ent_irl(
+                &Plane,
+                &dataset,
+                features,
+                20,
+                0.5,
+                Some(0.9),
+                &mut make_rng(0)
+            ),
+            None
+        );
+    }
+}
@@ -0,0 +1,33 @@
+pub mod approx;
+pub mod bandit;
+pub mod curriculum;
+pub mod dataset;
+pub mod dot;
+pub mod dp;
+pub mod env;
+pub mod error;
+pub mod export;
+pub mod irl;
+pub mod marl;
+pub mod mcts;
+pub mod monte_carlo;
+pub mod observer;
+pub mod options;
+#[cfg(feature = "serde")]
+pub mod persist;
+pub mod pg;
+pub mod planning;
+#[cfg(feature = "interactive")]
+pub mod play;
+pub mod policy;
+#[cfg(test)]
+mod properties;
+pub mod replay;
+pub mod rng;
+pub mod schedule;
+pub mod simulate;
+pub mod solve;
+pub mod td;
+#[cfg(test)]
+mod testing;
+pub mod validate;
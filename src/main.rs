@@ -1,140 +1,63 @@
-use std::collections::HashSet;
+use std::collections::HashMap;
 
-trait IEnvironment {
-    type Action;
-    type State;
-
-    /// The full dynamics of the environment: the probability of
-    /// transitioning from state `from` to state `to`, taking action
-    /// `take` and receiving rewards `with`.
-    fn prob(from: &Self::State, take: &Self::Action, to: &Self::State, with: &f32) -> f32;
-
-    // Expectations
-
-    /// Calculates the probability of transitioning from state `from`
-    /// to state `to`, taking action `take`.
-    ///
-    /// This is formally calcuated as the marginal probability over
-    /// different rewards values. If `rewards` is implemented, then
-    /// this probability can be calculated exactly, and is provided
-    /// through the trait's default implementation.
-    ///
-    /// If `rewards` is not or cannot be implemented (e.g. for an
-    /// infinite range), then this function must be defined explitly
-    fn prob_transition(from: &Self::State, take: &Self::Action, to: &Self::State) -> Option<f32> {
-        let rewards = Self::rewards();
-        if rewards.len() > 0 {
-            return Some(rewards.iter().map(|s| Self::prob(from, take, to, s)).sum());
-        } else {
-            None
-        }
-    }
-
-    /// Calculate the expected reward taking action `take` from state
-    /// `from`, marginalizing over all possible resulting states.
-    fn expected_reward(from: &Self::State, take: &Self::Action) -> Option<f32> {
-        let rewards = Self::rewards();
-        if rewards.len() > 0 {
-            let to = Self::states_from(from, take);
-            return Some(
-                rewards
-                    .iter()
-                    .flat_map(|r| to.iter().map(move |t| (r, t)))
-                    .map(|(r, t)| Self::prob(from, take, t, r) * r)
-                    .sum(),
-            );
-        } else {
-            None
-        }
-    }
-
-    /// Calculate the expected reward taking action `take` from state
-    /// `from`, arriving at state `to`.
-    fn expected_reward_at(
-        from: &Self::State,
-        take: &Self::Action,
-        to: &Self::State,
-    ) -> Option<f32> {
-        let rewards = Self::rewards();
-        if rewards.len() > 0 {
-            Some(
-                rewards
-                    .iter()
-                    .map(|r| Self::prob(from, take, to, r) * r)
-                    .sum(),
-            )
-        } else {
-            None
-        }
-    }
-
-    // Space enumeration functions:
-
-    fn actions_from(from: &Self::State) -> HashSet<Self::Action>;
-    fn states_from(from: &Self::State, take: &Self::Action) -> HashSet<Self::State>;
-
-    /// Enumerates the possible reward values in the environment
-    /// (optional)
-    fn rewards() -> Vec<f32> {
-        vec![]
-    }
-}
-
-// Example interface implementations
-#[derive(Hash, Eq, PartialEq, Debug)]
-enum DoNothing {
-    Nothing,
-}
-#[derive(Debug, Hash, Eq, PartialEq)]
-enum Always {
-    Same,
-}
-
-#[derive(Debug)]
-struct Dull;
-impl IEnvironment for Dull {
-    type State = Always;
-    type Action = DoNothing;
-
-    fn actions_from(from: &Self::State) -> HashSet<Self::Action> {
-        HashSet::from_iter(vec![DoNothing::Nothing])
-    }
-    fn states_from(from: &Self::State, take: &Self::Action) -> HashSet<Self::State> {
-        HashSet::from_iter(vec![Always::Same])
-    }
-    fn rewards() -> Vec<f32> {
-        vec![0.0]
-    }
-    fn prob(_: &Self::State, _: &Self::Action, _: &Self::State, with: &f32) -> f32 {
-        if *with == 0.0 {
-            1.0
-        } else {
-            0.0
-        }
-    }
-}
+use reinforce_rust::dp;
+use reinforce_rust::env::examples::{Always, DoNothing, Dull, GridWorld};
+use reinforce_rust::env::IEnvironment;
+use reinforce_rust::policy::{DeterministicPolicy, Policy};
+use reinforce_rust::rng::make_rng;
+use reinforce_rust::simulate;
 
 fn main() {
+    let mut rng = make_rng(0);
+    let env = Dull;
     let init = Always::Same;
-    println!("Available actions: {:?}", Dull::actions_from(&init));
+    println!("Available actions: {:?}", env.actions_from(&init));
+    println!("All states: {:?}", env.all_states());
+    println!("All actions: {:?}", env.all_actions());
     println!(
         "Prob: {:?}",
-        Dull::prob(&init, &DoNothing::Nothing, &init, &0.0)
+        env.prob(&init, &DoNothing::Nothing, &init, &0.0)
     );
     println!(
         "Prob: {:?}",
-        Dull::prob(&init, &DoNothing::Nothing, &init, &1.0)
+        env.prob(&init, &DoNothing::Nothing, &init, &1.0)
     );
     println!(
         "Transition prob: {:?}",
-        Dull::prob_transition(&init, &DoNothing::Nothing, &init)
+        env.prob_transition(&init, &DoNothing::Nothing, &init)
     );
     println!(
         "Expected reward: {:?}",
-        Dull::expected_reward(&init, &DoNothing::Nothing)
+        env.expected_reward(&init, &DoNothing::Nothing)
     );
     println!(
         "Expected reward at: {:?}",
-        Dull::expected_reward_at(&init, &DoNothing::Nothing, &init)
+        env.expected_reward_at(&init, &DoNothing::Nothing, &init)
+    );
+
+    let policy: DeterministicPolicy<Dull> =
+        DeterministicPolicy::new(HashMap::from([(Always::Same, DoNothing::Nothing)]));
+    println!(
+        "Policy distribution: {:?}",
+        policy.action_distribution(&env, &init)
+    );
+    println!("Policy sample: {:?}", policy.sample(&env, &init, &mut rng));
+    println!(
+        "Policy value: {:?}",
+        dp::evaluate_policy(&env, &policy, Some(0.9), 1e-6, 1000)
+    );
+    if let Some((v, optimal)) = dp::value_iteration(&env, Some(0.9), 1e-6, 100) {
+        println!("Optimal value: {:?}", v);
+        println!("Optimal action: {:?}", optimal.action(&init));
+    }
+    println!(
+        "Rollout: {:?}",
+        simulate::rollout(&env, &policy, init, 3, &mut rng)
     );
+
+    let grid = GridWorld::new(4, 3, [(1, 1)], (3, 0), 0.04, 0.2).with_start((0, 2));
+    println!("Grid world:\n{}", grid);
+    if let Some((_, optimal)) = dp::value_iteration(&grid, Some(0.99), 1e-6, 1000) {
+        println!("Optimal policy:\n{}", grid.display_policy(&optimal));
+    }
 }
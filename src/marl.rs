@@ -0,0 +1,665 @@
+//! Learning in two-agent Markov games, as described by
+//! `IMultiAgentEnvironment`.
+
+use std::collections::{HashMap, HashSet};
+
+use rand::Rng;
+
+use crate::dp::value_iteration;
+use crate::env::{IEnvironment, IMultiAgentEnvironment};
+use crate::policy::{fixed_order, DeterministicPolicy, Policy};
+use crate::rng::StdRng;
+use crate::schedule::Schedule;
+use crate::simulate::{sample_weighted, EPISODE_STEP_LIMIT};
+use crate::td::LearningRate;
+
+/// How far from 0 a tableau entry must be to count as nonzero.
+const PIVOT_TOLERANCE: f64 = 1e-9;
+
+/// The value of the zero-sum matrix game `payoff`, in which the row
+/// player, maximizing, picks a row and the column player, minimizing,
+/// a column at the same time, along with a mixed strategy over the
+/// rows that guarantees it: `max_x min_j sum_i x[i] * payoff[i][j]`.
+///
+/// The game is solved exactly, as a linear program, by the simplex
+/// method, choosing pivots by Bland's rule so that it always
+/// terminates. Returns `None` if `payoff` has no rows or no columns,
+/// or rows of different lengths.
+pub fn solve_matrix_game(payoff: &[Vec<f32>]) -> Option<(Vec<f32>, f32)> {
+    let rows = payoff.len();
+    let columns = payoff.first()?.len();
+    if columns == 0 || payoff.iter().any(|row| row.len() != columns) {
+        return None;
+    }
+    // Shifted so every payoff is positive, the game's value is too,
+    // and the column player's strategy `y / sum(y)` is optimal where
+    // `y` maximizes `sum(y)` subject to `payoff . y <= 1`, `y >= 0`,
+    // whose optimum is `1 / value`. The row player's strategy is the
+    // dual solution, read off the slack columns of the objective.
+    let min = payoff
+        .iter()
+        .flatten()
+        .copied()
+        .fold(f32::INFINITY, f32::min);
+    let shift = 1.0 - f64::from(min);
+    let width = columns + rows + 1;
+    let mut tableau: Vec<Vec<f64>> = payoff
+        .iter()
+        .enumerate()
+        .map(|(i, row)| {
+            let mut line: Vec<f64> = row.iter().map(|p| f64::from(*p) + shift).collect();
+            line.extend((0..rows).map(|k| f64::from(k == i)));
+            line.push(1.0);
+            line
+        })
+        .collect();
+    let mut objective = vec![0.0; width];
+    objective[..columns].fill(-1.0);
+    let mut basis: Vec<usize> = (columns..columns + rows).collect();
+
+    while let Some(entering) = (0..width - 1).find(|&j| objective[j] < -PIVOT_TOLERANCE) {
+        let mut leaving: Option<(usize, f64)> = None;
+        for (i, line) in tableau.iter().enumerate() {
+            if line[entering] <= PIVOT_TOLERANCE {
+                continue;
+            }
+            let ratio = line[width - 1] / line[entering];
+            let better = match leaving {
+                None => true,
+                Some((l, best)) => {
+                    ratio < best - PIVOT_TOLERANCE
+                        || (ratio <= best + PIVOT_TOLERANCE && basis[i] < basis[l])
+                }
+            };
+            if better {
+                leaving = Some((i, ratio));
+            }
+        }
+        // Every payoff is positive, so the program is bounded and
+        // some row always limits the entering column.
+        let (pivot, _) = leaving.expect("the shifted game's program is bounded");
+        let scale = tableau[pivot][entering];
+        tableau[pivot].iter_mut().for_each(|x| *x /= scale);
+        let pivot_line = tableau[pivot].clone();
+        for (i, line) in tableau.iter_mut().enumerate() {
+            if i != pivot && line[entering] != 0.0 {
+                let factor = line[entering];
+                line.iter_mut()
+                    .zip(&pivot_line)
+                    .for_each(|(x, p)| *x -= factor * p);
+            }
+        }
+        let factor = objective[entering];
+        objective
+            .iter_mut()
+            .zip(&pivot_line)
+            .for_each(|(x, p)| *x -= factor * p);
+        basis[pivot] = entering;
+    }
+
+    let total = objective[width - 1];
+    let strategy = objective[columns..columns + rows]
+        .iter()
+        .map(|x| (x.max(0.0) / total) as f32)
+        .collect();
+    Some((strategy, (1.0 / total - shift) as f32))
+}
+
+/// A state and the joint action taken from it.
+type Pair<E> = (
+    <E as IMultiAgentEnvironment>::State,
+    <E as IMultiAgentEnvironment>::Action1,
+    <E as IMultiAgentEnvironment>::Action2,
+);
+
+/// The action values, state values and strategies learned by
+/// `minimax_q` for agent 0 of a zero-sum game.
+pub struct MinimaxQ<E: IMultiAgentEnvironment> {
+    q: HashMap<Pair<E>, f32>,
+    values: HashMap<E::State, f32>,
+    strategies: HashMap<E::State, HashMap<E::Action1, f32>>,
+}
+
+impl<E: IMultiAgentEnvironment> MinimaxQ<E> {
+    /// The value to agent 0 of the joint action `(a, o)` from `state`,
+    /// or 0 if it was never taken.
+    pub fn q(&self, state: &E::State, a: &E::Action1, o: &E::Action2) -> f32 {
+        self.q
+            .get(&(state.clone(), a.clone(), o.clone()))
+            .copied()
+            .unwrap_or(0.0)
+    }
+
+    /// The minimax value to agent 0 of `state`, or 0 if it was never
+    /// visited.
+    pub fn value(&self, state: &E::State) -> f32 {
+        self.values.get(state).copied().unwrap_or(0.0)
+    }
+
+    /// Agent 0's minimax strategy in `state`, the probability of each
+    /// of its actions, or `None` if the state was never visited.
+    pub fn strategy(&self, state: &E::State) -> Option<&HashMap<E::Action1, f32>> {
+        self.strategies.get(state)
+    }
+
+    /// Refits the value and strategy of `state` to the matrix game of
+    /// its action values.
+    fn solve(&mut self, env: &E, state: &E::State) {
+        let (first, second) = env.actions_from(state);
+        let (first, second) = (fixed_order(first), fixed_order(second));
+        let payoff: Vec<Vec<f32>> = first
+            .iter()
+            .map(|a| second.iter().map(|o| self.q(state, a, o)).collect())
+            .collect();
+        if let Some((strategy, value)) = solve_matrix_game(&payoff) {
+            self.values.insert(state.clone(), value);
+            self.strategies
+                .insert(state.clone(), first.into_iter().zip(strategy).collect());
+        }
+    }
+}
+
+/// Minimax-Q: Q-learning for agent 0 of a two-agent zero-sum game,
+/// learning the value of each joint action and the strategy that
+/// maximizes agent 0's worst-case return, whatever agent 1 does.
+///
+/// Runs `episodes` episodes from states drawn uniformly from
+/// `env.start_states()`, with agent 0 playing a uniformly random action
+/// with probability `epsilon` and one drawn from its current strategy
+/// otherwise, and agent 1 playing uniformly at random, so every joint
+/// action goes on being tried. After every step it applies the update
+/// `Q(s,a,o) += alpha * (r + gamma * V(s') - Q(s,a,o))` with agent 0's
+/// reward `r`, then solves the matrix game of `Q(s,.,.)` by
+/// `solve_matrix_game` for the new `V(s)` and strategy. Episodes end
+/// on reaching a terminal state or after `EPISODE_STEP_LIMIT` steps.
+/// `epsilon` may be annealed with a `Schedule` over the total number
+/// of steps taken, and `alpha` over the visits to each joint action,
+/// as for `q_learning`. `gamma` defaults to `env.gamma()`.
+pub fn minimax_q<E: IMultiAgentEnvironment>(
+    env: &E,
+    episodes: usize,
+    alpha: impl Into<LearningRate>,
+    gamma: Option<f32>,
+    epsilon: impl Into<Schedule>,
+    rng: &mut StdRng,
+) -> MinimaxQ<E> {
+    let alpha = alpha.into();
+    let epsilon = epsilon.into();
+    let gamma = gamma.unwrap_or_else(|| env.gamma());
+    let starts = fixed_order(env.start_states());
+    let mut learned = MinimaxQ {
+        q: HashMap::new(),
+        values: HashMap::new(),
+        strategies: HashMap::new(),
+    };
+    let mut visits: HashMap<Pair<E>, usize> = HashMap::new();
+    let mut steps = 0;
+    for _ in 0..episodes {
+        if starts.is_empty() {
+            break;
+        }
+        let mut state = starts[rng.gen_range(0..starts.len())].clone();
+        for _ in 0..EPISODE_STEP_LIMIT {
+            if env.is_terminal(&state) {
+                break;
+            }
+            let (first, second) = env.actions_from(&state);
+            let (first, second) = (fixed_order(first), fixed_order(second));
+            if first.is_empty() || second.is_empty() {
+                break;
+            }
+            let strategy = learned.strategy(&state).filter(|_| {
+                let eps = epsilon.value(steps);
+                eps <= 0.0 || rng.gen::<f32>() >= eps
+            });
+            let a = match strategy {
+                Some(strategy) => {
+                    let weighted = first.iter().map(|a| (a.clone(), strategy[a])).collect();
+                    sample_weighted(weighted, rng)
+                        .expect("a minimax strategy is a distribution over the actions")
+                }
+                None => first[rng.gen_range(0..first.len())].clone(),
+            };
+            let o = second[rng.gen_range(0..second.len())].clone();
+            let take = (a, o);
+            let candidates = fixed_order(env.states_from(&state, &take))
+                .into_iter()
+                .map(|to| {
+                    let p = env.prob(&state, &take, &to);
+                    (to, p)
+                })
+                .collect();
+            let Some(next) = sample_weighted(candidates, rng) else {
+                break;
+            };
+            steps += 1;
+            let reward = env.rewards_for(0, &state, &take, &next);
+            let future = if env.is_terminal(&next) {
+                0.0
+            } else {
+                learned.value(&next)
+            };
+            let (a, o) = take;
+            let key = (state.clone(), a, o);
+            let n = visits.entry(key.clone()).or_insert(0);
+            *n += 1;
+            let step_size = alpha.at(*n);
+            let value = learned.q.entry(key).or_insert(0.0);
+            *value += step_size * (reward + gamma * future - *value);
+            learned.solve(env, &state);
+            state = next;
+        }
+    }
+    learned
+}
+
+/// The single-agent environment agent `agent` of a symmetric game, one
+/// whose agents both choose from actions of the same type, faces once
+/// the other agent's policy is fixed.
+///
+/// Each of its actions stands for the joint actions it makes with
+/// every reply the opponent might make, the transitions and rewards
+/// of which are weighted by the probability of that reply: taking `a`
+/// from `s` reaches `s'` with probability `sum_o pi(o|s) P(s'|s,a,o)`,
+/// and the reward is agent `agent`'s own. The opponent's distribution
+/// is tabulated once, from every non-terminal state, so that its
+/// policy isn't asked again at each step.
+///
+/// The rewards can't be enumerated without the game's, so `rewards` is
+/// empty, and the planners read the marginalized `prob_transition` and
+/// `expected_reward_at` instead.
+pub struct Facing<'a, E: IMultiAgentEnvironment> {
+    env: &'a E,
+    agent: usize,
+    replies: HashMap<E::State, Vec<(E::Action1, f32)>>,
+}
+
+impl<'a, E> Facing<'a, E>
+where
+    E: IMultiAgentEnvironment<Action2 = <E as IMultiAgentEnvironment>::Action1>,
+{
+    /// The environment agent `agent`, 0 or 1, faces in `env` when the
+    /// other agent plays `opponent`.
+    ///
+    /// The opponent's policy is asked for its distribution in its own
+    /// seat, as though it faced an agent playing uniformly at random,
+    /// which only matters to policies that look at the dynamics.
+    ///
+    /// Panics if `agent` is neither 0 nor 1.
+    pub fn new(env: &'a E, agent: usize, opponent: &impl Policy<Facing<'a, E>>) -> Self {
+        assert!(agent < 2, "a two-agent game has agents 0 and 1");
+        let seat = Facing::uniform(env, 1 - agent);
+        let replies = env
+            .all_states()
+            .into_iter()
+            .filter(|s| !env.is_terminal(s))
+            .map(|s| {
+                let distribution = opponent.action_distribution(&seat, &s);
+                let replies = fixed_order(distribution.keys().cloned())
+                    .into_iter()
+                    .map(|o| {
+                        let p = distribution[&o];
+                        (o, p)
+                    })
+                    .filter(|(_, p)| *p > 0.0)
+                    .collect();
+                (s, replies)
+            })
+            .collect();
+        Facing {
+            env,
+            agent,
+            replies,
+        }
+    }
+
+    /// The environment `agent` faces when the other agent plays
+    /// uniformly at random.
+    fn uniform(env: &'a E, agent: usize) -> Self {
+        let mut seat = Facing {
+            env,
+            agent,
+            replies: HashMap::new(),
+        };
+        for s in env.all_states() {
+            if env.is_terminal(&s) {
+                continue;
+            }
+            let others = fixed_order(seat.available(&s, 1 - agent));
+            let each = 1.0 / others.len() as f32;
+            let replies = others.into_iter().map(|o| (o, each)).collect();
+            seat.replies.insert(s, replies);
+        }
+        seat
+    }
+
+    /// The agent whose environment this is.
+    pub fn agent(&self) -> usize {
+        self.agent
+    }
+
+    /// The actions available to agent `agent` in `from`.
+    fn available(&self, from: &E::State, agent: usize) -> HashSet<E::Action1> {
+        let (first, second) = self.env.actions_from(from);
+        if agent == 0 {
+            first
+        } else {
+            second
+        }
+    }
+
+    /// The joint action of this agent taking `a` and the opponent `o`.
+    fn joint(&self, a: &E::Action1, o: &E::Action1) -> (E::Action1, E::Action1) {
+        if self.agent == 0 {
+            (a.clone(), o.clone())
+        } else {
+            (o.clone(), a.clone())
+        }
+    }
+
+    /// The opponent's replies in `from`, and their probabilities.
+    fn replies(&self, from: &E::State) -> &[(E::Action1, f32)] {
+        self.replies.get(from).map_or(&[], Vec::as_slice)
+    }
+}
+
+impl<E> IEnvironment for Facing<'_, E>
+where
+    E: IMultiAgentEnvironment<Action2 = <E as IMultiAgentEnvironment>::Action1>,
+{
+    type State = E::State;
+    type Action = E::Action1;
+
+    fn prob(&self, from: &E::State, take: &E::Action1, to: &E::State, with: &f32) -> f32 {
+        self.replies(from)
+            .iter()
+            .map(|(o, p)| {
+                let joint = self.joint(take, o);
+                if self.env.rewards_for(self.agent, from, &joint, to) == *with {
+                    p * self.env.prob(from, &joint, to)
+                } else {
+                    0.0
+                }
+            })
+            .sum()
+    }
+
+    fn prob_transition(&self, from: &E::State, take: &E::Action1, to: &E::State) -> Option<f32> {
+        Some(
+            self.replies(from)
+                .iter()
+                .map(|(o, p)| p * self.env.prob(from, &self.joint(take, o), to))
+                .sum(),
+        )
+    }
+
+    fn expected_reward(&self, from: &E::State, take: &E::Action1) -> Option<f32> {
+        self.states_from(from, take)
+            .iter()
+            .map(|to| self.expected_reward_at(from, take, to))
+            .sum()
+    }
+
+    fn expected_reward_at(&self, from: &E::State, take: &E::Action1, to: &E::State) -> Option<f32> {
+        Some(
+            self.replies(from)
+                .iter()
+                .map(|(o, p)| {
+                    let joint = self.joint(take, o);
+                    let reward = self.env.rewards_for(self.agent, from, &joint, to);
+                    p * self.env.prob(from, &joint, to) * reward
+                })
+                .sum(),
+        )
+    }
+
+    fn actions_from(&self, from: &E::State) -> HashSet<E::Action1> {
+        self.available(from, self.agent)
+    }
+
+    fn states_from(&self, from: &E::State, take: &E::Action1) -> HashSet<E::State> {
+        self.replies(from)
+            .iter()
+            .flat_map(|(o, _)| self.env.states_from(from, &self.joint(take, o)))
+            .collect()
+    }
+
+    fn is_terminal(&self, state: &E::State) -> bool {
+        self.env.is_terminal(state)
+    }
+
+    fn gamma(&self) -> f32 {
+        self.env.gamma()
+    }
+
+    fn start_states(&self) -> HashSet<E::State> {
+        self.env.start_states()
+    }
+
+    fn all_states(&self) -> HashSet<E::State> {
+        self.env.all_states()
+    }
+}
+
+/// The best response of agent `agent`, 0 or 1, of a symmetric game to
+/// the other agent playing `opponent`: the optimal policy of the
+/// single-agent environment it then faces, as `Facing` marginalizes
+/// the opponent out of the game, solved by `value_iteration` until
+/// the values change by less than `theta` or for `max_iters` sweeps.
+///
+/// The policy found can be fixed as the opponent in turn, for the
+/// other agent's best response to it. `gamma` defaults to
+/// `env.gamma()`. Returns `None` if `value_iteration` fails.
+///
+/// Panics if `agent` is neither 0 nor 1.
+pub fn best_response<'a, E>(
+    env: &'a E,
+    opponent: &impl Policy<Facing<'a, E>>,
+    agent: usize,
+    gamma: Option<f32>,
+    theta: f32,
+    max_iters: usize,
+) -> Option<DeterministicPolicy<Facing<'a, E>>>
+where
+    E: IMultiAgentEnvironment<Action2 = <E as IMultiAgentEnvironment>::Action1>,
+{
+    let facing = Facing::new(env, agent, opponent);
+    let (_, policy) = value_iteration(&facing, gamma, theta, max_iters)?;
+    Some(policy)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::policy::{ConstantPolicy, TabularStochasticPolicy};
+    use crate::rng::make_rng;
+
+    /// A matrix game played over and over from a single state, agent 0
+    /// winning `payoff[a][o]` from agent 1 each time.
+    struct Repeated {
+        payoff: Vec<Vec<f32>>,
+    }
+
+    impl IMultiAgentEnvironment for Repeated {
+        type State = ();
+        type Action1 = usize;
+        type Action2 = usize;
+
+        fn prob(&self, _: &(), _: &(usize, usize), _: &()) -> f32 {
+            1.0
+        }
+
+        fn rewards_for(&self, agent: usize, _: &(), take: &(usize, usize), _: &()) -> f32 {
+            let won = self.payoff[take.0][take.1];
+            if agent == 0 {
+                won
+            } else {
+                -won
+            }
+        }
+
+        fn actions_from(&self, _: &()) -> (HashSet<usize>, HashSet<usize>) {
+            (
+                (0..self.payoff.len()).collect(),
+                (0..self.payoff[0].len()).collect(),
+            )
+        }
+
+        fn states_from(&self, _: &(), _: &(usize, usize)) -> HashSet<()> {
+            HashSet::from([()])
+        }
+
+        fn gamma(&self) -> f32 {
+            0.9
+        }
+
+        fn start_states(&self) -> HashSet<()> {
+            HashSet::from([()])
+        }
+    }
+
+    /// A coordination game: both agents win `payoff[a][o]` when agent
+    /// 0 plays `a` and agent 1 `o`, and are together, state `true`, on
+    /// the next step once they've played the same action.
+    struct Coordination {
+        payoff: Vec<Vec<f32>>,
+    }
+
+    impl IMultiAgentEnvironment for Coordination {
+        type State = bool;
+        type Action1 = usize;
+        type Action2 = usize;
+
+        fn prob(&self, from: &bool, take: &(usize, usize), to: &bool) -> f32 {
+            f32::from(self.states_from(from, take).contains(to))
+        }
+
+        fn rewards_for(&self, _: usize, _: &bool, take: &(usize, usize), _: &bool) -> f32 {
+            self.payoff[take.0][take.1]
+        }
+
+        fn actions_from(&self, _: &bool) -> (HashSet<usize>, HashSet<usize>) {
+            (
+                (0..self.payoff.len()).collect(),
+                (0..self.payoff[0].len()).collect(),
+            )
+        }
+
+        fn states_from(&self, _: &bool, take: &(usize, usize)) -> HashSet<bool> {
+            HashSet::from([take.0 == take.1])
+        }
+
+        fn gamma(&self) -> f32 {
+            0.9
+        }
+
+        fn start_states(&self) -> HashSet<bool> {
+            HashSet::from([false])
+        }
+    }
+
+    fn rock_paper_scissors() -> Vec<Vec<f32>> {
+        vec![
+            vec![0.0, -1.0, 1.0],
+            vec![1.0, 0.0, -1.0],
+            vec![-1.0, 1.0, 0.0],
+        ]
+    }
+
+    #[test]
+    fn matrix_games_are_solved_exactly() {
+        let (strategy, value) = solve_matrix_game(&rock_paper_scissors()).unwrap();
+        assert!(value.abs() < 1e-6);
+        for p in strategy {
+            assert!((p - 1.0 / 3.0).abs() < 1e-6);
+        }
+
+        let (strategy, value) = solve_matrix_game(&[vec![3.0, -1.0], vec![-2.0, 1.0]]).unwrap();
+        assert!((value - 1.0 / 7.0).abs() < 1e-6);
+        assert!((strategy[0] - 3.0 / 7.0).abs() < 1e-6);
+        assert!((strategy[1] - 4.0 / 7.0).abs() < 1e-6);
+
+        // A saddle point: the first row guarantees 1, and the column
+        // player can hold it there.
+        let (strategy, value) = solve_matrix_game(&[vec![1.0, 2.0], vec![0.0, 3.0]]).unwrap();
+        assert!((value - 1.0).abs() < 1e-6);
+        assert_eq!(strategy, [1.0, 0.0]);
+
+        assert_eq!(solve_matrix_game(&[]), None);
+        assert_eq!(solve_matrix_game(&[vec![1.0], vec![]]), None);
+    }
+
+    #[test]
+    fn minimax_q_converges_to_the_mixed_equilibrium() {
+        let rps = Repeated {
+            payoff: rock_paper_scissors(),
+        };
+        let learned = minimax_q(&rps, 2, 0.1, None, 0.2, &mut make_rng(0));
+        assert!(learned.value(&()).abs() < 0.05, "{}", learned.value(&()));
+        for p in learned.strategy(&()).unwrap().values() {
+            assert!((p - 1.0 / 3.0).abs() < 0.05, "{}", p);
+        }
+        assert!((learned.q(&(), &1, &0) - 1.0).abs() < 0.1);
+
+        // Worth 1/7 a round, discounted by 0.9 forever.
+        let skewed = Repeated {
+            payoff: vec![vec![3.0, -1.0], vec![-2.0, 1.0]],
+        };
+        let learned = minimax_q(&skewed, 2, 0.1, None, 0.2, &mut make_rng(0));
+        let expected = 1.0 / 7.0 / (1.0 - 0.9);
+        assert!(
+            (learned.value(&()) - expected).abs() < 0.1,
+            "{}",
+            learned.value(&())
+        );
+        let strategy = learned.strategy(&()).unwrap();
+        assert!((strategy[&0] - 3.0 / 7.0).abs() < 0.05, "{}", strategy[&0]);
+    }
+
+    #[test]
+    fn the_best_response_to_a_cooperator_is_to_cooperate() {
+        // Cooperating, action 0, pays the most, but only if both do.
+        let game = Coordination {
+            payoff: vec![vec![2.0, 0.0], vec![0.0, 1.0]],
+        };
+        for agent in [0, 1] {
+            let policy =
+                best_response(&game, &ConstantPolicy::new(0), agent, None, 1e-6, 1000).unwrap();
+            assert_eq!(policy.action(&false), Some(&0));
+            assert_eq!(policy.action(&true), Some(&0));
+        }
+    }
+
+    #[test]
+    fn a_mixed_opponent_is_marginalized_out() {
+        // The other agent plays 0 with probability 0.3, and the payoffs
+        // aren't symmetric, so mixing up whose action is whose shows.
+        let game = Coordination {
+            payoff: vec![vec![2.0, 3.0], vec![0.0, 1.0]],
+        };
+        let opponent = TabularStochasticPolicy::new(HashMap::from([
+            (false, HashMap::from([(0, 0.3), (1, 0.7)])),
+            (true, HashMap::from([(0, 0.3), (1, 0.7)])),
+        ]));
+        let facing = Facing::new(&game, 0, &opponent);
+        assert_eq!(facing.states_from(&false, &0), HashSet::from([true, false]));
+        assert_eq!(facing.prob_transition(&false, &0, &true), Some(0.3));
+        assert_eq!(facing.prob_transition(&false, &0, &false), Some(0.7));
+        assert_eq!(facing.prob(&false, &0, &false, &3.0), 0.7);
+        assert_eq!(facing.prob(&false, &0, &false, &2.0), 0.0);
+        let expected = facing.expected_reward(&false, &0).unwrap();
+        assert!((expected - (0.3 * 2.0 + 0.7 * 3.0)).abs() < 1e-6);
+        let policy = best_response(&game, &opponent, 0, None, 1e-6, 1000).unwrap();
+        assert_eq!(policy.action(&false), Some(&0));
+
+        // Agent 1 picks the column: 0.3 * 2 from its 0, against
+        // 0.3 * 3 + 0.7 * 1 from its 1.
+        let facing = Facing::new(&game, 1, &opponent);
+        let expected = facing.expected_reward(&false, &1).unwrap();
+        assert!((expected - (0.3 * 3.0 + 0.7 * 1.0)).abs() < 1e-6);
+        let policy = best_response(&game, &opponent, 1, None, 1e-6, 1000).unwrap();
+        assert_eq!(policy.action(&false), Some(&1));
+    }
+}
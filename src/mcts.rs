@@ -0,0 +1,300 @@
+//! Monte Carlo tree search.
+
+use std::collections::{HashMap, HashSet};
+
+use rand::Rng;
+
+use crate::env::IEnvironment;
+use crate::planning::{EnvModel, Model};
+use crate::policy::fixed_order;
+use crate::rng::StdRng;
+use crate::simulate::EPISODE_STEP_LIMIT;
+
+/// The weight of the exploration bonus in UCT selection.
+const EXPLORATION: f32 = std::f32::consts::SQRT_2;
+
+/// The visit count and total return of an action from a tree node.
+#[derive(Default)]
+struct Edge {
+    visits: usize,
+    total: f32,
+}
+
+/// Monte Carlo tree search with UCT: plans from `root` over
+/// `iterations` simulated episodes and returns the action taken most
+/// often from it, or `None` if `root` is terminal or has no available
+/// actions.
+///
+/// Each iteration descends the tree from `root`, trying every action
+/// of a node once before choosing by the UCT score
+/// `Q(s,a) + c * sqrt(ln N(s) / N(s,a))`. The first state reached
+/// that isn't yet in the tree is added to it, and its value estimated
+/// by a uniformly random rollout. The discounted return is then
+/// backed up along the path.
+///
+/// Statistics are kept per state, not per path, so states reached
+/// along different paths share them. A descent that returns to a
+/// state it has already passed through rolls out from there instead. Transitions are sampled afresh
+/// each time an edge is followed, so a stochastic action leads to
+/// whichever child its sampled outcome is, and each edge's value
+/// averages over its outcomes. Descent and rollout together stop
+/// after `EPISODE_STEP_LIMIT` steps. `gamma` defaults to `env.gamma()`.
+pub fn mcts<E: IEnvironment>(
+    env: &E,
+    root: &E::State,
+    iterations: usize,
+    gamma: Option<f32>,
+    rng: &mut StdRng,
+) -> Option<E::Action> {
+    mcts_with_model(env, &EnvModel::new(env), root, iterations, gamma, rng)
+}
+
+/// As `mcts`, but sampling every transition, in the tree and in
+/// rollouts, from `model` rather than the environment, which is only
+/// asked for available actions and terminal states. A pair the model
+/// has no outcome for ends the simulated episode there, with a return
+/// of 0 for taking it.
+pub fn mcts_with_model<E, M>(
+    env: &E,
+    model: &M,
+    root: &E::State,
+    iterations: usize,
+    gamma: Option<f32>,
+    rng: &mut StdRng,
+) -> Option<E::Action>
+where
+    E: IEnvironment,
+    M: Model<E::Action, E::State>,
+{
+    if env.is_terminal(root) {
+        return None;
+    }
+    let gamma = gamma.unwrap_or_else(|| env.gamma());
+    let mut tree: HashSet<E::State> = HashSet::new();
+    let mut visits: HashMap<E::State, usize> = HashMap::new();
+    let mut edges: HashMap<(E::State, E::Action), Edge> = HashMap::new();
+
+    for _ in 0..iterations {
+        let mut path = Vec::new();
+        let mut state = root.clone();
+        let mut leaf_value = 0.0;
+        let mut steps = 0;
+        let mut on_path = HashSet::new();
+        while steps < EPISODE_STEP_LIMIT && !env.is_terminal(&state) {
+            let actions = fixed_order(env.actions_from(&state));
+            if actions.is_empty() {
+                break;
+            }
+            // Statistics don't change during a descent, so revisiting
+            // a state would repeat the same choices round the cycle.
+            if tree.insert(state.clone()) || !on_path.insert(state.clone()) {
+                leaf_value = rollout(env, model, state, gamma, EPISODE_STEP_LIMIT - steps, rng);
+                break;
+            }
+
+            let n = visits.get(&state).copied().unwrap_or(0) as f32;
+            let action = actions
+                .into_iter()
+                .map(|a| {
+                    let score = match edges.get(&(state.clone(), a.clone())) {
+                        Some(e) if e.visits > 0 => {
+                            e.total / e.visits as f32
+                                + EXPLORATION * (n.ln() / e.visits as f32).sqrt()
+                        }
+                        _ => f32::INFINITY,
+                    };
+                    (a, score)
+                })
+                .fold(
+                    None,
+                    |best: Option<(E::Action, f32)>, (a, score)| match best {
+                        Some((_, b)) if b >= score => best,
+                        _ => Some((a, score)),
+                    },
+                )
+                .map(|(a, _)| a)
+                .unwrap();
+
+            let Some((next, reward)) = model.sample_next(&state, &action, rng) else {
+                path.push((state, action, 0.0));
+                break;
+            };
+            path.push((state, action, reward));
+            state = next;
+            steps += 1;
+        }
+
+        let mut g = leaf_value;
+        for (s, a, r) in path.into_iter().rev() {
+            g = r + gamma * g;
+            *visits.entry(s.clone()).or_insert(0) += 1;
+            let edge = edges.entry((s, a)).or_default();
+            edge.visits += 1;
+            edge.total += g;
+        }
+    }
+
+    fixed_order(env.actions_from(root))
+        .into_iter()
+        .map(|a| {
+            let n = edges
+                .get(&(root.clone(), a.clone()))
+                .map_or(0, |e| e.visits);
+            (a, n)
+        })
+        .fold(
+            None,
+            |best: Option<(E::Action, usize)>, (a, n)| match best {
+                Some((_, b)) if b >= n => best,
+                _ => Some((a, n)),
+            },
+        )
+        .map(|(a, _)| a)
+}
+
+/// The discounted return of following uniformly random actions from
+/// `state` in `model` for at most `max_steps` steps.
+fn rollout<E: IEnvironment>(
+    env: &E,
+    model: &impl Model<E::Action, E::State>,
+    mut state: E::State,
+    gamma: f32,
+    max_steps: usize,
+    rng: &mut StdRng,
+) -> f32 {
+    let mut g = 0.0;
+    let mut discount = 1.0;
+    for _ in 0..max_steps {
+        if env.is_terminal(&state) {
+            break;
+        }
+        let mut actions = fixed_order(env.actions_from(&state));
+        if actions.is_empty() {
+            break;
+        }
+        let action = actions.swap_remove(rng.gen_range(0..actions.len()));
+        let Some((next, reward)) = model.sample_next(&state, &action, rng) else {
+            break;
+        };
+        g += discount * reward;
+        discount *= gamma;
+        state = next;
+    }
+    g
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::planning::TabularModel;
+    use crate::rng::make_rng;
+    use crate::testing::Chain;
+
+    /// Two-step choices ending in the terminal states 8 and 9. From 0,
+    /// action 0 leads to 1, from which action 0 earns 1; action 1 from
+    /// 0 earns 0.5 at once. From 5, action 0 earns 2 or nothing with
+    /// equal probability, and action 1 earns 0.8.
+    struct Tree;
+
+    impl Tree {
+        fn outcomes(from: u8, take: u8) -> Vec<(u8, f32, f32)> {
+            match (from, take) {
+                (0, 0) => vec![(1, 0.0, 1.0)],
+                (0, 1) => vec![(9, 0.5, 1.0)],
+                (0, _) => vec![(9, 0.0, 1.0)],
+                (1, 0) => vec![(9, 1.0, 1.0)],
+                (1, 1) => vec![(9, 0.0, 1.0)],
+                (1, _) => vec![(9, 0.2, 1.0)],
+                (5, 0) => vec![(9, 2.0, 0.5), (8, 0.0, 0.5)],
+                (5, _) => vec![(9, 0.8, 1.0)],
+                _ => vec![],
+            }
+        }
+    }
+
+    impl IEnvironment for Tree {
+        type State = u8;
+        type Action = u8;
+
+        fn prob(&self, from: &u8, take: &u8, to: &u8, with: &f32) -> f32 {
+            Self::outcomes(*from, *take)
+                .into_iter()
+                .filter(|(s, r, _)| s == to && r == with)
+                .map(|(_, _, p)| p)
+                .sum()
+        }
+
+        fn actions_from(&self, state: &u8) -> HashSet<u8> {
+            if *state == 5 {
+                HashSet::from([0, 1])
+            } else {
+                HashSet::from([0, 1, 2])
+            }
+        }
+
+        fn states_from(&self, from: &u8, take: &u8) -> HashSet<u8> {
+            Self::outcomes(*from, *take)
+                .into_iter()
+                .map(|(s, _, _)| s)
+                .collect()
+        }
+
+        fn is_terminal(&self, state: &u8) -> bool {
+            *state >= 8
+        }
+
+        fn start_states(&self) -> HashSet<u8> {
+            HashSet::from([0])
+        }
+
+        fn rewards(&self) -> Vec<f32> {
+            vec![0.0, 0.2, 0.5, 0.8, 1.0, 2.0]
+        }
+    }
+
+    #[test]
+    fn looks_past_an_immediate_reward() {
+        let mut rng = make_rng(0);
+        assert_eq!(mcts(&Tree, &0, 500, Some(1.0), &mut rng), Some(0));
+        assert_eq!(mcts(&Tree, &9, 10, Some(1.0), &mut rng), None);
+    }
+
+    #[test]
+    fn averages_over_stochastic_children() {
+        // Action 0 from 5 is worth 1 in expectation, against 0.8.
+        let mut rng = make_rng(0);
+        assert_eq!(mcts(&Tree, &5, 2000, Some(1.0), &mut rng), Some(0));
+    }
+
+    #[test]
+    fn leaves_cycles_by_rolling_out() {
+        // Moving left from 0 loops back to 0, and the other states
+        // can be revisited by moving back and forth.
+        let mut rng = make_rng(0);
+        for s in 0..Chain::GOAL {
+            assert_eq!(mcts(&Chain, &s, 200, Some(0.9), &mut rng), Some(1));
+        }
+    }
+
+    #[test]
+    fn plans_over_a_learned_model() {
+        // A model that has only seen moves to the right: moving left
+        // has no outcome to sample, so simulations end there.
+        let mut model = TabularModel::new();
+        for s in 0..Chain::GOAL {
+            let reward = if s + 1 == Chain::GOAL { 1.0 } else { 0.0 };
+            model.observe(s, 1, s + 1, reward);
+        }
+        let mut rng = make_rng(0);
+        for s in 0..Chain::GOAL {
+            assert_eq!(
+                mcts_with_model(&Chain, &model, &s, 100, Some(0.9), &mut rng),
+                Some(1)
+            );
+        }
+        assert_eq!(
+            mcts_with_model(&Chain, &model, &Chain::GOAL, 100, Some(0.9), &mut rng),
+            None
+        );
+    }
+}
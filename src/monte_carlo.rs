@@ -0,0 +1,487 @@
+use std::collections::{HashMap, HashSet};
+
+use rand::Rng;
+
+use crate::dp::{QTable, ValueFunction};
+use crate::env::IEnvironment;
+use crate::observer::{NoObserver, TrainingObserver};
+use crate::policy::{
+    epsilon_soft_improve, fixed_order, DeterministicPolicy, Policy, TabularStochasticPolicy,
+};
+use crate::rng::StdRng;
+use crate::simulate::{self, rollout, Trajectory, EPISODE_STEP_LIMIT};
+use crate::td::epsilon_greedy;
+
+/// Monte Carlo prediction: estimates the state-value function of
+/// `policy` by averaging the returns observed after the first visit
+/// to each state in `episodes` simulated episodes.
+///
+/// Episodes start from a state drawn uniformly from
+/// `env.start_states()`. An episode that hits `EPISODE_STEP_LIMIT`
+/// without terminating still contributes its partial returns.
+/// `gamma` defaults to `env.gamma()`.
+pub fn mc_predict<E, P>(
+    env: &E,
+    policy: &P,
+    episodes: usize,
+    gamma: Option<f32>,
+    rng: &mut StdRng,
+) -> ValueFunction<E>
+where
+    E: IEnvironment,
+    P: Policy<E>,
+{
+    predict(
+        env,
+        policy,
+        episodes,
+        gamma.unwrap_or_else(|| env.gamma()),
+        true,
+        rng,
+    )
+}
+
+/// As `mc_predict`, but averaging the returns after every visit to a
+/// state rather than only the first.
+pub fn mc_predict_every_visit<E, P>(
+    env: &E,
+    policy: &P,
+    episodes: usize,
+    gamma: Option<f32>,
+    rng: &mut StdRng,
+) -> ValueFunction<E>
+where
+    E: IEnvironment,
+    P: Policy<E>,
+{
+    predict(
+        env,
+        policy,
+        episodes,
+        gamma.unwrap_or_else(|| env.gamma()),
+        false,
+        rng,
+    )
+}
+
+fn predict<E, P>(
+    env: &E,
+    policy: &P,
+    episodes: usize,
+    gamma: f32,
+    first_visit: bool,
+    rng: &mut StdRng,
+) -> ValueFunction<E>
+where
+    E: IEnvironment,
+    P: Policy<E>,
+{
+    let mut totals: HashMap<E::State, (f32, usize)> = HashMap::new();
+    for _ in 0..episodes {
+        let Some(start) = simulate::sample_start(env, rng) else {
+            break;
+        };
+        let trajectory = rollout(env, policy, start, EPISODE_STEP_LIMIT, rng);
+
+        // Only the earliest visit to each state counts in first-visit
+        // mode, so note where each of those happens.
+        let mut seen = HashSet::new();
+        let counted: Vec<bool> = trajectory
+            .iter()
+            .map(|(s, _, _)| seen.insert(s) || !first_visit)
+            .collect();
+
+        let mut g = 0.0;
+        for ((s, _, r), counted) in trajectory.iter().zip(counted).rev() {
+            g = r + gamma * g;
+            if counted {
+                let (total, n) = totals.entry(s.clone()).or_insert((0.0, 0));
+                *total += g;
+                *n += 1;
+            }
+        }
+    }
+    totals
+        .into_iter()
+        .map(|(s, (total, n))| (s, total / n as f32))
+        .collect()
+}
+
+/// Monte Carlo control with exploring starts.
+///
+/// Each episode starts from a state-action pair drawn uniformly from
+/// the non-terminal states of `env.all_states()` and their available
+/// actions. The first action is always the drawn one, after which the
+/// episode follows the greedy policy of the current Q-table. Q is
+/// updated by averaging first-visit returns, so the policy improves
+/// after every episode. Returns the final greedy policy. `gamma`
+/// defaults to `env.gamma()`.
+pub fn mc_control_es<E: IEnvironment>(
+    env: &E,
+    episodes: usize,
+    gamma: Option<f32>,
+    rng: &mut StdRng,
+) -> DeterministicPolicy<E> {
+    mc_control_es_observed(env, episodes, gamma, rng, &mut NoObserver)
+}
+
+/// As `mc_control_es`, but reporting each episode's total reward to
+/// `observer` as it ends, and stopping training early if it returns
+/// `ControlFlow::Break`.
+pub fn mc_control_es_observed<E, O>(
+    env: &E,
+    episodes: usize,
+    gamma: Option<f32>,
+    rng: &mut StdRng,
+    observer: &mut O,
+) -> DeterministicPolicy<E>
+where
+    E: IEnvironment,
+    O: TrainingObserver<E>,
+{
+    let gamma = gamma.unwrap_or_else(|| env.gamma());
+    let states = env.all_states();
+    let pairs: Vec<(E::State, E::Action)> = fixed_order(
+        states
+            .iter()
+            .filter(|s| !env.is_terminal(s))
+            .flat_map(|s| env.actions_from(s).into_iter().map(move |a| (s.clone(), a))),
+    );
+
+    let mut q = QTable::<E>::new();
+    let mut counts: HashMap<(E::State, E::Action), usize> = HashMap::new();
+    if !pairs.is_empty() {
+        for episode in 0..episodes {
+            let (mut state, mut action) = pairs[rng.gen_range(0..pairs.len())].clone();
+            let mut trajectory = Vec::new();
+            for _ in 0..EPISODE_STEP_LIMIT {
+                let Some((next, reward)) = env.sample_transition(&state, &action, rng) else {
+                    break;
+                };
+                trajectory.push((state, action, reward));
+                if env.is_terminal(&next) {
+                    break;
+                }
+                let Some(a) = q.greedy_action(env, &next) else {
+                    break;
+                };
+                state = next;
+                action = a;
+            }
+
+            average_first_visits(&trajectory, gamma, &mut q, &mut counts);
+            let total = trajectory.iter().map(|(_, _, r)| r).sum();
+            if observer.on_episode_end(episode, total, &q).is_break() {
+                break;
+            }
+        }
+    }
+
+    DeterministicPolicy::new(
+        states
+            .into_iter()
+            .filter(|s| !env.is_terminal(s))
+            .filter_map(|s| q.greedy_action(env, &s).map(|a| (s, a)))
+            .collect(),
+    )
+}
+
+/// On-policy first-visit Monte Carlo control with epsilon-soft
+/// policies, for when episodes can't be started from arbitrary
+/// state-action pairs as `mc_control_es` needs.
+///
+/// Episodes start from states drawn uniformly from
+/// `env.start_states()` and follow the `epsilon_soft_improve`ment of
+/// the current Q-table, which is updated by averaging first-visit
+/// returns after every episode, so the policy improves after every
+/// episode while never ceasing to explore. Returns the final
+/// epsilon-soft policy, the best of those that keep exploring.
+/// `gamma` defaults to `env.gamma()`.
+pub fn mc_control_on_policy<E: IEnvironment>(
+    env: &E,
+    episodes: usize,
+    epsilon: f32,
+    gamma: Option<f32>,
+    rng: &mut StdRng,
+) -> TabularStochasticPolicy<E> {
+    let gamma = gamma.unwrap_or_else(|| env.gamma());
+    let mut q = QTable::<E>::new();
+    let mut counts: HashMap<(E::State, E::Action), usize> = HashMap::new();
+    for _ in 0..episodes {
+        let Some(mut state) = simulate::sample_start(env, rng) else {
+            break;
+        };
+        let mut trajectory = Vec::new();
+        for _ in 0..EPISODE_STEP_LIMIT {
+            if env.is_terminal(&state) {
+                break;
+            }
+            // Epsilon-greedy on `q` is exactly its epsilon-soft
+            // improvement, without building it for every state.
+            let Some(action) = epsilon_greedy(env, &q, &state, epsilon, rng) else {
+                break;
+            };
+            let Some((next, reward)) = env.sample_transition(&state, &action, rng) else {
+                break;
+            };
+            trajectory.push((state, action, reward));
+            state = next;
+        }
+        average_first_visits(&trajectory, gamma, &mut q, &mut counts);
+    }
+    epsilon_soft_improve(env, &q, epsilon)
+}
+
+/// Moves `Q(s,a)` for the first visit to each pair in `trajectory` to
+/// the running average of its returns, counted in `counts`.
+fn average_first_visits<E: IEnvironment>(
+    trajectory: &Trajectory<E>,
+    gamma: f32,
+    q: &mut QTable<E>,
+    counts: &mut HashMap<(E::State, E::Action), usize>,
+) {
+    let mut seen = HashSet::new();
+    let first: Vec<bool> = trajectory
+        .iter()
+        .map(|(s, a, _)| seen.insert((s, a)))
+        .collect();
+    let mut g = 0.0;
+    for ((s, a, r), first) in trajectory.iter().zip(first).rev() {
+        g = r + gamma * g;
+        if first {
+            let key = (s.clone(), a.clone());
+            let n = counts.entry(key).or_insert(0);
+            *n += 1;
+            let value = q.get_mut(s.clone(), a.clone());
+            *value += (g - *value) / *n as f32;
+        }
+    }
+}
+
+/// How `mc_off_policy` weights the returns it averages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportanceSampling {
+    /// Averages the ratio-scaled returns over all visits: unbiased,
+    /// but with potentially unbounded variance.
+    Ordinary,
+    /// Divides by the sum of the ratios instead: biased, but with
+    /// much lower variance.
+    Weighted,
+}
+
+/// Off-policy Monte Carlo prediction: estimates the action-value
+/// function of `target` from episodes generated by following
+/// `behavior`.
+///
+/// Every visit to a state-action pair contributes its return, scaled
+/// by the importance-sampling ratio of the actions taken after it,
+/// `prod pi(a_k|s_k) / b(a_k|s_k)`. Once an action the target would
+/// never take appears, every earlier pair in the episode has a ratio
+/// of zero, so it adds nothing to the weighted estimates and only
+/// zeros to the ordinary ones. `behavior`
+/// must cover `target`: it must give a positive probability to every
+/// action the target might take. When the two are the same policy
+/// this is on-policy every-visit Monte Carlo. `gamma` defaults to
+/// `env.gamma()`.
+///
+/// With `clip`, each step's ratio is capped at that value before it
+/// joins the product, as in V-trace, so that a few long runs of
+/// actions the behavior rarely takes can't dominate the estimates:
+/// their variance falls, at the cost of a bias towards the behavior's
+/// values. `None` leaves the ratios uncapped.
+#[allow(clippy::too_many_arguments)]
+pub fn mc_off_policy<E, B, T>(
+    env: &E,
+    behavior: &B,
+    target: &T,
+    sampling: ImportanceSampling,
+    clip: Option<f32>,
+    episodes: usize,
+    gamma: Option<f32>,
+    rng: &mut StdRng,
+) -> QTable<E>
+where
+    E: IEnvironment,
+    B: Policy<E>,
+    T: Policy<E>,
+{
+    let gamma = gamma.unwrap_or_else(|| env.gamma());
+    let mut q = QTable::<E>::new();
+    // The sum of the weights for weighted sampling, or the number of
+    // returns for ordinary sampling.
+    let mut totals: HashMap<(E::State, E::Action), f32> = HashMap::new();
+    for _ in 0..episodes {
+        let Some(start) = simulate::sample_start(env, rng) else {
+            break;
+        };
+        let trajectory = rollout(env, behavior, start, EPISODE_STEP_LIMIT, rng);
+
+        let mut g = 0.0;
+        let mut w = 1.0;
+        for (s, a, r) in trajectory.into_iter().rev() {
+            if w == 0.0 && sampling == ImportanceSampling::Weighted {
+                break;
+            }
+            g = r + gamma * g;
+            let b = behavior.action_prob(env, &s, &a);
+            let mut ratio = if b > 0.0 {
+                target.action_prob(env, &s, &a) / b
+            } else {
+                0.0
+            };
+            if let Some(clip) = clip {
+                ratio = ratio.min(clip);
+            }
+
+            let total = totals.entry((s.clone(), a.clone())).or_insert(0.0);
+            let value = q.get_mut(s, a);
+            match sampling {
+                ImportanceSampling::Ordinary => {
+                    *total += 1.0;
+                    *value += (w * g - *value) / *total;
+                }
+                ImportanceSampling::Weighted => {
+                    *total += w;
+                    *value += w / *total * (g - *value);
+                }
+            }
+            w *= ratio;
+        }
+    }
+    q
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dp;
+    use crate::policy::EpsilonGreedy;
+    use crate::rng::make_rng;
+    use crate::testing::{values_approx_eq, Chain, Missing};
+
+    #[test]
+    fn prediction_matches_policy_evaluation() {
+        let policy = Chain::wandering();
+        let v = dp::evaluate_policy(&Chain, &policy, Some(0.9), 1e-6, 1000).unwrap();
+        let mut rng = make_rng(1);
+        let first = mc_predict(&Chain, &policy, 5000, Some(0.9), &mut rng);
+        let every = mc_predict_every_visit(&Chain, &policy, 5000, Some(0.9), &mut rng);
+        assert!(values_approx_eq(&first, &v, 0.03, Missing::Zero));
+        assert!(values_approx_eq(&every, &v, 0.03, Missing::Zero));
+    }
+
+    #[cfg(feature = "examples")]
+    #[test]
+    fn exploring_starts_recover_the_optimal_grid_policy() {
+        use crate::env::examples::GridWorld;
+
+        let grid = GridWorld::new(3, 3, [(1, 1)], (2, 2), 0.04, 0.0);
+        let policy = mc_control_es(&grid, 2000, Some(0.9), &mut make_rng(0));
+        let (v, _) = dp::value_iteration(&grid, Some(0.9), 1e-6, 1000).unwrap();
+        let q = dp::q_from_v(&grid, &v, Some(0.9)).unwrap();
+        // Some cells have two equally short routes, so check that each
+        // action is optimal rather than that it is a particular one.
+        for s in grid.all_states() {
+            if grid.is_terminal(&s) {
+                continue;
+            }
+            let a = policy.action(&s).unwrap();
+            assert!((q.get(&s, a) - v[&s]).abs() < 1e-4, "{s:?}");
+        }
+    }
+
+    #[test]
+    fn on_policy_control_learns_a_soft_rightward_policy() {
+        let policy = mc_control_on_policy(&Chain, 500, 0.2, Some(0.9), &mut make_rng(0));
+        for s in 0..Chain::GOAL {
+            assert_eq!(policy.action_prob(&Chain, &s, &1), 1.0 - 0.2 + 0.1, "{s}");
+            assert_eq!(policy.action_prob(&Chain, &s, &-1), 0.1, "{s}");
+        }
+    }
+
+    #[test]
+    fn off_policy_with_the_same_policy_is_on_policy() {
+        let policy = Chain::wandering();
+        let v = dp::evaluate_policy(&Chain, &policy, Some(0.9), 1e-6, 1000).unwrap();
+        let q = dp::q_from_v(&Chain, &v, Some(0.9)).unwrap();
+        let run = |sampling| {
+            mc_off_policy(
+                &Chain,
+                &policy,
+                &policy,
+                sampling,
+                None,
+                3000,
+                Some(0.9),
+                &mut make_rng(4),
+            )
+        };
+        let ordinary = run(ImportanceSampling::Ordinary);
+        let weighted = run(ImportanceSampling::Weighted);
+        for s in 0..Chain::GOAL {
+            for a in [-1, 1] {
+                assert!((ordinary.get(&s, &a) - weighted.get(&s, &a)).abs() < 1e-5);
+                assert!(
+                    (weighted.get(&s, &a) - q.get(&s, &a)).abs() < 0.03,
+                    "{s} {a}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn off_policy_estimates_the_target_policy() {
+        // Always moving right, the goal is GOAL - 1 - s steps further
+        // after moving right from s.
+        let right = |s: i32| 0.9f32.powi(Chain::GOAL - 1 - s);
+        let run = |sampling| {
+            mc_off_policy(
+                &Chain,
+                &Chain::wandering(),
+                &Chain::rightward(),
+                sampling,
+                None,
+                3000,
+                Some(0.9),
+                &mut make_rng(4),
+            )
+        };
+        let weighted = run(ImportanceSampling::Weighted);
+        let ordinary = run(ImportanceSampling::Ordinary);
+        for s in 0..Chain::GOAL {
+            let left = 0.9 * right((s - 1).max(0));
+            assert!((weighted.get(&s, &1) - right(s)).abs() < 1e-4, "{s}");
+            assert!((weighted.get(&s, &-1) - left).abs() < 1e-4, "{s}");
+            assert!((ordinary.get(&s, &1) - right(s)).abs() < 0.1, "{s}");
+        }
+    }
+
+    #[test]
+    fn clipping_the_ratios_lowers_the_variance() {
+        // Moving right only 55% of the time, a ratio of almost 2 a step.
+        let behavior = EpsilonGreedy::new(Chain::rightward(), 0.9);
+        let run = |clip, seed| {
+            mc_off_policy(
+                &Chain,
+                &behavior,
+                &Chain::rightward(),
+                ImportanceSampling::Ordinary,
+                clip,
+                100,
+                Some(0.9),
+                &mut make_rng(seed),
+            )
+            .get(&0, &1)
+        };
+        let variance = |clip| {
+            let estimates: Vec<f32> = (0..20).map(|seed| run(clip, seed)).collect();
+            let mean = estimates.iter().sum::<f32>() / 20.0;
+            estimates.iter().map(|x| (x - mean).powi(2)).sum::<f32>() / 20.0
+        };
+        // A cap no ratio reaches is no cap at all.
+        assert_eq!(run(None, 0), run(Some(f32::INFINITY), 0));
+        let uncapped = variance(None);
+        let capped = variance(Some(1.0));
+        assert!(capped < uncapped / 4.0, "{capped} vs {uncapped}");
+    }
+}
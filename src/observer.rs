@@ -0,0 +1,279 @@
+//! Hooks for watching training as it runs, and stopping it early.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::ops::ControlFlow;
+use std::path::Path;
+
+use crate::dp::QTable;
+use crate::env::IEnvironment;
+
+/// Called by the control algorithms after every episode of training.
+pub trait TrainingObserver<E: IEnvironment> {
+    /// Called once `episode`, counting from 0, has ended, with the
+    /// undiscounted sum of its rewards and the Q-table after its
+    /// updates. Returning `ControlFlow::Break` stops training before
+    /// the next episode.
+    fn on_episode_end(
+        &mut self,
+        episode: usize,
+        total_reward: f32,
+        q: &QTable<E>,
+    ) -> ControlFlow<()>;
+}
+
+/// An observer that does nothing and never stops training.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoObserver;
+
+impl<E: IEnvironment> TrainingObserver<E> for NoObserver {
+    fn on_episode_end(&mut self, _: usize, _: f32, _: &QTable<E>) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+}
+
+/// An observer that writes a CSV row `episode,total_reward` for every
+/// episode, after an initial header row.
+///
+/// A failed write stops training, and the error is returned by
+/// `finish`.
+pub struct CsvLogger<W: Write> {
+    writer: W,
+    header: bool,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> CsvLogger<W> {
+    pub fn new(writer: W) -> Self {
+        CsvLogger {
+            writer,
+            header: false,
+            error: None,
+        }
+    }
+
+    /// Flushes and returns the writer, or the first error writing to
+    /// it.
+    pub fn finish(mut self) -> io::Result<W> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn write_row(&mut self, episode: usize, total_reward: f32) -> io::Result<()> {
+        if !self.header {
+            writeln!(self.writer, "episode,total_reward")?;
+            self.header = true;
+        }
+        writeln!(self.writer, "{episode},{total_reward}")
+    }
+}
+
+impl<E: IEnvironment, W: Write> TrainingObserver<E> for CsvLogger<W> {
+    fn on_episode_end(
+        &mut self,
+        episode: usize,
+        total_reward: f32,
+        _: &QTable<E>,
+    ) -> ControlFlow<()> {
+        if self.error.is_some() {
+            return ControlFlow::Break(());
+        }
+        match self.write_row(episode, total_reward) {
+            Ok(()) => ControlFlow::Continue(()),
+            Err(e) => {
+                self.error = Some(e);
+                ControlFlow::Break(())
+            }
+        }
+    }
+}
+
+/// An observer that records every episode's total reward, for
+/// plotting smoothed learning curves.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LearningCurve {
+    returns: Vec<f32>,
+}
+
+impl LearningCurve {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Each episode's total reward, in order.
+    pub fn returns(&self) -> &[f32] {
+        &self.returns
+    }
+
+    /// The mean of each episode's total reward and those of the
+    /// `window - 1` episodes before it, or of all those before it for
+    /// the first `window - 1` episodes.
+    ///
+    /// Panics if `window` is 0.
+    pub fn moving_average(&self, window: usize) -> Vec<f32> {
+        assert!(
+            window > 0,
+            "the moving average needs a window of at least 1"
+        );
+        let mut total: f64 = 0.0;
+        let mut averages = Vec::with_capacity(self.returns.len());
+        for (i, r) in self.returns.iter().enumerate() {
+            total += f64::from(*r);
+            if i >= window {
+                total -= f64::from(self.returns[i - window]);
+            }
+            averages.push((total / (i + 1).min(window) as f64) as f32);
+        }
+        averages
+    }
+
+    /// Writes the curve to `path` as CSV, with an
+    /// `episode,total_reward,moving_average` header and one row per
+    /// episode, averaged over `window` as by `moving_average`.
+    ///
+    /// Panics if `window` is 0.
+    pub fn to_csv(&self, path: impl AsRef<Path>, window: usize) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "episode,total_reward,moving_average")?;
+        let averages = self.moving_average(window);
+        for (episode, (r, average)) in self.returns.iter().zip(averages).enumerate() {
+            writeln!(writer, "{episode},{r},{average}")?;
+        }
+        writer.flush()
+    }
+}
+
+impl<E: IEnvironment> TrainingObserver<E> for LearningCurve {
+    fn on_episode_end(&mut self, _: usize, total_reward: f32, _: &QTable<E>) -> ControlFlow<()> {
+        self.returns.push(total_reward);
+        ControlFlow::Continue(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::monte_carlo::mc_control_es_observed;
+    use crate::planning::{dyna_q_observed, prioritized_sweeping_observed};
+    use crate::replay::q_learning_replay_observed;
+    use crate::rng::make_rng;
+    use crate::td::{double_q_learning_observed, q_learning_observed, sarsa_observed};
+    use crate::testing::Chain;
+
+    /// Records the episodes it's told about, and stops training once
+    /// `stop_at` has ended.
+    struct StopAt {
+        stop_at: usize,
+        seen: Vec<usize>,
+    }
+
+    impl StopAt {
+        fn new(stop_at: usize) -> Self {
+            StopAt {
+                stop_at,
+                seen: Vec::new(),
+            }
+        }
+    }
+
+    impl<E: IEnvironment> TrainingObserver<E> for StopAt {
+        fn on_episode_end(&mut self, episode: usize, _: f32, _: &QTable<E>) -> ControlFlow<()> {
+            self.seen.push(episode);
+            if episode == self.stop_at {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
+        }
+    }
+
+    #[test]
+    fn break_stops_training_at_that_episode() {
+        let expected: Vec<usize> = (0..=3).collect();
+        let mut rng = make_rng(0);
+        let gamma = Some(0.9);
+
+        let mut stop = StopAt::new(3);
+        q_learning_observed(&Chain, 100, 0.5, gamma, 0.1, &mut rng, &mut stop);
+        assert_eq!(stop.seen, expected);
+
+        let mut stop = StopAt::new(3);
+        sarsa_observed(&Chain, 100, 0.5, gamma, 0.1, &mut rng, &mut stop);
+        assert_eq!(stop.seen, expected);
+
+        let mut stop = StopAt::new(3);
+        double_q_learning_observed(&Chain, 100, 0.5, gamma, 0.1, &mut rng, &mut stop);
+        assert_eq!(stop.seen, expected);
+
+        let mut stop = StopAt::new(3);
+        dyna_q_observed(&Chain, 100, 5, 0.5, gamma, 0.1, &mut rng, &mut stop);
+        assert_eq!(stop.seen, expected);
+
+        let mut stop = StopAt::new(3);
+        prioritized_sweeping_observed(&Chain, 100, 5, 1e-4, 0.5, gamma, 0.1, &mut rng, &mut stop);
+        assert_eq!(stop.seen, expected);
+
+        let mut stop = StopAt::new(3);
+        q_learning_replay_observed(&Chain, 100, 50, 4, 0.5, gamma, 0.1, &mut rng, &mut stop);
+        assert_eq!(stop.seen, expected);
+
+        let mut stop = StopAt::new(3);
+        mc_control_es_observed(&Chain, 100, gamma, &mut rng, &mut stop);
+        assert_eq!(stop.seen, expected);
+    }
+
+    #[test]
+    fn continuing_observers_see_every_episode() {
+        let mut stop = StopAt::new(usize::MAX);
+        q_learning_observed(&Chain, 20, 0.5, None, 0.1, &mut make_rng(0), &mut stop);
+        assert_eq!(stop.seen, (0..20).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn csv_logger_writes_a_row_per_episode() {
+        let mut logger = CsvLogger::new(Vec::new());
+        // Acting at random, every episode reaches the goal for its reward of 1.
+        q_learning_observed(&Chain, 3, 0.5, None, 1.0, &mut make_rng(0), &mut logger);
+        let csv = String::from_utf8(logger.finish().unwrap()).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines, ["episode,total_reward", "0,1", "1,1", "2,1"]);
+    }
+
+    #[test]
+    fn moving_averages_use_the_prefix_until_the_window_fills() {
+        let curve = LearningCurve {
+            returns: vec![1.0, 3.0, 2.0, 6.0, -1.0],
+        };
+        assert_eq!(curve.moving_average(1), curve.returns());
+        assert_eq!(
+            curve.moving_average(3),
+            [1.0, 2.0, 2.0, 11.0 / 3.0, 7.0 / 3.0]
+        );
+        assert_eq!(curve.moving_average(10), [1.0, 2.0, 2.0, 3.0, 2.2]);
+    }
+
+    #[test]
+    fn learning_curves_fill_in_during_training() {
+        let mut curve = LearningCurve::new();
+        q_learning_observed(&Chain, 4, 0.5, None, 1.0, &mut make_rng(0), &mut curve);
+        assert_eq!(curve.returns(), [1.0; 4]);
+
+        let path = std::env::temp_dir().join(format!("reinforce-{}-curve.csv", std::process::id()));
+        curve.to_csv(&path, 2).unwrap();
+        let csv = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            csv.lines().collect::<Vec<_>>(),
+            [
+                "episode,total_reward,moving_average",
+                "0,1,1",
+                "1,1,1",
+                "2,1,1",
+                "3,1,1"
+            ]
+        );
+    }
+}
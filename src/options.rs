@@ -0,0 +1,348 @@
+//! Options: temporally extended actions, for hierarchical
+//! reinforcement learning over a semi-Markov decision process.
+
+use std::collections::HashMap;
+
+use rand::Rng;
+
+use crate::env::IEnvironment;
+use crate::rng::StdRng;
+use crate::schedule::Schedule;
+use crate::simulate::{self, EPISODE_STEP_LIMIT};
+use crate::td::LearningRate;
+
+/// A temporally extended action: a policy that may be started in some
+/// states and runs until it terminates.
+pub trait OptionPolicy<E: IEnvironment> {
+    /// Whether the option may be started in `state`.
+    fn initiation(&self, env: &E, state: &E::State) -> bool;
+
+    /// The primitive action the option takes in `state`.
+    fn action(&self, env: &E, state: &E::State, rng: &mut StdRng) -> E::Action;
+
+    /// The probability that the option ends on arriving in `state`.
+    fn terminates(&self, env: &E, state: &E::State) -> f32;
+}
+
+/// One option executed by `rollout_with_options`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OptionStep<S> {
+    /// The state the option started in.
+    pub state: S,
+    /// The index of the option taken.
+    pub option: usize,
+    /// The rewards received while the option ran, discounted from its
+    /// first step.
+    pub reward: f32,
+    /// The number of primitive steps the option ran for.
+    pub duration: usize,
+}
+
+/// Runs an episode from `start` in which `choose` picks an option to
+/// run from each state it is asked about, returning each option taken
+/// in order with its discounted reward and duration.
+///
+/// `choose` is passed the indices into `options` of those whose
+/// `initiation` holds in the current state, and must return one of
+/// them. An option runs until it terminates, drawn with its
+/// `terminates` probability after each step, or until the episode
+/// ends. The episode ends on reaching a terminal state, a state with
+/// no available actions or no reachable next state, or a state where
+/// no option can be started, and in any case after `max_steps`
+/// primitive steps in total, which cuts off an option that would
+/// never terminate. `gamma` defaults to `env.gamma()`.
+pub fn rollout_with_options<E, F>(
+    env: &E,
+    options: &[Box<dyn OptionPolicy<E>>],
+    choose: F,
+    start: E::State,
+    max_steps: usize,
+    gamma: Option<f32>,
+    rng: &mut StdRng,
+) -> Vec<OptionStep<E::State>>
+where
+    E: IEnvironment,
+    F: FnMut(&E::State, &[usize], &mut StdRng) -> usize,
+{
+    let gamma = gamma.unwrap_or_else(|| env.gamma());
+    run_options(env, options, choose, start, max_steps, gamma, rng).0
+}
+
+/// The indices into `options` of those whose `initiation` holds in
+/// `state`, in order.
+fn available<E: IEnvironment>(
+    env: &E,
+    options: &[Box<dyn OptionPolicy<E>>],
+    state: &E::State,
+) -> Vec<usize> {
+    (0..options.len())
+        .filter(|i| options[*i].initiation(env, state))
+        .collect()
+}
+
+/// As `rollout_with_options`, but also returning the state the episode
+/// ended in.
+fn run_options<E, F>(
+    env: &E,
+    options: &[Box<dyn OptionPolicy<E>>],
+    mut choose: F,
+    start: E::State,
+    max_steps: usize,
+    gamma: f32,
+    rng: &mut StdRng,
+) -> (Vec<OptionStep<E::State>>, E::State)
+where
+    E: IEnvironment,
+    F: FnMut(&E::State, &[usize], &mut StdRng) -> usize,
+{
+    let ends = |s: &E::State| env.is_terminal(s) || env.actions_from(s).is_empty();
+    let mut taken = Vec::new();
+    let mut state = start;
+    let mut steps = 0;
+    while steps < max_steps && !ends(&state) {
+        let available = available(env, options, &state);
+        if available.is_empty() {
+            break;
+        }
+        let i = choose(&state, &available, rng);
+        let option = &options[i];
+        let mut current = OptionStep {
+            state: state.clone(),
+            option: i,
+            reward: 0.0,
+            duration: 0,
+        };
+        let mut discount = 1.0;
+        let mut stuck = false;
+        while steps < max_steps {
+            let action = option.action(env, &state, rng);
+            let Some((next, reward)) = env.sample_transition(&state, &action, rng) else {
+                stuck = true;
+                break;
+            };
+            current.reward += discount * reward;
+            current.duration += 1;
+            discount *= gamma;
+            steps += 1;
+            state = next;
+            if ends(&state) || rng.gen::<f32>() < option.terminates(env, &state) {
+                break;
+            }
+        }
+        taken.push(current);
+        if stuck {
+            break;
+        }
+    }
+    (taken, state)
+}
+
+/// SMDP Q-learning: Q-learning over `options` in place of primitive
+/// actions, learning the value `Q(s, o)` of starting option `o`, by its
+/// index, in state `s`.
+///
+/// Runs `episodes` episodes of `rollout_with_options`, of at most
+/// `EPISODE_STEP_LIMIT` primitive steps, from states drawn uniformly
+/// from `env.start_states()`, choosing among the options that may be
+/// started epsilon-greedily, ties going to the lowest index. After each
+/// episode, every option it took, from `s` for `k` steps to `s'` with
+/// discounted reward `r`, is updated in turn by
+/// `Q(s, o) += alpha * (r + gamma^k * max_o' Q(s', o') - Q(s, o))`, the
+/// maximum ranging over the options that may be started in `s'`, and
+/// being 0 if none may be or `s'` ends the episode. An option is never
+/// chosen, nor valued, where it may not be started, so the table has
+/// no such pairs. `epsilon` advances with every option chosen, and
+/// `alpha` with the visits to each pair; `gamma` defaults to
+/// `env.gamma()`.
+pub fn smdp_q_learning<E: IEnvironment>(
+    env: &E,
+    options: &[Box<dyn OptionPolicy<E>>],
+    episodes: usize,
+    alpha: impl Into<LearningRate>,
+    gamma: Option<f32>,
+    epsilon: impl Into<Schedule>,
+    rng: &mut StdRng,
+) -> HashMap<(E::State, usize), f32> {
+    let gamma = gamma.unwrap_or_else(|| env.gamma());
+    let alpha = alpha.into();
+    let epsilon = epsilon.into();
+    let mut q: HashMap<(E::State, usize), f32> = HashMap::new();
+    let mut visits: HashMap<(E::State, usize), usize> = HashMap::new();
+    let mut choices = 0;
+    let value = |q: &HashMap<(E::State, usize), f32>, s: &E::State, o: usize| {
+        q.get(&(s.clone(), o)).copied().unwrap_or(0.0)
+    };
+    for _ in 0..episodes {
+        let Some(start) = simulate::sample_start(env, rng) else {
+            break;
+        };
+        let choose = |s: &E::State, available: &[usize], rng: &mut StdRng| {
+            let eps = epsilon.value(choices);
+            choices += 1;
+            if rng.gen::<f32>() < eps {
+                return available[rng.gen_range(0..available.len())];
+            }
+            let mut best = available[0];
+            for &o in &available[1..] {
+                if value(&q, s, o) > value(&q, s, best) {
+                    best = o;
+                }
+            }
+            best
+        };
+        let (taken, end) = run_options(env, options, choose, start, EPISODE_STEP_LIMIT, gamma, rng);
+        for (i, step) in taken.iter().enumerate() {
+            let next = taken.get(i + 1).map_or(&end, |later| &later.state);
+            let future = if env.is_terminal(next) || env.actions_from(next).is_empty() {
+                0.0
+            } else {
+                available(env, options, next)
+                    .into_iter()
+                    .map(|o| value(&q, next, o))
+                    .reduce(f32::max)
+                    .unwrap_or(0.0)
+            };
+            let target = step.reward + gamma.powi(step.duration as i32) * future;
+            let key = (step.state.clone(), step.option);
+            let n = visits.entry(key.clone()).or_insert(0);
+            *n += 1;
+            let current = q.entry(key).or_insert(0.0);
+            *current += alpha.at(*n) * (target - *current);
+        }
+    }
+    q
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::make_rng;
+    use crate::testing::Chain;
+
+    /// Keeps moving in `direction`, ending with probability `beta`
+    /// after each step.
+    struct Drive {
+        direction: i32,
+        beta: f32,
+    }
+
+    impl OptionPolicy<Chain> for Drive {
+        fn initiation(&self, _: &Chain, _: &i32) -> bool {
+            true
+        }
+
+        fn action(&self, _: &Chain, _: &i32, _: &mut StdRng) -> i32 {
+            self.direction
+        }
+
+        fn terminates(&self, _: &Chain, _: &i32) -> f32 {
+            self.beta
+        }
+    }
+
+    fn options() -> Vec<Box<dyn OptionPolicy<Chain>>> {
+        vec![
+            Box::new(Drive {
+                direction: 1,
+                beta: 0.0,
+            }),
+            Box::new(Drive {
+                direction: -1,
+                beta: 1.0,
+            }),
+        ]
+    }
+
+    #[test]
+    fn an_option_can_drive_to_the_goal() {
+        let rushed = rollout_with_options(
+            &Chain,
+            &options(),
+            |_, _, _| 0,
+            0,
+            100,
+            Some(0.9),
+            &mut make_rng(0),
+        );
+        assert_eq!(rushed.len(), 1);
+        assert_eq!(rushed[0].duration, 3);
+        assert!((rushed[0].reward - 0.81).abs() < 1e-6);
+
+        // One step left from 1, then the rest of the way right.
+        let mut first = true;
+        let choose = |_: &i32, available: &[usize], _: &mut StdRng| {
+            assert_eq!(available, [0, 1]);
+            let i = if first { 1 } else { 0 };
+            first = false;
+            i
+        };
+        let detour =
+            rollout_with_options(&Chain, &options(), choose, 1, 100, None, &mut make_rng(0));
+        let taken: Vec<_> = detour
+            .iter()
+            .map(|o| (o.state, o.option, o.duration))
+            .collect();
+        assert_eq!(taken, [(1, 1, 1), (0, 0, 3)]);
+    }
+
+    #[test]
+    fn options_that_never_terminate_are_cut_off() {
+        let options: Vec<Box<dyn OptionPolicy<Chain>>> = vec![Box::new(Drive {
+            direction: -1,
+            beta: 0.0,
+        })];
+        let stuck =
+            rollout_with_options(&Chain, &options, |_, _, _| 0, 0, 5, None, &mut make_rng(0));
+        assert_eq!(
+            stuck,
+            [OptionStep {
+                state: 0,
+                option: 0,
+                reward: 0.0,
+                duration: 5,
+            }]
+        );
+    }
+
+    /// Keeps moving left, for a single step, from anywhere but the
+    /// left end.
+    struct Back;
+
+    impl OptionPolicy<Chain> for Back {
+        fn initiation(&self, _: &Chain, state: &i32) -> bool {
+            *state > 0
+        }
+
+        fn action(&self, _: &Chain, _: &i32, _: &mut StdRng) -> i32 {
+            -1
+        }
+
+        fn terminates(&self, _: &Chain, _: &i32) -> f32 {
+            1.0
+        }
+    }
+
+    #[test]
+    fn smdp_q_learning_picks_the_option_to_the_goal() {
+        let options: Vec<Box<dyn OptionPolicy<Chain>>> = vec![
+            Box::new(Drive {
+                direction: 1,
+                beta: 0.5,
+            }),
+            Box::new(Back),
+        ];
+        let q = smdp_q_learning(&Chain, &options, 500, 0.5, Some(0.9), 0.5, &mut make_rng(0));
+        // However many times it breaks off, driving right from s takes
+        // 3 - s steps.
+        for s in 0..Chain::GOAL {
+            let expected = 0.9_f32.powi(Chain::GOAL - s - 1);
+            assert!((q[&(s, 0)] - expected).abs() < 1e-3, "{s}");
+        }
+        for s in 1..Chain::GOAL {
+            assert!(q[&(s, 1)] < q[&(s, 0)], "{s}");
+        }
+        // Back can't be started at the left end, so it's never tried.
+        assert!(!q.contains_key(&(0, 1)));
+        assert!(!q.keys().any(|(s, _)| *s == Chain::GOAL));
+    }
+}
@@ -0,0 +1,196 @@
+//! Saving and loading policies and value functions as JSON, and
+//! datasets as JSON Lines.
+//!
+//! Maps are written as sequences of entries rather than JSON
+//! objects, since states and actions generally don't serialize as
+//! strings.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::dataset::{Dataset, Record};
+use crate::dp::{QTable, ValueFunction};
+use crate::env::IEnvironment;
+use crate::policy::DeterministicPolicy;
+
+fn save<T: Serialize + ?Sized>(value: &T, path: impl AsRef<Path>) -> io::Result<()> {
+    let writer = BufWriter::new(File::create(path)?);
+    serde_json::to_writer(writer, value)?;
+    Ok(())
+}
+
+fn load<T: DeserializeOwned>(path: impl AsRef<Path>) -> io::Result<T> {
+    let reader = BufReader::new(File::open(path)?);
+    Ok(serde_json::from_reader(reader)?)
+}
+
+/// Writes `policy` to `path`. A loaded policy takes exactly the saved
+/// action in every state, so any tie-breaking done when the policy
+/// was built is preserved.
+pub fn save_policy<E>(policy: &DeterministicPolicy<E>, path: impl AsRef<Path>) -> io::Result<()>
+where
+    E: IEnvironment,
+    E::State: Serialize,
+    E::Action: Serialize,
+{
+    save(policy, path)
+}
+
+/// Reads a policy written by `save_policy`.
+pub fn load_policy<E>(path: impl AsRef<Path>) -> io::Result<DeterministicPolicy<E>>
+where
+    E: IEnvironment,
+    E::State: DeserializeOwned,
+    E::Action: DeserializeOwned,
+{
+    load(path)
+}
+
+/// Writes the state-value function `v` to `path`.
+pub fn save_values<E>(v: &ValueFunction<E>, path: impl AsRef<Path>) -> io::Result<()>
+where
+    E: IEnvironment,
+    E::State: Serialize,
+{
+    save(&v.iter().collect::<Vec<_>>(), path)
+}
+
+/// Reads a state-value function written by `save_values`.
+pub fn load_values<E>(path: impl AsRef<Path>) -> io::Result<ValueFunction<E>>
+where
+    E: IEnvironment,
+    E::State: DeserializeOwned,
+{
+    Ok(load::<Vec<(E::State, f32)>>(path)?.into_iter().collect())
+}
+
+/// Writes the action-value function `q` to `path`. Only the pairs
+/// that have been set are written, so `load_q` reads back a table
+/// whose default value is 0.
+pub fn save_q<E>(q: &QTable<E>, path: impl AsRef<Path>) -> io::Result<()>
+where
+    E: IEnvironment,
+    E::State: Serialize,
+    E::Action: Serialize,
+{
+    save(&q.iter().collect::<Vec<_>>(), path)
+}
+
+/// Reads an action-value function written by `save_q`.
+pub fn load_q<E>(path: impl AsRef<Path>) -> io::Result<QTable<E>>
+where
+    E: IEnvironment,
+    E::State: DeserializeOwned,
+    E::Action: DeserializeOwned,
+{
+    Ok(load::<Vec<((E::State, E::Action), f32)>>(path)?
+        .into_iter()
+        .collect())
+}
+
+/// Writes `dataset` to `path` as JSON Lines: one object per line for
+/// each transition, with its `episode`, `state`, `action`, `reward`,
+/// `next` and `done`.
+pub fn save_dataset<E>(dataset: &Dataset<E>, path: impl AsRef<Path>) -> io::Result<()>
+where
+    E: IEnvironment,
+    E::State: Serialize,
+    E::Action: Serialize,
+{
+    let mut writer = BufWriter::new(File::create(path)?);
+    for record in dataset.records() {
+        serde_json::to_writer(&mut writer, record)?;
+        writeln!(writer)?;
+    }
+    writer.flush()
+}
+
+/// Reads a dataset written by `save_dataset`, skipping blank lines.
+pub fn load_dataset<E>(path: impl AsRef<Path>) -> io::Result<Dataset<E>>
+where
+    E: IEnvironment,
+    E::State: DeserializeOwned,
+    E::Action: DeserializeOwned,
+{
+    let mut records = Vec::new();
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            records.push(serde_json::from_str::<Record<E::State, E::Action>>(&line)?);
+        }
+    }
+    Ok(Dataset::from_records(records))
+}
+
+#[cfg(all(test, feature = "examples"))]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::dp::{self, greedy_action};
+    use crate::env::examples::GridWorld;
+    use crate::policy::TieBreak;
+    use crate::rng::make_rng;
+    use crate::td;
+    use crate::testing::policies_equal;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("reinforce-{}-{name}.json", std::process::id()))
+    }
+
+    #[test]
+    fn loaded_policies_take_the_saved_actions() {
+        let env = GridWorld::new(4, 3, vec![(1, 1)], (3, 0), 0.04, 0.0);
+        // A short run leaves many actions tied at 0, so the policy
+        // depends on how the ties were broken.
+        let q = td::q_learning(&env, 3, 0.5, Some(0.9), 0.2, &mut make_rng(0));
+        let policy = DeterministicPolicy::<GridWorld>::new(
+            env.all_states()
+                .into_iter()
+                .filter(|s| !env.is_terminal(s))
+                .filter_map(|s| greedy_action(&env, &q, &s, &TieBreak::Random(7)).map(|a| (s, a)))
+                .collect(),
+        );
+
+        let path = temp_path("policy");
+        save_policy(&policy, &path).unwrap();
+        let loaded: DeterministicPolicy<GridWorld> = load_policy(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(policies_equal(&loaded, &policy, &env.all_states()));
+    }
+
+    #[test]
+    fn datasets_round_trip_a_line_per_transition() {
+        let env = GridWorld::new(4, 3, vec![(1, 1)], (3, 0), 0.04, 0.2);
+        let policy = crate::policy::UniformRandomPolicy;
+        let dataset = crate::dataset::collect_dataset(&env, &policy, 5, 10, &mut make_rng(0));
+
+        let path = temp_path("dataset");
+        save_dataset(&dataset, &path).unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(text.lines().count(), dataset.len());
+        let (x, y) = env.start();
+        assert!(text.starts_with(&format!("{{\"episode\":0,\"state\":[{x},{y}],")));
+        let loaded: Dataset<GridWorld> = load_dataset(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded, dataset);
+    }
+
+    #[test]
+    fn values_round_trip() {
+        let env = GridWorld::new(4, 3, vec![(1, 1)], (3, 0), 0.04, 0.2);
+        let (v, _) = dp::value_iteration(&env, Some(0.9), 1e-6, 1000).unwrap();
+        let q = dp::q_from_v(&env, &v, Some(0.9)).unwrap();
+
+        let path = temp_path("values");
+        save_values::<GridWorld>(&v, &path).unwrap();
+        assert_eq!(load_values::<GridWorld>(&path).unwrap(), v);
+        save_q(&q, &path).unwrap();
+        assert_eq!(load_q::<GridWorld>(&path).unwrap(), q);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
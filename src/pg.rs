@@ -0,0 +1,561 @@
+//! Policy-gradient methods for parameterized policies.
+
+use std::collections::HashMap;
+
+use crate::approx::LinearVF;
+use crate::env::IEnvironment;
+use crate::policy::{fixed_order, Policy};
+use crate::rng::StdRng;
+use crate::simulate::{self, sample_weighted, EPISODE_STEP_LIMIT};
+
+type ActionFeatureMap<S, A> = Box<dyn Fn(&S, &A) -> Vec<f32>>;
+
+/// A policy that is a softmax over action preferences linear in a
+/// feature map: `pi(a|s)` is proportional to `exp(theta . x(s, a))`.
+///
+/// Like `LinearVF`, the parameters start at zero, taking their length
+/// from the first feature vector an update sees; until then every
+/// available action is equally likely. From then on every feature
+/// vector must have the same length, and a mismatch panics.
+pub struct ParamPolicy<E: IEnvironment> {
+    features: ActionFeatureMap<E::State, E::Action>,
+    theta: Vec<f32>,
+}
+
+impl<E: IEnvironment> ParamPolicy<E> {
+    pub fn new(features: impl Fn(&E::State, &E::Action) -> Vec<f32> + 'static) -> Self {
+        ParamPolicy {
+            features: Box::new(features),
+            theta: Vec::new(),
+        }
+    }
+
+    pub fn theta(&self) -> &[f32] {
+        &self.theta
+    }
+
+    /// The features of taking `a` in `s`, checked against the
+    /// parameters' length.
+    pub fn features(&self, s: &E::State, a: &E::Action) -> Vec<f32> {
+        let x = (self.features)(s, a);
+        assert!(
+            self.theta.is_empty() || x.len() == self.theta.len(),
+            "feature vector has length {}, expected {}",
+            x.len(),
+            self.theta.len()
+        );
+        x
+    }
+
+    /// The probability of every action available from `s`.
+    pub fn probs(&self, env: &E, s: &E::State) -> HashMap<E::Action, f32> {
+        self.weights(env, s).into_iter().collect()
+    }
+
+    /// The gradient of `ln pi(a|s)` with respect to the parameters:
+    /// `x(s, a) - sum_b pi(b|s) x(s, b)`.
+    pub fn log_prob_grad(&self, env: &E, s: &E::State, a: &E::Action) -> Vec<f32> {
+        let mut grad = self.features(s, a);
+        for (b, p) in self.weights(env, s) {
+            for (g, x) in grad.iter_mut().zip(self.features(s, &b)) {
+                *g -= p * x;
+            }
+        }
+        grad
+    }
+
+    /// A step of `alpha` along `grad`.
+    fn ascend(&mut self, grad: &[f32], alpha: f32) {
+        if self.theta.is_empty() {
+            self.theta = vec![0.0; grad.len()];
+        }
+        for (t, g) in self.theta.iter_mut().zip(grad) {
+            *t += alpha * g;
+        }
+    }
+
+    /// The probability of each action available from `s`, in a fixed
+    /// order.
+    fn weights(&self, env: &E, s: &E::State) -> Vec<(E::Action, f32)> {
+        let preferences: Vec<(E::Action, f32)> = fixed_order(env.action_mask(s))
+            .into_iter()
+            .map(|a| {
+                let h = dot(&self.theta, &self.features(s, &a));
+                (a, h)
+            })
+            .collect();
+        let max = preferences
+            .iter()
+            .map(|(_, h)| *h)
+            .fold(f32::NEG_INFINITY, f32::max);
+        let weights: Vec<(E::Action, f32)> = preferences
+            .into_iter()
+            .map(|(a, h)| (a, (h - max).exp()))
+            .collect();
+        let total: f32 = weights.iter().map(|(_, w)| w).sum();
+        weights.into_iter().map(|(a, w)| (a, w / total)).collect()
+    }
+}
+
+impl<E: IEnvironment> Policy<E> for ParamPolicy<E> {
+    fn action_prob(&self, env: &E, state: &E::State, action: &E::Action) -> f32 {
+        self.weights(env, state)
+            .into_iter()
+            .find(|(a, _)| a == action)
+            .map_or(0.0, |(_, p)| p)
+    }
+
+    fn action_distribution(&self, env: &E, state: &E::State) -> HashMap<E::Action, f32> {
+        self.probs(env, state)
+    }
+
+    fn sample(&self, env: &E, state: &E::State, rng: &mut StdRng) -> E::Action {
+        sample_weighted(self.weights(env, state), rng).expect("no actions available from state")
+    }
+}
+
+fn dot(w: &[f32], x: &[f32]) -> f32 {
+    w.iter().zip(x).map(|(w, x)| w * x).sum()
+}
+
+/// REINFORCE: Monte Carlo policy gradient over `episodes` simulated
+/// episodes.
+///
+/// After each episode the parameters take a step of
+/// `alpha * sum_t G_t * grad ln pi(a_t|s_t)`, where `G_t` is the
+/// discounted return from step `t`, with the gradients taken at the
+/// parameters the episode was run with. `gamma` defaults to
+/// `env.gamma()`.
+pub fn reinforce<E: IEnvironment>(
+    env: &E,
+    params: &mut ParamPolicy<E>,
+    episodes: usize,
+    alpha: f32,
+    gamma: Option<f32>,
+    rng: &mut StdRng,
+) {
+    train(env, params, None, episodes, alpha, gamma, rng);
+}
+
+/// REINFORCE with a learned baseline: as `reinforce`, but each
+/// return has `baseline`'s estimate of its state's value subtracted
+/// from it, which lowers the variance of the updates without biasing
+/// them.
+///
+/// `baseline` is itself trained towards the observed returns, with
+/// step size `baseline_alpha`, after each episode's policy update.
+#[allow(clippy::too_many_arguments)]
+pub fn reinforce_with_baseline<E: IEnvironment>(
+    env: &E,
+    params: &mut ParamPolicy<E>,
+    baseline: &mut LinearVF<E>,
+    episodes: usize,
+    alpha: f32,
+    baseline_alpha: f32,
+    gamma: Option<f32>,
+    rng: &mut StdRng,
+) {
+    train(
+        env,
+        params,
+        Some((baseline, baseline_alpha)),
+        episodes,
+        alpha,
+        gamma,
+        rng,
+    );
+}
+
+fn train<E: IEnvironment>(
+    env: &E,
+    params: &mut ParamPolicy<E>,
+    mut baseline: Option<(&mut LinearVF<E>, f32)>,
+    episodes: usize,
+    alpha: f32,
+    gamma: Option<f32>,
+    rng: &mut StdRng,
+) {
+    let gamma = gamma.unwrap_or_else(|| env.gamma());
+    for _ in 0..episodes {
+        let Some(start) = simulate::sample_start(env, rng) else {
+            break;
+        };
+        let trajectory = simulate::rollout(env, params, start, EPISODE_STEP_LIMIT, rng);
+
+        let mut returns = vec![0.0; trajectory.len()];
+        let mut g = 0.0;
+        for (t, (_, _, r)) in trajectory.iter().enumerate().rev() {
+            g = r + gamma * g;
+            returns[t] = g;
+        }
+
+        let mut grad: Vec<f32> = Vec::new();
+        for ((s, a, _), g) in trajectory.iter().zip(&returns) {
+            let advantage = match &baseline {
+                Some((vf, _)) => g - vf.predict(s),
+                None => *g,
+            };
+            let step = params.log_prob_grad(env, s, a);
+            if grad.is_empty() {
+                grad = vec![0.0; step.len()];
+            }
+            for (total, x) in grad.iter_mut().zip(step) {
+                *total += advantage * x;
+            }
+        }
+        if !grad.is_empty() {
+            params.ascend(&grad, alpha);
+        }
+        if let Some((vf, baseline_alpha)) = &mut baseline {
+            for ((s, _, _), g) in trajectory.iter().zip(&returns) {
+                vf.update(s, *g, *baseline_alpha);
+            }
+        }
+    }
+}
+
+/// One-step actor-critic over `episodes` simulated episodes, updating
+/// online after every step.
+///
+/// Each step computes the TD error
+/// `delta = r + gamma * V(s') - V(s)` from the `critic`, with
+/// terminal states valued at 0. The `actor` takes a step of
+/// `alpha_actor * delta * grad ln pi(a|s)`, using `delta` as the
+/// advantage, and the critic a semi-gradient step of `alpha_critic`
+/// towards `r + gamma * V(s')`. `gamma` defaults to `env.gamma()`.
+#[allow(clippy::too_many_arguments)]
+pub fn actor_critic<E: IEnvironment>(
+    env: &E,
+    actor: &mut ParamPolicy<E>,
+    critic: &mut LinearVF<E>,
+    episodes: usize,
+    alpha_actor: f32,
+    alpha_critic: f32,
+    gamma: Option<f32>,
+    rng: &mut StdRng,
+) {
+    let gamma = gamma.unwrap_or_else(|| env.gamma());
+    for _ in 0..episodes {
+        let Some(mut state) = simulate::sample_start(env, rng) else {
+            break;
+        };
+        for _ in 0..EPISODE_STEP_LIMIT {
+            if env.is_terminal(&state) || env.actions_from(&state).is_empty() {
+                break;
+            }
+            let action = actor.sample(env, &state, rng);
+            let Some((next, reward)) = env.sample_transition(&state, &action, rng) else {
+                break;
+            };
+            let next_value = if env.is_terminal(&next) {
+                0.0
+            } else {
+                critic.predict(&next)
+            };
+            let target = reward + gamma * next_value;
+            let delta = target - critic.predict(&state);
+            let grad = actor.log_prob_grad(env, &state, &action);
+            actor.ascend(&grad, alpha_actor * delta);
+            critic.update(&state, target, alpha_critic);
+            state = next;
+        }
+    }
+}
+
+/// Generalized advantage estimation: the advantage of each step of a
+/// batch of experience, as the exponentially weighted sum of the TD
+/// residuals after it,
+/// `A_t = delta_t + (gamma * lambda) * delta_{t+1} + ...`, where
+/// `delta_t = r_t + gamma * V(s_{t+1}) - V(s_t)`.
+///
+/// `values[t]` is the estimated value of the state step `t` starts
+/// from, and `values` has one more entry than `rewards`: the value of
+/// the state the last step leads to, to bootstrap from. `dones[t]`
+/// marks step `t` as the last of its episode, in which case the value
+/// after it is taken to be 0 and no residual from a later episode
+/// flows back into it. `lambda = 0` gives the one-step TD residuals,
+/// and `lambda = 1` the discounted returns less the values.
+///
+/// Panics if `values` doesn't have one more entry than `rewards`, or
+/// `dones` a different number.
+pub fn compute_gae(
+    rewards: &[f32],
+    values: &[f32],
+    gamma: f32,
+    lambda: f32,
+    dones: &[bool],
+) -> Vec<f32> {
+    assert_eq!(
+        values.len(),
+        rewards.len() + 1,
+        "values must have one more entry than rewards"
+    );
+    assert_eq!(
+        dones.len(),
+        rewards.len(),
+        "dones must have an entry for every reward"
+    );
+    let mut advantages = vec![0.0; rewards.len()];
+    let mut next = 0.0;
+    for t in (0..rewards.len()).rev() {
+        let (next_value, carry) = if dones[t] {
+            (0.0, 0.0)
+        } else {
+            (values[t + 1], next)
+        };
+        let delta = rewards[t] + gamma * next_value - values[t];
+        next = delta + gamma * lambda * carry;
+        advantages[t] = next;
+    }
+    advantages
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::rng::make_rng;
+    use crate::simulate::evaluate_episodes;
+
+    /// A one-shot choice from state 0 into the terminal state 1:
+    /// action 0 pays 5 and action 1 pays 6.
+    struct Shot;
+
+    impl IEnvironment for Shot {
+        type State = u8;
+        type Action = u8;
+
+        fn prob(&self, from: &u8, take: &u8, to: &u8, with: &f32) -> f32 {
+            if *from == 0 && *to == 1 && *with == 5.0 + *take as f32 {
+                1.0
+            } else {
+                0.0
+            }
+        }
+
+        fn actions_from(&self, _: &u8) -> HashSet<u8> {
+            HashSet::from([0, 1])
+        }
+
+        fn states_from(&self, _: &u8, _: &u8) -> HashSet<u8> {
+            HashSet::from([1])
+        }
+
+        fn is_terminal(&self, state: &u8) -> bool {
+            *state == 1
+        }
+
+        fn start_states(&self) -> HashSet<u8> {
+            HashSet::from([0])
+        }
+
+        fn rewards(&self) -> Vec<f32> {
+            vec![5.0, 6.0]
+        }
+    }
+
+    /// One indicator feature per action.
+    fn one_hot(_: &u8, a: &u8) -> Vec<f32> {
+        let mut x = vec![0.0; 2];
+        x[*a as usize] = 1.0;
+        x
+    }
+
+    fn mean_return(policy: &ParamPolicy<Shot>, rng: &mut StdRng) -> f32 {
+        evaluate_episodes(&Shot, policy, 500, 10, None, rng)
+            .unwrap()
+            .undiscounted
+            .mean
+    }
+
+    fn variance(xs: &[f32]) -> f32 {
+        let mean = xs.iter().sum::<f32>() / xs.len() as f32;
+        xs.iter().map(|x| (x - mean).powi(2)).sum::<f32>() / xs.len() as f32
+    }
+
+    #[test]
+    fn log_prob_gradients_are_centred() {
+        let policy = ParamPolicy::<Shot>::new(one_hot);
+        assert_eq!(policy.probs(&Shot, &0), HashMap::from([(0, 0.5), (1, 0.5)]));
+        let grad = policy.log_prob_grad(&Shot, &0, &1);
+        assert_eq!(grad, vec![-0.5, 0.5]);
+    }
+
+    #[test]
+    fn reinforce_improves_the_average_return() {
+        let mut rng = make_rng(1);
+        let mut policy = ParamPolicy::<Shot>::new(one_hot);
+        let before = mean_return(&policy, &mut rng);
+        reinforce(&Shot, &mut policy, 300, 0.01, None, &mut rng);
+        assert!(mean_return(&policy, &mut rng) > before + 0.3);
+        assert!(policy.action_prob(&Shot, &0, &1) > 0.75);
+    }
+
+    #[test]
+    fn a_baseline_lowers_the_variance_of_updates() {
+        // The parameter of the better action after a single update,
+        // over many seeds.
+        let mut plain = Vec::new();
+        let mut baselined = Vec::new();
+        for seed in 0..200 {
+            let mut policy = ParamPolicy::<Shot>::new(one_hot);
+            reinforce(&Shot, &mut policy, 1, 0.1, None, &mut make_rng(seed));
+            plain.push(policy.theta()[1]);
+
+            let mut baseline = LinearVF::<Shot>::new(|_| vec![1.0]);
+            for _ in 0..200 {
+                baseline.update(&0, 5.5, 0.1);
+            }
+            let mut policy = ParamPolicy::<Shot>::new(one_hot);
+            reinforce_with_baseline(
+                &Shot,
+                &mut policy,
+                &mut baseline,
+                1,
+                0.1,
+                0.1,
+                None,
+                &mut make_rng(seed),
+            );
+            baselined.push(policy.theta()[1]);
+        }
+        assert!(variance(&baselined) < variance(&plain) / 10.0);
+    }
+
+    /// From state 0, action 0 pays 2 and moves to state 1, and action
+    /// 1 pays 2.3 and ends the episode. From state 1, action 0 pays 3
+    /// and action 1 pays 2, both ending the episode in state 2. So
+    /// action 0 is best in both states, though it pays less at first.
+    struct Detour;
+
+    impl Detour {
+        fn step(from: u8, take: u8) -> (u8, f32) {
+            match (from, take) {
+                (0, 0) => (1, 2.0),
+                (0, _) => (2, 2.3),
+                (1, 0) => (2, 3.0),
+                _ => (2, 2.0),
+            }
+        }
+    }
+
+    impl IEnvironment for Detour {
+        type State = u8;
+        type Action = u8;
+
+        fn prob(&self, from: &u8, take: &u8, to: &u8, with: &f32) -> f32 {
+            if Self::step(*from, *take) == (*to, *with) {
+                1.0
+            } else {
+                0.0
+            }
+        }
+
+        fn actions_from(&self, _: &u8) -> HashSet<u8> {
+            HashSet::from([0, 1])
+        }
+
+        fn states_from(&self, from: &u8, take: &u8) -> HashSet<u8> {
+            HashSet::from([Self::step(*from, *take).0])
+        }
+
+        fn is_terminal(&self, state: &u8) -> bool {
+            *state == 2
+        }
+
+        fn start_states(&self) -> HashSet<u8> {
+            HashSet::from([0])
+        }
+
+        fn rewards(&self) -> Vec<f32> {
+            vec![2.0, 2.3, 3.0]
+        }
+    }
+
+    /// The number of episodes `train` takes to make `policy` choose
+    /// action 0 in both states with probability 0.9, up to 2000.
+    fn episodes_to_learn(
+        policy: &mut ParamPolicy<Detour>,
+        mut train: impl FnMut(&mut ParamPolicy<Detour>),
+    ) -> usize {
+        let optimal = |p: &ParamPolicy<Detour>| p.probs(&Detour, &0)[&0] * p.probs(&Detour, &1)[&0];
+        let mut episodes = 0;
+        while optimal(policy) < 0.9 && episodes < 2000 {
+            train(policy);
+            episodes += 1;
+        }
+        episodes
+    }
+
+    #[test]
+    fn actor_critic_learns_faster_than_reinforce() {
+        let features = |s: &u8, a: &u8| {
+            let mut x = vec![0.0; 4];
+            x[2 * *s as usize + *a as usize] = 1.0;
+            x
+        };
+        let (mut online, mut episodic) = (0, 0);
+        for seed in 0..20 {
+            let mut rng = make_rng(seed);
+            let mut critic = LinearVF::<Detour>::new(|s| {
+                let mut x = vec![0.0; 3];
+                x[*s as usize] = 1.0;
+                x
+            });
+            online += episodes_to_learn(&mut ParamPolicy::new(features), |actor| {
+                actor_critic(&Detour, actor, &mut critic, 1, 0.3, 0.8, None, &mut rng)
+            });
+            let mut rng = make_rng(seed);
+            episodic += episodes_to_learn(&mut ParamPolicy::new(features), |policy| {
+                reinforce(&Detour, policy, 1, 0.3, None, &mut rng)
+            });
+        }
+        assert!(2 * online < episodic, "{online} vs {episodic}");
+    }
+
+    fn assert_close(actual: &[f32], expected: &[f32]) {
+        assert_eq!(actual.len(), expected.len());
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < 1e-5, "{actual:?} vs {expected:?}");
+        }
+    }
+
+    #[test]
+    fn gae_matches_hand_computed_advantages() {
+        let rewards = [1.0, 0.0, 2.0];
+        let values = [0.5, 1.0, 0.0, 3.0];
+        // The TD residuals are 1.4, -1 and 4.7, and gamma * lambda is
+        // 0.72.
+        let advantages = compute_gae(&rewards, &values, 0.9, 0.8, &[false; 3]);
+        assert_close(&advantages, &[1.4 + 0.72 * 2.384, -1.0 + 0.72 * 4.7, 4.7]);
+        assert_close(
+            &compute_gae(&rewards, &values, 0.9, 0.0, &[false; 3]),
+            &[1.4, -1.0, 4.7],
+        );
+    }
+
+    #[test]
+    fn gae_stops_at_episode_boundaries() {
+        // The second step ends an episode, so it neither bootstraps
+        // from the third step's state nor sees its residual.
+        let advantages = compute_gae(
+            &[1.0, 0.0, 2.0],
+            &[0.5, 1.0, 0.0, 3.0],
+            0.9,
+            0.8,
+            &[false, true, false],
+        );
+        assert_close(&advantages, &[1.4 - 0.72, -1.0, 4.7]);
+        // With lambda = 1 the advantages are the returns less the
+        // values.
+        let advantages = compute_gae(&[1.0, 1.0], &[0.0; 3], 0.5, 1.0, &[false, true]);
+        assert_close(&advantages, &[1.5, 1.0]);
+    }
+
+    #[test]
+    #[should_panic(expected = "values must have one more entry than rewards")]
+    fn gae_needs_a_value_to_bootstrap_from() {
+        compute_gae(&[1.0], &[0.0], 0.9, 0.9, &[false]);
+    }
+}
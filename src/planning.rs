@@ -0,0 +1,642 @@
+//! Methods that learn a model of the environment from experience and
+//! plan with it.
+
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+use std::hash::Hash;
+
+use rand::Rng;
+
+use crate::dp::QTable;
+use crate::env::IEnvironment;
+use crate::observer::{NoObserver, TrainingObserver};
+use crate::policy::fixed_order;
+use crate::rng::StdRng;
+use crate::schedule::Schedule;
+use crate::simulate::{self, sample_weighted, EPISODE_STEP_LIMIT};
+use crate::td::{epsilon_greedy, update, LearningRate};
+
+/// A learned deterministic model: the latest outcome observed for
+/// each state-action pair.
+type LatestOutcomes<E> = HashMap<
+    (<E as IEnvironment>::State, <E as IEnvironment>::Action),
+    (<E as IEnvironment>::State, f32),
+>;
+
+/// Dyna-Q: Q-learning on real experience, interleaved with extra
+/// Q-learning updates on transitions replayed from a learned model.
+///
+/// As `dyna_q_with_model`, learning a `TabularModel`.
+pub fn dyna_q<E: IEnvironment>(
+    env: &E,
+    episodes: usize,
+    planning_steps: usize,
+    alpha: impl Into<LearningRate>,
+    gamma: Option<f32>,
+    epsilon: impl Into<Schedule>,
+    rng: &mut StdRng,
+) -> QTable<E> {
+    dyna_q_observed(
+        env,
+        episodes,
+        planning_steps,
+        alpha,
+        gamma,
+        epsilon,
+        rng,
+        &mut NoObserver,
+    )
+}
+
+/// As `dyna_q`, but reporting each episode's total reward to
+/// `observer` as it ends, and stopping training early if it returns
+/// `ControlFlow::Break`.
+#[allow(clippy::too_many_arguments)]
+pub fn dyna_q_observed<E, O>(
+    env: &E,
+    episodes: usize,
+    planning_steps: usize,
+    alpha: impl Into<LearningRate>,
+    gamma: Option<f32>,
+    epsilon: impl Into<Schedule>,
+    rng: &mut StdRng,
+    observer: &mut O,
+) -> QTable<E>
+where
+    E: IEnvironment,
+    O: TrainingObserver<E>,
+{
+    let mut model = TabularModel::new();
+    dyna(
+        env,
+        &mut model,
+        episodes,
+        planning_steps,
+        alpha.into(),
+        gamma,
+        epsilon.into(),
+        rng,
+        observer,
+    )
+}
+
+/// Dyna-Q, planning with `model`.
+///
+/// Runs episodes like `td::q_learning`. After each real step the
+/// model `observe`s the transition, and then `planning_steps` pairs
+/// are drawn uniformly from its `observed_pairs` and updated as if
+/// the outcome `sample_next` gives for them had just happened. The
+/// model can be learned, like a `TabularModel`, or exact, like an
+/// `EnvModel`, which plans over every pair from the start. `gamma`
+/// defaults to `env.gamma()`.
+#[allow(clippy::too_many_arguments)]
+pub fn dyna_q_with_model<E, M>(
+    env: &E,
+    model: &mut M,
+    episodes: usize,
+    planning_steps: usize,
+    alpha: impl Into<LearningRate>,
+    gamma: Option<f32>,
+    epsilon: impl Into<Schedule>,
+    rng: &mut StdRng,
+) -> QTable<E>
+where
+    E: IEnvironment,
+    M: Model<E::Action, E::State>,
+{
+    dyna(
+        env,
+        model,
+        episodes,
+        planning_steps,
+        alpha.into(),
+        gamma,
+        epsilon.into(),
+        rng,
+        &mut NoObserver,
+    )
+}
+
+/// The shared body of `dyna_q_observed` and `dyna_q_with_model`.
+#[allow(clippy::too_many_arguments)]
+fn dyna<E, M, O>(
+    env: &E,
+    model: &mut M,
+    episodes: usize,
+    planning_steps: usize,
+    alpha: LearningRate,
+    gamma: Option<f32>,
+    epsilon: Schedule,
+    rng: &mut StdRng,
+    observer: &mut O,
+) -> QTable<E>
+where
+    E: IEnvironment,
+    M: Model<E::Action, E::State>,
+    O: TrainingObserver<E>,
+{
+    let gamma = gamma.unwrap_or_else(|| env.gamma());
+    let mut q = QTable::<E>::new();
+    let mut visits: HashMap<(E::State, E::Action), usize> = HashMap::new();
+    let mut steps = 0;
+    for episode in 0..episodes {
+        let Some(mut state) = simulate::sample_start(env, rng) else {
+            break;
+        };
+        let mut total = 0.0;
+        for _ in 0..EPISODE_STEP_LIMIT {
+            if env.is_terminal(&state) {
+                break;
+            }
+            let Some(action) = epsilon_greedy(env, &q, &state, epsilon.value(steps), rng) else {
+                break;
+            };
+            let Some((next, reward)) = env.sample_transition(&state, &action, rng) else {
+                break;
+            };
+            steps += 1;
+            total += reward;
+            let target = reward + gamma * q.max_action_value(env, &next);
+            let key = (state, action);
+            update::<E>(&mut q, &mut visits, key.clone(), &alpha, target);
+            model.observe(key.0, key.1, next.clone(), reward);
+
+            let pairs = model.observed_pairs();
+            if !pairs.is_empty() {
+                for _ in 0..planning_steps {
+                    let (s, a) = &pairs[rng.gen_range(0..pairs.len())];
+                    let Some((to, r)) = model.sample_next(s, a, rng) else {
+                        continue;
+                    };
+                    let target = r + gamma * q.max_action_value(env, &to);
+                    update::<E>(&mut q, &mut visits, (s.clone(), a.clone()), &alpha, target);
+                }
+            }
+            state = next;
+        }
+        if observer.on_episode_end(episode, total, &q).is_break() {
+            break;
+        }
+    }
+    q
+}
+
+/// The reverse of a `LatestOutcomes` model: the pairs observed to
+/// lead to each state.
+type Predecessors<E> = HashMap<
+    <E as IEnvironment>::State,
+    Vec<(<E as IEnvironment>::State, <E as IEnvironment>::Action)>,
+>;
+
+/// A state-action pair waiting in the prioritized sweeping queue, by
+/// its index in the queue's list of keys.
+struct Queued {
+    priority: f32,
+    index: usize,
+}
+
+impl PartialEq for Queued {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Queued {}
+
+impl PartialOrd for Queued {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Highest priority first, and the earliest queued among equals.
+impl Ord for Queued {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.priority
+            .total_cmp(&other.priority)
+            .then_with(|| other.index.cmp(&self.index))
+    }
+}
+
+/// A max-priority queue of state-action pairs, holding each pair at
+/// most once, at the highest priority it has been queued with.
+struct PriorityQueue<E: IEnvironment> {
+    heap: BinaryHeap<Queued>,
+    keys: Vec<(E::State, E::Action)>,
+    queued: HashMap<(E::State, E::Action), f32>,
+}
+
+impl<E: IEnvironment> PriorityQueue<E> {
+    fn new() -> Self {
+        PriorityQueue {
+            heap: BinaryHeap::new(),
+            keys: Vec::new(),
+            queued: HashMap::new(),
+        }
+    }
+
+    fn push(&mut self, key: (E::State, E::Action), priority: f32) {
+        if self.queued.get(&key).is_some_and(|p| *p >= priority) {
+            return;
+        }
+        self.queued.insert(key.clone(), priority);
+        self.heap.push(Queued {
+            priority,
+            index: self.keys.len(),
+        });
+        self.keys.push(key);
+    }
+
+    fn pop(&mut self) -> Option<(E::State, E::Action)> {
+        while let Some(Queued { priority, index }) = self.heap.pop() {
+            let key = &self.keys[index];
+            // Skip entries superseded by a later, higher priority.
+            if self.queued.get(key) == Some(&priority) {
+                self.queued.remove(key);
+                return Some(key.clone());
+            }
+        }
+        None
+    }
+}
+
+/// Prioritized sweeping: Dyna-style planning that replays the
+/// transitions whose values are most out of date first, rather than
+/// uniformly.
+///
+/// Runs episodes like `dyna_q`, but learns a deterministic model,
+/// keeping only the latest outcome observed for each pair, along with
+/// the pairs observed to lead into each state.
+/// Rather than updating `Q` directly, each real step queues its pair
+/// with priority `|r + gamma * max_a' Q(s',a') - Q(s,a)|` if that
+/// exceeds `theta`. Planning then repeatedly takes the pair with the
+/// highest priority and updates it from the model, queuing every pair
+/// leading into its state by the same rule, until the queue is empty
+/// or `planning_steps` updates have been made since the real step.
+/// Pairs left in the queue stay there for the next real step.
+///
+/// Returns the learned Q-table and the total number of planning
+/// updates made. `gamma` defaults to `env.gamma()`.
+#[allow(clippy::too_many_arguments)]
+pub fn prioritized_sweeping<E: IEnvironment>(
+    env: &E,
+    episodes: usize,
+    planning_steps: usize,
+    theta: f32,
+    alpha: impl Into<LearningRate>,
+    gamma: Option<f32>,
+    epsilon: impl Into<Schedule>,
+    rng: &mut StdRng,
+) -> (QTable<E>, usize) {
+    prioritized_sweeping_observed(
+        env,
+        episodes,
+        planning_steps,
+        theta,
+        alpha,
+        gamma,
+        epsilon,
+        rng,
+        &mut NoObserver,
+    )
+}
+
+/// As `prioritized_sweeping`, but reporting each episode's total
+/// reward to `observer` as it ends, and stopping training early if it
+/// returns `ControlFlow::Break`.
+#[allow(clippy::too_many_arguments)]
+pub fn prioritized_sweeping_observed<E, O>(
+    env: &E,
+    episodes: usize,
+    planning_steps: usize,
+    theta: f32,
+    alpha: impl Into<LearningRate>,
+    gamma: Option<f32>,
+    epsilon: impl Into<Schedule>,
+    rng: &mut StdRng,
+    observer: &mut O,
+) -> (QTable<E>, usize)
+where
+    E: IEnvironment,
+    O: TrainingObserver<E>,
+{
+    let gamma = gamma.unwrap_or_else(|| env.gamma());
+    let alpha = alpha.into();
+    let epsilon = epsilon.into();
+    let mut q = QTable::<E>::new();
+    let mut visits: HashMap<(E::State, E::Action), usize> = HashMap::new();
+    let mut model = LatestOutcomes::<E>::new();
+    // The pairs leading to each state, in the order first observed.
+    let mut predecessors = Predecessors::<E>::new();
+    let mut queue = PriorityQueue::<E>::new();
+    let mut backups = 0;
+
+    let priority = |q: &QTable<E>, key: &(E::State, E::Action), to: &E::State, r: f32| {
+        let value = q.get(&key.0, &key.1);
+        (r + gamma * q.max_action_value(env, to) - value).abs()
+    };
+
+    let mut steps = 0;
+    for episode in 0..episodes {
+        let Some(mut state) = simulate::sample_start(env, rng) else {
+            break;
+        };
+        let mut total = 0.0;
+        for _ in 0..EPISODE_STEP_LIMIT {
+            if env.is_terminal(&state) {
+                break;
+            }
+            let Some(action) = epsilon_greedy(env, &q, &state, epsilon.value(steps), rng) else {
+                break;
+            };
+            let Some((next, reward)) = env.sample_transition(&state, &action, rng) else {
+                break;
+            };
+            steps += 1;
+            total += reward;
+            let key = (state, action);
+            match model.insert(key.clone(), (next.clone(), reward)) {
+                Some((before, _)) if before == next => {}
+                previous => {
+                    if let Some((before, _)) = previous {
+                        if let Some(keys) = predecessors.get_mut(&before) {
+                            keys.retain(|k| *k != key);
+                        }
+                    }
+                    predecessors
+                        .entry(next.clone())
+                        .or_default()
+                        .push(key.clone());
+                }
+            }
+            let p = priority(&q, &key, &next, reward);
+            if p > theta {
+                queue.push(key, p);
+            }
+
+            for _ in 0..planning_steps {
+                let Some(key) = queue.pop() else {
+                    break;
+                };
+                let (to, r) = model[&key].clone();
+                let target = r + gamma * q.max_action_value(env, &to);
+                update::<E>(&mut q, &mut visits, key.clone(), &alpha, target);
+                backups += 1;
+                for pred in predecessors.get(&key.0).into_iter().flatten() {
+                    let (_, r) = &model[pred];
+                    let p = priority(&q, pred, &key.0, *r);
+                    if p > theta {
+                        queue.push(pred.clone(), p);
+                    }
+                }
+            }
+            state = next;
+        }
+        if observer.on_episode_end(episode, total, &q).is_break() {
+            break;
+        }
+    }
+    (q, backups)
+}
+
+/// A model of an environment's dynamics that can be sampled from,
+/// whether learned from experience or exact.
+pub trait Model<A, S> {
+    /// Samples a next state and reward for taking action `a` from
+    /// state `s`, or `None` if the model knows no outcome for the pair.
+    fn sample_next(&self, s: &S, a: &A, rng: &mut StdRng) -> Option<(S, f32)>;
+
+    /// The state-action pairs the model can sample outcomes for, in
+    /// an order that doesn't vary between runs.
+    fn observed_pairs(&self) -> Vec<(S, A)>;
+
+    /// Records that taking `a` from `s` led to `to` with reward
+    /// `reward`. Models learned from experience update on it; the
+    /// default ignores it, as suits an exact model.
+    fn observe(&mut self, _s: S, _a: A, _to: S, _reward: f32) {}
+}
+
+/// The distinct outcomes of a state-action pair, with how often each
+/// was observed.
+type Counts<S> = Vec<(S, f32, usize)>;
+
+/// A model learned from observed transitions, sampling each pair's
+/// outcomes in proportion to how often they were observed.
+pub struct TabularModel<A, S> {
+    outcomes: HashMap<(S, A), Counts<S>>,
+    // The keys of `outcomes`, in the order first observed.
+    pairs: Vec<(S, A)>,
+}
+
+impl<A: Hash + Eq + Clone, S: Hash + Eq + Clone> TabularModel<A, S> {
+    pub fn new() -> Self {
+        TabularModel {
+            outcomes: HashMap::new(),
+            pairs: Vec::new(),
+        }
+    }
+
+    /// The distinct outcomes observed for taking `a` from `s`, with
+    /// the number of times each was observed.
+    pub fn outcomes(&self, s: &S, a: &A) -> &[(S, f32, usize)] {
+        self.outcomes
+            .get(&(s.clone(), a.clone()))
+            .map_or(&[], Vec::as_slice)
+    }
+}
+
+impl<A: Hash + Eq + Clone, S: Hash + Eq + Clone> Default for TabularModel<A, S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<A: Hash + Eq + Clone, S: Hash + Eq + Clone> Model<A, S> for TabularModel<A, S> {
+    fn sample_next(&self, s: &S, a: &A, rng: &mut StdRng) -> Option<(S, f32)> {
+        let weighted = self
+            .outcomes(s, a)
+            .iter()
+            .map(|(to, r, n)| ((to.clone(), *r), *n as f32))
+            .collect();
+        sample_weighted(weighted, rng)
+    }
+
+    /// The pairs observed, in the order first observed.
+    fn observed_pairs(&self) -> Vec<(S, A)> {
+        self.pairs.clone()
+    }
+
+    fn observe(&mut self, s: S, a: A, to: S, reward: f32) {
+        let key = (s, a);
+        let outcomes = match self.outcomes.get_mut(&key) {
+            Some(outcomes) => outcomes,
+            None => {
+                self.pairs.push(key.clone());
+                self.outcomes.entry(key).or_default()
+            }
+        };
+        match outcomes
+            .iter_mut()
+            .find(|(t, r, _)| *t == to && *r == reward)
+        {
+            Some((_, _, n)) => *n += 1,
+            None => outcomes.push((to, reward, 1)),
+        }
+    }
+}
+
+/// An environment's true dynamics as a `Model`, sampling through the
+/// simulator.
+///
+/// As in simulation, rewards are sampled if the environment's rewards
+/// are enumerable, and otherwise the expected reward of the sampled
+/// transition is used.
+pub struct EnvModel<'a, E>(&'a E);
+
+impl<'a, E: IEnvironment> EnvModel<'a, E> {
+    pub fn new(env: &'a E) -> Self {
+        EnvModel(env)
+    }
+
+    pub fn env(&self) -> &'a E {
+        self.0
+    }
+}
+
+impl<E: IEnvironment> Model<E::Action, E::State> for EnvModel<'_, E> {
+    fn sample_next(
+        &self,
+        s: &E::State,
+        a: &E::Action,
+        rng: &mut StdRng,
+    ) -> Option<(E::State, f32)> {
+        self.0.sample_transition(s, a, rng)
+    }
+
+    /// Every available action from every non-terminal state in
+    /// the environment's `all_states`, in the fixed order of
+    /// `TieBreak::First`.
+    fn observed_pairs(&self) -> Vec<(E::State, E::Action)> {
+        let env = self.0;
+        fixed_order(env.all_states())
+            .into_iter()
+            .filter(|s| !env.is_terminal(s))
+            .flat_map(|s| {
+                fixed_order(env.actions_from(&s))
+                    .into_iter()
+                    .map(move |a| (s.clone(), a))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::make_rng;
+    use crate::testing::{Chain, Loop};
+
+    #[test]
+    fn sweeping_plans_at_most_planning_steps_per_real_step() {
+        let (_, backups) =
+            prioritized_sweeping(&Loop, 1, 5, 1e-4, 0.5, Some(1.0), 0.0, &mut make_rng(0));
+        assert_eq!(backups, 5 * EPISODE_STEP_LIMIT);
+    }
+
+    #[cfg(feature = "examples")]
+    #[test]
+    fn sweeping_finds_the_corridor_policy_in_fewer_updates_than_dyna_q() {
+        use crate::env::examples::{GridWorld, Move};
+
+        let corridor = GridWorld::new(25, 1, [], (24, 0), 0.0, 0.0);
+        let optimal = |q: &QTable<GridWorld>| {
+            (0..24).all(|x| q.greedy_action(&corridor, &(x, 0)) == Some(Move::Right))
+        };
+        let (q, backups) = prioritized_sweeping(
+            &corridor,
+            1,
+            100,
+            1e-4,
+            1.0,
+            Some(0.95),
+            0.1,
+            &mut make_rng(1),
+        );
+        assert!(optimal(&q));
+        // The episode takes at least 24 real steps, so Dyna-Q planning
+        // five times a step makes more updates, and still doesn't get
+        // there.
+        assert!(backups < 5 * 24, "{backups} backups");
+        let dyna = dyna_q(&corridor, 1, 5, 1.0, Some(0.95), 0.1, &mut make_rng(1));
+        assert!(!optimal(&dyna));
+    }
+
+    #[test]
+    fn tabular_model_reproduces_observed_transitions() {
+        let mut model = TabularModel::<u8, u8>::new();
+        model.observe(0, 1, 2, 0.5);
+        model.observe(0, 1, 2, 0.5);
+        model.observe(0, 1, 3, -1.0);
+        model.observe(1, 0, 1, 0.0);
+        assert_eq!(model.outcomes(&0, &1), &[(2, 0.5, 2), (3, -1.0, 1)]);
+        assert_eq!(model.observed_pairs(), vec![(0, 1), (1, 0)]);
+
+        let mut rng = make_rng(0);
+        assert_eq!(model.sample_next(&1, &0, &mut rng), Some((1, 0.0)));
+        assert_eq!(model.sample_next(&1, &1, &mut rng), None);
+        let draws = 3000;
+        let twos = (0..draws)
+            .filter(|_| model.sample_next(&0, &1, &mut rng) == Some((2, 0.5)))
+            .count();
+        assert!((twos as f32 / draws as f32 - 2.0 / 3.0).abs() < 0.03);
+    }
+
+    #[test]
+    fn env_model_samples_the_true_dynamics() {
+        let model = EnvModel::new(&Chain);
+        let mut rng = make_rng(0);
+        assert_eq!(model.sample_next(&2, &1, &mut rng), Some((3, 1.0)));
+        assert_eq!(model.sample_next(&0, &-1, &mut rng), Some((0, 0.0)));
+        assert_eq!(model.observed_pairs().len(), 6);
+        assert_eq!(
+            model.observed_pairs(),
+            EnvModel::new(&Chain).observed_pairs()
+        );
+    }
+
+    #[test]
+    fn dyna_q_plans_with_an_exact_model() {
+        let mut model = EnvModel::new(&Chain);
+        let q = dyna_q_with_model(
+            &Chain,
+            &mut model,
+            1,
+            50,
+            0.5,
+            Some(0.9),
+            0.1,
+            &mut make_rng(0),
+        );
+        for s in 0..Chain::GOAL {
+            assert_eq!(q.greedy_action(&Chain, &s), Some(1));
+        }
+        assert!((q.get(&0, &1) - 0.81).abs() < 0.01);
+    }
+
+    #[cfg(feature = "examples")]
+    #[test]
+    fn dyna_q_needs_fewer_real_episodes_than_q_learning() {
+        use crate::td;
+        use crate::testing::{maze, optimal_states};
+
+        let maze = maze();
+        let optimal = |q: &QTable<_>| optimal_states(&maze, q, 0.95);
+        let states = maze.all_states().len() - 1;
+        let dyna = dyna_q(&maze, 5, 50, 0.5, Some(0.95), 0.1, &mut make_rng(0));
+        assert_eq!(optimal(&dyna), states);
+        // Ten times as many real episodes still aren't enough without
+        // planning.
+        let q = td::q_learning(&maze, 50, 0.5, Some(0.95), 0.1, &mut make_rng(0));
+        assert!(optimal(&q) < states);
+    }
+}
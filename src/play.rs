@@ -0,0 +1,181 @@
+//! Playing an environment by hand from the terminal, to get a feel for
+//! its dynamics before writing an agent for it.
+
+use std::fmt::Debug;
+use std::io::{self, BufRead, Write};
+
+use crate::env::IEnvironment;
+use crate::policy::fixed_order;
+use crate::simulate::Episodic;
+
+/// Plays one episode of `env` on stdin and stdout, showing each state
+/// as its `Debug` form. Returns the total reward.
+///
+/// See `play_with` for how a turn goes.
+pub fn play<E>(env: &mut Episodic<E>) -> io::Result<f32>
+where
+    E: IEnvironment,
+    E::State: Debug,
+    E::Action: Debug,
+{
+    play_with(
+        env,
+        |_, s| format!("{:?}", s),
+        io::stdin().lock(),
+        io::stdout(),
+    )
+}
+
+/// As `play`, but drawing each state as a grid.
+#[cfg(feature = "examples")]
+pub fn play_grid<E>(env: &mut Episodic<E>) -> io::Result<f32>
+where
+    E: crate::env::examples::GridRenderable,
+    E::Action: Debug,
+{
+    play_with(
+        env,
+        |env, s| env.render_state(s),
+        io::stdin().lock(),
+        io::stdout(),
+    )
+}
+
+/// Plays one episode of `env`, reading choices from `input` and
+/// writing to `output`, and returns the total reward.
+///
+/// Each turn shows the current state drawn by `render`, lists the
+/// available actions by index, in a fixed order, and reads the index
+/// of one to take, asking again until a line holds a valid index. The
+/// reward is shown after each step. The episode ends as
+/// `Episodic::step` ends it, or on reaching a state with no actions.
+///
+/// Returns an `UnexpectedEof` error if `input` runs out first.
+pub fn play_with<E, R, W>(
+    env: &mut Episodic<E>,
+    render: impl Fn(&E, &E::State) -> String,
+    mut input: R,
+    mut output: W,
+) -> io::Result<f32>
+where
+    E: IEnvironment,
+    E::Action: Debug,
+    R: BufRead,
+    W: Write,
+{
+    let mut state = env.reset();
+    let mut total = 0.0;
+    loop {
+        writeln!(output, "{}", render(env.env(), &state))?;
+        if env.env().is_terminal(&state) {
+            break;
+        }
+        let actions = fixed_order(env.env().actions_from(&state));
+        if actions.is_empty() {
+            writeln!(output, "No actions are available.")?;
+            break;
+        }
+        for (i, a) in actions.iter().enumerate() {
+            writeln!(output, "  {}: {:?}", i, a)?;
+        }
+        let choice = read_choice(&mut input, &mut output, actions.len())?;
+        let Ok((next, reward, done)) = env.step(&actions[choice]) else {
+            unreachable!("listed actions are valid, after a reset");
+        };
+        total += reward;
+        writeln!(output, "Reward: {}", reward)?;
+        state = next;
+        if done {
+            writeln!(output, "{}", render(env.env(), &state))?;
+            break;
+        }
+    }
+    writeln!(output, "Episode over, with a total reward of {}.", total)?;
+    Ok(total)
+}
+
+/// Reads lines from `input` until one holds an index below `n`.
+fn read_choice(input: &mut impl BufRead, output: &mut impl Write, n: usize) -> io::Result<usize> {
+    let mut line = String::new();
+    loop {
+        write!(output, "> ")?;
+        output.flush()?;
+        line.clear();
+        if input.read_line(&mut line)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        match line.trim().parse::<usize>() {
+            Ok(i) if i < n => return Ok(i),
+            _ => writeln!(output, "Enter a number from 0 to {}.", n - 1)?,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::make_rng;
+    use crate::testing::{Chain, Trap};
+
+    /// Input choosing to move right, by its index among the actions,
+    /// `moves` times, after each of `junk`.
+    fn script(junk: &[&str], moves: usize) -> String {
+        let right = fixed_order(Chain.actions_from(&0))
+            .iter()
+            .position(|a| *a == 1)
+            .unwrap();
+        let mut input = String::new();
+        for _ in 0..moves {
+            for line in junk {
+                input.push_str(line);
+                input.push('\n');
+            }
+            input.push_str(&format!("{}\n", right));
+        }
+        input
+    }
+
+    #[test]
+    fn scripted_play_reaches_the_goal() {
+        let mut env = Episodic::new(Chain, make_rng(0));
+        let input = script(&["left", "2", ""], 3);
+        let mut output = Vec::new();
+        let total = play_with(
+            &mut env,
+            |_, s| s.to_string(),
+            input.as_bytes(),
+            &mut output,
+        );
+        assert_eq!(total.unwrap(), 1.0);
+        assert_eq!(env.state(), Some(&Chain::GOAL));
+
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(output.matches("Enter a number from 0 to 1.").count(), 9);
+        assert!(output.ends_with("Reward: 1\n3\nEpisode over, with a total reward of 1.\n"));
+    }
+
+    #[test]
+    fn play_stops_in_traps_and_at_the_end_of_input() {
+        let mut env = Episodic::new(Trap, make_rng(0));
+        let mut output = Vec::new();
+        let total = play_with(
+            &mut env,
+            |_, s| s.to_string(),
+            script(&[], 3).as_bytes(),
+            &mut output,
+        );
+        assert_eq!(total.unwrap(), 1.0);
+        assert!(String::from_utf8(output)
+            .unwrap()
+            .contains("No actions are available."));
+
+        let mut env = Episodic::new(Chain, make_rng(0));
+        let ran_out = play_with(
+            &mut env,
+            |_, s| s.to_string(),
+            script(&[], 2).as_bytes(),
+            io::sink(),
+        );
+        assert_eq!(ran_out.unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,1059 @@
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+
+use rand::Rng;
+
+use crate::dp::QTable;
+use crate::env::IEnvironment;
+use crate::rng::StdRng;
+use crate::simulate::sample_weighted;
+
+/// A (possibly stochastic) rule for choosing actions in an
+/// environment `E`.
+pub trait Policy<E: IEnvironment> {
+    /// The probability of taking action `action` in state `state`.
+    fn action_prob(&self, env: &E, state: &E::State, action: &E::Action) -> f32;
+
+    /// The probability of every action available from `state`.
+    ///
+    /// The default implementation enumerates the environment's
+    /// `action_mask` and
+    /// queries `action_prob` for each action.
+    fn action_distribution(&self, env: &E, state: &E::State) -> HashMap<E::Action, f32> {
+        env.action_mask(state)
+            .into_iter()
+            .map(|a| {
+                let p = self.action_prob(env, state, &a);
+                (a, p)
+            })
+            .collect()
+    }
+
+    /// Draws an action for `state` according to `action_distribution`.
+    fn sample(&self, env: &E, state: &E::State, rng: &mut StdRng) -> E::Action {
+        let mut distribution: Vec<(E::Action, f32)> =
+            self.action_distribution(env, state).into_iter().collect();
+        // Walk the actions in a fixed order, so a seeded `rng` always
+        // gives the same draws.
+        distribution.sort_by_cached_key(|(a, _)| stable_hash(a));
+        let mut u: f32 = rng.gen();
+        let mut last = None;
+        for (a, p) in distribution {
+            if u < p {
+                return a;
+            }
+            u -= p;
+            last = Some(a);
+        }
+        // Rounding can leave a little mass unaccounted for; fall back
+        // to the last action seen.
+        last.expect("no actions available from state")
+    }
+}
+
+/// How to choose between equally good actions.
+///
+/// `actions_from` returns a `HashSet`, whose iteration order changes
+/// from run to run, so none of these depend on it. `First` and `Last`
+/// instead refer to a fixed order of the actions by their FNV-1a
+/// hash. The order is arbitrary, but since FNV-1a is fully specified
+/// it depends only on the actions' `Hash` impls, not on the platform
+/// or the release of Rust.
+#[derive(Default)]
+pub enum TieBreak<A> {
+    /// The first tied action in the fixed order.
+    #[default]
+    First,
+    /// The last tied action in the fixed order.
+    Last,
+    /// A tied action drawn pseudo-randomly from the seed and the
+    /// state, so the same state always gets the same choice.
+    Random(u64),
+    /// The smallest tied action under the given ordering.
+    Lowest(fn(&A, &A) -> Ordering),
+}
+
+impl<A: Ord> TieBreak<A> {
+    /// Breaks ties in favour of the smallest action under `Ord`.
+    pub fn lowest() -> Self {
+        TieBreak::Lowest(A::cmp)
+    }
+}
+
+// Derived impls would needlessly require `A: Clone`.
+impl<A> Clone for TieBreak<A> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<A> Copy for TieBreak<A> {}
+
+impl<A: Hash> TieBreak<A> {
+    /// Chooses one of the actions `tied` for the best in `state`, or
+    /// `None` if there are none.
+    pub fn choose<S: Hash>(&self, state: &S, tied: Vec<A>) -> Option<A> {
+        match self {
+            TieBreak::First => tied.into_iter().min_by_key(stable_hash),
+            TieBreak::Last => tied.into_iter().max_by_key(stable_hash),
+            TieBreak::Random(seed) => {
+                let state = stable_hash(state);
+                tied.into_iter()
+                    .min_by_key(|a| stable_hash(&(seed, state, stable_hash(a))))
+            }
+            TieBreak::Lowest(cmp) => tied.into_iter().min_by(cmp),
+        }
+    }
+}
+
+/// The 64-bit FNV-1a hash of `x`.
+///
+/// Unlike a `HashSet`'s randomly keyed hasher, or std's
+/// `DefaultHasher`, whose algorithm may change between releases, this
+/// depends only on the bytes `x`'s `Hash` impl writes.
+pub(crate) fn stable_hash<T: Hash>(x: &T) -> u64 {
+    let mut hasher = Fnv1a::default();
+    x.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// The FNV-1a hash, writing integers as little-endian bytes, and
+/// `usize`s as `u64`s, so that hashes agree across platforms.
+pub(crate) struct Fnv1a(u64);
+
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Fnv1a(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for Fnv1a {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.0 ^= u64::from(*b);
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    fn write_u16(&mut self, i: u16) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u32(&mut self, i: u32) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u128(&mut self, i: u128) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_usize(&mut self, i: usize) {
+        self.write_u64(i as u64);
+    }
+}
+
+/// `items` in the fixed order used by `TieBreak::First`, so that
+/// picking from them by index is reproducible.
+pub(crate) fn fixed_order<T: Hash>(items: impl IntoIterator<Item = T>) -> Vec<T> {
+    let mut items: Vec<T> = items.into_iter().collect();
+    items.sort_by_cached_key(stable_hash);
+    items
+}
+
+/// A policy that always takes one fixed action in each state.
+pub struct DeterministicPolicy<E: IEnvironment> {
+    actions: HashMap<E::State, E::Action>,
+}
+
+impl<E: IEnvironment> DeterministicPolicy<E> {
+    pub fn new(actions: HashMap<E::State, E::Action>) -> Self {
+        DeterministicPolicy { actions }
+    }
+
+    /// The action taken in `state`, if the policy defines one.
+    pub fn action(&self, state: &E::State) -> Option<&E::Action> {
+        self.actions.get(state)
+    }
+
+    /// The state-to-action map defining the policy.
+    pub fn into_actions(self) -> HashMap<E::State, E::Action> {
+        self.actions
+    }
+}
+
+/// Serialized as a sequence of `(state, action)` pairs, since states
+/// need not serialize as map keys.
+#[cfg(feature = "serde")]
+impl<E: IEnvironment> serde::Serialize for DeterministicPolicy<E>
+where
+    E::State: serde::Serialize,
+    E::Action: serde::Serialize,
+{
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(&self.actions)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, E: IEnvironment> serde::Deserialize<'de> for DeterministicPolicy<E>
+where
+    E::State: serde::Deserialize<'de>,
+    E::Action: serde::Deserialize<'de>,
+{
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let pairs: Vec<(E::State, E::Action)> = serde::Deserialize::deserialize(deserializer)?;
+        Ok(DeterministicPolicy::new(pairs.into_iter().collect()))
+    }
+}
+
+impl<E: IEnvironment> Policy<E> for DeterministicPolicy<E> {
+    fn action_prob(&self, _: &E, state: &E::State, action: &E::Action) -> f32 {
+        match self.actions.get(state) {
+            Some(a) if a == action => 1.0,
+            _ => 0.0,
+        }
+    }
+
+    /// Returns the stored action for `state`.
+    ///
+    /// Panics if the policy has no action for `state`.
+    fn sample(&self, _: &E, state: &E::State, _: &mut StdRng) -> E::Action {
+        self.action(state)
+            .cloned()
+            .expect("no action defined for state")
+    }
+}
+
+/// A policy that draws from a fixed distribution over actions in each
+/// state.
+pub struct TabularStochasticPolicy<E: IEnvironment> {
+    probs: HashMap<E::State, HashMap<E::Action, f32>>,
+}
+
+impl<E: IEnvironment> TabularStochasticPolicy<E> {
+    pub fn new(probs: HashMap<E::State, HashMap<E::Action, f32>>) -> Self {
+        TabularStochasticPolicy { probs }
+    }
+
+    /// The distribution over actions in `state`, if the policy defines
+    /// one.
+    pub fn distribution(&self, state: &E::State) -> Option<&HashMap<E::Action, f32>> {
+        self.probs.get(state)
+    }
+
+    /// The state-to-distribution map defining the policy.
+    pub fn into_probs(self) -> HashMap<E::State, HashMap<E::Action, f32>> {
+        self.probs
+    }
+}
+
+impl<E: IEnvironment> Policy<E> for TabularStochasticPolicy<E> {
+    /// The stored probability, or 0 for actions and states the policy
+    /// doesn't list.
+    fn action_prob(&self, _: &E, state: &E::State, action: &E::Action) -> f32 {
+        self.distribution(state)
+            .and_then(|d| d.get(action))
+            .copied()
+            .unwrap_or(0.0)
+    }
+
+    /// The stored distribution, which is empty for states the policy
+    /// doesn't list.
+    fn action_distribution(&self, _: &E, state: &E::State) -> HashMap<E::Action, f32> {
+        self.distribution(state).cloned().unwrap_or_default()
+    }
+}
+
+/// The epsilon-soft improvement of `q`: in each non-terminal state of
+/// `env.all_states()`, probability `1 - epsilon + epsilon / |A|` for
+/// the greedy action of `QTable::greedy_action` and `epsilon / |A|`
+/// for each other available action.
+///
+/// Every available action keeps some probability, so on-policy
+/// control improving its policy this way never stops exploring.
+/// States without actions are left out.
+pub fn epsilon_soft_improve<E: IEnvironment>(
+    env: &E,
+    q: &QTable<E>,
+    epsilon: f32,
+) -> TabularStochasticPolicy<E> {
+    let mut probs = HashMap::new();
+    for s in env.all_states() {
+        let Some(greedy) = q.greedy_action(env, &s) else {
+            continue;
+        };
+        let actions = env.action_mask(&s);
+        let uniform = epsilon / actions.len() as f32;
+        let distribution = actions
+            .into_iter()
+            .map(|a| {
+                let p = if a == greedy {
+                    1.0 - epsilon + uniform
+                } else {
+                    uniform
+                };
+                (a, p)
+            })
+            .collect();
+        probs.insert(s, distribution);
+    }
+    TabularStochasticPolicy::new(probs)
+}
+
+/// The Shannon entropy, in nats, of `policy`'s action distribution in
+/// each of `states`: `-sum_a pi(a|s) ln pi(a|s)`, for watching a
+/// stochastic policy's exploration collapse as it trains.
+///
+/// Actions of probability 0 contribute 0, the limit of `p ln p`, so a
+/// deterministic policy, or a state without actions, has entropy 0,
+/// and a uniform choice among `k` actions has `ln k`, the most there
+/// can be.
+pub fn policy_entropy<E, P>(
+    env: &E,
+    policy: &P,
+    states: &HashSet<E::State>,
+) -> HashMap<E::State, f32>
+where
+    E: IEnvironment,
+    P: Policy<E>,
+{
+    states
+        .iter()
+        .map(|s| {
+            let entropy = policy
+                .action_distribution(env, s)
+                .values()
+                .filter(|p| **p > 0.0)
+                .map(|p| -p * p.ln())
+                .sum::<f32>();
+            (s.clone(), entropy.max(0.0))
+        })
+        .collect()
+}
+
+/// The mean of the entropies `policy_entropy` gives, or `None` if there
+/// are none.
+pub fn mean_entropy<S>(entropy: &HashMap<S, f32>) -> Option<f32> {
+    if entropy.is_empty() {
+        return None;
+    }
+    Some(entropy.values().sum::<f32>() / entropy.len() as f32)
+}
+
+/// A policy that takes an action drawn uniformly from those available
+/// in each state; a baseline to compare learned policies against.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UniformRandomPolicy;
+
+impl<E: IEnvironment> Policy<E> for UniformRandomPolicy {
+    /// `1 / |A(state)|` for each available action, and 0 otherwise.
+    fn action_prob(&self, env: &E, state: &E::State, action: &E::Action) -> f32 {
+        let actions = env.action_mask(state);
+        if actions.contains(action) {
+            1.0 / actions.len() as f32
+        } else {
+            0.0
+        }
+    }
+
+    fn sample(&self, env: &E, state: &E::State, rng: &mut StdRng) -> E::Action {
+        uniform_action(env, state, rng)
+    }
+}
+
+/// Draws one of the actions available from `state` uniformly.
+///
+/// Panics if there are none.
+fn uniform_action<E: IEnvironment>(env: &E, state: &E::State, rng: &mut StdRng) -> E::Action {
+    let mut actions = fixed_order(env.action_mask(state));
+    assert!(!actions.is_empty(), "no actions available from state");
+    let i = rng.gen_range(0..actions.len());
+    actions.swap_remove(i)
+}
+
+/// A policy that takes the same action in every state.
+///
+/// In states where that action isn't available, it falls back to
+/// `UniformRandomPolicy`, so it can still be run in environments
+/// whose actions vary by state.
+#[derive(Debug, Clone)]
+pub struct ConstantPolicy<A> {
+    action: A,
+}
+
+impl<A> ConstantPolicy<A> {
+    pub fn new(action: A) -> Self {
+        ConstantPolicy { action }
+    }
+
+    pub fn action(&self) -> &A {
+        &self.action
+    }
+}
+
+impl<E: IEnvironment> Policy<E> for ConstantPolicy<E::Action> {
+    fn action_prob(&self, env: &E, state: &E::State, action: &E::Action) -> f32 {
+        let actions = env.action_mask(state);
+        if actions.contains(&self.action) {
+            if *action == self.action {
+                1.0
+            } else {
+                0.0
+            }
+        } else {
+            UniformRandomPolicy.action_prob(env, state, action)
+        }
+    }
+
+    fn sample(&self, env: &E, state: &E::State, rng: &mut StdRng) -> E::Action {
+        if env.action_mask(state).contains(&self.action) {
+            self.action.clone()
+        } else {
+            uniform_action(env, state, rng)
+        }
+    }
+}
+
+/// Wraps an inner policy, taking a uniformly random available action
+/// with probability `epsilon` and deferring to the inner policy
+/// otherwise.
+///
+/// `epsilon` may follow a schedule over training steps: see
+/// `with_schedule` and `set_step`.
+pub struct EpsilonGreedy<E: IEnvironment, P: Policy<E>> {
+    inner: P,
+    epsilon: f32,
+    epsilon_at: Option<Box<dyn Fn(usize) -> f32>>,
+    _env: PhantomData<E>,
+}
+
+impl<E: IEnvironment, P: Policy<E>> EpsilonGreedy<E, P> {
+    /// Explores with a fixed probability `epsilon`.
+    pub fn new(inner: P, epsilon: f32) -> Self {
+        EpsilonGreedy {
+            inner,
+            epsilon,
+            epsilon_at: None,
+            _env: PhantomData,
+        }
+    }
+
+    /// Explores with probability `epsilon_at(step)`, starting from
+    /// step 0.
+    pub fn with_schedule(inner: P, epsilon_at: impl Fn(usize) -> f32 + 'static) -> Self {
+        EpsilonGreedy {
+            inner,
+            epsilon: epsilon_at(0),
+            epsilon_at: Some(Box::new(epsilon_at)),
+            _env: PhantomData,
+        }
+    }
+
+    /// Moves the schedule to `step`. Has no effect on a fixed
+    /// `epsilon`.
+    pub fn set_step(&mut self, step: usize) {
+        if let Some(epsilon_at) = &self.epsilon_at {
+            self.epsilon = epsilon_at(step);
+        }
+    }
+
+    pub fn epsilon(&self) -> f32 {
+        self.epsilon
+    }
+
+    pub fn inner(&self) -> &P {
+        &self.inner
+    }
+}
+
+impl<E: IEnvironment, P: Policy<E>> Policy<E> for EpsilonGreedy<E, P> {
+    /// The mixture `(1 - epsilon) * inner + epsilon / |A(state)|`, so
+    /// the inner policy's action also receives its share of the
+    /// uniform exploration mass.
+    fn action_prob(&self, env: &E, state: &E::State, action: &E::Action) -> f32 {
+        let actions = env.action_mask(state);
+        if !actions.contains(action) {
+            return 0.0;
+        }
+        (1.0 - self.epsilon) * self.inner.action_prob(env, state, action)
+            + self.epsilon / actions.len() as f32
+    }
+
+    fn sample(&self, env: &E, state: &E::State, rng: &mut StdRng) -> E::Action {
+        if rng.gen::<f32>() < self.epsilon {
+            uniform_action(env, state, rng)
+        } else {
+            self.inner.sample(env, state, rng)
+        }
+    }
+}
+
+/// A Boltzmann policy over a Q-table: each available action is taken
+/// with probability proportional to `exp(Q(s, a) / tau)`.
+///
+/// Missing Q-values count as 0. The maximum Q-value is subtracted
+/// before exponentiating, so large Q-values don't overflow. The
+/// temperature `tau` trades off exploration: as `tau -> 0` the policy
+/// becomes greedy, and `tau <= 0` is taken to mean exactly greedy,
+/// splitting the probability evenly between tied best actions.
+pub struct Softmax<E: IEnvironment> {
+    q: QTable<E>,
+    tau: f32,
+}
+
+impl<E: IEnvironment> Softmax<E> {
+    pub fn new(q: QTable<E>, tau: f32) -> Self {
+        Softmax { q, tau }
+    }
+
+    pub fn tau(&self) -> f32 {
+        self.tau
+    }
+
+    pub fn q(&self) -> &QTable<E> {
+        &self.q
+    }
+
+    fn weights(&self, env: &E, state: &E::State) -> Vec<(E::Action, f32)> {
+        softmax_weights(env, &self.q, state, self.tau)
+    }
+}
+
+/// The unnormalized Boltzmann weight of each action available from
+/// `state` under `q` at temperature `tau`, with the best action at
+/// weight 1.
+fn softmax_weights<E: IEnvironment>(
+    env: &E,
+    q: &QTable<E>,
+    state: &E::State,
+    tau: f32,
+) -> Vec<(E::Action, f32)> {
+    let values: Vec<(E::Action, f32)> = fixed_order(env.action_mask(state))
+        .into_iter()
+        .map(|a| {
+            let q = q.get(state, &a);
+            (a, q)
+        })
+        .collect();
+    let max = values
+        .iter()
+        .map(|(_, q)| *q)
+        .fold(f32::NEG_INFINITY, f32::max);
+    values
+        .into_iter()
+        .map(|(a, q)| {
+            let w = if tau > 0.0 {
+                ((q - max) / tau).exp()
+            } else if q == max {
+                1.0
+            } else {
+                0.0
+            };
+            (a, w)
+        })
+        .collect()
+}
+
+impl<E: IEnvironment> Policy<E> for Softmax<E> {
+    fn action_prob(&self, env: &E, state: &E::State, action: &E::Action) -> f32 {
+        let weights = self.weights(env, state);
+        let total: f32 = weights.iter().map(|(_, w)| w).sum();
+        weights
+            .into_iter()
+            .find(|(a, _)| a == action)
+            .map_or(0.0, |(_, w)| w / total)
+    }
+
+    fn action_distribution(&self, env: &E, state: &E::State) -> HashMap<E::Action, f32> {
+        let weights = self.weights(env, state);
+        let total: f32 = weights.iter().map(|(_, w)| w).sum();
+        weights.into_iter().map(|(a, w)| (a, w / total)).collect()
+    }
+
+    fn sample(&self, env: &E, state: &E::State, rng: &mut StdRng) -> E::Action {
+        sample_weighted(self.weights(env, state), rng).expect("no actions available from state")
+    }
+}
+
+/// How an `ActionValuePolicy` chooses actions from its Q-table.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Selection {
+    /// The greedy action, ties going to the first in a fixed order,
+    /// as `QTable::greedy_action`.
+    Greedy,
+    /// A uniformly random action with the given probability, and the
+    /// greedy action otherwise, as in the temporal-difference learners.
+    EpsilonGreedy(f32),
+    /// A Boltzmann distribution at the given temperature, as `Softmax`.
+    Softmax(f32),
+}
+
+/// A policy read directly off a Q-table by a selection rule, so that
+/// it can be evaluated under the same rule it was learned with.
+///
+/// Missing Q-values count as 0.
+pub struct ActionValuePolicy<E: IEnvironment> {
+    q: QTable<E>,
+    selection: Selection,
+}
+
+impl<E: IEnvironment> ActionValuePolicy<E> {
+    pub fn new(q: QTable<E>, selection: Selection) -> Self {
+        ActionValuePolicy { q, selection }
+    }
+
+    pub fn q(&self) -> &QTable<E> {
+        &self.q
+    }
+
+    pub fn selection(&self) -> Selection {
+        self.selection
+    }
+}
+
+impl<E: IEnvironment> Policy<E> for ActionValuePolicy<E> {
+    fn action_prob(&self, env: &E, state: &E::State, action: &E::Action) -> f32 {
+        let greedy = |epsilon: f32| {
+            let actions = env.action_mask(state);
+            if !actions.contains(action) {
+                return 0.0;
+            }
+            let exploit = if self.q.greedy_action(env, state).as_ref() == Some(action) {
+                1.0 - epsilon
+            } else {
+                0.0
+            };
+            exploit + epsilon / actions.len() as f32
+        };
+        match self.selection {
+            Selection::Greedy => greedy(0.0),
+            Selection::EpsilonGreedy(epsilon) => greedy(epsilon),
+            Selection::Softmax(tau) => {
+                let weights = softmax_weights(env, &self.q, state, tau);
+                let total: f32 = weights.iter().map(|(_, w)| w).sum();
+                weights
+                    .into_iter()
+                    .find(|(a, _)| a == action)
+                    .map_or(0.0, |(_, w)| w / total)
+            }
+        }
+    }
+
+    fn sample(&self, env: &E, state: &E::State, rng: &mut StdRng) -> E::Action {
+        let greedy = || {
+            self.q
+                .greedy_action(env, state)
+                .expect("no actions available from state")
+        };
+        match self.selection {
+            Selection::Greedy => greedy(),
+            Selection::EpsilonGreedy(epsilon) => {
+                if rng.gen::<f32>() < epsilon {
+                    uniform_action(env, state, rng)
+                } else {
+                    greedy()
+                }
+            }
+            Selection::Softmax(tau) => {
+                sample_weighted(softmax_weights(env, &self.q, state, tau), rng)
+                    .expect("no actions available from state")
+            }
+        }
+    }
+}
+
+/// A blend of several policies: each decision is made by one of them,
+/// chosen at random in proportion to its weight.
+///
+/// The weights are normalized to sum to 1 on construction, so only
+/// their ratios matter.
+pub struct MixturePolicy<E: IEnvironment> {
+    components: Vec<(Box<dyn Policy<E>>, f32)>,
+}
+
+impl<E: IEnvironment> MixturePolicy<E> {
+    /// Panics if a weight is negative or the weights sum to 0.
+    pub fn new(components: Vec<(Box<dyn Policy<E>>, f32)>) -> Self {
+        assert!(
+            components.iter().all(|(_, w)| *w >= 0.0),
+            "mixture weights must not be negative"
+        );
+        let total: f32 = components.iter().map(|(_, w)| w).sum();
+        assert!(total > 0.0, "mixture weights must not all be 0");
+        let components = components
+            .into_iter()
+            .map(|(policy, w)| (policy, w / total))
+            .collect();
+        MixturePolicy { components }
+    }
+
+    /// The component policies and their normalized weights.
+    pub fn components(&self) -> &[(Box<dyn Policy<E>>, f32)] {
+        &self.components
+    }
+}
+
+impl<E: IEnvironment> Policy<E> for MixturePolicy<E> {
+    /// The components' probabilities of `action`, weighted.
+    fn action_prob(&self, env: &E, state: &E::State, action: &E::Action) -> f32 {
+        self.components
+            .iter()
+            .map(|(policy, w)| w * policy.action_prob(env, state, action))
+            .sum()
+    }
+
+    fn action_distribution(&self, env: &E, state: &E::State) -> HashMap<E::Action, f32> {
+        let mut mixed = HashMap::new();
+        for (policy, w) in &self.components {
+            for (a, p) in policy.action_distribution(env, state) {
+                *mixed.entry(a).or_insert(0.0) += w * p;
+            }
+        }
+        mixed
+    }
+
+    /// Picks a component by weight, then samples from it.
+    fn sample(&self, env: &E, state: &E::State, rng: &mut StdRng) -> E::Action {
+        let weighted = self.components.iter().map(|(p, w)| (p, *w)).collect();
+        let policy = sample_weighted(weighted, rng).expect("mixture weights sum to 1");
+        policy.sample(env, state, rng)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dp;
+    use crate::rng::make_rng;
+    use crate::testing::{values_approx_eq, Chain, Missing};
+
+    /// From state 0, each of eight actions ends the episode in state
+    /// 1 with the same reward, so every action is tied.
+    struct Ties;
+
+    impl IEnvironment for Ties {
+        type State = u8;
+        type Action = u8;
+
+        fn prob(&self, from: &u8, _: &u8, to: &u8, with: &f32) -> f32 {
+            if *from == 0 && *to == 1 && *with == 1.0 {
+                1.0
+            } else {
+                0.0
+            }
+        }
+
+        fn actions_from(&self, _: &u8) -> HashSet<u8> {
+            (0..8).collect()
+        }
+
+        fn states_from(&self, _: &u8, _: &u8) -> HashSet<u8> {
+            HashSet::from([1])
+        }
+
+        fn is_terminal(&self, state: &u8) -> bool {
+            *state == 1
+        }
+
+        fn start_states(&self) -> HashSet<u8> {
+            HashSet::from([0])
+        }
+
+        fn rewards(&self) -> Vec<f32> {
+            vec![1.0]
+        }
+    }
+
+    /// `Chain`, but with moving left masked out in state 1, and every
+    /// action in state 2 if `trapped`.
+    struct Masked {
+        trapped: bool,
+    }
+
+    impl IEnvironment for Masked {
+        type State = i32;
+        type Action = i32;
+
+        fn prob(&self, from: &i32, take: &i32, to: &i32, with: &f32) -> f32 {
+            Chain.prob(from, take, to, with)
+        }
+
+        fn actions_from(&self, from: &i32) -> HashSet<i32> {
+            Chain.actions_from(from)
+        }
+
+        fn action_mask(&self, from: &i32) -> HashSet<i32> {
+            match from {
+                1 => HashSet::from([1]),
+                2 if self.trapped => HashSet::new(),
+                _ => Chain.actions_from(from),
+            }
+        }
+
+        fn states_from(&self, from: &i32, take: &i32) -> HashSet<i32> {
+            Chain.states_from(from, take)
+        }
+
+        fn is_terminal(&self, state: &i32) -> bool {
+            Chain.is_terminal(state)
+        }
+
+        fn start_states(&self) -> HashSet<i32> {
+            Chain.start_states()
+        }
+
+        fn rewards(&self) -> Vec<f32> {
+            Chain.rewards()
+        }
+    }
+
+    #[test]
+    fn entropy_is_largest_for_uniform_choices() {
+        let states = HashSet::from([0]);
+        let uniform = policy_entropy(&Ties, &UniformRandomPolicy, &states);
+        assert!((uniform[&0] - 8f32.ln()).abs() < 1e-6);
+        let constant = policy_entropy(&Ties, &ConstantPolicy::new(3), &states);
+        assert_eq!(constant[&0], 0.0);
+
+        // Impossible actions add nothing, rather than NaN.
+        let skewed = TabularStochasticPolicy::<Chain>::new(HashMap::from([
+            (0, HashMap::from([(1, 1.0), (-1, 0.0)])),
+            (1, HashMap::from([(1, 0.5), (-1, 0.5)])),
+        ]));
+        let entropy = policy_entropy(&Chain, &skewed, &HashSet::from([0, 1]));
+        assert_eq!(entropy[&0], 0.0);
+        assert!((entropy[&1] - 2f32.ln()).abs() < 1e-6);
+        assert!((mean_entropy(&entropy).unwrap() - 2f32.ln() / 2.0).abs() < 1e-6);
+        assert_eq!(mean_entropy::<i32>(&HashMap::new()), None);
+    }
+
+    #[test]
+    fn epsilon_soft_improvement_keeps_every_action() {
+        let q: QTable<Chain> = [((0, 1), 1.0), ((1, -1), 2.0)].into_iter().collect();
+        let epsilon = 0.2;
+        let policy = epsilon_soft_improve(&Chain, &q, epsilon);
+        let (greedy, other) = (1.0 - epsilon + epsilon / 2.0, epsilon / 2.0);
+        assert_eq!(policy.action_prob(&Chain, &0, &1), greedy);
+        assert_eq!(policy.action_prob(&Chain, &0, &-1), other);
+        assert_eq!(policy.action_prob(&Chain, &1, &-1), greedy);
+        assert_eq!(policy.action_prob(&Chain, &1, &1), other);
+        assert!(policy.distribution(&Chain::GOAL).is_none());
+    }
+
+    #[test]
+    fn an_even_mixture_splits_between_its_components() {
+        let mixture: MixturePolicy<Chain> = MixturePolicy::new(vec![
+            (
+                Box::new(DeterministicPolicy::new(HashMap::from([(1, 1)]))),
+                2.0,
+            ),
+            (
+                Box::new(DeterministicPolicy::new(HashMap::from([(1, -1)]))),
+                2.0,
+            ),
+        ]);
+        assert_eq!(mixture.components()[0].1, 0.5);
+        assert_eq!(mixture.action_prob(&Chain, &1, &1), 0.5);
+        assert_eq!(
+            mixture.action_distribution(&Chain, &1),
+            HashMap::from([(1, 0.5), (-1, 0.5)])
+        );
+        let mut rng = make_rng(0);
+        let draws = 10_000;
+        let rights = (0..draws)
+            .filter(|_| mixture.sample(&Chain, &1, &mut rng) == 1)
+            .count();
+        assert!((rights as f32 / draws as f32 - 0.5).abs() < 0.02);
+    }
+
+    #[test]
+    fn masked_actions_are_never_taken() {
+        let env = Masked { trapped: false };
+        // Q-values that would rather move left from 1.
+        let q: QTable<Masked> = [((1, -1), 5.0), ((1, 1), 0.0)].into_iter().collect();
+        let policies: Vec<Box<dyn Policy<Masked>>> = vec![
+            Box::new(UniformRandomPolicy),
+            Box::new(ConstantPolicy::new(-1)),
+            Box::new(EpsilonGreedy::new(ConstantPolicy::new(-1), 0.5)),
+            Box::new(Softmax::new(q.clone(), 1.0)),
+            Box::new(ActionValuePolicy::new(
+                q.clone(),
+                Selection::EpsilonGreedy(0.5),
+            )),
+        ];
+        let mut rng = make_rng(0);
+        for policy in &policies {
+            assert_eq!(policy.action_prob(&env, &1, &-1), 0.0);
+            assert_eq!(
+                policy.action_distribution(&env, &1),
+                HashMap::from([(1, 1.0)])
+            );
+            for _ in 0..100 {
+                assert_eq!(policy.sample(&env, &1, &mut rng), 1);
+            }
+        }
+        assert_eq!(q.greedy_action(&env, &1), Some(1));
+        assert_eq!(q.max_action_value(&env, &1), 0.0);
+        assert_eq!(crate::validate::validate_masks(&env), Ok(()));
+
+        let (_, policy) = dp::value_iteration(&env, Some(0.9), 1e-6, 100).unwrap();
+        assert_eq!(policy.action(&1), Some(&1));
+    }
+
+    #[test]
+    fn masking_out_every_action_is_an_error() {
+        let env = Masked { trapped: true };
+        assert_eq!(crate::validate::validate_masks(&env), Err(2));
+        // Unlike a trap, whose actions_from is empty too.
+        assert_eq!(
+            crate::validate::validate_masks(&crate::testing::Trap),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn first_gives_identical_policies_despite_ties() {
+        let choose = || {
+            let (_, policy) = dp::value_iteration(&Ties, None, 1e-6, 10).unwrap();
+            *policy.action(&0).unwrap()
+        };
+        let first = choose();
+        for _ in 0..20 {
+            assert_eq!(choose(), first);
+        }
+    }
+
+    #[test]
+    fn choices_ignore_the_order_of_the_ties() {
+        let forward: Vec<u8> = (0..8).collect();
+        let backward: Vec<u8> = (0..8).rev().collect();
+        for tie in [TieBreak::First, TieBreak::Last, TieBreak::Random(3)] {
+            assert_eq!(
+                tie.choose(&0, forward.clone()),
+                tie.choose(&0, backward.clone())
+            );
+        }
+        assert_eq!(TieBreak::lowest().choose(&0, backward), Some(0));
+        assert_eq!(TieBreak::<u8>::First.choose(&0, vec![]), None);
+    }
+
+    #[test]
+    fn fixed_order_uses_fnv1a() {
+        let mut hasher = Fnv1a::default();
+        hasher.write(b"a");
+        assert_eq!(hasher.finish(), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(fixed_order([3u8, 1, 2]), fixed_order([2u8, 3, 1]));
+    }
+
+    #[test]
+    fn uniform_random_spreads_over_available_actions() {
+        assert_eq!(UniformRandomPolicy.action_prob(&Ties, &0, &3), 0.125);
+        assert_eq!(UniformRandomPolicy.action_prob(&Ties, &0, &8), 0.0);
+
+        let mut rng = make_rng(0);
+        let drawn: HashSet<u8> = (0..200)
+            .map(|_| UniformRandomPolicy.sample(&Ties, &0, &mut rng))
+            .collect();
+        assert_eq!(drawn, (0..8).collect());
+    }
+
+    #[test]
+    fn constant_policy_falls_back_to_uniform_when_unavailable() {
+        let right = ConstantPolicy::new(1);
+        assert_eq!(right.action_prob(&Chain, &0, &1), 1.0);
+        assert_eq!(right.action_prob(&Chain, &0, &-1), 0.0);
+        assert_eq!(right.sample(&Chain, &2, &mut make_rng(0)), 1);
+
+        let missing = ConstantPolicy::new(5);
+        assert_eq!(missing.action_prob(&Chain, &0, &5), 0.0);
+        assert_eq!(missing.action_prob(&Chain, &0, &-1), 0.5);
+        let mut rng = make_rng(0);
+        let drawn: HashSet<i32> = (0..100)
+            .map(|_| missing.sample(&Chain, &0, &mut rng))
+            .collect();
+        assert_eq!(drawn, HashSet::from([-1, 1]));
+    }
+
+    #[test]
+    fn epsilon_greedy_mixes_in_uniform_exploration() {
+        let right = DeterministicPolicy::<Chain>::new(HashMap::from([(0, 1)]));
+        let policy = EpsilonGreedy::new(right, 0.2);
+        let distribution = policy.action_distribution(&Chain, &0);
+        assert!((distribution.values().sum::<f32>() - 1.0).abs() < 1e-6);
+        assert!((distribution[&1] - 0.9).abs() < 1e-6);
+        assert!((distribution[&-1] - 0.1).abs() < 1e-6);
+        assert_eq!(policy.action_prob(&Chain, &0, &5), 0.0);
+
+        let mut rng = make_rng(0);
+        let draws = 10_000;
+        let lefts = (0..draws)
+            .filter(|_| policy.sample(&Chain, &0, &mut rng) == -1)
+            .count();
+        assert!((lefts as f32 / draws as f32 - 0.1).abs() < 0.01);
+    }
+
+    #[test]
+    fn softmax_stays_finite_for_large_q_values() {
+        let q: QTable<Chain> = [((0, 1), 1001.0), ((0, -1), 1000.0)].into_iter().collect();
+        let policy = Softmax::new(q, 1.0);
+        let right = policy.action_prob(&Chain, &0, &1);
+        let left = policy.action_prob(&Chain, &0, &-1);
+        assert!(right.is_finite() && left.is_finite());
+        // exp(1) / (exp(1) + 1)
+        assert!((right - 0.731_058_6).abs() < 1e-5);
+        assert!((right + left - 1.0).abs() < 1e-6);
+
+        let mut rng = make_rng(0);
+        let draws = 10_000;
+        let rights = (0..draws)
+            .filter(|_| policy.sample(&Chain, &0, &mut rng) == 1)
+            .count();
+        assert!((rights as f32 / draws as f32 - right).abs() < 0.02);
+    }
+
+    #[test]
+    fn cold_softmax_is_greedy() {
+        let q: QTable<Chain> = [((0, 1), 1.0), ((0, -1), 0.5)].into_iter().collect();
+        let cold = Softmax::new(q.clone(), 1e-3);
+        assert_eq!(cold.action_prob(&Chain, &0, &1), 1.0);
+        let greedy = Softmax::new(q, 0.0);
+        assert_eq!(greedy.action_prob(&Chain, &0, &1), 1.0);
+        assert_eq!(greedy.action_prob(&Chain, &0, &-1), 0.0);
+        // Unseen actions count as 0, so from state 1 both are tied.
+        assert_eq!(greedy.action_prob(&Chain, &1, &1), 0.5);
+    }
+
+    #[test]
+    fn action_value_policies_follow_their_selection_rule() {
+        let (v, _) = dp::value_iteration(&Chain, Some(0.9), 1e-6, 1000).unwrap();
+        let q = dp::q_from_v(&Chain, &v, Some(0.9)).unwrap();
+
+        let greedy = ActionValuePolicy::new(q.clone(), Selection::Greedy);
+        assert_eq!(greedy.action_prob(&Chain, &0, &1), 1.0);
+        let greedy_v = dp::evaluate_policy(&Chain, &greedy, Some(0.9), 1e-6, 1000).unwrap();
+        assert!(values_approx_eq(&greedy_v, &v, 1e-4, Missing::Mismatch));
+
+        let explore = ActionValuePolicy::new(q.clone(), Selection::EpsilonGreedy(0.2));
+        assert!((explore.action_prob(&Chain, &0, &1) - 0.9).abs() < 1e-6);
+        assert!((explore.action_prob(&Chain, &0, &-1) - 0.1).abs() < 1e-6);
+
+        let boltzmann = ActionValuePolicy::new(q.clone(), Selection::Softmax(0.5));
+        let softmax = Softmax::new(q, 0.5);
+        for a in [-1, 1] {
+            assert_eq!(
+                boltzmann.action_prob(&Chain, &1, &a),
+                softmax.action_prob(&Chain, &1, &a)
+            );
+        }
+    }
+}
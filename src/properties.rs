@@ -0,0 +1,93 @@
+//! Invariants of the numerical core, checked over many randomly
+//! generated MDPs.
+//!
+//! Each property draws its MDPs' sizes and seeds from a fixed seed,
+//! so a failure always reproduces, and reports the case it failed on.
+
+use rand::Rng;
+
+use crate::dp::{evaluate_policy, policy_iteration, value_iteration};
+use crate::env::{random_mdp, IEnvironment, TabularMDP};
+use crate::monte_carlo::mc_predict;
+use crate::policy::UniformRandomPolicy;
+use crate::rng::make_rng;
+use crate::testing::{values_approx_eq, Missing};
+
+/// `cases` random MDPs of up to `max_states` states and 4 actions,
+/// with the parameters each was generated from.
+fn cases(cases: usize, max_states: usize) -> Vec<((usize, usize, usize, u64), TabularMDP)> {
+    let mut rng = make_rng(0);
+    (0..cases)
+        .map(|_| {
+            let states = rng.gen_range(1..=max_states);
+            let actions = rng.gen_range(1..=4);
+            let branching = rng.gen_range(1..=states);
+            let seed = rng.gen();
+            let params = (states, actions, branching, seed);
+            (params, random_mdp(states, actions, branching, seed))
+        })
+        .collect()
+}
+
+#[test]
+fn transition_probabilities_sum_to_one() {
+    for (params, env) in cases(100, 10) {
+        for s in env.all_states() {
+            for a in env.actions_from(&s) {
+                let total: f32 = env
+                    .states_from(&s, &a)
+                    .iter()
+                    .map(|t| env.prob_transition(&s, &a, t).unwrap())
+                    .sum();
+                assert!((total - 1.0).abs() < 1e-5, "{params:?}: {s} {a} {total}");
+            }
+        }
+    }
+}
+
+#[test]
+fn value_and_policy_iteration_agree() {
+    let theta = 1e-6;
+    for (params, env) in cases(50, 8) {
+        let (v, _) = value_iteration(&env, None, theta, 10_000).unwrap();
+        let (w, _, _) = policy_iteration(&env, None, theta, 10_000).unwrap();
+        // Stopping once the sweeps change by less than theta leaves the
+        // values within theta * gamma / (1 - gamma) of their limit.
+        let gamma = env.gamma();
+        let tol = 2.0 * theta * gamma / (1.0 - gamma) + 1e-4;
+        assert!(
+            values_approx_eq(&v, &w, tol, Missing::Mismatch),
+            "{params:?}"
+        );
+    }
+}
+
+#[test]
+fn monte_carlo_converges_to_the_policy_value() {
+    // Every reward is within 1 of 0, so every return is within
+    // 1 / (1 - gamma) of 0, which bounds its standard deviation too.
+    // The mean of `n` returns from the start state is then within six
+    // deviations, `6 / (1 - gamma) / sqrt(n)`, of its expectation but
+    // for a chance of about 1e-9, and the fixed seed fixes the draws
+    // besides. The last state is terminal and reachable from every
+    // state in one step, so episodes are short.
+    for (params, _) in cases(10, 5) {
+        let (states, actions, _, seed) = params;
+        let states = states.max(2);
+        let env = random_mdp(states, actions, states, seed).with_terminals([states - 1]);
+        let v = evaluate_policy(&env, &UniformRandomPolicy, None, 1e-7, 10_000).unwrap();
+        let bound = 6.0 / (1.0 - env.gamma());
+        for episodes in [200, 5000] {
+            let estimate = mc_predict(
+                &env,
+                &UniformRandomPolicy,
+                episodes,
+                None,
+                &mut make_rng(seed),
+            );
+            let tol = bound / (episodes as f32).sqrt();
+            let error = (estimate[&0] - v[&0]).abs();
+            assert!(error < tol, "{params:?} after {episodes}: {error} vs {tol}");
+        }
+    }
+}
@@ -0,0 +1,520 @@
+//! Experience replay: storing past transitions and learning from
+//! them again.
+
+use std::collections::HashMap;
+
+use rand::seq::index;
+use rand::Rng;
+
+use crate::dp::QTable;
+use crate::env::IEnvironment;
+use crate::observer::{NoObserver, TrainingObserver};
+use crate::rng::StdRng;
+use crate::schedule::Schedule;
+use crate::simulate::{self, EPISODE_STEP_LIMIT};
+use crate::td::{epsilon_greedy, update, LearningRate};
+
+/// A single observed step: taking `action` in `state` gave `reward`
+/// and led to `next`, which is terminal if `done`.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Transition<S, A> {
+    pub state: S,
+    pub action: A,
+    pub reward: f32,
+    pub next: S,
+    pub done: bool,
+}
+
+/// A ring buffer of the most recent transitions, up to a fixed
+/// capacity; once full, each new transition overwrites the oldest.
+#[derive(Debug, Clone)]
+pub struct ReplayBuffer<S, A> {
+    capacity: usize,
+    transitions: Vec<Transition<S, A>>,
+    oldest: usize,
+}
+
+impl<S, A> ReplayBuffer<S, A> {
+    pub fn new(capacity: usize) -> Self {
+        ReplayBuffer {
+            capacity,
+            transitions: Vec::with_capacity(capacity),
+            oldest: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.transitions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transitions.is_empty()
+    }
+
+    /// Stores `transition`, evicting the oldest one if the buffer is
+    /// full. A buffer with no capacity stores nothing.
+    pub fn push(&mut self, transition: Transition<S, A>) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.transitions.len() < self.capacity {
+            self.transitions.push(transition);
+        } else {
+            self.transitions[self.oldest] = transition;
+            self.oldest = (self.oldest + 1) % self.capacity;
+        }
+    }
+
+    /// Draws `batch` distinct stored transitions uniformly at random,
+    /// or all of them, in random order, if fewer are stored.
+    pub fn sample(&self, batch: usize, rng: &mut StdRng) -> Vec<&Transition<S, A>> {
+        let n = batch.min(self.transitions.len());
+        index::sample(rng, self.transitions.len(), n)
+            .into_iter()
+            .map(|i| &self.transitions[i])
+            .collect()
+    }
+}
+
+/// A binary tree over `len` leaf values in which each node holds the
+/// sum of its children, so that a leaf can be changed or found by its
+/// cumulative sum in O(log n).
+#[derive(Debug, Clone)]
+struct SumTree {
+    /// The number of leaves, a power of two; `nodes[leaves + i]` is
+    /// leaf `i` and `nodes[1]` the root.
+    leaves: usize,
+    nodes: Vec<f32>,
+}
+
+impl SumTree {
+    fn new(len: usize) -> Self {
+        let leaves = len.max(1).next_power_of_two();
+        SumTree {
+            leaves,
+            nodes: vec![0.0; 2 * leaves],
+        }
+    }
+
+    fn total(&self) -> f32 {
+        self.nodes[1]
+    }
+
+    fn get(&self, i: usize) -> f32 {
+        self.nodes[self.leaves + i]
+    }
+
+    /// Sets leaf `i` to `value`, recomputing its ancestors from their
+    /// children rather than adding the difference, so rounding errors
+    /// don't build up over many updates.
+    fn set(&mut self, i: usize, value: f32) {
+        let mut node = self.leaves + i;
+        self.nodes[node] = value;
+        while node > 1 {
+            node /= 2;
+            self.nodes[node] = self.nodes[2 * node] + self.nodes[2 * node + 1];
+        }
+    }
+
+    /// The leaf at which the cumulative sum of the leaves passes
+    /// `mass`, for `0 <= mass < total()`. Only leaves with positive
+    /// values are found, even if rounding puts `mass` past the total.
+    fn find(&self, mut mass: f32) -> usize {
+        let mut node = 1;
+        while node < self.leaves {
+            let (left, right) = (2 * node, 2 * node + 1);
+            if mass < self.nodes[left] || self.nodes[right] <= 0.0 {
+                node = left;
+            } else {
+                mass -= self.nodes[left];
+                node = right;
+            }
+        }
+        node - self.leaves
+    }
+}
+
+/// A transition drawn from a `PrioritizedReplayBuffer`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PrioritizedSample<'a, S, A> {
+    /// Where the transition is stored, for `update_priorities`.
+    pub index: usize,
+    /// The importance-sampling weight correcting for the transition
+    /// having been drawn non-uniformly.
+    pub weight: f32,
+    pub transition: &'a Transition<S, A>,
+}
+
+/// A `ReplayBuffer` that draws transitions in proportion to a priority
+/// each one is given, such as the magnitude of its last TD error, so
+/// that the most surprising transitions are learned from most often.
+///
+/// Transition `i` with priority `p_i` is drawn with probability
+/// `P(i) = p_i^alpha / sum_k p_k^alpha`: `alpha` of 0 is uniform
+/// sampling, and 1 fully proportional. A priority of 0 counts as 0
+/// whatever `alpha` is, so such a transition is never drawn, even
+/// when sampling uniformly. The priorities are kept in a sum tree, so
+/// sampling and updating are O(log n).
+#[derive(Debug, Clone)]
+pub struct PrioritizedReplayBuffer<S, A> {
+    buffer: ReplayBuffer<S, A>,
+    alpha: f32,
+    /// Holds each stored transition's priority raised to `alpha`.
+    tree: SumTree,
+    priorities: Vec<f32>,
+    max_priority: f32,
+}
+
+impl<S, A> PrioritizedReplayBuffer<S, A> {
+    pub fn new(capacity: usize, alpha: f32) -> Self {
+        PrioritizedReplayBuffer {
+            buffer: ReplayBuffer::new(capacity),
+            alpha,
+            tree: SumTree::new(capacity),
+            priorities: Vec::with_capacity(capacity),
+            max_priority: 1.0,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.buffer.capacity()
+    }
+
+    pub fn len(&self) -> usize {
+        self.buffer.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
+    pub fn alpha(&self) -> f32 {
+        self.alpha
+    }
+
+    /// The priority of the transition stored at `index`.
+    pub fn priority(&self, index: usize) -> f32 {
+        self.priorities[index]
+    }
+
+    /// Stores `transition` as `ReplayBuffer::push` does, with the
+    /// highest priority given so far, or 1 if none has been, so that
+    /// it is likely to be drawn at least once before its priority is
+    /// updated.
+    pub fn push(&mut self, transition: Transition<S, A>) {
+        if self.buffer.capacity == 0 {
+            return;
+        }
+        let index = if self.buffer.len() < self.buffer.capacity {
+            self.priorities.push(0.0);
+            self.buffer.len()
+        } else {
+            self.buffer.oldest
+        };
+        self.buffer.push(transition);
+        self.set_priority(index, self.max_priority);
+    }
+
+    /// Replaces the priorities of the transitions stored at `indices`,
+    /// typically the `index` of each sample, with `priorities`, such as
+    /// the magnitudes of their fresh TD errors. A transition with
+    /// priority 0 is never drawn, so it's usual to add a small constant
+    /// to TD errors first.
+    ///
+    /// Panics if `indices` and `priorities` differ in length, or a
+    /// priority is negative or NaN.
+    pub fn update_priorities(&mut self, indices: &[usize], priorities: &[f32]) {
+        assert_eq!(
+            indices.len(),
+            priorities.len(),
+            "each index needs one priority"
+        );
+        for (&i, &priority) in indices.iter().zip(priorities) {
+            assert!(priority >= 0.0, "priorities must be non-negative");
+            self.set_priority(i, priority);
+            self.max_priority = self.max_priority.max(priority);
+        }
+    }
+
+    fn set_priority(&mut self, index: usize, priority: f32) {
+        self.priorities[index] = priority;
+        // `powf` takes 0 to the power 0 to 1.
+        let leaf = if priority == 0.0 {
+            0.0
+        } else {
+            priority.powf(self.alpha)
+        };
+        self.tree.set(index, leaf);
+    }
+
+    /// Draws `batch` stored transitions, with replacement, in
+    /// proportion to their priorities, each with the importance-sampling
+    /// weight `(1 / (N * P(i)))^beta` for the `N` transitions stored.
+    /// `beta` of 1 fully corrects the bias of prioritized sampling,
+    /// and is usually annealed towards it over training.
+    ///
+    /// Sampling is stratified: the total priority is split into `batch`
+    /// equal segments and one transition drawn from each. Nothing is
+    /// drawn if every priority is 0.
+    pub fn sample(
+        &self,
+        batch: usize,
+        beta: f32,
+        rng: &mut StdRng,
+    ) -> Vec<PrioritizedSample<'_, S, A>> {
+        let total = self.tree.total();
+        if self.is_empty() || total <= 0.0 {
+            return Vec::new();
+        }
+        let segment = total / batch as f32;
+        let n = self.len() as f32;
+        (0..batch)
+            .map(|k| {
+                let mass = (k as f32 + rng.gen::<f32>()) * segment;
+                let index = self.tree.find(mass.min(total));
+                let p = self.tree.get(index) / total;
+                PrioritizedSample {
+                    index,
+                    weight: (n * p).recip().powf(beta),
+                    transition: &self.buffer.transitions[index],
+                }
+            })
+            .collect()
+    }
+}
+
+/// Q-learning with experience replay.
+///
+/// Runs episodes like `td::q_learning`, but rather than updating on
+/// each real step as it happens, stores it in a `ReplayBuffer` of
+/// `capacity` transitions and then applies the Q-learning update to a
+/// minibatch of `batch` transitions drawn from the buffer, so every
+/// real step can be learned from many times. Transitions into a
+/// terminal state use a next value of 0. `gamma` defaults to
+/// `env.gamma()`.
+#[allow(clippy::too_many_arguments)]
+pub fn q_learning_replay<E: IEnvironment>(
+    env: &E,
+    episodes: usize,
+    capacity: usize,
+    batch: usize,
+    alpha: impl Into<LearningRate>,
+    gamma: Option<f32>,
+    epsilon: impl Into<Schedule>,
+    rng: &mut StdRng,
+) -> QTable<E> {
+    q_learning_replay_observed(
+        env,
+        episodes,
+        capacity,
+        batch,
+        alpha,
+        gamma,
+        epsilon,
+        rng,
+        &mut NoObserver,
+    )
+}
+
+/// As `q_learning_replay`, but reporting each episode's total reward
+/// to `observer` as it ends, and stopping training early if it
+/// returns `ControlFlow::Break`.
+#[allow(clippy::too_many_arguments)]
+pub fn q_learning_replay_observed<E, O>(
+    env: &E,
+    episodes: usize,
+    capacity: usize,
+    batch: usize,
+    alpha: impl Into<LearningRate>,
+    gamma: Option<f32>,
+    epsilon: impl Into<Schedule>,
+    rng: &mut StdRng,
+    observer: &mut O,
+) -> QTable<E>
+where
+    E: IEnvironment,
+    O: TrainingObserver<E>,
+{
+    let gamma = gamma.unwrap_or_else(|| env.gamma());
+    let alpha = alpha.into();
+    let epsilon = epsilon.into();
+    let mut q = QTable::<E>::new();
+    let mut visits: HashMap<(E::State, E::Action), usize> = HashMap::new();
+    let mut buffer = ReplayBuffer::new(capacity);
+    let mut steps = 0;
+    for episode in 0..episodes {
+        let Some(mut state) = simulate::sample_start(env, rng) else {
+            break;
+        };
+        let mut total = 0.0;
+        for _ in 0..EPISODE_STEP_LIMIT {
+            if env.is_terminal(&state) {
+                break;
+            }
+            let Some(action) = epsilon_greedy(env, &q, &state, epsilon.value(steps), rng) else {
+                break;
+            };
+            let Some((next, reward)) = env.sample_transition(&state, &action, rng) else {
+                break;
+            };
+            steps += 1;
+            total += reward;
+            buffer.push(Transition {
+                state,
+                action,
+                reward,
+                next: next.clone(),
+                done: env.is_terminal(&next),
+            });
+            for t in buffer.sample(batch, rng) {
+                let next_value = if t.done {
+                    0.0
+                } else {
+                    q.max_action_value(env, &t.next)
+                };
+                let target = t.reward + gamma * next_value;
+                let key = (t.state.clone(), t.action.clone());
+                update::<E>(&mut q, &mut visits, key, &alpha, target);
+            }
+            state = next;
+        }
+        if observer.on_episode_end(episode, total, &q).is_break() {
+            break;
+        }
+    }
+    q
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::make_rng;
+
+    fn step(state: u8) -> Transition<u8, u8> {
+        Transition {
+            state,
+            action: 0,
+            reward: 0.0,
+            next: state,
+            done: false,
+        }
+    }
+
+    #[test]
+    fn small_buffers_sample_what_they_have() {
+        let mut rng = make_rng(0);
+        let mut buffer = ReplayBuffer::new(3);
+        assert!(buffer.sample(5, &mut rng).is_empty());
+        buffer.push(step(0));
+        buffer.push(step(1));
+        assert_eq!(buffer.sample(5, &mut rng).len(), 2);
+        for state in 2..5 {
+            buffer.push(step(state));
+        }
+        assert_eq!(buffer.len(), 3);
+        let mut kept: Vec<u8> = buffer.sample(5, &mut rng).iter().map(|t| t.state).collect();
+        kept.sort();
+        assert_eq!(kept, [2, 3, 4]);
+
+        let mut none = ReplayBuffer::new(0);
+        none.push(step(0));
+        assert!(none.sample(1, &mut rng).is_empty());
+    }
+
+    #[test]
+    fn sum_trees_find_leaves_by_cumulative_sum() {
+        let mut tree = SumTree::new(3);
+        for (i, value) in [1.0, 0.0, 2.0].into_iter().enumerate() {
+            tree.set(i, value);
+        }
+        assert_eq!(tree.total(), 3.0);
+        assert_eq!(tree.find(0.5), 0);
+        assert_eq!(tree.find(1.0), 2);
+        assert_eq!(tree.find(2.9), 2);
+        // Past the total, still a leaf with a positive value.
+        assert_eq!(tree.find(3.5), 2);
+    }
+
+    #[test]
+    fn higher_priorities_are_sampled_more() {
+        let mut rng = make_rng(0);
+        let mut buffer = PrioritizedReplayBuffer::new(4, 1.0);
+        assert!(buffer.sample(4, 1.0, &mut rng).is_empty());
+        buffer.push(step(0));
+        buffer.push(step(1));
+        buffer.update_priorities(&[0, 1], &[1.0, 3.0]);
+
+        let mut counts = [0; 2];
+        for _ in 0..1000 {
+            for sample in buffer.sample(4, 1.0, &mut rng) {
+                assert_eq!(sample.transition.state as usize, sample.index);
+                counts[sample.index] += 1;
+                let expected = if sample.index == 0 { 2.0 } else { 2.0 / 3.0 };
+                assert!((sample.weight - expected).abs() < 1e-5);
+            }
+        }
+        let ratio = counts[1] as f32 / counts[0] as f32;
+        assert!((ratio - 3.0).abs() < 0.2, "{counts:?}");
+
+        // New transitions come in at the highest priority so far.
+        buffer.push(step(2));
+        assert_eq!(buffer.priority(2), 3.0);
+    }
+
+    #[test]
+    fn full_prioritized_buffers_overwrite_the_oldest() {
+        let mut rng = make_rng(0);
+        let mut buffer = PrioritizedReplayBuffer::new(2, 0.5);
+        buffer.push(step(0));
+        buffer.push(step(1));
+        buffer.update_priorities(&[0, 1], &[4.0, 0.0]);
+        buffer.push(step(2));
+        assert_eq!(buffer.len(), 2);
+        assert_eq!(buffer.priority(0), 4.0);
+        // Only the new transition has a positive priority.
+        for sample in buffer.sample(8, 0.0, &mut rng) {
+            assert_eq!((sample.index, sample.transition.state), (0, 2));
+            assert_eq!(sample.weight, 1.0);
+        }
+
+        // Even when sampling uniformly.
+        let mut uniform = PrioritizedReplayBuffer::new(2, 0.0);
+        uniform.push(step(0));
+        uniform.push(step(1));
+        uniform.update_priorities(&[0, 1], &[0.0, 5.0]);
+        for sample in uniform.sample(8, 1.0, &mut rng) {
+            assert_eq!((sample.index, sample.weight), (1, 0.5));
+        }
+    }
+
+    #[cfg(feature = "examples")]
+    #[test]
+    fn replay_learns_more_from_the_same_episodes() {
+        use crate::td;
+        use crate::testing::{maze, optimal_states};
+
+        let maze = maze();
+        let (mut replayed, mut online) = (0, 0);
+        for seed in 0..10 {
+            let q = q_learning_replay(
+                &maze,
+                5,
+                1000,
+                16,
+                0.5,
+                Some(0.95),
+                0.1,
+                &mut make_rng(seed),
+            );
+            replayed += optimal_states(&maze, &q, 0.95);
+            let q = td::q_learning(&maze, 5, 0.5, Some(0.95), 0.1, &mut make_rng(seed));
+            online += optimal_states(&maze, &q, 0.95);
+        }
+        assert!(replayed > online, "{replayed} vs {online}");
+    }
+}
@@ -0,0 +1,112 @@
+//! Hyperparameters that change over the course of training.
+
+use std::fmt;
+
+/// A value that varies with the training step, such as an exploration
+/// rate annealed from mostly random to mostly greedy.
+pub enum Schedule {
+    /// The same value at every step.
+    Constant(f32),
+    /// Moves linearly from `start` at step 0 to `end` at step
+    /// `steps`, and stays at `end` after that.
+    LinearDecay { start: f32, end: f32, steps: usize },
+    /// `start * rate^step`.
+    ExponentialDecay { start: f32, rate: f32 },
+    /// The value returned by the function at each step.
+    Custom(Box<dyn Fn(usize) -> f32>),
+}
+
+impl Schedule {
+    /// The value at `step`, counting from 0.
+    pub fn value(&self, step: usize) -> f32 {
+        match self {
+            Schedule::Constant(x) => *x,
+            Schedule::LinearDecay { start, end, steps } => {
+                if step >= *steps {
+                    *end
+                } else {
+                    start + (end - start) * step as f32 / *steps as f32
+                }
+            }
+            Schedule::ExponentialDecay { start, rate } => {
+                start * rate.powi(step.min(i32::MAX as usize) as i32)
+            }
+            Schedule::Custom(f) => f(step),
+        }
+    }
+}
+
+impl From<f32> for Schedule {
+    fn from(x: f32) -> Self {
+        Schedule::Constant(x)
+    }
+}
+
+impl fmt::Debug for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Schedule::Constant(x) => f.debug_tuple("Constant").field(x).finish(),
+            Schedule::LinearDecay { start, end, steps } => f
+                .debug_struct("LinearDecay")
+                .field("start", start)
+                .field("end", end)
+                .field("steps", steps)
+                .finish(),
+            Schedule::ExponentialDecay { start, rate } => f
+                .debug_struct("ExponentialDecay")
+                .field("start", start)
+                .field("rate", rate)
+                .finish(),
+            Schedule::Custom(_) => f.write_str("Custom(..)"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn schedules_take_their_values_at_each_step() {
+        let constant = Schedule::from(0.3);
+        assert_eq!(constant.value(0), 0.3);
+        assert_eq!(constant.value(1_000_000), 0.3);
+
+        let linear = Schedule::LinearDecay {
+            start: 1.0,
+            end: 0.1,
+            steps: 10,
+        };
+        assert_eq!(linear.value(0), 1.0);
+        assert!((linear.value(5) - 0.55).abs() < 1e-6);
+        assert_eq!(linear.value(10), 0.1);
+        assert_eq!(linear.value(1000), 0.1);
+
+        let exponential = Schedule::ExponentialDecay {
+            start: 2.0,
+            rate: 0.5,
+        };
+        assert_eq!(exponential.value(0), 2.0);
+        assert_eq!(exponential.value(3), 0.25);
+        assert_eq!(exponential.value(usize::MAX), 0.0);
+
+        let custom = Schedule::Custom(Box::new(|step| 1.0 / (step + 1) as f32));
+        assert_eq!(custom.value(3), 0.25);
+        assert_eq!(format!("{custom:?}"), "Custom(..)");
+    }
+
+    #[test]
+    fn scheduled_learning_rates_anneal_by_visit() {
+        use crate::td::LearningRate;
+
+        let alpha = LearningRate::from(Schedule::LinearDecay {
+            start: 0.5,
+            end: 0.1,
+            steps: 4,
+        });
+        assert_eq!(alpha.at(1), 0.5);
+        assert!((alpha.at(3) - 0.3).abs() < 1e-6);
+        assert_eq!(alpha.at(100), 0.1);
+        assert_eq!(LearningRate::VisitDecayed(1.0).at(4), 0.25);
+    }
+}
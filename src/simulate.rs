@@ -0,0 +1,995 @@
+use std::collections::HashMap;
+
+use rand::Rng;
+
+use crate::env::{IEnvironment, IPartialEnvironment};
+use crate::error::ReinforceError;
+use crate::policy::{fixed_order, Policy};
+use crate::rng::StdRng;
+
+/// The maximum number of steps in a single training episode, so that
+/// training terminates even if a policy never reaches a terminal
+/// state.
+pub const EPISODE_STEP_LIMIT: usize = 10_000;
+
+/// Draws one item from `weighted`, with probability proportional to
+/// its weight. Returns `None` if there is nothing with positive
+/// weight to draw.
+pub(crate) fn sample_weighted<T>(weighted: Vec<(T, f32)>, rng: &mut StdRng) -> Option<T> {
+    let total: f32 = weighted.iter().map(|(_, w)| w.max(0.0)).sum();
+    if total <= 0.0 {
+        return None;
+    }
+    let mut u = rng.gen::<f32>() * total;
+    let mut last = None;
+    for (x, w) in weighted {
+        if w <= 0.0 {
+            continue;
+        }
+        if u < w {
+            return Some(x);
+        }
+        u -= w;
+        last = Some(x);
+    }
+    last
+}
+
+/// Draws a start state uniformly from `env.start_states()`, or `None`
+/// if there are none.
+pub(crate) fn sample_start<E: IEnvironment>(env: &E, rng: &mut StdRng) -> Option<E::State> {
+    let mut starts = fixed_order(env.start_states());
+    if starts.is_empty() {
+        return None;
+    }
+    let i = rng.gen_range(0..starts.len());
+    Some(starts.swap_remove(i))
+}
+
+/// The `(state, action, reward)` triples of an episode, in order.
+pub type Trajectory<E> = Vec<(<E as IEnvironment>::State, <E as IEnvironment>::Action, f32)>;
+
+/// Runs `policy` in the environment from state `start`, returning
+/// the `(state, action, reward)` triples of the resulting episode in
+/// order.
+///
+/// The episode ends on reaching a terminal state, after `max_steps`
+/// steps, or earlier if a state has no available actions or no
+/// reachable next state.
+pub fn rollout<E, P>(
+    env: &E,
+    policy: &P,
+    start: E::State,
+    max_steps: usize,
+    rng: &mut StdRng,
+) -> Trajectory<E>
+where
+    E: IEnvironment,
+    P: Policy<E>,
+{
+    run_episode(env, policy, start, max_steps, rng).0
+}
+
+/// As `rollout`, but also returning the state the episode ended in.
+pub(crate) fn run_episode<E, P>(
+    env: &E,
+    policy: &P,
+    start: E::State,
+    max_steps: usize,
+    rng: &mut StdRng,
+) -> (Trajectory<E>, E::State)
+where
+    E: IEnvironment,
+    P: Policy<E>,
+{
+    let mut trajectory = Vec::new();
+    let mut state = start;
+    for _ in 0..max_steps {
+        if env.is_terminal(&state) || env.actions_from(&state).is_empty() {
+            break;
+        }
+        let action = policy.sample(env, &state, rng);
+        let Some((next, reward)) = env.sample_transition(&state, &action, rng) else {
+            break;
+        };
+        trajectory.push((state.clone(), action, reward));
+        state = next;
+    }
+    (trajectory, state)
+}
+
+/// As `rollout`, but also attributing the episode's return, discounted
+/// by `gamma` from its first step, to the named `reward_components` of
+/// its transitions: each component's discounted sum over the episode.
+///
+/// `gamma` defaults to `env.gamma()`. Transitions the environment
+/// gives no components for count towards none, so with no components
+/// at all the map is empty. In debug builds, panics if a transition's
+/// components don't sum to the reward it was sampled with.
+pub fn rollout_attributed<E, P>(
+    env: &E,
+    policy: &P,
+    start: E::State,
+    max_steps: usize,
+    gamma: Option<f32>,
+    rng: &mut StdRng,
+) -> (Trajectory<E>, HashMap<&'static str, f32>)
+where
+    E: IEnvironment,
+    P: Policy<E>,
+{
+    let gamma = gamma.unwrap_or_else(|| env.gamma());
+    let (trajectory, end) = run_episode(env, policy, start, max_steps, rng);
+    let mut attribution: HashMap<&'static str, f32> = HashMap::new();
+    let mut discount = 1.0;
+    for (t, (from, take, reward)) in trajectory.iter().enumerate() {
+        let to = trajectory.get(t + 1).map_or(&end, |(s, _, _)| s);
+        let components = env.reward_components(from, take, to);
+        if !components.is_empty() {
+            let total: f32 = components.values().sum();
+            debug_assert!(
+                (total - reward).abs() <= 1e-4 * reward.abs().max(1.0),
+                "reward components sum to {total}, not the reward {reward}"
+            );
+        }
+        for (name, r) in components {
+            *attribution.entry(name).or_insert(0.0) += discount * r;
+        }
+        discount *= gamma;
+    }
+    (trajectory, attribution)
+}
+
+/// Runs `policy` from state `start` with no randomness at all, giving
+/// a reproducible typical episode, e.g. for visualizing a learned
+/// policy in a stochastic environment.
+///
+/// Each step takes the policy's most likely action, and moves to the
+/// most likely next state with the most likely reward given it, in
+/// place of sampling them. Ties between actions are broken as
+/// `TieBreak::First` orders them, between next states by their order
+/// in `transition_distribution`, and between rewards by their order in
+/// `rewards`. If the rewards are not enumerable, the reward is the
+/// expected reward given the next state, as in simulation.
+///
+/// The episode ends as in `rollout`. Panics if `transition_distribution`
+/// is undefined for the environment.
+pub fn rollout_greedy<E, P>(env: &E, policy: &P, start: E::State, max_steps: usize) -> Trajectory<E>
+where
+    E: IEnvironment,
+    P: Policy<E>,
+{
+    let rewards = env.rewards();
+    let mut trajectory = Vec::new();
+    let mut state = start;
+    for _ in 0..max_steps {
+        if env.is_terminal(&state) {
+            break;
+        }
+        let mut actions = policy.action_distribution(env, &state);
+        let order = fixed_order(actions.keys().cloned());
+        let weighted = order.into_iter().map(|a| {
+            let p = actions.remove(&a).unwrap_or(0.0);
+            (a, p)
+        });
+        let Some(action) = most_likely(weighted) else {
+            break;
+        };
+        let candidates = env
+            .transition_distribution(&state, &action)
+            .expect("prob_transition must be defined to simulate the environment");
+        let Some(next) = most_likely(candidates) else {
+            break;
+        };
+        let reward = likely_reward(env, &rewards, &state, &action, &next);
+        trajectory.push((state, action, reward));
+        state = next;
+    }
+    trajectory
+}
+
+/// The most likely reward for the transition from `from` to `to`
+/// taking `take`, `rewards` being the environment's, or the expected
+/// reward given `to` if they are not enumerable.
+fn likely_reward<E: IEnvironment>(
+    env: &E,
+    rewards: &[f32],
+    from: &E::State,
+    take: &E::Action,
+    to: &E::State,
+) -> f32 {
+    if !rewards.is_empty() {
+        let weighted = rewards.iter().map(|r| (*r, env.prob(from, take, to, r)));
+        most_likely(weighted).unwrap_or(0.0)
+    } else {
+        let p = env.prob_transition(from, take, to).unwrap_or(0.0);
+        match env.expected_reward_at(from, take, to) {
+            Some(r) if p > 0.0 => r / p,
+            _ => 0.0,
+        }
+    }
+}
+
+/// The single most probable episode of `policy` from `start` to a
+/// terminal state within `max_steps` steps, e.g. to explain what an
+/// agent will most likely do.
+///
+/// Unlike `rollout_greedy`, which takes the likeliest step at each
+/// point in turn, this maximizes the joint probability of the whole
+/// path, the product of `pi(a|s) * p(s'|s,a)` over its steps, by the
+/// Viterbi algorithm over the chain the policy induces: a likely first
+/// step that only leads on to unlikely ones loses out to a less likely
+/// one with likelier successors. The rewards are chosen as
+/// `rollout_greedy` chooses them, and do not count towards the
+/// probability. Ties go to the first path found, in fixed orders. If
+/// no terminal state can be reached within `max_steps`, as when the
+/// policy cycles, the most probable path of exactly `max_steps` steps,
+/// or of as many as the episode can last, is returned instead.
+///
+/// Panics if `transition_distribution` is undefined for the
+/// environment.
+pub fn most_likely_path<E, P>(
+    env: &E,
+    policy: &P,
+    start: E::State,
+    max_steps: usize,
+) -> Trajectory<E>
+where
+    E: IEnvironment,
+    P: Policy<E>,
+{
+    type Layer<E> = HashMap<
+        <E as IEnvironment>::State,
+        (
+            f64,
+            Option<(<E as IEnvironment>::State, <E as IEnvironment>::Action, f32)>,
+        ),
+    >;
+
+    let rewards = env.rewards();
+    // The likeliest path to each state after each number of steps: its
+    // log probability and its last step.
+    let mut layers: Vec<Layer<E>> = vec![HashMap::from([(start.clone(), (0.0, None))])];
+    // The likeliest end found so far: its log probability, step and
+    // state.
+    let mut end: Option<(f64, usize, E::State)> =
+        env.is_terminal(&start).then_some((0.0, 0, start));
+    while layers.len() <= max_steps {
+        let mut next: Layer<E> = HashMap::new();
+        let layer = layers.last().expect("the start layer is always there");
+        for from in fixed_order(layer.keys().cloned()) {
+            if env.is_terminal(&from) {
+                continue;
+            }
+            let log_p = layer[&from].0;
+            let actions = policy.action_distribution(env, &from);
+            for take in fixed_order(actions.keys().cloned()) {
+                let pa = actions[&take];
+                if pa <= 0.0 {
+                    continue;
+                }
+                let candidates = env
+                    .transition_distribution(&from, &take)
+                    .expect("prob_transition must be defined to simulate the environment");
+                for (to, pt) in candidates.into_iter().filter(|(_, p)| *p > 0.0) {
+                    let log_q = log_p + (pa as f64).ln() + (pt as f64).ln();
+                    if next.get(&to).is_none_or(|(best, _)| log_q > *best) {
+                        let reward = likely_reward(env, &rewards, &from, &take, &to);
+                        next.insert(to, (log_q, Some((from.clone(), take.clone(), reward))));
+                    }
+                }
+            }
+        }
+        let t = layers.len();
+        for to in fixed_order(next.keys().cloned()) {
+            let log_q = next[&to].0;
+            if env.is_terminal(&to) && end.as_ref().is_none_or(|(best, _, _)| log_q > *best) {
+                end = Some((log_q, t, to));
+            }
+        }
+        // Every step only makes a path less likely, so paths no more
+        // likely than the best end already found can be dropped.
+        if let Some((best, end_t, end_state)) = &end {
+            next.retain(|s, (log_q, _)| {
+                if env.is_terminal(s) {
+                    *end_t == t && s == end_state
+                } else {
+                    *log_q > *best
+                }
+            });
+        }
+        let goes_on = next.keys().any(|s| !env.is_terminal(s));
+        if next.is_empty() {
+            break;
+        }
+        layers.push(next);
+        if !goes_on {
+            break;
+        }
+    }
+
+    let (mut t, mut state) = match end {
+        Some((_, t, state)) => (t, state),
+        None => {
+            let t = layers.len() - 1;
+            let last = &layers[t];
+            let state = fixed_order(last.keys().cloned())
+                .into_iter()
+                .reduce(|a, b| if last[&b].0 > last[&a].0 { b } else { a })
+                .expect("every layer kept has a state");
+            (t, state)
+        }
+    };
+    let mut trajectory = Vec::with_capacity(t);
+    while let Some((from, take, reward)) = layers[t][&state].1.clone() {
+        trajectory.push((from.clone(), take, reward));
+        state = from;
+        t -= 1;
+    }
+    trajectory.reverse();
+    trajectory
+}
+
+/// The first item of `weighted` with the greatest positive weight, or
+/// `None` if none has positive weight.
+fn most_likely<T>(weighted: impl IntoIterator<Item = (T, f32)>) -> Option<T> {
+    let mut best: Option<(T, f32)> = None;
+    for (x, w) in weighted {
+        if w > 0.0 && best.as_ref().is_none_or(|(_, b)| w > *b) {
+            best = Some((x, w));
+        }
+    }
+    best.map(|(x, _)| x)
+}
+
+/// Summary statistics of a sample of values.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Summary {
+    pub mean: f32,
+    /// The population standard deviation.
+    pub std: f32,
+    pub min: f32,
+    pub max: f32,
+}
+
+impl Summary {
+    /// Summarizes `values`, or returns `None` if there are none.
+    ///
+    /// The mean and deviation are accumulated in `f64`, so that many
+    /// equal values summarize to exactly that value with no spread.
+    pub fn of(values: &[f32]) -> Option<Self> {
+        if values.is_empty() {
+            return None;
+        }
+        let n = values.len() as f64;
+        let mean = values.iter().map(|x| *x as f64).sum::<f64>() / n;
+        let variance = values
+            .iter()
+            .map(|x| (*x as f64 - mean).powi(2))
+            .sum::<f64>()
+            / n;
+        Some(Summary {
+            mean: mean as f32,
+            std: variance.sqrt() as f32,
+            min: values.iter().copied().fold(f32::INFINITY, f32::min),
+            max: values.iter().copied().fold(f32::NEG_INFINITY, f32::max),
+        })
+    }
+}
+
+/// Aggregate results of running a policy for several episodes; see
+/// `evaluate_episodes`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EvalStats {
+    pub episodes: usize,
+    /// The plain sum of each episode's rewards.
+    pub undiscounted: Summary,
+    /// Each episode's return discounted from its first step.
+    pub discounted: Summary,
+    /// The mean number of steps per episode. Episodes cut off at the
+    /// step cap count with the cap as their length.
+    pub mean_length: f32,
+    /// The number of episodes cut off at the step cap before reaching
+    /// a terminal state.
+    pub capped: usize,
+}
+
+/// Runs `policy` for `episodes` episodes of at most `max_steps` steps
+/// each, from start states drawn uniformly from `env.start_states()`,
+/// and summarizes the returns and lengths. `gamma` defaults to
+/// `env.gamma()`.
+///
+/// Returns `None` if no episodes were run, either because `episodes`
+/// is 0 or because the environment has no start states.
+pub fn evaluate_episodes<E, P>(
+    env: &E,
+    policy: &P,
+    episodes: usize,
+    max_steps: usize,
+    gamma: Option<f32>,
+    rng: &mut StdRng,
+) -> Option<EvalStats>
+where
+    E: IEnvironment,
+    P: Policy<E>,
+{
+    let gamma = gamma.unwrap_or_else(|| env.gamma());
+    let mut undiscounted = Vec::with_capacity(episodes);
+    let mut discounted = Vec::with_capacity(episodes);
+    let mut total_length = 0;
+    let mut capped = 0;
+    for _ in 0..episodes {
+        let start = sample_start(env, rng)?;
+        let (trajectory, end) = run_episode(env, policy, start, max_steps, rng);
+        undiscounted.push(trajectory.iter().map(|(_, _, r)| r).sum());
+        discounted.push(
+            trajectory
+                .iter()
+                .rev()
+                .fold(0.0, |g, (_, _, r)| r + gamma * g),
+        );
+        total_length += trajectory.len();
+        if trajectory.len() == max_steps && !env.is_terminal(&end) {
+            capped += 1;
+        }
+    }
+    Some(EvalStats {
+        episodes,
+        undiscounted: Summary::of(&undiscounted)?,
+        discounted: Summary::of(&discounted)?,
+        mean_length: total_length as f32 / episodes as f32,
+        capped,
+    })
+}
+
+/// The empirical distribution of the states `policy` acts from, over
+/// `episodes` episodes of at most `max_steps` steps each, from start
+/// states drawn uniformly from `env.start_states()`.
+///
+/// A visit `t` steps into an episode counts `gamma^t`, and the counts
+/// are normalized to sum to 1, giving the discounted state
+/// distribution; `gamma` defaults to `env.gamma()`, and `Some(1.0)`
+/// counts every visit the same, for the plain visitation frequencies.
+/// The states episodes end in aren't counted, as no action is taken
+/// there. States never visited are missing, and the map is empty if
+/// nothing was visited at all.
+pub fn state_visitation<E, P>(
+    env: &E,
+    policy: &P,
+    episodes: usize,
+    max_steps: usize,
+    gamma: Option<f32>,
+    rng: &mut StdRng,
+) -> HashMap<E::State, f32>
+where
+    E: IEnvironment,
+    P: Policy<E>,
+{
+    let gamma = gamma.unwrap_or_else(|| env.gamma());
+    let mut visits: HashMap<E::State, f32> = HashMap::new();
+    for _ in 0..episodes {
+        let Some(start) = sample_start(env, rng) else {
+            break;
+        };
+        let (trajectory, _) = run_episode(env, policy, start, max_steps, rng);
+        let mut discount = 1.0;
+        for (s, _, _) in trajectory {
+            *visits.entry(s).or_insert(0.0) += discount;
+            discount *= gamma;
+        }
+    }
+    let total: f32 = visits.values().sum();
+    if total > 0.0 {
+        visits.values_mut().for_each(|w| *w /= total);
+    }
+    visits
+}
+
+/// The return and average reward of a stream of rewards, updated one
+/// reward at a time, for continuing tasks whose trajectories are too
+/// long to keep.
+///
+/// The discounted return is counted from the first reward, `r_0 +
+/// gamma r_1 + gamma^2 r_2 + ...`. It is accumulated in `f64` with
+/// compensated (Kahan) summation, so that millions of small terms add
+/// up without losing precision; the discount `gamma^t` only shrinks,
+/// underflowing harmlessly to 0 over very long horizons, so nothing
+/// can overflow.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReturnAccumulator {
+    gamma: f64,
+    discount: f64,
+    total: f64,
+    compensation: f64,
+    steps: usize,
+    rate: f64,
+    average: f64,
+    /// The unbiased step-size trace of Sutton & Barto's exercise 2.7.
+    trace: f64,
+}
+
+impl ReturnAccumulator {
+    /// An accumulator discounting by `gamma`, and averaging rewards
+    /// with step size `rate`.
+    pub fn new(gamma: f32, rate: f32) -> Self {
+        ReturnAccumulator {
+            gamma: gamma as f64,
+            discount: 1.0,
+            total: 0.0,
+            compensation: 0.0,
+            steps: 0,
+            rate: rate as f64,
+            average: 0.0,
+            trace: 0.0,
+        }
+    }
+
+    /// Adds the next reward in the stream.
+    pub fn push(&mut self, reward: f32) {
+        let term = self.discount * reward as f64 - self.compensation;
+        let total = self.total + term;
+        self.compensation = (total - self.total) - term;
+        self.total = total;
+        self.discount *= self.gamma;
+        self.steps += 1;
+
+        // Stepping by `rate / trace` weights rewards exponentially by
+        // recency without the bias towards 0 of starting the average
+        // there: the first reward is taken as the average outright.
+        self.trace += self.rate * (1.0 - self.trace);
+        self.average += self.rate / self.trace * (reward as f64 - self.average);
+    }
+
+    /// The discounted return of the rewards so far.
+    pub fn discounted_return(&self) -> f32 {
+        self.total as f32
+    }
+
+    /// The exponentially weighted average of the rewards so far, or 0
+    /// if there are none.
+    pub fn average_reward(&self) -> f32 {
+        self.average as f32
+    }
+
+    /// The number of rewards so far.
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+}
+
+/// The next state, reward and whether the episode is done, as
+/// returned by `Episodic::step`, or why the step couldn't be taken.
+pub type StepResult<E> = Result<
+    (<E as IEnvironment>::State, f32, bool),
+    ReinforceError<<E as IEnvironment>::State, <E as IEnvironment>::Action>,
+>;
+
+/// A stateful, gym-style view of an environment: holds the current
+/// state and a random number generator, and advances one sampled
+/// step at a time.
+pub struct Episodic<E: IEnvironment> {
+    env: E,
+    state: Option<E::State>,
+    rng: StdRng,
+    starts: Option<Vec<(E::State, f32)>>,
+}
+
+impl<E: IEnvironment> Episodic<E> {
+    /// An adapter over `env` drawing its samples from `rng`. `reset`
+    /// must be called before the first `step`.
+    pub fn new(env: E, rng: StdRng) -> Self {
+        Episodic {
+            env,
+            state: None,
+            rng,
+            starts: None,
+        }
+    }
+
+    /// This adapter, with episodes starting from states drawn from
+    /// `distribution` instead of uniformly from `start_states`.
+    ///
+    /// The weights are renormalized, so they need not sum to 1.
+    /// States with no positive weight are never drawn.
+    ///
+    /// Panics if no state has a positive weight.
+    pub fn with_start_distribution(mut self, distribution: HashMap<E::State, f32>) -> Self {
+        let total: f32 = distribution.values().map(|p| p.max(0.0)).sum();
+        assert!(total > 0.0, "start distribution has no positive weight");
+        // In a fixed order, so that a seeded `rng` draws the same starts.
+        let starts = fixed_order(distribution.keys().cloned())
+            .into_iter()
+            .map(|s| {
+                let p = distribution[&s].max(0.0) / total;
+                (s, p)
+            })
+            .collect();
+        self.starts = Some(starts);
+        self
+    }
+
+    /// Starts a new episode from a state drawn from the start
+    /// distribution, by default uniform over the environment's
+    /// `start_states`, and returns it.
+    ///
+    /// Panics if the environment has no start states.
+    pub fn reset(&mut self) -> E::State {
+        let start = match &self.starts {
+            Some(starts) => sample_weighted(starts.clone(), &mut self.rng),
+            None => sample_start(&self.env, &mut self.rng),
+        }
+        .expect("environment has no start states");
+        self.state = Some(start.clone());
+        start
+    }
+
+    /// Takes `action` from the current state, returning the next
+    /// state, the sampled reward, and whether the episode is done.
+    ///
+    /// The episode is done on reaching a terminal state. A state with
+    /// no reachable next state also ends the episode, leaving the
+    /// state unchanged with a reward of 0.
+    ///
+    /// Returns `ReinforceError::InvalidAction` without stepping if
+    /// `action` is not valid from the current state, and
+    /// `ReinforceError::NotReset` if called before `reset`.
+    pub fn step(&mut self, action: &E::Action) -> StepResult<E> {
+        let from = self.state.as_ref().ok_or(ReinforceError::NotReset)?;
+        if !self.env.is_valid_action(from, action) {
+            return Err(ReinforceError::InvalidAction {
+                state: from.clone(),
+                action: action.clone(),
+            });
+        }
+        Ok(
+            match self.env.sample_transition(from, action, &mut self.rng) {
+                Some((next, reward)) => {
+                    let done = self.env.is_terminal(&next);
+                    self.state = Some(next.clone());
+                    (next, reward, done)
+                }
+                None => (from.clone(), 0.0, true),
+            },
+        )
+    }
+
+    /// The current state, or `None` before the first `reset`.
+    pub fn state(&self) -> Option<&E::State> {
+        self.state.as_ref()
+    }
+
+    pub fn env(&self) -> &E {
+        &self.env
+    }
+
+    pub fn rng(&mut self) -> &mut StdRng {
+        &mut self.rng
+    }
+}
+
+impl<E: IPartialEnvironment> Episodic<E> {
+    /// As `reset`, but returns an observation of the start state
+    /// rather than the state itself.
+    pub fn reset_observed(&mut self) -> E::Observation {
+        let start = self.reset();
+        self.env.observe(&start, &mut self.rng)
+    }
+
+    /// As `step`, but returns an observation of the next state rather
+    /// than the state itself. The true state is still tracked, and
+    /// available from `state`.
+    #[allow(clippy::type_complexity)]
+    pub fn step_observed(
+        &mut self,
+        action: &E::Action,
+    ) -> Result<(E::Observation, f32, bool), ReinforceError<E::State, E::Action>> {
+        let (next, reward, done) = self.step(action)?;
+        Ok((self.env.observe(&next, &mut self.rng), reward, done))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::policy::{DeterministicPolicy, UniformRandomPolicy};
+    use crate::rng::make_rng;
+    use crate::testing::Chain;
+
+    /// A coin flip from state 0 to heads (1) or tails (2), paying 2
+    /// for heads, and described only by its expectations.
+    struct Coin;
+
+    impl IEnvironment for Coin {
+        type State = u8;
+        type Action = ();
+
+        fn prob(&self, _: &u8, _: &(), _: &u8, _: &f32) -> f32 {
+            unreachable!("the rewards aren't enumerable")
+        }
+
+        fn prob_transition(&self, from: &u8, _: &(), to: &u8) -> Option<f32> {
+            Some(if *from == 0 && *to != 0 { 0.5 } else { 0.0 })
+        }
+
+        fn expected_reward_at(&self, from: &u8, _: &(), to: &u8) -> Option<f32> {
+            Some(if *from == 0 && *to == 1 { 1.0 } else { 0.0 })
+        }
+
+        fn actions_from(&self, _: &u8) -> HashSet<()> {
+            HashSet::from([()])
+        }
+
+        fn states_from(&self, _: &u8, _: &()) -> HashSet<u8> {
+            HashSet::from([1, 2])
+        }
+
+        fn is_terminal(&self, state: &u8) -> bool {
+            *state != 0
+        }
+    }
+
+    #[test]
+    fn rollout_follows_the_policy_up_to_max_steps() {
+        let policy = DeterministicPolicy::new((0..Chain::GOAL).map(|s| (s, 1)).collect());
+        let trajectory = rollout(&Chain, &policy, 0, 2, &mut make_rng(0));
+        assert_eq!(trajectory, [(0, 1, 0.0), (1, 1, 0.0)]);
+    }
+
+    #[test]
+    fn visitation_weighs_each_visit_by_its_discount() {
+        let path = Chain::rightward();
+        let mut rng = make_rng(0);
+        let visits = state_visitation(&Chain, &path, 10, 100, Some(0.5), &mut rng);
+        // Steps 0 to 2 of every episode, weighted 1, 1/2 and 1/4.
+        let expected = HashMap::from([(0, 4.0 / 7.0), (1, 2.0 / 7.0), (2, 1.0 / 7.0)]);
+        assert_eq!(visits.len(), expected.len());
+        for (s, p) in expected {
+            assert!((visits[&s] - p).abs() < 1e-6, "{s}");
+        }
+
+        let visits = state_visitation(&Chain, &path, 10, 100, Some(1.0), &mut rng);
+        assert!(visits.values().all(|p| (p - 1.0 / 3.0).abs() < 1e-6));
+        assert!(state_visitation(&Chain, &path, 0, 100, None, &mut rng).is_empty());
+    }
+
+    #[cfg(feature = "examples")]
+    #[test]
+    fn greedy_rollouts_are_reproducible() {
+        use crate::dp::value_iteration;
+        use crate::env::examples::GridWorld;
+
+        let grid = GridWorld::new(4, 4, [(1, 1), (2, 2)], (3, 3), 0.04, 0.2);
+        let (_, policy) = value_iteration(&grid, Some(0.9), 1e-6, 1000).unwrap();
+        let trajectory = rollout_greedy(&grid, &policy, grid.start(), 100);
+        assert_eq!(trajectory.len(), 6);
+        for (s, a, _) in &trajectory {
+            assert_eq!(policy.action(s), Some(a));
+        }
+        assert_eq!(trajectory.last().unwrap().2, 1.0);
+        for _ in 0..10 {
+            assert_eq!(
+                rollout_greedy(&grid, &policy, grid.start(), 100),
+                trajectory
+            );
+        }
+    }
+
+    #[cfg(feature = "examples")]
+    #[test]
+    fn the_most_likely_path_is_the_optimal_one_without_slipping() {
+        use crate::dp::value_iteration;
+        use crate::env::examples::GridWorld;
+
+        let grid = GridWorld::new(4, 4, [(1, 1), (2, 2)], (3, 3), 0.04, 0.0);
+        let (_, policy) = value_iteration(&grid, Some(0.9), 1e-6, 1000).unwrap();
+        let path = most_likely_path(&grid, &policy, grid.start(), 100);
+        assert_eq!(path, rollout_greedy(&grid, &policy, grid.start(), 100));
+        assert_eq!(path.len(), 6);
+        let (last, a, reward) = path.last().unwrap();
+        assert_eq!(grid.moved(last, *a), grid.goal());
+        assert_eq!(*reward, 1.0);
+    }
+
+    #[test]
+    fn the_most_likely_path_maximizes_the_joint_probability() {
+        use crate::env::TabularMDP;
+
+        // The likelier first step, to 1, only reaches the end at 3
+        // half the time after, while 2 always does.
+        let transition = vec![
+            vec![vec![0.0, 0.6, 0.4, 0.0, 0.0]],
+            vec![vec![0.0, 0.0, 0.0, 0.5, 0.5]],
+            vec![vec![0.0, 0.0, 0.0, 1.0, 0.0]],
+            vec![vec![0.0, 0.0, 0.0, 1.0, 0.0]],
+            vec![vec![0.0, 0.0, 0.0, 0.0, 1.0]],
+        ];
+        let reward = vec![vec![vec![0.0; 5]]; 5];
+        let env = TabularMDP::from_matrix(transition, reward, 1.0)
+            .unwrap()
+            .with_terminals([3]);
+        let path = most_likely_path(&env, &UniformRandomPolicy, 0, 10);
+        assert_eq!(path, [(0, 0, 0.0), (2, 0, 0.0)]);
+        assert_eq!(rollout_greedy(&env, &UniformRandomPolicy, 0, 10)[1].0, 1);
+
+        // Cycling forever, the path runs to the step cap.
+        let path = most_likely_path(
+            &Chain,
+            &DeterministicPolicy::new(HashMap::from([(0, -1)])),
+            0,
+            5,
+        );
+        assert_eq!(path, [(0, -1, 0.0); 5]);
+    }
+
+    #[test]
+    fn greedy_rollouts_break_ties_by_fixed_orders() {
+        let trajectory = rollout_greedy(&Coin, &UniformRandomPolicy, 0, 10);
+        let to = fixed_order([1u8, 2])[0];
+        assert_eq!(trajectory, [(0, (), if to == 1 { 2.0 } else { 0.0 })]);
+
+        let first = rollout_greedy(&Chain, &UniformRandomPolicy, 0, 5);
+        assert_eq!(first.len(), 5);
+        assert_eq!(rollout_greedy(&Chain, &UniformRandomPolicy, 0, 5), first);
+    }
+
+    #[test]
+    fn rewards_fall_back_to_expectations() {
+        let mut rng = make_rng(0);
+        let mut seen = HashSet::new();
+        for _ in 0..100 {
+            let (to, reward) = Coin.sample_transition(&0, &(), &mut rng).unwrap();
+            assert_eq!(reward, if to == 1 { 2.0 } else { 0.0 });
+            seen.insert(to);
+        }
+        assert_eq!(seen, HashSet::from([1, 2]));
+    }
+
+    #[test]
+    fn rollout_stops_at_the_goal() {
+        let policy = DeterministicPolicy::new((0..=Chain::GOAL).map(|s| (s, 1)).collect());
+        let trajectory = rollout(&Chain, &policy, 0, 100, &mut make_rng(0));
+        assert_eq!(trajectory, [(0, 1, 0.0), (1, 1, 0.0), (2, 1, 1.0)]);
+        assert!(rollout(&Chain, &policy, Chain::GOAL, 100, &mut make_rng(0)).is_empty());
+    }
+
+    #[cfg(feature = "examples")]
+    #[test]
+    fn returns_are_attributed_to_their_components() {
+        use crate::env::examples::GridWorld;
+
+        let grid = GridWorld::new(3, 3, [(1, 1)], (2, 2), 0.1, 0.2);
+        let mut rng = make_rng(0);
+        for _ in 0..20 {
+            let (trajectory, attribution) =
+                rollout_attributed(&grid, &UniformRandomPolicy, (0, 0), 50, Some(1.0), &mut rng);
+            let total: f32 = trajectory.iter().map(|(_, _, r)| r).sum();
+            assert!((attribution["cost"] + attribution["bonus"] - total).abs() < 1e-4);
+            // Only entering the goal pays 1.
+            let reached = trajectory.last().is_some_and(|(_, _, r)| *r == 1.0);
+            assert_eq!(attribution["bonus"], if reached { 1.0 } else { 0.0 });
+            let costly = trajectory.len() - usize::from(reached);
+            assert!((attribution["cost"] + 0.1 * costly as f32).abs() < 1e-4);
+        }
+
+        // Without components, nothing is attributed.
+        let (_, attribution) =
+            rollout_attributed(&Chain, &Chain::rightward(), 0, 10, None, &mut make_rng(0));
+        assert!(attribution.is_empty());
+    }
+
+    #[test]
+    fn evaluation_of_a_deterministic_walk_has_no_spread() {
+        let stats = evaluate_episodes(
+            &Chain,
+            &Chain::rightward(),
+            50,
+            100,
+            Some(0.9),
+            &mut make_rng(0),
+        )
+        .unwrap();
+        assert_eq!(stats.episodes, 50);
+        assert_eq!(stats.undiscounted.mean, 1.0);
+        assert_eq!(stats.undiscounted.std, 0.0);
+        assert!((stats.discounted.mean - 0.81).abs() < 1e-6);
+        assert_eq!(stats.discounted.std, 0.0);
+        assert_eq!(stats.mean_length, 3.0);
+        assert_eq!(stats.capped, 0);
+    }
+
+    #[test]
+    fn evaluation_counts_capped_episodes() {
+        let left = DeterministicPolicy::<Chain>::new((0..Chain::GOAL).map(|s| (s, -1)).collect());
+        let stats = evaluate_episodes(&Chain, &left, 10, 20, None, &mut make_rng(0)).unwrap();
+        assert_eq!(stats.capped, 10);
+        assert_eq!(stats.mean_length, 20.0);
+        assert_eq!(stats.undiscounted.max, 0.0);
+        assert!(evaluate_episodes(&Chain, &left, 0, 20, None, &mut make_rng(0)).is_none());
+    }
+
+    #[cfg(feature = "examples")]
+    #[test]
+    fn accumulated_returns_match_the_geometric_series() {
+        let (gamma, n) = (0.999_999_f64, 2_000_000);
+        let mut acc = ReturnAccumulator::new(gamma as f32, 0.01);
+        for _ in 0..n {
+            acc.push(0.5);
+        }
+        let gamma = gamma as f32 as f64;
+        let closed = 0.5 * (1.0 - gamma.powi(n)) / (1.0 - gamma);
+        let error = (acc.discounted_return() as f64 - closed).abs() / closed;
+        assert!(error < 1e-6, "{} vs {closed}", acc.discounted_return());
+        assert_eq!(acc.steps(), n as usize);
+        assert!((acc.average_reward() - 0.5).abs() < 1e-6);
+
+        // Long past the point at which the discount underflows.
+        let mut acc = ReturnAccumulator::new(0.5, 0.5);
+        for _ in 0..10_000 {
+            acc.push(1.0);
+        }
+        assert_eq!(acc.discounted_return(), 2.0);
+    }
+
+    #[test]
+    fn average_rewards_follow_recent_rewards() {
+        let mut acc = ReturnAccumulator::new(1.0, 0.1);
+        assert_eq!(acc.average_reward(), 0.0);
+        acc.push(3.0);
+        assert_eq!(acc.average_reward(), 3.0);
+        for _ in 0..200 {
+            acc.push(-1.0);
+        }
+        assert!((acc.average_reward() + 1.0).abs() < 1e-6);
+    }
+
+    #[cfg(feature = "examples")]
+    #[test]
+    fn episodic_steps_through_a_grid() {
+        use crate::env::examples::{GridWorld, Move};
+
+        let grid = GridWorld::new(3, 2, [], (2, 0), 0.1, 0.0).with_start((0, 1));
+        let mut episode = Episodic::new(grid, make_rng(0));
+        assert_eq!(episode.state(), None);
+        assert_eq!(episode.reset(), (0, 1));
+        assert_eq!(episode.step(&Move::Left), Ok(((0, 1), -0.1, false)));
+        assert_eq!(episode.step(&Move::Up), Ok(((0, 0), -0.1, false)));
+        assert_eq!(episode.step(&Move::Right), Ok(((1, 0), -0.1, false)));
+        assert_eq!(episode.step(&Move::Right), Ok(((2, 0), 1.0, true)));
+        assert_eq!(episode.state(), Some(&(2, 0)));
+        assert_eq!(episode.reset(), (0, 1));
+    }
+
+    #[test]
+    fn invalid_actions_are_refused() {
+        let mut episode = Episodic::new(Chain, make_rng(0));
+        assert_eq!(episode.step(&1), Err(ReinforceError::NotReset));
+        episode.reset();
+        assert_eq!(
+            episode.step(&2),
+            Err(ReinforceError::InvalidAction {
+                state: 0,
+                action: 2
+            })
+        );
+        assert_eq!(episode.state(), Some(&0));
+        assert_eq!(episode.step(&1), Ok((1, 0.0, false)));
+    }
+
+    #[test]
+    fn resets_follow_the_start_distribution() {
+        // Weights summing to 2, renormalized to 0.25, 0.5 and 0.25.
+        let weights = HashMap::from([(0, 0.5), (1, 1.0), (2, 0.5), (3, 0.0)]);
+        let mut episode = Episodic::new(Chain, make_rng(0)).with_start_distribution(weights);
+        let draws = 10_000;
+        let mut counts = HashMap::new();
+        for _ in 0..draws {
+            *counts.entry(episode.reset()).or_insert(0) += 1;
+        }
+        assert_eq!(counts.get(&3), None);
+        for (s, p) in [(0, 0.25), (1, 0.5), (2, 0.25)] {
+            assert!((counts[&s] as f32 / draws as f32 - p).abs() < 0.02, "{s}");
+        }
+    }
+}
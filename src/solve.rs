@@ -0,0 +1,152 @@
+//! A single entry point that picks a suitable algorithm, for when
+//! any reasonable solution will do.
+
+use crate::dp::{self, QTable};
+use crate::env::IEnvironment;
+use crate::rng::make_rng;
+use crate::td;
+
+/// The settings `solve` passes on to whichever algorithm it runs; see
+/// `Default` for the values used when left unset.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SolveConfig {
+    /// The discount, by default `env.gamma()`.
+    pub gamma: Option<f32>,
+    /// Value iteration's convergence threshold.
+    pub theta: f32,
+    /// Value iteration's limit on sweeps.
+    pub max_iters: usize,
+    /// The number of episodes of Q-learning.
+    pub episodes: usize,
+    /// Q-learning's step size.
+    pub alpha: f32,
+    /// Q-learning's exploration rate.
+    pub epsilon: f32,
+    /// The seed of the random number generator Q-learning samples
+    /// from.
+    pub seed: u64,
+}
+
+impl Default for SolveConfig {
+    fn default() -> Self {
+        SolveConfig {
+            gamma: None,
+            theta: 1e-6,
+            max_iters: 10_000,
+            episodes: 5_000,
+            alpha: 0.1,
+            epsilon: 0.1,
+            seed: 0,
+        }
+    }
+}
+
+/// Solves `env`, returning the action values of the policy found.
+///
+/// If the environment has a model to plan with, i.e. `all_states`
+/// lists some states and value iteration's backups are defined over
+/// them, this is `dp::value_iteration` followed by `dp::q_from_v`,
+/// giving the optimal action values. Otherwise the environment is
+/// learned from samples by `td::q_learning`, which only needs
+/// `start_states` and to be able to simulate steps, and whose values
+/// are only estimates.
+pub fn solve<E: IEnvironment>(env: &E, config: &SolveConfig) -> QTable<E> {
+    let gamma = config.gamma.unwrap_or_else(|| env.gamma());
+    if !env.all_states().is_empty() {
+        if let Some((v, _)) = dp::value_iteration(env, Some(gamma), config.theta, config.max_iters)
+        {
+            if let Some(q) = dp::q_from_v(env, &v, Some(gamma)) {
+                return q;
+            }
+        }
+    }
+    td::q_learning(
+        env,
+        config.episodes,
+        config.alpha,
+        Some(gamma),
+        config.epsilon,
+        &mut make_rng(config.seed),
+    )
+}
+
+#[cfg(all(test, feature = "examples"))]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::env::examples::{GridWorld, Move};
+
+    type Cell = (usize, usize);
+
+    /// A grid that can't list its states, so can only be learned from
+    /// samples.
+    struct Unlisted(GridWorld);
+
+    impl IEnvironment for Unlisted {
+        type State = Cell;
+        type Action = Move;
+
+        fn prob(&self, from: &Cell, take: &Move, to: &Cell, with: &f32) -> f32 {
+            self.0.prob(from, take, to, with)
+        }
+
+        fn actions_from(&self, from: &Cell) -> HashSet<Move> {
+            self.0.actions_from(from)
+        }
+
+        fn states_from(&self, from: &Cell, take: &Move) -> HashSet<Cell> {
+            self.0.states_from(from, take)
+        }
+
+        fn is_terminal(&self, state: &Cell) -> bool {
+            self.0.is_terminal(state)
+        }
+
+        fn start_states(&self) -> HashSet<Cell> {
+            self.0.start_states()
+        }
+
+        fn all_states(&self) -> HashSet<Cell> {
+            HashSet::new()
+        }
+
+        fn rewards(&self) -> Vec<f32> {
+            self.0.rewards()
+        }
+    }
+
+    /// The number of steps following `q` greedily takes from the start
+    /// to the goal, if it gets there.
+    fn greedy_steps<E>(env: &E, grid: &GridWorld, q: &QTable<E>) -> Option<usize>
+    where
+        E: IEnvironment<State = Cell, Action = Move>,
+    {
+        let mut state = grid.start();
+        for steps in 0..20 {
+            if state == grid.goal() {
+                return Some(steps);
+            }
+            state = grid.moved(&state, q.greedy_action(env, &state)?);
+        }
+        None
+    }
+
+    #[test]
+    fn solves_grids_with_and_without_a_model() {
+        let grid = GridWorld::new(3, 3, [(1, 1)], (2, 2), 0.1, 0.0);
+        let config = SolveConfig {
+            gamma: Some(0.9),
+            ..SolveConfig::default()
+        };
+
+        let planned = solve(&grid, &config);
+        let (v, _) = dp::value_iteration(&grid, Some(0.9), 1e-6, 1000).unwrap();
+        assert_eq!(planned, dp::q_from_v(&grid, &v, Some(0.9)).unwrap());
+        assert_eq!(greedy_steps(&grid, &grid, &planned), Some(4));
+
+        let unlisted = Unlisted(grid.clone());
+        let learned = solve(&unlisted, &config);
+        assert_eq!(greedy_steps(&unlisted, &grid, &learned), Some(4));
+    }
+}
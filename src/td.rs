@@ -0,0 +1,2021 @@
+use std::collections::{HashMap, VecDeque};
+
+use rand::Rng;
+
+use crate::dp::{expected_q, QTable, ValueFunction};
+use crate::env::IEnvironment;
+use crate::observer::{NoObserver, TrainingObserver};
+use crate::policy::{fixed_order, Policy};
+use crate::rng::StdRng;
+use crate::schedule::Schedule;
+use crate::simulate::{self, EPISODE_STEP_LIMIT};
+
+/// The step size used by the temporal-difference updates.
+#[derive(Debug)]
+pub enum LearningRate {
+    /// A fixed step size.
+    Constant(f32),
+    /// A step size of `alpha / n`, where `n` is the number of times
+    /// the state-action pair being updated has been visited.
+    VisitDecayed(f32),
+    /// A step size of `schedule.value(n - 1)` on the `n`-th visit to
+    /// the state-action pair being updated, so each pair anneals
+    /// along the schedule as it is visited.
+    Scheduled(Schedule),
+}
+
+impl LearningRate {
+    /// The step size for an update on the `visits`-th visit of a
+    /// state-action pair.
+    pub fn at(&self, visits: usize) -> f32 {
+        match self {
+            LearningRate::Constant(alpha) => *alpha,
+            LearningRate::VisitDecayed(alpha) => alpha / visits.max(1) as f32,
+            LearningRate::Scheduled(schedule) => schedule.value(visits.saturating_sub(1)),
+        }
+    }
+}
+
+impl From<f32> for LearningRate {
+    fn from(alpha: f32) -> Self {
+        LearningRate::Constant(alpha)
+    }
+}
+
+impl From<Schedule> for LearningRate {
+    fn from(schedule: Schedule) -> Self {
+        LearningRate::Scheduled(schedule)
+    }
+}
+
+/// Picks a uniformly random action from `state` with probability
+/// `epsilon`, and the greedy action under `q` otherwise. Returns
+/// `None` if `state` has no available actions.
+pub(crate) fn epsilon_greedy<E: IEnvironment>(
+    env: &E,
+    q: &QTable<E>,
+    state: &E::State,
+    epsilon: f32,
+    rng: &mut StdRng,
+) -> Option<E::Action> {
+    if rng.gen::<f32>() < epsilon {
+        random_action(env, state, rng)
+    } else {
+        q.greedy_action(env, state)
+    }
+}
+
+/// A uniformly random action from `state`, or `None` if it has no
+/// available actions.
+fn random_action<E: IEnvironment>(
+    env: &E,
+    state: &E::State,
+    rng: &mut StdRng,
+) -> Option<E::Action> {
+    let mut actions = fixed_order(env.action_mask(state));
+    if actions.is_empty() {
+        return None;
+    }
+    let i = rng.gen_range(0..actions.len());
+    Some(actions.swap_remove(i))
+}
+
+/// Moves the value of `key` in `q` a step of size `alpha` towards
+/// `target`, counting the visit to `key`.
+pub(crate) fn update<E: IEnvironment>(
+    q: &mut QTable<E>,
+    visits: &mut HashMap<(E::State, E::Action), usize>,
+    key: (E::State, E::Action),
+    alpha: &LearningRate,
+    target: f32,
+) {
+    let n = visits.entry(key.clone()).or_insert(0);
+    *n += 1;
+    let step = alpha.at(*n);
+    let (state, action) = key;
+    let value = q.get_mut(state, action);
+    *value += step * (target - *value);
+}
+
+/// What happened in one episode of temporal-difference control, as
+/// recorded by `q_learning_traced`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EpisodeMetrics {
+    /// The number of steps taken.
+    pub steps: usize,
+    /// The undiscounted sum of the episode's rewards.
+    pub total_reward: f32,
+    /// The mean temporal-difference error, the update target less the
+    /// value it updated, over the episode's steps, or 0 if it took
+    /// none.
+    pub td_error_mean: f32,
+    /// The exploration rate the episode's first action was chosen with.
+    pub epsilon: f32,
+    /// Whether the episode was cut off at `EPISODE_STEP_LIMIT` steps,
+    /// rather than ending on its own.
+    pub capped: bool,
+}
+
+/// Runs `episodes` episodes of epsilon-greedy temporal-difference
+/// control, the loop shared by `q_learning`, `sarsa` and
+/// `expected_sarsa`, continuing from the action values `q`.
+///
+/// Episodes start from states drawn uniformly from
+/// `env.start_states()`, or with probability `restart` from any
+/// non-terminal state of `env.all_states()` with actions, and end on
+/// reaching a terminal state or after `EPISODE_STEP_LIMIT` steps. At
+/// each step the next action is
+/// selected epsilon-greedily before `Q(s,a)` is moved towards
+/// `r + gamma * next_value(q, s', a', epsilon)`, where `a'` is `None`
+/// if `s'` is terminal or has no actions and `epsilon` is the rate it
+/// was selected with; `next_value` is what distinguishes the
+/// algorithms. Both schedules advance with every step taken, counted
+/// across episodes: `epsilon` by the total number of steps, and
+/// `alpha` by the visits to each pair. `observer` is told about every
+/// episode as it ends, and may stop training there, and each episode's
+/// `EpisodeMetrics` are pushed onto `metrics`, if given.
+#[allow(clippy::too_many_arguments)]
+fn control<E, O, F>(
+    env: &E,
+    mut q: QTable<E>,
+    episodes: usize,
+    alpha: &LearningRate,
+    gamma: f32,
+    epsilon: &Schedule,
+    rng: &mut StdRng,
+    observer: &mut O,
+    restart: f32,
+    mut metrics: Option<&mut Vec<EpisodeMetrics>>,
+    next_value: F,
+) -> QTable<E>
+where
+    E: IEnvironment,
+    O: TrainingObserver<E>,
+    F: Fn(&QTable<E>, &E::State, Option<&E::Action>, f32) -> f32,
+{
+    let restarts = if restart > 0.0 {
+        fixed_order(env.all_states())
+            .into_iter()
+            .filter(|s| !env.is_terminal(s) && !env.action_mask(s).is_empty())
+            .collect()
+    } else {
+        Vec::new()
+    };
+    let mut visits: HashMap<(E::State, E::Action), usize> = HashMap::new();
+    let mut steps = 0;
+    for episode in 0..episodes {
+        let restarted = (!restarts.is_empty() && rng.gen::<f32>() < restart)
+            .then(|| restarts[rng.gen_range(0..restarts.len())].clone());
+        let Some(mut state) = restarted.or_else(|| simulate::sample_start(env, rng)) else {
+            break;
+        };
+        let mut total = 0.0;
+        let first_epsilon = epsilon.value(steps);
+        let (mut taken, mut td_errors, mut capped) = (0, 0.0, false);
+        'episode: {
+            if env.is_terminal(&state) {
+                break 'episode;
+            }
+            let Some(mut action) = epsilon_greedy(env, &q, &state, first_epsilon, rng) else {
+                break 'episode;
+            };
+            for _ in 0..EPISODE_STEP_LIMIT {
+                let Some((next, reward)) = env.sample_transition(&state, &action, rng) else {
+                    break;
+                };
+                steps += 1;
+                taken += 1;
+                total += reward;
+                let eps = epsilon.value(steps);
+                let next_action = if env.is_terminal(&next) {
+                    None
+                } else {
+                    epsilon_greedy(env, &q, &next, eps, rng)
+                };
+                let target = reward + gamma * next_value(&q, &next, next_action.as_ref(), eps);
+                td_errors += target - q.get(&state, &action);
+                update::<E>(&mut q, &mut visits, (state, action), alpha, target);
+                match next_action {
+                    Some(a) => {
+                        state = next;
+                        action = a;
+                        capped = taken == EPISODE_STEP_LIMIT;
+                    }
+                    None => break,
+                }
+            }
+        }
+        if let Some(metrics) = metrics.as_mut() {
+            metrics.push(EpisodeMetrics {
+                steps: taken,
+                total_reward: total,
+                td_error_mean: if taken > 0 {
+                    td_errors / taken as f32
+                } else {
+                    0.0
+                },
+                epsilon: first_epsilon,
+                capped,
+            });
+        }
+        if observer.on_episode_end(episode, total, &q).is_break() {
+            break;
+        }
+    }
+    q
+}
+
+/// Tabular Q-learning: off-policy temporal-difference control.
+///
+/// Runs `episodes` episodes from states drawn uniformly from
+/// `env.start_states()`, choosing actions epsilon-greedily from the
+/// current Q-table and applying the update
+/// `Q(s,a) += alpha * (r + gamma * max_a' Q(s',a') - Q(s,a))` after
+/// every step. Episodes end on reaching a terminal state or after
+/// `EPISODE_STEP_LIMIT` steps. `epsilon` may be annealed with a
+/// `Schedule` over the total number of steps taken, as may `alpha`,
+/// via `LearningRate::Scheduled`, over the visits to each pair.
+/// `gamma` defaults to `env.gamma()`.
+pub fn q_learning<E: IEnvironment>(
+    env: &E,
+    episodes: usize,
+    alpha: impl Into<LearningRate>,
+    gamma: Option<f32>,
+    epsilon: impl Into<Schedule>,
+    rng: &mut StdRng,
+) -> QTable<E> {
+    q_learning_observed(env, episodes, alpha, gamma, epsilon, rng, &mut NoObserver)
+}
+
+/// As `q_learning`, but reporting each episode's total reward to
+/// `observer` as it ends, and stopping training early if it returns
+/// `ControlFlow::Break`.
+#[allow(clippy::too_many_arguments)]
+pub fn q_learning_observed<E, O>(
+    env: &E,
+    episodes: usize,
+    alpha: impl Into<LearningRate>,
+    gamma: Option<f32>,
+    epsilon: impl Into<Schedule>,
+    rng: &mut StdRng,
+    observer: &mut O,
+) -> QTable<E>
+where
+    E: IEnvironment,
+    O: TrainingObserver<E>,
+{
+    let gamma = gamma.unwrap_or_else(|| env.gamma());
+    q_learning_continued(
+        env,
+        QTable::new(),
+        episodes,
+        &alpha.into(),
+        gamma,
+        &epsilon.into(),
+        rng,
+        observer,
+    )
+}
+
+/// As `q_learning`, but starting from the action values `q` rather
+/// than from an empty table, such as an optimistic `QTable::filled`
+/// one.
+///
+/// With every unseen pair valued above any return the environment can
+/// give, each pair tried falls below those not yet tried, so even the
+/// greedy policy, with `epsilon` = 0, goes on to try them in turn
+/// until the values have settled.
+pub fn q_learning_from<E: IEnvironment>(
+    env: &E,
+    q: QTable<E>,
+    episodes: usize,
+    alpha: impl Into<LearningRate>,
+    gamma: Option<f32>,
+    epsilon: impl Into<Schedule>,
+    rng: &mut StdRng,
+) -> QTable<E> {
+    q_learning_continued(
+        env,
+        q,
+        episodes,
+        &alpha.into(),
+        gamma.unwrap_or_else(|| env.gamma()),
+        &epsilon.into(),
+        rng,
+        &mut NoObserver,
+    )
+}
+
+/// As `q_learning_observed`, but starting from the action values `q`
+/// rather than from an empty table.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn q_learning_continued<E, O>(
+    env: &E,
+    q: QTable<E>,
+    episodes: usize,
+    alpha: &LearningRate,
+    gamma: f32,
+    epsilon: &Schedule,
+    rng: &mut StdRng,
+    observer: &mut O,
+) -> QTable<E>
+where
+    E: IEnvironment,
+    O: TrainingObserver<E>,
+{
+    control(
+        env,
+        q,
+        episodes,
+        alpha,
+        gamma,
+        epsilon,
+        rng,
+        observer,
+        0.0,
+        None,
+        |q, next, _, _| q.max_action_value(env, next),
+    )
+}
+
+/// As `q_learning`, but also returning the `EpisodeMetrics` of every
+/// episode, in order, for plotting learning curves.
+pub fn q_learning_traced<E: IEnvironment>(
+    env: &E,
+    episodes: usize,
+    alpha: impl Into<LearningRate>,
+    gamma: Option<f32>,
+    epsilon: impl Into<Schedule>,
+    rng: &mut StdRng,
+) -> (QTable<E>, Vec<EpisodeMetrics>) {
+    let mut metrics = Vec::with_capacity(episodes);
+    let q = control(
+        env,
+        QTable::new(),
+        episodes,
+        &alpha.into(),
+        gamma.unwrap_or_else(|| env.gamma()),
+        &epsilon.into(),
+        rng,
+        &mut NoObserver,
+        0.0,
+        Some(&mut metrics),
+        |q, next, _, _| q.max_action_value(env, next),
+    );
+    (q, metrics)
+}
+
+/// As `q_learning`, but starting each episode, with probability
+/// `restart`, from a state drawn uniformly from the non-terminal states
+/// of `env.all_states()` with actions, rather than from
+/// `env.start_states()`.
+///
+/// Where the rewards lie far from the start, epsilon-greedy episodes
+/// from it may never get there; restarting all over the state space
+/// has episodes begin near the rewards too, from where their values
+/// are learned and carried back towards the start.
+pub fn q_learning_with_restarts<E: IEnvironment>(
+    env: &E,
+    episodes: usize,
+    alpha: impl Into<LearningRate>,
+    gamma: Option<f32>,
+    epsilon: impl Into<Schedule>,
+    restart: f32,
+    rng: &mut StdRng,
+) -> QTable<E> {
+    control(
+        env,
+        QTable::new(),
+        episodes,
+        &alpha.into(),
+        gamma.unwrap_or_else(|| env.gamma()),
+        &epsilon.into(),
+        rng,
+        &mut NoObserver,
+        restart,
+        None,
+        |q, next, _, _| q.max_action_value(env, next),
+    )
+}
+
+/// How many times each state-action pair has been visited, for
+/// count-based exploration bonuses.
+pub struct VisitCounts<E: IEnvironment> {
+    counts: HashMap<(E::State, E::Action), usize>,
+}
+
+impl<E: IEnvironment> VisitCounts<E> {
+    /// The count an unvisited pair is given by `bonus`, so that its
+    /// bonus is large, ten times `beta`, but finite.
+    pub const UNVISITED: f32 = 0.01;
+
+    pub fn new() -> Self {
+        VisitCounts {
+            counts: HashMap::new(),
+        }
+    }
+
+    /// Counts a visit to taking `action` in `state`, returning the new
+    /// count.
+    pub fn increment(&mut self, state: E::State, action: E::Action) -> usize {
+        let n = self.counts.entry((state, action)).or_insert(0);
+        *n += 1;
+        *n
+    }
+
+    pub fn count(&self, state: &E::State, action: &E::Action) -> usize {
+        self.counts
+            .get(&(state.clone(), action.clone()))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// The exploration bonus `beta / sqrt(count)` of taking `action` in
+    /// `state`, counting an unvisited pair as `UNVISITED` visits.
+    pub fn bonus(&self, state: &E::State, action: &E::Action, beta: f32) -> f32 {
+        let n = match self.count(state, action) {
+            0 => Self::UNVISITED,
+            n => n as f32,
+        };
+        beta / n.sqrt()
+    }
+}
+
+impl<E: IEnvironment> Default for VisitCounts<E> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Q-learning with a count-based exploration bonus: as `q_learning`,
+/// but learning from the reward plus `beta / sqrt(n)` on the `n`-th
+/// visit to each state-action pair, and exploiting by the greatest
+/// `Q(s,a)` plus the bonus `VisitCounts::bonus` would give the pair
+/// next, so that rarely tried actions look better than they are and
+/// untried ones best of all.
+///
+/// On sparse rewards this draws the agent on to unexplored parts of
+/// the environment long after epsilon-greedy exploration alone would
+/// have settled. The bonus shrinks with every visit, and the learned
+/// Q-values include what's left of it. Returns the Q-table along with
+/// the visit counts.
+pub fn q_learning_with_bonus<E: IEnvironment>(
+    env: &E,
+    episodes: usize,
+    alpha: impl Into<LearningRate>,
+    gamma: Option<f32>,
+    epsilon: impl Into<Schedule>,
+    beta: f32,
+    rng: &mut StdRng,
+) -> (QTable<E>, VisitCounts<E>) {
+    let gamma = gamma.unwrap_or_else(|| env.gamma());
+    let alpha = alpha.into();
+    let epsilon = epsilon.into();
+    let mut q = QTable::<E>::new();
+    let mut visits: HashMap<(E::State, E::Action), usize> = HashMap::new();
+    let mut counts = VisitCounts::new();
+    let mut steps = 0;
+    for _ in 0..episodes {
+        let Some(mut state) = simulate::sample_start(env, rng) else {
+            break;
+        };
+        for _ in 0..EPISODE_STEP_LIMIT {
+            if env.is_terminal(&state) {
+                break;
+            }
+            let action = if rng.gen::<f32>() < epsilon.value(steps) {
+                random_action(env, &state, rng)
+            } else {
+                let optimistic = fixed_order(env.action_mask(&state)).into_iter().map(|a| {
+                    let value = q.get(&state, &a) + counts.bonus(&state, &a, beta);
+                    (a, value)
+                });
+                optimistic
+                    .reduce(|best, next| if next.1 > best.1 { next } else { best })
+                    .map(|(a, _)| a)
+            };
+            let Some(action) = action else {
+                break;
+            };
+            let Some((next, reward)) = env.sample_transition(&state, &action, rng) else {
+                break;
+            };
+            steps += 1;
+            counts.increment(state.clone(), action.clone());
+            let bonus = counts.bonus(&state, &action, beta);
+            let target = reward + bonus + gamma * q.max_action_value(env, &next);
+            update::<E>(&mut q, &mut visits, (state, action), &alpha, target);
+            state = next;
+        }
+    }
+    (q, counts)
+}
+
+/// Double Q-learning: Q-learning with two Q-tables, each bootstrapped
+/// from the other's estimate, which removes the maximization bias of
+/// taking the maximum over noisy estimates.
+///
+/// Actions are chosen epsilon-greedily from the sum of the two
+/// tables. After every step one table, chosen by a fair coin, is
+/// updated with
+/// `Q1(s,a) += alpha * (r + gamma * Q2(s', argmax_a' Q1(s',a')) - Q1(s,a))`,
+/// with roles swapped for the other. Transitions into a terminal
+/// state use a next value of 0. `gamma` defaults to `env.gamma()`.
+pub fn double_q_learning<E: IEnvironment>(
+    env: &E,
+    episodes: usize,
+    alpha: impl Into<LearningRate>,
+    gamma: Option<f32>,
+    epsilon: impl Into<Schedule>,
+    rng: &mut StdRng,
+) -> (QTable<E>, QTable<E>) {
+    double_q_learning_observed(env, episodes, alpha, gamma, epsilon, rng, &mut NoObserver)
+}
+
+/// As `double_q_learning`, but reporting each episode to `observer`
+/// as it ends, along with the sum of the two tables, and stopping
+/// training early if it returns `ControlFlow::Break`.
+#[allow(clippy::too_many_arguments)]
+pub fn double_q_learning_observed<E, O>(
+    env: &E,
+    episodes: usize,
+    alpha: impl Into<LearningRate>,
+    gamma: Option<f32>,
+    epsilon: impl Into<Schedule>,
+    rng: &mut StdRng,
+    observer: &mut O,
+) -> (QTable<E>, QTable<E>)
+where
+    E: IEnvironment,
+    O: TrainingObserver<E>,
+{
+    let gamma = gamma.unwrap_or_else(|| env.gamma());
+    let alpha = alpha.into();
+    let epsilon = epsilon.into();
+    let mut tables = [QTable::<E>::new(), QTable::<E>::new()];
+    let mut visits = [HashMap::new(), HashMap::new()];
+    let mut steps = 0;
+    for episode in 0..episodes {
+        let Some(mut state) = simulate::sample_start(env, rng) else {
+            break;
+        };
+        let mut total = 0.0;
+        for _ in 0..EPISODE_STEP_LIMIT {
+            if env.is_terminal(&state) {
+                break;
+            }
+            let sum: QTable<E> = env
+                .action_mask(&state)
+                .into_iter()
+                .map(|a| {
+                    let value = tables.iter().map(|q| q.get(&state, &a)).sum();
+                    ((state.clone(), a), value)
+                })
+                .collect();
+            let Some(action) = epsilon_greedy(env, &sum, &state, epsilon.value(steps), rng) else {
+                break;
+            };
+            let Some((next, reward)) = env.sample_transition(&state, &action, rng) else {
+                break;
+            };
+            steps += 1;
+            total += reward;
+            let (i, j) = if rng.gen::<bool>() { (0, 1) } else { (1, 0) };
+            let next_value = if env.is_terminal(&next) {
+                0.0
+            } else {
+                tables[i]
+                    .greedy_action(env, &next)
+                    .map_or(0.0, |a| tables[j].get(&next, &a))
+            };
+            let target = reward + gamma * next_value;
+            update::<E>(
+                &mut tables[i],
+                &mut visits[i],
+                (state, action),
+                &alpha,
+                target,
+            );
+            state = next;
+        }
+        let mut sum = tables[0].clone();
+        for (key, value) in &tables[1] {
+            *sum.get_mut(key.0.clone(), key.1.clone()) += value;
+        }
+        if observer.on_episode_end(episode, total, &sum).is_break() {
+            break;
+        }
+    }
+    let [q1, q2] = tables;
+    (q1, q2)
+}
+
+/// SARSA: on-policy temporal-difference control.
+///
+/// Mirrors `q_learning`, but bootstraps from the action actually
+/// selected in the next state rather than the greedy one, applying
+/// `Q(s,a) += alpha * (r + gamma * Q(s',a') - Q(s,a))`. Transitions
+/// into a terminal state use a next value of 0.
+pub fn sarsa<E: IEnvironment>(
+    env: &E,
+    episodes: usize,
+    alpha: impl Into<LearningRate>,
+    gamma: Option<f32>,
+    epsilon: impl Into<Schedule>,
+    rng: &mut StdRng,
+) -> QTable<E> {
+    sarsa_observed(env, episodes, alpha, gamma, epsilon, rng, &mut NoObserver)
+}
+
+/// As `sarsa`, but reporting each episode's total reward to
+/// `observer` as it ends, and stopping training early if it returns
+/// `ControlFlow::Break`.
+#[allow(clippy::too_many_arguments)]
+pub fn sarsa_observed<E, O>(
+    env: &E,
+    episodes: usize,
+    alpha: impl Into<LearningRate>,
+    gamma: Option<f32>,
+    epsilon: impl Into<Schedule>,
+    rng: &mut StdRng,
+    observer: &mut O,
+) -> QTable<E>
+where
+    E: IEnvironment,
+    O: TrainingObserver<E>,
+{
+    let gamma = gamma.unwrap_or_else(|| env.gamma());
+    control(
+        env,
+        QTable::new(),
+        episodes,
+        &alpha.into(),
+        gamma,
+        &epsilon.into(),
+        rng,
+        observer,
+        0.0,
+        None,
+        |q, next, a, _| a.map_or(0.0, |a| q.get(next, a)),
+    )
+}
+
+/// As `sarsa`, but restarting episodes all over the state space with
+/// probability `restart`, as `q_learning_with_restarts` does.
+pub fn sarsa_with_restarts<E: IEnvironment>(
+    env: &E,
+    episodes: usize,
+    alpha: impl Into<LearningRate>,
+    gamma: Option<f32>,
+    epsilon: impl Into<Schedule>,
+    restart: f32,
+    rng: &mut StdRng,
+) -> QTable<E> {
+    control(
+        env,
+        QTable::new(),
+        episodes,
+        &alpha.into(),
+        gamma.unwrap_or_else(|| env.gamma()),
+        &epsilon.into(),
+        rng,
+        &mut NoObserver,
+        restart,
+        None,
+        |q, next, a, _| a.map_or(0.0, |a| q.get(next, a)),
+    )
+}
+
+/// Expected SARSA: temporal-difference control bootstrapping from
+/// the expected next value under the epsilon-greedy policy,
+/// `sum_a' pi(a'|s') Q(s',a')`, rather than from a sampled action.
+///
+/// Mirrors `sarsa` otherwise. Averaging over the next action removes
+/// the variance SARSA's update has from sampling it.
+pub fn expected_sarsa<E: IEnvironment>(
+    env: &E,
+    episodes: usize,
+    alpha: impl Into<LearningRate>,
+    gamma: Option<f32>,
+    epsilon: impl Into<Schedule>,
+    rng: &mut StdRng,
+) -> QTable<E> {
+    expected_sarsa_observed(env, episodes, alpha, gamma, epsilon, rng, &mut NoObserver)
+}
+
+/// As `expected_sarsa`, but reporting each episode's total reward to
+/// `observer` as it ends, and stopping training early if it returns
+/// `ControlFlow::Break`.
+#[allow(clippy::too_many_arguments)]
+pub fn expected_sarsa_observed<E, O>(
+    env: &E,
+    episodes: usize,
+    alpha: impl Into<LearningRate>,
+    gamma: Option<f32>,
+    epsilon: impl Into<Schedule>,
+    rng: &mut StdRng,
+    observer: &mut O,
+) -> QTable<E>
+where
+    E: IEnvironment,
+    O: TrainingObserver<E>,
+{
+    let gamma = gamma.unwrap_or_else(|| env.gamma());
+    control(
+        env,
+        QTable::new(),
+        episodes,
+        &alpha.into(),
+        gamma,
+        &epsilon.into(),
+        rng,
+        observer,
+        0.0,
+        None,
+        |q, next, _, epsilon| epsilon_greedy_value(env, q, next, epsilon),
+    )
+}
+
+/// The expected value in `q` of the epsilon-greedy action from
+/// `state`; 0 for terminal states and states without actions.
+fn epsilon_greedy_value<E: IEnvironment>(
+    env: &E,
+    q: &QTable<E>,
+    state: &E::State,
+    epsilon: f32,
+) -> f32 {
+    if env.is_terminal(state) {
+        return 0.0;
+    }
+    let actions = env.action_mask(state);
+    let Some(greedy) = q.greedy_action(env, state) else {
+        return 0.0;
+    };
+    let uniform = epsilon / actions.len() as f32;
+    actions
+        .into_iter()
+        .map(|a| {
+            let p = if a == greedy {
+                1.0 - epsilon + uniform
+            } else {
+                uniform
+            };
+            p * q.get(state, &a)
+        })
+        .sum()
+}
+
+/// How the eligibility trace of a state is bumped when it is visited
+/// in `td_lambda`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceKind {
+    /// Adds 1 to the trace, so states visited repeatedly in quick
+    /// succession build up traces larger than 1.
+    Accumulating,
+    /// Resets the trace to 1.
+    Replacing,
+}
+
+/// Traces that have decayed below this are dropped.
+const TRACE_THRESHOLD: f32 = 1e-6;
+
+/// n-step temporal-difference prediction: estimates the state-value
+/// function of `policy`, bootstrapping each update from the value of
+/// the state reached `n` steps later.
+///
+/// Each update moves `V(s_t)` towards the n-step return
+/// `r_{t+1} + gamma * r_{t+2} + ... + gamma^(n-1) * r_{t+n}
+/// + gamma^n * V(s_{t+n})`, keeping only the last `n` states and
+/// rewards. Near the end of an episode fewer than `n` steps remain,
+/// and the return is truncated to the rewards available without
+/// bootstrapping; an episode that hits `EPISODE_STEP_LIMIT` is
+/// treated the same way. Hence `n = 1` is TD(0), and `n` at least the
+/// episode length is every-visit Monte Carlo with a constant step
+/// size. The step size comes from `alpha`, keyed by the number of
+/// visits to the updated state, so `LearningRate::VisitDecayed(1.0)`
+/// averages each state's returns instead. `gamma` defaults to
+/// `env.gamma()`.
+pub fn n_step_td<E, P>(
+    env: &E,
+    policy: &P,
+    n: usize,
+    episodes: usize,
+    alpha: impl Into<LearningRate>,
+    gamma: Option<f32>,
+    rng: &mut StdRng,
+) -> ValueFunction<E>
+where
+    E: IEnvironment,
+    P: Policy<E>,
+{
+    n_step(
+        env,
+        policy,
+        n,
+        episodes,
+        alpha.into(),
+        gamma.unwrap_or_else(|| env.gamma()),
+        rng,
+        |_, _| 1.0,
+    )
+}
+
+/// Off-policy n-step temporal-difference prediction: estimates the
+/// state-value function of `target` from episodes generated by
+/// following `behavior`.
+///
+/// Mirrors `n_step_td`, but scales each update by the
+/// importance-sampling ratio of the actions its return covers,
+/// `prod pi(a_k|s_k) / b(a_k|s_k)` for `k` from `t` to `t + n - 1`,
+/// so `V(s_t)` moves by `alpha * rho * (G - V(s_t))`. If the target
+/// would never take one of those actions the product is zero and the
+/// return contributes nothing: `V(s_t)` is left unchanged and the
+/// visit isn't counted. `behavior` must cover `target`, and when the
+/// two are the same policy this is exactly `n_step_td`. `gamma`
+/// defaults to `env.gamma()`.
+#[allow(clippy::too_many_arguments)]
+pub fn off_policy_n_step<E, B, T>(
+    env: &E,
+    behavior: &B,
+    target: &T,
+    n: usize,
+    episodes: usize,
+    alpha: impl Into<LearningRate>,
+    gamma: Option<f32>,
+    rng: &mut StdRng,
+) -> ValueFunction<E>
+where
+    E: IEnvironment,
+    B: Policy<E>,
+    T: Policy<E>,
+{
+    n_step(
+        env,
+        behavior,
+        n,
+        episodes,
+        alpha.into(),
+        gamma.unwrap_or_else(|| env.gamma()),
+        rng,
+        |s, a| {
+            let b = behavior.action_prob(env, s, a);
+            if b > 0.0 {
+                target.action_prob(env, s, a) / b
+            } else {
+                0.0
+            }
+        },
+    )
+}
+
+/// The n-step prediction loop shared by `n_step_td` and
+/// `off_policy_n_step`, following `policy` and scaling each update by
+/// the product of `ratio(s_k, a_k)` over the steps its return covers.
+#[allow(clippy::too_many_arguments)]
+fn n_step<E, P, R>(
+    env: &E,
+    policy: &P,
+    n: usize,
+    episodes: usize,
+    alpha: LearningRate,
+    gamma: f32,
+    rng: &mut StdRng,
+    ratio: R,
+) -> ValueFunction<E>
+where
+    E: IEnvironment,
+    P: Policy<E>,
+    R: Fn(&E::State, &E::Action) -> f32,
+{
+    let n = n.max(1);
+    let mut v = ValueFunction::<E>::new();
+    let mut visits: HashMap<E::State, usize> = HashMap::new();
+    for _ in 0..episodes {
+        let Some(start) = simulate::sample_start(env, rng) else {
+            break;
+        };
+        let mut states = VecDeque::from([start]);
+        let mut rewards: VecDeque<f32> = VecDeque::new();
+        let mut ratios: VecDeque<f32> = VecDeque::new();
+        let mut steps = 0;
+        let mut done = false;
+        loop {
+            if !done {
+                let state = states.back().unwrap();
+                if steps >= EPISODE_STEP_LIMIT
+                    || env.is_terminal(state)
+                    || env.actions_from(state).is_empty()
+                {
+                    done = true;
+                } else {
+                    let action = policy.sample(env, state, rng);
+                    match env.sample_transition(state, &action, rng) {
+                        Some((next, reward)) => {
+                            ratios.push_back(ratio(state, &action));
+                            states.push_back(next);
+                            rewards.push_back(reward);
+                            steps += 1;
+                        }
+                        None => done = true,
+                    }
+                }
+            }
+
+            if rewards.is_empty() {
+                if done {
+                    break;
+                }
+                continue;
+            }
+            if rewards.len() < n && !done {
+                continue;
+            }
+
+            let mut g: f32 = rewards.iter().rev().fold(0.0, |g, r| r + gamma * g);
+            if !done {
+                let last = states.back().unwrap();
+                if !env.is_terminal(last) {
+                    g += gamma.powi(n as i32) * v.get(last).copied().unwrap_or(0.0);
+                }
+            }
+            let rho: f32 = ratios.iter().product();
+            let state = states.pop_front().unwrap();
+            rewards.pop_front();
+            ratios.pop_front();
+            if rho == 0.0 {
+                continue;
+            }
+
+            let visits = visits.entry(state.clone()).or_insert(0);
+            *visits += 1;
+            let value = v.entry(state).or_insert(0.0);
+            *value += alpha.at(*visits) * rho * (g - *value);
+        }
+    }
+    v
+}
+
+/// TD(lambda) prediction: estimates the state-value function of
+/// `policy` online, with eligibility traces spreading each TD error
+/// back over recently visited states.
+///
+/// After every step, each traced state moves by `alpha * delta *
+/// e(s)`, where `delta` is the one-step TD error, and every trace then
+/// decays by `gamma * lambda`. Traces are cleared at the start of each
+/// episode and dropped once they decay below a small threshold, so
+/// only the states visited recently are updated. `lambda = 0` is
+/// TD(0), and `lambda = 1` with accumulating traces is online
+/// every-visit Monte Carlo. Each traced state's step size comes from
+/// `alpha`, keyed by its visit count. `gamma` defaults to
+/// `env.gamma()`.
+#[allow(clippy::too_many_arguments)]
+pub fn td_lambda<E, P>(
+    env: &E,
+    policy: &P,
+    lambda: f32,
+    traces: TraceKind,
+    episodes: usize,
+    alpha: impl Into<LearningRate>,
+    gamma: Option<f32>,
+    rng: &mut StdRng,
+) -> ValueFunction<E>
+where
+    E: IEnvironment,
+    P: Policy<E>,
+{
+    let gamma = gamma.unwrap_or_else(|| env.gamma());
+    let alpha = alpha.into();
+    let mut v = ValueFunction::<E>::new();
+    let mut visits: HashMap<E::State, usize> = HashMap::new();
+    let mut e: HashMap<E::State, f32> = HashMap::new();
+    for _ in 0..episodes {
+        let Some(mut state) = simulate::sample_start(env, rng) else {
+            break;
+        };
+        e.clear();
+        for _ in 0..EPISODE_STEP_LIMIT {
+            if env.is_terminal(&state) || env.actions_from(&state).is_empty() {
+                break;
+            }
+            let action = policy.sample(env, &state, rng);
+            let Some((next, reward)) = env.sample_transition(&state, &action, rng) else {
+                break;
+            };
+
+            let next_value = if env.is_terminal(&next) {
+                0.0
+            } else {
+                v.get(&next).copied().unwrap_or(0.0)
+            };
+            let delta = reward + gamma * next_value - v.get(&state).copied().unwrap_or(0.0);
+
+            let trace = e.entry(state.clone()).or_insert(0.0);
+            *trace = match traces {
+                TraceKind::Accumulating => *trace + 1.0,
+                TraceKind::Replacing => 1.0,
+            };
+            *visits.entry(state).or_insert(0) += 1;
+
+            e.retain(|s, trace| {
+                let value = v.entry(s.clone()).or_insert(0.0);
+                *value += alpha.at(visits[s]) * delta * *trace;
+                *trace *= gamma * lambda;
+                *trace >= TRACE_THRESHOLD
+            });
+            state = next;
+        }
+    }
+    v
+}
+
+/// Retrace(lambda): estimates the action-value function of `target`
+/// online from episodes generated by following `behavior`, with
+/// eligibility traces cut by truncated importance ratios.
+///
+/// After every step from `s` with action `a`, each traced pair moves
+/// by `alpha * delta * e(s,a)`, where `delta` is the TD error
+/// `r + gamma * sum_a' pi(a'|s') Q(s',a') - Q(s,a)` of the expected
+/// target, and the existing traces have first decayed by `gamma *
+/// c`, with `c = lambda * min(1, pi(a|s) / b(a|s))`, before the trace
+/// of `(s,a)` is bumped by 1. Clipping the ratio at 1 keeps the
+/// traces from growing however unlike the policies are, so the
+/// estimate stays low-variance, at the cost of cutting traces short
+/// where the target is likelier than the behavior to act. With
+/// `behavior` the same as `target` the ratio is 1 and this is TD(lambda)
+/// over action values, and with `lambda = 0` it is one-step expected
+/// SARSA under the target. `behavior` must cover `target`. Traces are
+/// cleared at the start of each episode and dropped once they decay
+/// below a small threshold. Each traced pair's step size comes from
+/// `alpha`, keyed by its visit count. `gamma` defaults to
+/// `env.gamma()`.
+#[allow(clippy::too_many_arguments)]
+pub fn retrace<E, B, T>(
+    env: &E,
+    behavior: &B,
+    target: &T,
+    episodes: usize,
+    alpha: impl Into<LearningRate>,
+    gamma: Option<f32>,
+    lambda: f32,
+    rng: &mut StdRng,
+) -> QTable<E>
+where
+    E: IEnvironment,
+    B: Policy<E>,
+    T: Policy<E>,
+{
+    let gamma = gamma.unwrap_or_else(|| env.gamma());
+    let alpha = alpha.into();
+    let mut q = QTable::<E>::new();
+    let mut visits: HashMap<(E::State, E::Action), usize> = HashMap::new();
+    let mut e: HashMap<(E::State, E::Action), f32> = HashMap::new();
+    for _ in 0..episodes {
+        let Some(mut state) = simulate::sample_start(env, rng) else {
+            break;
+        };
+        e.clear();
+        for _ in 0..EPISODE_STEP_LIMIT {
+            if env.is_terminal(&state) || env.actions_from(&state).is_empty() {
+                break;
+            }
+            let action = behavior.sample(env, &state, rng);
+            let Some((next, reward)) = env.sample_transition(&state, &action, rng) else {
+                break;
+            };
+
+            let next_value = expected_q(env, &q, target, &next);
+            let delta = reward + gamma * next_value - q.get(&state, &action);
+
+            let b = behavior.action_prob(env, &state, &action);
+            let ratio = if b > 0.0 {
+                target.action_prob(env, &state, &action) / b
+            } else {
+                0.0
+            };
+            let c = lambda * ratio.min(1.0);
+            e.retain(|_, trace| {
+                *trace *= gamma * c;
+                *trace >= TRACE_THRESHOLD
+            });
+            let key = (state, action);
+            *e.entry(key.clone()).or_insert(0.0) += 1.0;
+            *visits.entry(key).or_insert(0) += 1;
+
+            for ((s, a), trace) in &e {
+                let step = alpha.at(visits[&(s.clone(), a.clone())]);
+                *q.get_mut(s.clone(), a.clone()) += step * delta * *trace;
+            }
+            state = next;
+        }
+    }
+    q
+}
+
+/// n-step tree-backup: off-policy temporal-difference control that
+/// learns the action values of `target` from episodes generated by
+/// following `behavior`, with no importance-sampling ratios.
+///
+/// Each pair `(s_t, a_t)` moves by `alpha` towards the tree-backup
+/// return over the next `n` steps, built from the last step back:
+/// the return from step `t + n` is `r + gamma * sum_a pi(a|s) Q(s,a)`,
+/// the expected target, and every earlier step `k` replaces its
+/// observed action's share of that sum with the return from the step
+/// after, `r_k + gamma * (sum_{a != a_k} pi(a|s_k) Q(s_k,a)
+/// + pi(a_k|s_k) * G)`. Each level of the tree weighs the continuation
+/// the behavior actually took by the target's probability of taking
+/// it, which never exceeds 1, so the variance can't blow up however
+/// unlike the policies are; an action the target would never take cuts
+/// the return off there. Near the end of an episode the return is
+/// truncated to the steps available. With `n = 1` this is one-step
+/// expected SARSA under `target`. `behavior` must cover `target`, the
+/// step sizes come from `alpha`, keyed by each pair's visit count, and
+/// `gamma` defaults to `env.gamma()`.
+#[allow(clippy::too_many_arguments)]
+pub fn tree_backup<E, B, T>(
+    env: &E,
+    behavior: &B,
+    target: &T,
+    n: usize,
+    episodes: usize,
+    alpha: impl Into<LearningRate>,
+    gamma: Option<f32>,
+    rng: &mut StdRng,
+) -> QTable<E>
+where
+    E: IEnvironment,
+    B: Policy<E>,
+    T: Policy<E>,
+{
+    let n = n.max(1);
+    let gamma = gamma.unwrap_or_else(|| env.gamma());
+    let alpha = alpha.into();
+    let mut q = QTable::<E>::new();
+    let mut visits: HashMap<(E::State, E::Action), usize> = HashMap::new();
+    for _ in 0..episodes {
+        let Some(start) = simulate::sample_start(env, rng) else {
+            break;
+        };
+        let (trajectory, last) =
+            simulate::run_episode(env, behavior, start, EPISODE_STEP_LIMIT, rng);
+        let state_at = |k: usize| trajectory.get(k).map_or(&last, |(s, _, _)| s);
+        // Each update only reads pairs later in the episode than its
+        // own, so updating once the episode is over, in order, is the
+        // same as updating as soon as each return is complete.
+        for t in 0..trajectory.len() {
+            let horizon = (t + n).min(trajectory.len());
+            let mut g =
+                trajectory[horizon - 1].2 + gamma * expected_q(env, &q, target, state_at(horizon));
+            for k in (t + 1..horizon).rev() {
+                let (s, a, _) = &trajectory[k];
+                let p = target.action_prob(env, s, a);
+                let others = expected_q(env, &q, target, s) - p * q.get(s, a);
+                g = trajectory[k - 1].2 + gamma * (others + p * g);
+            }
+            let (s, a, _) = &trajectory[t];
+            let key = (s.clone(), a.clone());
+            let visits = visits.entry(key.clone()).or_insert(0);
+            *visits += 1;
+            let value = q.get_mut(key.0, key.1);
+            *value += alpha.at(*visits) * (g - *value);
+        }
+    }
+    q
+}
+
+/// Differential TD prediction for continuing tasks: estimates the
+/// average reward `rho` of `policy` and its differential state values
+/// over a single run of `steps` steps.
+///
+/// Each step moves `V(s)` towards `r - rho + V(s')`, with `rho` the
+/// running mean of the rewards seen so far. There is no discounting,
+/// and `is_terminal` is ignored: the run is one endless episode, so
+/// this is meant for environments that never terminate, and episodic
+/// environments should use the discounted methods instead. The run
+/// ends early if a state has no available actions or no reachable
+/// next state. The differential values are only defined up to a
+/// constant. The step size comes from `alpha`, keyed by the number of
+/// visits to the updated state.
+pub fn average_reward_evaluation<E, P>(
+    env: &E,
+    policy: &P,
+    steps: usize,
+    alpha: impl Into<LearningRate>,
+    rng: &mut StdRng,
+) -> (f32, ValueFunction<E>)
+where
+    E: IEnvironment,
+    P: Policy<E>,
+{
+    let alpha = alpha.into();
+    let mut rho = 0.0;
+    let mut v = ValueFunction::<E>::new();
+    let mut visits: HashMap<E::State, usize> = HashMap::new();
+    let Some(mut state) = simulate::sample_start(env, rng) else {
+        return (rho, v);
+    };
+    for t in 1..=steps {
+        if env.actions_from(&state).is_empty() {
+            break;
+        }
+        let action = policy.sample(env, &state, rng);
+        let Some((next, reward)) = env.sample_transition(&state, &action, rng) else {
+            break;
+        };
+        rho += (reward - rho) / t as f32;
+
+        let next_value = v.get(&next).copied().unwrap_or(0.0);
+        let visits = visits.entry(state.clone()).or_insert(0);
+        *visits += 1;
+        let value = v.entry(state).or_insert(0.0);
+        *value += alpha.at(*visits) * (reward - rho + next_value - *value);
+        state = next;
+    }
+    (rho, v)
+}
+
+/// Categorical temporal-difference prediction, as in C51: estimates
+/// the distribution of `policy`'s return from each state, as
+/// probabilities over the fixed return values `atoms`.
+///
+/// Each step moves the distribution at `s` towards that of
+/// `r + gamma * Z(s')`, where `Z(s')` is the distribution at `s'`, or
+/// all mass at 0 if `s'` is terminal: each atom of `Z(s')` is shifted
+/// and scaled, then its mass is projected back onto `atoms` by
+/// splitting it between the two atoms either side, in proportion to
+/// how close it is to each. Values beyond the first or last atom are
+/// clamped to it. Distributions start with all their mass on the atom
+/// nearest 0, so while every target falls within the atoms, the mean
+/// of each distribution follows TD(0) exactly. The step size comes from
+/// `alpha`, keyed by the number of visits to the updated state.
+/// `gamma` defaults to `env.gamma()`.
+///
+/// Panics if `atoms` is empty or not strictly increasing.
+#[allow(clippy::too_many_arguments)]
+pub fn categorical_td<E, P>(
+    env: &E,
+    atoms: &[f32],
+    policy: &P,
+    episodes: usize,
+    alpha: impl Into<LearningRate>,
+    gamma: Option<f32>,
+    rng: &mut StdRng,
+) -> HashMap<E::State, Vec<f32>>
+where
+    E: IEnvironment,
+    P: Policy<E>,
+{
+    assert!(!atoms.is_empty(), "categorical_td needs at least one atom");
+    assert!(
+        atoms.windows(2).all(|w| w[0] < w[1]),
+        "atoms must be strictly increasing"
+    );
+    let gamma = gamma.unwrap_or_else(|| env.gamma());
+    let alpha = alpha.into();
+    let zero = project(atoms, &point_mass(atoms.len(), 0), 0.0, 0.0);
+    let mut z: HashMap<E::State, Vec<f32>> = HashMap::new();
+    let mut visits: HashMap<E::State, usize> = HashMap::new();
+    for _ in 0..episodes {
+        let Some(mut state) = simulate::sample_start(env, rng) else {
+            break;
+        };
+        for _ in 0..EPISODE_STEP_LIMIT {
+            if env.is_terminal(&state) || env.actions_from(&state).is_empty() {
+                break;
+            }
+            let action = policy.sample(env, &state, rng);
+            let Some((next, reward)) = env.sample_transition(&state, &action, rng) else {
+                break;
+            };
+
+            let target = if env.is_terminal(&next) {
+                project(atoms, &zero, reward, 0.0)
+            } else {
+                project(atoms, z.get(&next).unwrap_or(&zero), reward, gamma)
+            };
+            let visits = visits.entry(state.clone()).or_insert(0);
+            *visits += 1;
+            let step = alpha.at(*visits);
+            let probs = z.entry(state).or_insert_with(|| zero.clone());
+            for (p, t) in probs.iter_mut().zip(target) {
+                *p += step * (t - *p);
+            }
+            state = next;
+        }
+    }
+    z
+}
+
+/// The mean of the distribution `probs` over the return values
+/// `atoms`, e.g. from `categorical_td`.
+pub fn categorical_mean(atoms: &[f32], probs: &[f32]) -> f32 {
+    atoms.iter().zip(probs).map(|(z, p)| z * p).sum()
+}
+
+fn point_mass(len: usize, at: usize) -> Vec<f32> {
+    let mut probs = vec![0.0; len];
+    probs[at] = 1.0;
+    probs
+}
+
+/// The distribution of `reward + gamma * Z`, for `Z` distributed as
+/// `probs` over `atoms`, projected back onto `atoms`.
+fn project(atoms: &[f32], probs: &[f32], reward: f32, gamma: f32) -> Vec<f32> {
+    let last = atoms.len() - 1;
+    let mut projected = vec![0.0; atoms.len()];
+    for (z, p) in atoms.iter().zip(probs) {
+        if *p == 0.0 {
+            continue;
+        }
+        let value = reward + gamma * z;
+        // The index of the first atom above `value`.
+        let upper = atoms.partition_point(|a| *a <= value);
+        if upper == 0 {
+            projected[0] += p;
+        } else if upper > last {
+            projected[last] += p;
+        } else {
+            let (low, high) = (atoms[upper - 1], atoms[upper]);
+            let w = (value - low) / (high - low);
+            projected[upper - 1] += p * (1.0 - w);
+            projected[upper] += p * w;
+        }
+    }
+    projected
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::policy::DeterministicPolicy;
+    use crate::rng::make_rng;
+    use crate::testing::{values_approx_eq, Chain, Loop, Missing};
+
+    /// A ledge of states 0 to `LENGTH`, starting at 0, along which
+    /// action 1 moves on, to a reward of 1 at the terminal end, while
+    /// action -1 climbs down, to a reward of 0.01, ending the episode
+    /// in the terminal state -1.
+    struct Ledge;
+
+    impl Ledge {
+        const LENGTH: i32 = 10;
+    }
+
+    impl IEnvironment for Ledge {
+        type State = i32;
+        type Action = i32;
+
+        fn prob(&self, from: &i32, take: &i32, to: &i32, with: &f32) -> f32 {
+            let (next, reward) = match take {
+                -1 => (-1, 0.01),
+                _ if from + 1 == Self::LENGTH => (Self::LENGTH, 1.0),
+                _ => (from + 1, 0.0),
+            };
+            f32::from(*to == next && *with == reward)
+        }
+
+        fn actions_from(&self, _: &i32) -> HashSet<i32> {
+            HashSet::from([-1, 1])
+        }
+
+        fn states_from(&self, from: &i32, take: &i32) -> HashSet<i32> {
+            HashSet::from([if *take == -1 { -1 } else { from + 1 }])
+        }
+
+        fn is_terminal(&self, state: &i32) -> bool {
+            *state == -1 || *state == Self::LENGTH
+        }
+
+        fn start_states(&self) -> HashSet<i32> {
+            HashSet::from([0])
+        }
+
+        fn rewards(&self) -> Vec<f32> {
+            vec![0.0, 0.01, 1.0]
+        }
+    }
+
+    #[test]
+    fn restarts_find_a_far_goal() {
+        // Once climbing down has paid off, only a long run of random
+        // steps along the ledge from the start would find the goal.
+        let optimal =
+            |q: &QTable<Ledge>| (0..Ledge::LENGTH).all(|s| q.greedy_action(&Ledge, &s) == Some(1));
+        let fixed = q_learning(&Ledge, 2000, 0.5, Some(0.9), 0.2, &mut make_rng(0));
+        assert!(!optimal(&fixed));
+        assert_eq!(fixed.get(&(Ledge::LENGTH - 1), &1), 0.0);
+        let restarted =
+            q_learning_with_restarts(&Ledge, 2000, 0.5, Some(0.9), 0.2, 0.5, &mut make_rng(0));
+        assert!(optimal(&restarted));
+        let restarted =
+            sarsa_with_restarts(&Ledge, 2000, 0.5, Some(0.9), 0.2, 0.5, &mut make_rng(0));
+        assert!(optimal(&restarted));
+    }
+
+    #[test]
+    fn tracing_records_every_episode() {
+        let (q, metrics) = q_learning_traced(&Chain, 50, 0.5, Some(0.9), 0.3, &mut make_rng(0));
+        assert_eq!(metrics.len(), 50);
+        assert_eq!(
+            q,
+            q_learning(&Chain, 50, 0.5, Some(0.9), 0.3, &mut make_rng(0))
+        );
+        for m in &metrics {
+            assert!(m.total_reward.is_finite() && m.td_error_mean.is_finite());
+            // Every episode reaches the goal, for a reward of 1.
+            assert_eq!(m.total_reward, 1.0);
+            assert!(m.steps >= 3 && !m.capped);
+            assert_eq!(m.epsilon, 0.3);
+        }
+        // The values settle, so later steps surprise less.
+        assert!(metrics[49].td_error_mean.abs() < metrics[0].td_error_mean.abs());
+
+        // The loop never ends, so each episode is cut off.
+        let (_, metrics) = q_learning_traced(&Loop, 2, 0.5, None, 0.0, &mut make_rng(0));
+        for m in &metrics {
+            assert_eq!(m.steps, EPISODE_STEP_LIMIT);
+            assert!(m.capped);
+            assert_eq!(m.total_reward, EPISODE_STEP_LIMIT as f32);
+        }
+    }
+
+    /// Cliff walking on a 5x3 grid: the start and goal are the bottom
+    /// corners, and the cells between them are a cliff. Each step
+    /// costs 1, and stepping off the cliff costs 100 and returns the
+    /// agent to the start.
+    struct Cliff;
+
+    impl Cliff {
+        const WIDTH: i32 = 5;
+        const START: (i32, i32) = (0, 2);
+        const GOAL: (i32, i32) = (4, 2);
+
+        fn step(from: (i32, i32), take: (i32, i32)) -> ((i32, i32), f32) {
+            let to = (
+                (from.0 + take.0).clamp(0, Self::WIDTH - 1),
+                (from.1 + take.1).clamp(0, 2),
+            );
+            if to.1 == 2 && to != Self::START && to != Self::GOAL {
+                (Self::START, -100.0)
+            } else {
+                (to, -1.0)
+            }
+        }
+
+        /// The cells visited following `q` greedily from the start.
+        fn greedy_path(q: &QTable<Cliff>) -> Vec<(i32, i32)> {
+            let mut path = vec![Self::START];
+            let mut state = Self::START;
+            while state != Self::GOAL && path.len() < 20 {
+                state = Self::step(state, q.greedy_action(&Cliff, &state).unwrap()).0;
+                path.push(state);
+            }
+            path
+        }
+    }
+
+    impl IEnvironment for Cliff {
+        type State = (i32, i32);
+        type Action = (i32, i32);
+
+        fn prob(&self, from: &(i32, i32), take: &(i32, i32), to: &(i32, i32), with: &f32) -> f32 {
+            if Self::step(*from, *take) == (*to, *with) {
+                1.0
+            } else {
+                0.0
+            }
+        }
+
+        fn actions_from(&self, _: &(i32, i32)) -> HashSet<(i32, i32)> {
+            HashSet::from([(0, -1), (0, 1), (-1, 0), (1, 0)])
+        }
+
+        fn states_from(&self, from: &(i32, i32), take: &(i32, i32)) -> HashSet<(i32, i32)> {
+            HashSet::from([Self::step(*from, *take).0])
+        }
+
+        fn is_terminal(&self, state: &(i32, i32)) -> bool {
+            *state == Self::GOAL
+        }
+
+        fn start_states(&self) -> HashSet<(i32, i32)> {
+            HashSet::from([Self::START])
+        }
+
+        fn rewards(&self) -> Vec<f32> {
+            vec![-1.0, -100.0]
+        }
+    }
+
+    #[cfg(feature = "examples")]
+    #[test]
+    fn control_stops_at_states_without_actions() {
+        use crate::testing::Trap;
+
+        let mut rng = make_rng(0);
+        let q = q_learning(&Trap, 200, 0.5, Some(0.9), 0.1, &mut rng);
+        assert!((q.get(&2, &1) - 1.0).abs() < 1e-3);
+        assert_eq!(q.greedy_action(&Trap, &Chain::GOAL), None);
+        let q = expected_sarsa(&Trap, 200, 0.5, Some(0.9), 0.1, &mut rng);
+        assert!((q.get(&2, &1) - 1.0).abs() < 1e-3);
+    }
+
+    #[cfg(feature = "examples")]
+    #[test]
+    fn q_learning_converges_to_the_analytic_q() {
+        use crate::env::examples::{GridWorld, Move};
+
+        // The two cells next to the goal are worth 1 and the far
+        // corner 0.9, so every Q-value is r + 0.9 * V(s').
+        let grid = GridWorld::new(2, 2, [], (1, 1), 0.0, 0.0);
+        let v = |cell: (usize, usize)| match cell {
+            (1, 1) => 0.0,
+            (0, 0) => 0.9,
+            _ => 1.0,
+        };
+        let q = q_learning(&grid, 2000, 0.5, Some(0.9), 0.5, &mut make_rng(1));
+        for s in [(0, 0), (1, 0), (0, 1)] {
+            for a in Move::ALL {
+                let to = grid.moved(&s, a);
+                let reward = if to == grid.goal() { 1.0 } else { 0.0 };
+                let expected = reward + 0.9 * v(to);
+                assert!((q.get(&s, &a) - expected).abs() < 1e-2, "{s:?} {a:?}");
+            }
+        }
+    }
+
+    #[cfg(feature = "examples")]
+    #[test]
+    fn optimism_explores_without_epsilon() {
+        use crate::env::examples::{GridWorld, Move};
+
+        let grid = GridWorld::new(4, 4, [], (3, 3), 0.0, 0.0);
+        // Whether following `q` greedily from the start reaches the goal.
+        let reaches_goal = |q: &QTable<GridWorld>| {
+            let mut state = grid.start();
+            for _ in 0..20 {
+                match q.greedy_action(&grid, &state) {
+                    Some(a) => state = grid.moved(&state, a),
+                    None => return true,
+                }
+            }
+            false
+        };
+
+        // From all zeros, the first action tried keeps its value of 0
+        // however often it is taken, so it is taken forever.
+        let mut rng = make_rng(0);
+        let zero = q_learning(&grid, 2, 0.5, Some(0.9), 0.0, &mut rng);
+        assert!(!reaches_goal(&zero));
+
+        let optimistic = q_learning_from(
+            &grid,
+            QTable::filled(1.0),
+            100,
+            0.5,
+            Some(0.9),
+            0.0,
+            &mut rng,
+        );
+        assert!(reaches_goal(&optimistic));
+        assert_eq!(optimistic.get(&(3, 3), &Move::Up), 1.0);
+        assert_eq!(optimistic.max_action_value(&grid, &(3, 3)), 0.0);
+    }
+
+    #[test]
+    fn bonuses_decay_with_visits() {
+        let mut counts = VisitCounts::<Chain>::new();
+        assert_eq!(counts.count(&0, &1), 0);
+        let unvisited = counts.bonus(&0, &1, 0.5);
+        assert!(unvisited.is_finite());
+        assert!((unvisited - 5.0).abs() < 1e-5);
+
+        assert_eq!(counts.increment(0, 1), 1);
+        assert_eq!(counts.bonus(&0, &1, 0.5), 0.5);
+        for _ in 0..3 {
+            counts.increment(0, 1);
+        }
+        counts.increment(1, 1);
+        assert_eq!(counts.count(&0, &1), 4);
+        assert_eq!(counts.bonus(&0, &1, 0.5), 0.25);
+        // The less visited pair has the larger bonus.
+        assert!(counts.bonus(&1, &1, 0.5) > counts.bonus(&0, &1, 0.5));
+        assert!(unvisited > counts.bonus(&1, &1, 0.5));
+    }
+
+    #[test]
+    fn bonuses_draw_greedy_learners_out_to_the_goal() {
+        // Unrewarded, greedy Q-learning has no reason to ever reach the
+        // goal; the bonus makes it try each action in turn.
+        let (q, counts) =
+            q_learning_with_bonus(&Chain, 50, 0.5, Some(0.9), 0.0, 0.1, &mut make_rng(0));
+        assert!(counts.count(&2, &1) > 0);
+        for s in 0..Chain::GOAL {
+            assert!(counts.count(&s, &-1) > 0 && counts.count(&s, &1) > 0);
+        }
+        assert_eq!(q.greedy_action(&Chain, &2), Some(1));
+    }
+
+    #[test]
+    fn sarsa_walks_further_from_the_cliff_than_q_learning() {
+        let q = q_learning(&Cliff, 2000, 0.1, Some(1.0), 0.1, &mut make_rng(0));
+        let sarsa = sarsa(&Cliff, 2000, 0.1, Some(1.0), 0.1, &mut make_rng(0));
+        // Q-learning learns the shortest path, along the cliff edge;
+        // SARSA, which accounts for its own exploration, the safer
+        // one along the top.
+        assert_eq!(Cliff::greedy_path(&q).len(), 7);
+        assert!(Cliff::greedy_path(&q).contains(&(2, 1)));
+        assert!(Cliff::greedy_path(&sarsa).contains(&(2, 0)));
+    }
+
+    #[test]
+    fn sarsa_uses_a_next_value_of_zero_at_the_goal() {
+        // The goal loops back to itself with a reward of 1, which
+        // bootstrapping from it would add to the last move's value.
+        let q = sarsa(&Chain, 500, 0.5, Some(0.9), 0.1, &mut make_rng(0));
+        assert!((q.get(&2, &1) - 1.0).abs() < 1e-4);
+        assert!(!q.contains(&Chain::GOAL, &1));
+    }
+
+    #[test]
+    fn expected_sarsa_converges_to_the_optimal_q() {
+        // Once exploration has decayed away, Expected SARSA's target
+        // is Q-learning's. Actions off the greedy path are rarely
+        // tried by then, so compare the greedy actions and values.
+        let epsilon = Schedule::LinearDecay {
+            start: 0.5,
+            end: 0.0,
+            steps: 300,
+        };
+        let expected = expected_sarsa(&Chain, 500, 0.5, Some(0.9), epsilon, &mut make_rng(0));
+        let q = q_learning(&Chain, 500, 0.5, Some(0.9), 0.5, &mut make_rng(0));
+        for s in 0..Chain::GOAL {
+            let greedy = q.greedy_action(&Chain, &s).unwrap();
+            assert_eq!(expected.greedy_action(&Chain, &s), Some(greedy));
+            assert!((expected.get(&s, &greedy) - q.get(&s, &greedy)).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn expected_sarsa_varies_less_than_sarsa() {
+        // The spread over seeds of Q(0, +1) after a few episodes.
+        let spread = |run: &dyn Fn(&mut StdRng) -> QTable<Chain>| {
+            let values: Vec<f32> = (0..20)
+                .map(|seed| run(&mut make_rng(seed)).get(&0, &1))
+                .collect();
+            let mean = values.iter().sum::<f32>() / values.len() as f32;
+            values.iter().map(|x| (x - mean).powi(2)).sum::<f32>() / values.len() as f32
+        };
+        let expected = spread(&|rng| expected_sarsa(&Chain, 20, 0.5, Some(0.9), 0.3, rng));
+        let sampled = spread(&|rng| sarsa(&Chain, 20, 0.5, Some(0.9), 0.3, rng));
+        assert!(expected < sampled);
+    }
+
+    #[test]
+    fn one_step_td_bootstraps_from_the_next_state() {
+        // Walking right, the first episode only reaches V(2) through
+        // the goal's reward, and the second passes it back to V(1).
+        let v = n_step_td(
+            &Chain,
+            &Chain::rightward(),
+            1,
+            2,
+            0.5,
+            Some(0.9),
+            &mut make_rng(0),
+        );
+        assert_eq!(v[&2], 0.75);
+        assert!((v[&1] - 0.225).abs() < 1e-6);
+        assert_eq!(v[&0], 0.0);
+    }
+
+    #[test]
+    fn long_n_step_td_is_monte_carlo() {
+        // Walking right, every state gets its full discounted return
+        // from the first episode.
+        let v = n_step_td(
+            &Chain,
+            &Chain::rightward(),
+            10,
+            1,
+            1.0,
+            Some(0.9),
+            &mut make_rng(0),
+        );
+        for (s, g) in [(0, 0.81), (1, 0.9), (2, 1.0)] {
+            assert!((v[&s] - g).abs() < 1e-6, "{s}");
+        }
+
+        let policy = Chain::wandering();
+        let averaged = LearningRate::VisitDecayed(1.0);
+        let v = n_step_td(
+            &Chain,
+            &policy,
+            EPISODE_STEP_LIMIT,
+            200,
+            averaged,
+            Some(0.9),
+            &mut make_rng(5),
+        );
+        let mc = crate::monte_carlo::mc_predict_every_visit(
+            &Chain,
+            &policy,
+            200,
+            Some(0.9),
+            &mut make_rng(5),
+        );
+        assert!(values_approx_eq(&v, &mc, 1e-4, Missing::Zero));
+    }
+
+    #[test]
+    fn td_lambda_without_traces_is_td_zero() {
+        let policy = Chain::wandering();
+        let traced = td_lambda(
+            &Chain,
+            &policy,
+            0.0,
+            TraceKind::Accumulating,
+            300,
+            0.1,
+            Some(0.9),
+            &mut make_rng(3),
+        );
+        let v = n_step_td(&Chain, &policy, 1, 300, 0.1, Some(0.9), &mut make_rng(3));
+        assert!(values_approx_eq(&traced, &v, 1e-5, Missing::Zero));
+    }
+
+    #[test]
+    fn td_lambda_with_full_traces_is_monte_carlo() {
+        // The goal's reward reaches every state on the way, scaled by
+        // its discounted trace, just as the Monte Carlo return does.
+        for kind in [TraceKind::Accumulating, TraceKind::Replacing] {
+            let v = td_lambda(
+                &Chain,
+                &Chain::rightward(),
+                1.0,
+                kind,
+                1,
+                0.5,
+                Some(0.9),
+                &mut make_rng(0),
+            );
+            for (s, g) in [(0, 0.81), (1, 0.9), (2, 1.0)] {
+                assert!((v[&s] - 0.5 * g).abs() < 1e-6, "{s}");
+            }
+        }
+    }
+
+    /// A continuing task over states 0 and 1: state 0 moves to 1 for
+    /// a reward of 2, and state 1 either returns to 0 for nothing or
+    /// stays put for 1, each with probability 1/2.
+    struct Alternating;
+
+    impl IEnvironment for Alternating {
+        type State = u8;
+        type Action = ();
+
+        fn prob(&self, from: &u8, _: &(), to: &u8, with: &f32) -> f32 {
+            match (from, to) {
+                (0, 1) if *with == 2.0 => 1.0,
+                (1, 0) if *with == 0.0 => 0.5,
+                (1, 1) if *with == 1.0 => 0.5,
+                _ => 0.0,
+            }
+        }
+
+        fn actions_from(&self, _: &u8) -> HashSet<()> {
+            HashSet::from([()])
+        }
+
+        fn states_from(&self, from: &u8, _: &()) -> HashSet<u8> {
+            if *from == 0 {
+                HashSet::from([1])
+            } else {
+                HashSet::from([0, 1])
+            }
+        }
+
+        fn start_states(&self) -> HashSet<u8> {
+            HashSet::from([0])
+        }
+
+        fn rewards(&self) -> Vec<f32> {
+            vec![0.0, 1.0, 2.0]
+        }
+    }
+
+    #[test]
+    fn average_reward_evaluation_finds_the_stationary_reward() {
+        // The chain spends a third of its time in state 0, earning 2,
+        // and the rest in state 1, earning 1/2 on average, so rho = 1.
+        // Then V(0) = 2 - rho + V(1).
+        let policy = DeterministicPolicy::<Alternating>::new(HashMap::from([(0, ()), (1, ())]));
+        let (rho, v) =
+            average_reward_evaluation(&Alternating, &policy, 100_000, 0.01, &mut make_rng(2));
+        assert!((rho - 1.0).abs() < 0.02);
+        assert!((v[&0] - v[&1] - 1.0).abs() < 0.1);
+    }
+
+    #[cfg(feature = "examples")]
+    #[test]
+    fn double_q_learning_resists_maximization_bias() {
+        use crate::env::examples::{BiasAction, BiasState, MaximizationBias};
+
+        let env = MaximizationBias;
+        let goes_right = |q: &QTable<MaximizationBias>| {
+            q.greedy_action(&env, &BiasState::A) == Some(BiasAction::Right)
+        };
+        let (mut single, mut double) = (0, 0);
+        for seed in 0..100 {
+            let q = q_learning(&env, 100, 0.1, None, 0.1, &mut make_rng(seed));
+            single += goes_right(&q) as usize;
+            let (q1, mut sum) = double_q_learning(&env, 100, 0.1, None, 0.1, &mut make_rng(seed));
+            for ((s, a), v) in q1 {
+                *sum.get_mut(s, a) += v;
+            }
+            double += goes_right(&sum) as usize;
+        }
+        assert!(double > 2 * single, "{double} vs {single}");
+    }
+
+    #[test]
+    fn off_policy_n_step_with_one_policy_is_n_step_td() {
+        let policy = Chain::wandering();
+        for n in [1, 3, 100] {
+            let on = n_step_td(&Chain, &policy, n, 300, 0.05, Some(0.9), &mut make_rng(2));
+            let off = off_policy_n_step(
+                &Chain,
+                &policy,
+                &policy,
+                n,
+                300,
+                0.05,
+                Some(0.9),
+                &mut make_rng(2),
+            );
+            assert_eq!(on, off, "n = {n}");
+        }
+    }
+
+    #[test]
+    fn off_policy_n_step_estimates_the_target_policy() {
+        let target = Chain::rightward();
+        let v = crate::dp::evaluate_policy(&Chain, &target, Some(0.9), 1e-6, 1000).unwrap();
+        let estimate = off_policy_n_step(
+            &Chain,
+            &Chain::wandering(),
+            &target,
+            2,
+            3000,
+            0.01,
+            Some(0.9),
+            &mut make_rng(3),
+        );
+        assert!(values_approx_eq(&estimate, &v, 0.05, Missing::Zero));
+    }
+
+    #[test]
+    fn retrace_on_policy_is_td_lambda() {
+        // Following the target, the ratio is 1 and the traces of the
+        // pairs it takes decay exactly as TD(lambda)'s traces of states.
+        let policy = Chain::rightward();
+        let q = retrace(
+            &Chain,
+            &policy,
+            &policy,
+            5,
+            0.5,
+            Some(0.9),
+            0.5,
+            &mut make_rng(0),
+        );
+        let v = td_lambda(
+            &Chain,
+            &policy,
+            0.5,
+            TraceKind::Accumulating,
+            5,
+            0.5,
+            Some(0.9),
+            &mut make_rng(0),
+        );
+        for s in 0..Chain::GOAL {
+            assert!((q.get(&s, &1) - v[&s]).abs() < 1e-6, "{s}");
+        }
+    }
+
+    #[test]
+    fn retrace_is_stable_under_a_distant_behavior() {
+        // The uniform behavior takes the target's action only half the
+        // time, and unclipped ratios of 2 would compound along its runs.
+        let target = Chain::rightward();
+        let v = crate::dp::evaluate_policy(&Chain, &target, Some(0.9), 1e-6, 1000).unwrap();
+        let q_pi = crate::dp::q_from_v(&Chain, &v, Some(0.9)).unwrap();
+        let estimate = retrace(
+            &Chain,
+            &crate::policy::UniformRandomPolicy,
+            &target,
+            3000,
+            0.01,
+            Some(0.9),
+            1.0,
+            &mut make_rng(3),
+        );
+        for s in 0..Chain::GOAL {
+            for a in [-1, 1] {
+                let value = estimate.get(&s, &a);
+                assert!((value - q_pi.get(&s, &a)).abs() < 0.05, "{s} {a}: {value}");
+            }
+        }
+    }
+
+    #[test]
+    fn one_step_tree_backup_is_expected_sarsa_under_the_target() {
+        // Retrace with no traces is the one-step expected SARSA update.
+        let behavior = crate::policy::UniformRandomPolicy;
+        let target = Chain::rightward();
+        let tree = tree_backup(
+            &Chain,
+            &behavior,
+            &target,
+            1,
+            50,
+            0.5,
+            Some(0.9),
+            &mut make_rng(0),
+        );
+        let expected = retrace(
+            &Chain,
+            &behavior,
+            &target,
+            50,
+            0.5,
+            Some(0.9),
+            0.0,
+            &mut make_rng(0),
+        );
+        assert_eq!(tree, expected);
+    }
+
+    #[test]
+    fn tree_backup_is_stable_under_a_distant_behavior() {
+        let target = Chain::rightward();
+        let v = crate::dp::evaluate_policy(&Chain, &target, Some(0.9), 1e-6, 1000).unwrap();
+        let q_pi = crate::dp::q_from_v(&Chain, &v, Some(0.9)).unwrap();
+        let estimate = tree_backup(
+            &Chain,
+            &crate::policy::UniformRandomPolicy,
+            &target,
+            4,
+            3000,
+            0.01,
+            Some(0.9),
+            &mut make_rng(3),
+        );
+        for s in 0..Chain::GOAL {
+            for a in [-1, 1] {
+                let value = estimate.get(&s, &a);
+                assert!((value - q_pi.get(&s, &a)).abs() < 0.05, "{s} {a}: {value}");
+            }
+        }
+    }
+
+    #[test]
+    fn projection_splits_mass_and_clamps_to_the_support() {
+        let atoms = [0.0, 1.0, 2.0];
+        assert_eq!(project(&atoms, &[1.0, 0.0, 0.0], 0.5, 0.9), [0.5, 0.5, 0.0]);
+        assert_eq!(project(&atoms, &[0.0, 0.0, 1.0], 5.0, 0.9), [0.0, 0.0, 1.0]);
+        assert_eq!(
+            project(&atoms, &[0.5, 0.0, 0.5], -3.0, 1.0),
+            [1.0, 0.0, 0.0]
+        );
+        // 0.5 * 2 = 1 lands exactly on an atom.
+        assert_eq!(project(&atoms, &[0.0, 0.0, 1.0], 0.0, 0.5), [0.0, 1.0, 0.0]);
+    }
+
+    #[test]
+    fn categorical_means_match_td_zero() {
+        let atoms: Vec<f32> = (0..=10).map(|i| i as f32 / 10.0).collect();
+        let policy = Chain::wandering();
+        let z = categorical_td(
+            &Chain,
+            &atoms,
+            &policy,
+            300,
+            0.1,
+            Some(0.9),
+            &mut make_rng(4),
+        );
+        let v = n_step_td(&Chain, &policy, 1, 300, 0.1, Some(0.9), &mut make_rng(4));
+        for s in 0..Chain::GOAL {
+            assert!((z[&s].iter().sum::<f32>() - 1.0).abs() < 1e-5);
+            assert!(
+                (categorical_mean(&atoms, &z[&s]) - v[&s]).abs() < 1e-4,
+                "{s}"
+            );
+        }
+        // Stepping right from 2 always pays exactly 1.
+        assert!(z[&2][10] > 0.5);
+    }
+}
@@ -0,0 +1,235 @@
+//! Small environments shared by the unit tests.
+
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+
+use crate::env::IEnvironment;
+use crate::policy::{DeterministicPolicy, EpsilonGreedy};
+
+/// A corridor of states 0 to 3, starting at 0, in which action 1
+/// moves right and -1 moves left. Entering the terminal state 3 gives
+/// a reward of 1; every other step gives 0.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Chain;
+
+impl Chain {
+    pub(crate) const GOAL: i32 = 3;
+
+    /// Always moves right.
+    pub(crate) fn rightward() -> DeterministicPolicy<Chain> {
+        DeterministicPolicy::new((0..Self::GOAL).map(|s| (s, 1)).collect())
+    }
+
+    /// Moves right half the time, and otherwise at random.
+    pub(crate) fn wandering() -> EpsilonGreedy<Chain, DeterministicPolicy<Chain>> {
+        EpsilonGreedy::new(Self::rightward(), 0.5)
+    }
+}
+
+impl IEnvironment for Chain {
+    type State = i32;
+    type Action = i32;
+
+    fn prob(&self, from: &i32, take: &i32, to: &i32, with: &f32) -> f32 {
+        let next = (from + take).clamp(0, Self::GOAL);
+        let reward = if next == Self::GOAL { 1.0 } else { 0.0 };
+        if *to == next && *with == reward {
+            1.0
+        } else {
+            0.0
+        }
+    }
+
+    fn actions_from(&self, _: &i32) -> HashSet<i32> {
+        HashSet::from([-1, 1])
+    }
+
+    fn states_from(&self, from: &i32, take: &i32) -> HashSet<i32> {
+        HashSet::from([(from + take).clamp(0, Self::GOAL)])
+    }
+
+    fn is_terminal(&self, state: &i32) -> bool {
+        *state == Self::GOAL
+    }
+
+    fn start_states(&self) -> HashSet<i32> {
+        HashSet::from([0])
+    }
+
+    fn rewards(&self) -> Vec<f32> {
+        vec![0.0, 1.0]
+    }
+}
+
+/// `Chain`, except that its goal isn't terminal but a trap: a state
+/// with no available actions.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Trap;
+
+impl IEnvironment for Trap {
+    type State = i32;
+    type Action = i32;
+
+    fn prob(&self, from: &i32, take: &i32, to: &i32, with: &f32) -> f32 {
+        Chain.prob(from, take, to, with)
+    }
+
+    fn actions_from(&self, from: &i32) -> HashSet<i32> {
+        if *from == Chain::GOAL {
+            HashSet::new()
+        } else {
+            Chain.actions_from(from)
+        }
+    }
+
+    fn states_from(&self, from: &i32, take: &i32) -> HashSet<i32> {
+        Chain.states_from(from, take)
+    }
+
+    fn start_states(&self) -> HashSet<i32> {
+        Chain.start_states()
+    }
+
+    fn rewards(&self) -> Vec<f32> {
+        Chain.rewards()
+    }
+}
+
+/// A continuing task: a single state looping back to itself with a
+/// reward of 1, discounted by 0.9.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Loop;
+
+impl IEnvironment for Loop {
+    type State = ();
+    type Action = ();
+
+    fn prob(&self, _: &(), _: &(), _: &(), with: &f32) -> f32 {
+        if *with == 1.0 {
+            1.0
+        } else {
+            0.0
+        }
+    }
+
+    fn actions_from(&self, _: &()) -> HashSet<()> {
+        HashSet::from([()])
+    }
+
+    fn states_from(&self, _: &(), _: &()) -> HashSet<()> {
+        HashSet::from([()])
+    }
+
+    fn gamma(&self) -> f32 {
+        0.9
+    }
+
+    fn start_states(&self) -> HashSet<()> {
+        HashSet::from([()])
+    }
+
+    fn rewards(&self) -> Vec<f32> {
+        vec![1.0]
+    }
+}
+
+/// A small maze: a 6x4 grid with a wall to get around on the way from
+/// the bottom-left corner to the goal in the top-right.
+#[cfg(feature = "examples")]
+pub(crate) fn maze() -> crate::env::examples::GridWorld {
+    let walls = [(2, 1), (2, 2), (2, 3), (4, 0), (4, 1)];
+    crate::env::examples::GridWorld::new(6, 4, walls, (5, 0), 0.05, 0.0).with_start((0, 3))
+}
+
+/// The number of non-terminal states of `env` in which `q`'s greedy
+/// action is optimal under the discount `gamma`.
+#[cfg(feature = "examples")]
+pub(crate) fn optimal_states<E: IEnvironment>(
+    env: &E,
+    q: &crate::dp::QTable<E>,
+    gamma: f32,
+) -> usize {
+    let (v, _) = crate::dp::value_iteration(env, Some(gamma), 1e-8, 1000).unwrap();
+    let q_star = crate::dp::q_from_v(env, &v, Some(gamma)).unwrap();
+    env.all_states()
+        .into_iter()
+        .filter(|s| !env.is_terminal(s))
+        .filter(|s| {
+            let a = q.greedy_action(env, s).unwrap();
+            (q_star.get(s, &a) - v[s]).abs() < 1e-4
+        })
+        .count()
+}
+
+/// How `values_approx_eq` treats a state in only one of its maps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Missing {
+    /// As a mismatch, making the maps unequal.
+    Mismatch,
+    /// As having value 0, as the planners treat unvalued states.
+    Zero,
+}
+
+/// Whether every state's value in `a` is within `tol` of its value in
+/// `b`, inclusive, with states in only one of them treated as `missing`
+/// says.
+pub(crate) fn values_approx_eq<S: Hash + Eq>(
+    a: &HashMap<S, f32>,
+    b: &HashMap<S, f32>,
+    tol: f32,
+    missing: Missing,
+) -> bool {
+    let keys_match = || a.len() == b.len() && a.keys().all(|s| b.contains_key(s));
+    if missing == Missing::Mismatch && !keys_match() {
+        return false;
+    }
+    let value = |v: &HashMap<S, f32>, s: &S| v.get(s).copied().unwrap_or(0.0);
+    a.keys()
+        .chain(b.keys())
+        .all(|s| (value(a, s) - value(b, s)).abs() <= tol)
+}
+
+/// Whether `a` and `b` choose the same action, or both none, in each
+/// of `states`.
+pub(crate) fn policies_equal<E: IEnvironment>(
+    a: &DeterministicPolicy<E>,
+    b: &DeterministicPolicy<E>,
+    states: &HashSet<E::State>,
+) -> bool {
+    states.iter().all(|s| a.action(s) == b.action(s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_match_within_the_tolerance_inclusive() {
+        let a = HashMap::from([(0, 1.0), (1, 0.5)]);
+        let b = HashMap::from([(0, 1.25), (1, 0.5)]);
+        assert!(values_approx_eq(&a, &b, 0.25, Missing::Mismatch));
+        assert!(!values_approx_eq(&a, &b, 0.2499, Missing::Mismatch));
+        assert!(values_approx_eq(&a, &a, 0.0, Missing::Mismatch));
+    }
+
+    #[test]
+    fn missing_states_mismatch_or_count_as_zero() {
+        let a = HashMap::from([(0, 1.0), (1, 0.0)]);
+        let b = HashMap::from([(0, 1.0)]);
+        assert!(!values_approx_eq(&a, &b, 1e-6, Missing::Mismatch));
+        assert!(!values_approx_eq(&b, &a, 1e-6, Missing::Mismatch));
+        assert!(values_approx_eq(&a, &b, 1e-6, Missing::Zero));
+        assert!(values_approx_eq(&b, &a, 1e-6, Missing::Zero));
+        let c = HashMap::from([(0, 1.0), (2, 0.5)]);
+        assert!(!values_approx_eq(&b, &c, 1e-6, Missing::Zero));
+    }
+
+    #[test]
+    fn policies_are_compared_over_the_given_states() {
+        let right = Chain::rightward();
+        let wander = DeterministicPolicy::<Chain>::new(HashMap::from([(0, 1), (1, -1), (2, 1)]));
+        assert!(policies_equal(&right, &right, &Chain.all_states()));
+        assert!(!policies_equal(&right, &wander, &Chain.all_states()));
+        assert!(policies_equal(&right, &wander, &HashSet::from([0, 2, 3])));
+    }
+}
@@ -0,0 +1,266 @@
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+
+use crate::env::{search, IEnvironment};
+use crate::policy::{fixed_order, Fnv1a};
+
+/// The decimal places to which `dynamics_fingerprint` rounds the
+/// probabilities and rewards it hashes.
+const FINGERPRINT_DECIMALS: i32 = 6;
+
+/// Checks that the dynamics of taking `action` from `state` form a
+/// probability distribution: the total of `prob` over `states_from`
+/// and `rewards` must be within `tol` of 1.
+///
+/// If the rewards aren't enumerable, `prob_transition` is totalled
+/// over `states_from` instead. On failure the actual total is
+/// returned. An environment whose transition probabilities are then
+/// undefined totals 0.
+pub fn validate_dynamics<E: IEnvironment>(
+    env: &E,
+    state: &E::State,
+    action: &E::Action,
+    tol: f32,
+) -> Result<(), f32> {
+    let total = total_probability(env, state, action);
+    if (total - 1.0).abs() <= tol {
+        Ok(())
+    } else {
+        Err(total)
+    }
+}
+
+/// The total probability checked by `validate_dynamics`.
+pub(crate) fn total_probability<E: IEnvironment>(
+    env: &E,
+    state: &E::State,
+    action: &E::Action,
+) -> f32 {
+    let rewards = env.rewards();
+    let to = env.states_from(state, action);
+    if rewards.is_empty() {
+        to.iter()
+            .map(|to| env.prob_transition(state, action, to).unwrap_or(0.0))
+            .sum()
+    } else {
+        to.iter()
+            .flat_map(|to| rewards.iter().map(move |r| env.prob(state, action, to, r)))
+            .sum()
+    }
+}
+
+/// Runs `validate_dynamics` on every action from every non-terminal
+/// state in `env.all_states()`, returning the first state-action pair
+/// that fails along with its total probability.
+pub fn validate_all<E: IEnvironment>(env: &E, tol: f32) -> Result<(), (E::State, E::Action, f32)> {
+    for s in env.all_states().into_iter().filter(|s| !env.is_terminal(s)) {
+        for a in env.actions_from(&s) {
+            if let Err(total) = validate_dynamics(env, &s, &a, tol) {
+                return Err((s, a, total));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Checks that every non-terminal state in `env.all_states()` with any
+/// actions has some left after `action_mask`, returning the first
+/// state whose actions are all masked out: no policy can act there.
+pub fn validate_masks<E: IEnvironment>(env: &E) -> Result<(), E::State> {
+    for s in env.all_states().into_iter().filter(|s| !env.is_terminal(s)) {
+        if env.action_mask(&s).is_empty() && !env.actions_from(&s).is_empty() {
+            return Err(s);
+        }
+    }
+    Ok(())
+}
+
+/// The non-terminal states of `env.all_states()` from which no
+/// sequence of actions reaches a terminal state, in a fixed order, or
+/// none if every episode can end.
+///
+/// These are the strongly-connected components of the transition graph
+/// with no path out to a terminal, such as a room with no door, along
+/// with any states that can only lead into them and any non-terminal
+/// states without actions. Episodes entering one never end, so
+/// episodic algorithms run on until their step limit. They are found
+/// by searching back from the terminal states along the reversed
+/// graph. A continuing task, which has no terminal states, has all its
+/// states flagged.
+pub fn detect_absorbing_nonterminal<E: IEnvironment>(env: &E) -> Vec<E::State> {
+    let states = env.all_states();
+    let mut predecessors: HashMap<E::State, Vec<E::State>> = HashMap::new();
+    for s in states.iter().filter(|s| !env.is_terminal(s)) {
+        for a in env.actions_from(s) {
+            for to in env.states_from(s, &a) {
+                predecessors.entry(to).or_default().push(s.clone());
+            }
+        }
+    }
+    let terminals: HashSet<E::State> = states
+        .iter()
+        .filter(|s| env.is_terminal(s))
+        .cloned()
+        .collect();
+    let escapable = search(terminals, |to| {
+        predecessors.get(to).cloned().unwrap_or_default()
+    });
+    fixed_order(states.into_iter().filter(|s| !escapable.contains(s)))
+}
+
+/// `x` rounded to `FINGERPRINT_DECIMALS` places, as a whole number of
+/// those places, so that values differing only by rounding error hash
+/// the same, 0 and -0 included.
+fn quantize(x: f32) -> i64 {
+    (f64::from(x) * 10f64.powi(FINGERPRINT_DECIMALS)).round() as i64
+}
+
+/// A hash of the whole of `env`'s dynamics, for regression tests to
+/// compare against one recorded earlier, so that changes to `prob` or
+/// the rewards, intended or not, show up as a different fingerprint.
+///
+/// Every state of `env.all_states()`, whether it's terminal, and from
+/// each non-terminal one every action, next state, reward and
+/// probability with which the action reaches it with that reward, are
+/// hashed in a fixed order by FNV-1a, as `TieBreak::First` orders
+/// them, so the fingerprint is the same on every run and platform.
+/// Probabilities and rewards are rounded to 6 decimal places first,
+/// and outcomes of probability 0 are skipped. If the rewards aren't
+/// enumerable, each next state's `prob_transition` and
+/// `expected_reward_at` are hashed instead, or nothing if they're
+/// undefined.
+pub fn dynamics_fingerprint<E: IEnvironment>(env: &E) -> u64 {
+    let mut hasher = Fnv1a::default();
+    let mut rewards = env.rewards();
+    rewards.sort_by_key(|r| quantize(*r));
+    for s in fixed_order(env.all_states()) {
+        s.hash(&mut hasher);
+        let terminal = env.is_terminal(&s);
+        terminal.hash(&mut hasher);
+        if terminal {
+            continue;
+        }
+        for a in fixed_order(env.actions_from(&s)) {
+            a.hash(&mut hasher);
+            for to in fixed_order(env.states_from(&s, &a)) {
+                if rewards.is_empty() {
+                    let outcome = env
+                        .prob_transition(&s, &a, &to)
+                        .zip(env.expected_reward_at(&s, &a, &to));
+                    if let Some((p, r)) = outcome.filter(|(p, _)| quantize(*p) != 0) {
+                        (&to, quantize(p), quantize(r)).hash(&mut hasher);
+                    }
+                    continue;
+                }
+                for r in &rewards {
+                    let p = quantize(env.prob(&s, &a, &to, r));
+                    if p != 0 {
+                        (&to, quantize(*r), p).hash(&mut hasher);
+                    }
+                }
+            }
+        }
+    }
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Chain;
+
+    /// `Chain`, but with `prob` doubled, while `prob_transition` is
+    /// still correct.
+    struct Doubled;
+
+    impl IEnvironment for Doubled {
+        type State = i32;
+        type Action = i32;
+
+        fn prob(&self, from: &i32, take: &i32, to: &i32, with: &f32) -> f32 {
+            2.0 * Chain.prob(from, take, to, with)
+        }
+
+        fn prob_transition(&self, from: &i32, take: &i32, to: &i32) -> Option<f32> {
+            Chain.prob_transition(from, take, to)
+        }
+
+        fn actions_from(&self, from: &i32) -> HashSet<i32> {
+            Chain.actions_from(from)
+        }
+
+        fn states_from(&self, from: &i32, take: &i32) -> HashSet<i32> {
+            Chain.states_from(from, take)
+        }
+
+        fn is_terminal(&self, state: &i32) -> bool {
+            Chain.is_terminal(state)
+        }
+
+        fn start_states(&self) -> HashSet<i32> {
+            Chain.start_states()
+        }
+
+        fn rewards(&self) -> Vec<f32> {
+            Chain.rewards()
+        }
+    }
+
+    #[test]
+    fn flags_an_unnormalized_prob() {
+        assert_eq!(validate_dynamics(&Doubled, &0, &1, 1e-6), Err(2.0));
+        assert!(matches!(validate_all(&Doubled, 1e-6), Err((_, _, t)) if t == 2.0));
+        assert_eq!(validate_all(&Chain, 1e-6), Ok(()));
+    }
+
+    #[cfg(feature = "examples")]
+    #[test]
+    fn passes_dull() {
+        use crate::env::examples::{Always, DoNothing, Dull};
+
+        assert_eq!(
+            validate_dynamics(&Dull, &Always::Same, &DoNothing::Nothing, 1e-6),
+            Ok(())
+        );
+        assert_eq!(validate_all(&Dull, 1e-6), Ok(()));
+    }
+
+    #[cfg(feature = "examples")]
+    #[test]
+    fn flags_an_inescapable_room() {
+        use crate::env::examples::GridWorld;
+
+        let open = GridWorld::new(5, 5, [], (0, 0), -1.0, 0.0);
+        assert_eq!(detect_absorbing_nonterminal(&open), vec![]);
+
+        // A wall down the middle shuts the right-hand column off from
+        // the goal, with the start inside it.
+        let walls = (0..5).map(|y| (3, y));
+        let closed = GridWorld::new(5, 5, walls, (0, 0), -1.0, 0.0).with_start((4, 2));
+        let mut room = detect_absorbing_nonterminal(&closed);
+        room.sort();
+        assert_eq!(room, (0..5).map(|y| (4, y)).collect::<Vec<_>>());
+    }
+
+    #[cfg(feature = "examples")]
+    #[test]
+    fn fingerprints_change_only_with_the_dynamics() {
+        use crate::env::examples::GridWorld;
+
+        let grid = |slip| GridWorld::new(4, 3, [(1, 1)], (3, 0), 0.04, slip);
+        let fingerprint = dynamics_fingerprint(&grid(0.2));
+        // Recorded once, so that a change to the hashing scheme, or to
+        // `GridWorld`'s dynamics, shows up here.
+        assert_eq!(fingerprint, 0x2c2a_6333_9920_b78e);
+        let costlier = GridWorld::new(4, 3, [(1, 1)], (3, 0), 0.05, 0.2);
+        assert_ne!(dynamics_fingerprint(&costlier), fingerprint);
+        // Built again, its sets iterate in another order, but hash the
+        // same.
+        for _ in 0..5 {
+            assert_eq!(dynamics_fingerprint(&grid(0.2)), fingerprint);
+        }
+        assert_ne!(dynamics_fingerprint(&grid(0.1)), fingerprint);
+        assert_ne!(dynamics_fingerprint(&grid(0.0)), fingerprint);
+        assert_ne!(dynamics_fingerprint(&Chain), fingerprint);
+    }
+}